@@ -149,7 +149,7 @@ fn ui<T: Backend>(f: &mut Frame<T>, active_menu_item: MenuItem, pet_list_state:
     ])
     .split(size);
 
-  let menu_titles = vec!["Home", "Pets", "Add", "Delete", "Quit"];
+  let menu_titles = ["Home", "Pets", "Add", "Delete", "Quit"];
   let menu = menu_titles
     .iter()
     .map(|t| {
@@ -39,7 +39,7 @@ fn ui<B: Backend>(f: &mut Frame<B>) {
     );
   f.render_widget(heading, chunks[0]);
 
-  let menu_titles = vec!["Add", "Update", "Delete", "Quit"];
+  let menu_titles = ["Add", "Update", "Delete", "Quit"];
   let menu = menu_titles
     .iter()
     .map(|t| {
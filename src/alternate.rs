@@ -0,0 +1,91 @@
+//! The files `:A` switches between, like a C header and its source or a module and its tests.
+//! Each group names related files with `%` standing for the part they share:
+//!
+//! ```json
+//! { "alternates": [["%.vert", "%.frag"], ["%.rs", "%_bench.rs"]] }
+//! ```
+//!
+//! The user's groups under `alternates` in the settings file are looked at before the
+//! built-in ones. A counterpart is looked for next to the file and in the directories next to
+//! the file's own.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const GROUPS: &[&[&str]] = &[
+  &[
+    "%.h", "%.hpp", "%.hh", "%.c", "%.cpp", "%.cc", "%.cxx", "%.m",
+  ],
+  &["%.rs", "%_test.rs", "%_tests.rs"],
+  &["%.go", "%_test.go"],
+  &["%.py", "test_%.py", "%_test.py"],
+  &["%.js", "%.test.js", "%.spec.js"],
+  &["%.ts", "%.test.ts", "%.spec.ts"],
+];
+
+/// The existing files related to `path` by the first group that has a pattern for its name,
+/// nearest first.
+pub fn find(path: &Path, user: &[Vec<String>]) -> Vec<PathBuf> {
+  let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+    return Vec::new();
+  };
+  let user = user
+    .iter()
+    .map(|group| group.iter().map(String::as_str).collect());
+  let builtin = GROUPS.iter().map(|group| group.to_vec());
+  let dir = path.parent().unwrap_or(Path::new(""));
+  for group in user.chain(builtin).collect::<Vec<Vec<&str>>>() {
+    // `%_test.rs` says more about `a_test.rs` than `%.rs` does.
+    let mut matches: Vec<(&str, &str)> = group
+      .iter()
+      .filter_map(|p| stem(p, name).map(|stem| (*p, stem)))
+      .collect();
+    matches.sort_by_key(|(_, stem)| stem.len());
+    for (own, stem) in matches {
+      let names: Vec<String> = group
+        .iter()
+        .filter(|&&p| p != own)
+        .map(|p| p.replacen('%', stem, 1))
+        .collect();
+      let found: Vec<PathBuf> = places(dir)
+        .iter()
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .filter(|candidate| candidate.is_file() && candidate != path)
+        .collect();
+      if !found.is_empty() {
+        return found;
+      }
+    }
+  }
+  Vec::new()
+}
+
+/// What `%` stands for if file name `name` matches `pattern`.
+fn stem<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+  let (before, after) = pattern.split_once('%')?;
+  let stem = name.strip_prefix(before)?.strip_suffix(after)?;
+  (!stem.is_empty()).then_some(stem)
+}
+
+/// `dir` and the directories next to it, like `include` for `src`.
+fn places(dir: &Path) -> Vec<PathBuf> {
+  let mut places = vec![dir.to_path_buf()];
+  let Some(parent) = dir.parent() else {
+    return places;
+  };
+  let Ok(entries) = fs::read_dir(match parent.as_os_str().is_empty() {
+    true => Path::new("."),
+    false => parent,
+  }) else {
+    return places;
+  };
+  let mut siblings: Vec<PathBuf> = entries
+    .flatten()
+    .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+    .map(|entry| parent.join(entry.file_name()))
+    .filter(|sibling| sibling != dir)
+    .collect();
+  siblings.sort();
+  places.extend(siblings);
+  places
+}
@@ -0,0 +1,62 @@
+//! Virtual text: notes drawn after the end of a buffer's lines, like a diagnostic's message or
+//! who last changed a line, that aren't part of the text and can't be edited. Each note comes
+//! from a named source, which replaces or clears its own notes without touching the others'.
+//! Edits move notes along with their lines.
+
+/// A note on `line` from `source`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+  pub line: usize,
+  pub text: String,
+  pub source: &'static str,
+}
+
+/// The notes of a buffer, in the order they were added.
+#[derive(Clone, Debug, Default)]
+pub struct Annotations {
+  notes: Vec<Annotation>,
+}
+
+impl Annotations {
+  /// Replaces the notes of `source` with `notes`, by line.
+  pub fn set(&mut self, source: &'static str, notes: impl IntoIterator<Item = (usize, String)>) {
+    self.clear(source);
+    self.notes.extend(
+      notes
+        .into_iter()
+        .map(|(line, text)| Annotation { line, text, source }),
+    );
+  }
+
+  /// Whether `source` has any notes.
+  pub fn has(&self, source: &str) -> bool {
+    self.notes.iter().any(|note| note.source == source)
+  }
+
+  pub fn clear(&mut self, source: &'static str) {
+    self.notes.retain(|note| note.source != source);
+  }
+
+  /// The notes on `line`.
+  pub fn on(&self, line: usize) -> impl Iterator<Item = &Annotation> {
+    self.notes.iter().filter(move |note| note.line == line)
+  }
+
+  /// Moves the notes from line `first` on down by `count`, for lines inserted before it.
+  pub fn insert_lines(&mut self, first: usize, count: usize) {
+    for note in self.notes.iter_mut().filter(|note| note.line >= first) {
+      note.line += count;
+    }
+  }
+
+  /// Drops the notes on the `count` lines from `first`, which are gone, and moves those after
+  /// them up.
+  pub fn remove_lines(&mut self, first: usize, count: usize) {
+    self
+      .notes
+      .retain(|note| !(first..first + count).contains(&note.line));
+    for note in self.notes.iter_mut().filter(|note| note.line >= first) {
+      note.line -= count;
+    }
+  }
+}
@@ -0,0 +1,47 @@
+//! Timestamped copies of files written by autosave when `backup` is set.
+//!
+//! Each file gets its own directory under `backupdir`, named after the file's full path with
+//! `%` and `/` escaped, so files with the same name in different places never share one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where backups go when `backupdir` isn't set.
+pub fn default_dir() -> Option<PathBuf> {
+  crate::state::dir().map(|dir| dir.join("backups"))
+}
+
+/// The directory holding the backups of `path`.
+fn file_dir(backupdir: &Path, path: &Path) -> PathBuf {
+  let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+  let name = path
+    .to_string_lossy()
+    .replace('%', "%25")
+    .replace('/', "%2F");
+  backupdir.join(name)
+}
+
+/// Writes a new backup of `path` holding `contents`, then removes all but the newest `keep`.
+pub fn write(backupdir: &Path, path: &Path, contents: &str, keep: usize) -> io::Result<PathBuf> {
+  let dir = file_dir(backupdir, path);
+  fs::create_dir_all(&dir)?;
+  let stamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S%.3f");
+  let backup = dir.join(stamp.to_string());
+  fs::write(&backup, contents)?;
+  for old in list(backupdir, path).into_iter().skip(keep.max(1)) {
+    let _ = fs::remove_file(old);
+  }
+  Ok(backup)
+}
+
+/// The backups of `path`, newest first.
+pub fn list(backupdir: &Path, path: &Path) -> Vec<PathBuf> {
+  let Ok(entries) = fs::read_dir(file_dir(backupdir, path)) else {
+    return Vec::new();
+  };
+  let mut backups: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+  // Timestamps sort in the order they were taken.
+  backups.sort_by(|a, b| b.cmp(a));
+  backups
+}
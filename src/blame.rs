@@ -0,0 +1,97 @@
+//! `:blame`: who last changed each line of a buffer, from `git blame --porcelain`, shown as
+//! virtual text. Git runs on a thread of its own since it can take a while on a long history.
+
+use chrono::{Local, TimeZone};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+/// The blame of the file at `path`: a note for each line, by line. Empty if git has nothing to
+/// say about the file, as when it isn't tracked or isn't in a repository.
+#[derive(Debug)]
+pub struct Blame {
+  pub path: PathBuf,
+  pub lines: Vec<(usize, String)>,
+}
+
+/// Hands a blame to the editor.
+pub type Notify = Arc<dyn Fn(Blame) -> bool + Send + Sync>;
+
+/// Blames `contents` as the text of the file at the absolute `path`, so lines added or moved
+/// since it was saved line up, and hands the result to `notify` once git is done.
+pub fn spawn(path: PathBuf, contents: String, notify: Notify) {
+  thread::spawn(move || {
+    let lines = run(&path, &contents).unwrap_or_default();
+    notify(Blame { path, lines });
+  });
+}
+
+fn run(path: &Path, contents: &str) -> Option<Vec<(usize, String)>> {
+  let mut child = Command::new("git")
+    .args(["blame", "--porcelain", "--contents", "-", "--"])
+    .arg(path.file_name()?)
+    .current_dir(path.parent()?)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::null())
+    .spawn()
+    .ok()?;
+  let mut stdin = child.stdin.take().expect("stdin is piped");
+  let input = contents.to_owned();
+  let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+  let output = child.wait_with_output().ok()?;
+  let _ = writer.join();
+  if !output.status.success() {
+    return None;
+  }
+  Some(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Reads the porcelain format: for each line, a header with the commit's hash and the line's
+/// number, the commit's details the first time it comes up, and the line itself after a tab.
+fn parse(porcelain: &str) -> Vec<(usize, String)> {
+  let mut authors: HashMap<&str, (&str, i64)> = HashMap::new();
+  let mut notes = Vec::new();
+  let mut header: Option<(&str, usize)> = None;
+  for line in porcelain.lines() {
+    if line.starts_with('\t') {
+      if let Some((hash, number)) = header.take() {
+        let (author, time) = authors.get(hash).copied().unwrap_or_default();
+        notes.push((number.saturating_sub(1), describe(hash, author, time)));
+      }
+      continue;
+    }
+    let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+    match header {
+      None => {
+        let number = value.split(' ').nth(1).and_then(|n| n.parse().ok());
+        header = number.map(|number| (key, number));
+      }
+      Some((hash, _)) => {
+        let entry = authors.entry(hash).or_default();
+        match key {
+          "author" => entry.0 = value,
+          "author-time" => entry.1 = value.parse().unwrap_or_default(),
+          _ => (),
+        }
+      }
+    }
+  }
+  notes
+}
+
+/// A line's note: the commit's short hash, its author and the day it was written.
+fn describe(hash: &str, author: &str, time: i64) -> String {
+  // Lines changed since the last commit are blamed on a hash of zeros.
+  if hash.bytes().all(|b| b == b'0') {
+    return "Not committed yet".to_owned();
+  }
+  let date = match Local.timestamp_opt(time, 0).single() {
+    Some(date) => date.format("%Y-%m-%d").to_string(),
+    None => String::new(),
+  };
+  format!("{} {} {}", &hash[..hash.len().min(7)], author, date)
+}
@@ -0,0 +1,469 @@
+use crate::annotation::Annotations;
+use crate::diff::{self, Change};
+use crate::fold::{self, Fold};
+use crate::lsp::Hints;
+use crate::options::{BufferOptions, FileFormat};
+use crate::symbol::Functions;
+use crate::undo::{Edit, UndoTree};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fs, io};
+
+/// A location in a buffer. `col` counts chars, not bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+  pub line: usize,
+  pub col: usize,
+}
+
+impl Position {
+  pub fn new(line: usize, col: usize) -> Self {
+    Position { line, col }
+  }
+}
+
+pub struct Buffer {
+  pub lines: Vec<String>,
+  pub path: Option<PathBuf>,
+  pub modified: bool,
+  /// Modification time of the file when it was last loaded or saved.
+  pub mtime: Option<SystemTime>,
+  pub undo: UndoTree,
+  pub folds: Vec<Fold>,
+  pub options: BufferOptions,
+  /// The language of the file, as found by `filetype::detect`.
+  pub filetype: Option<&'static str>,
+  /// Whether the file ended with a line ending when it was read.
+  pub final_newline: bool,
+  /// Whether the file started with a UTF-8 byte order mark when it was read. The mark itself
+  /// is kept out of `lines`; `bomb` decides whether it is written back.
+  pub had_bom: bool,
+  /// Where the cursor was when a window last switched away from the buffer.
+  pub last_cursor: Position,
+  /// What the language server shows in the text with `inlayhints`.
+  pub hints: Hints,
+  /// Where the language server says the functions are, for `if` and `af`.
+  pub functions: Functions,
+  /// The language server's folding ranges with `foldmethod=lsp`.
+  pub folding: fold::Ranges,
+  /// Notes shown after the end of lines.
+  pub annotations: Annotations,
+  /// The directory set with `:lcd`, which commands run in and relative paths are taken from
+  /// while the buffer is focused.
+  pub local_dir: Option<PathBuf>,
+  /// The positions `m` set, by mark name.
+  pub marks: HashMap<char, Position>,
+  /// Where the text was last changed, oldest first, for `g;` and `g,`.
+  pub changes: Vec<Position>,
+  /// The entry of `changes` that `g;` and `g,` last went to, or its length after a change.
+  pub change_index: usize,
+  /// The lines as last read from or written to the file.
+  saved: Vec<String>,
+  /// Counts edits, to tell when `signs` are out of date.
+  edits: u64,
+  /// The `version` autosave last handled.
+  pub autosaved: u64,
+  /// How each line differs from `saved`, as of edit number `signs_at`.
+  signs: Vec<Option<Change>>,
+  signs_at: u64,
+}
+
+impl Default for Buffer {
+  fn default() -> Self {
+    Buffer {
+      lines: vec![String::new()],
+      path: None,
+      modified: false,
+      mtime: None,
+      undo: UndoTree::default(),
+      folds: Vec::new(),
+      options: BufferOptions::default(),
+      filetype: None,
+      final_newline: true,
+      had_bom: false,
+      last_cursor: Position::default(),
+      hints: Hints::default(),
+      functions: Functions::default(),
+      folding: fold::Ranges::default(),
+      annotations: Annotations::default(),
+      local_dir: None,
+      marks: HashMap::new(),
+      changes: Vec::new(),
+      change_index: 0,
+      saved: Vec::new(),
+      edits: 0,
+      autosaved: 0,
+      signs: Vec::new(),
+      signs_at: 0,
+    }
+  }
+}
+
+impl Buffer {
+  /// Opens `path`, or starts an empty buffer for it if the file doesn't exist yet. A file with
+  /// DOS line endings gets `fileformat=dos`.
+  pub fn open(path: &Path) -> io::Result<Self> {
+    let mut buffer = Buffer {
+      path: Some(path.to_path_buf()),
+      ..Buffer::default()
+    };
+    match fs::read_to_string(path) {
+      Ok(content) => {
+        buffer = Buffer::from_content(&content);
+        buffer.path = Some(path.to_path_buf());
+        buffer.mtime = disk_mtime(path);
+      }
+      Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+      Err(e) => return Err(e),
+    }
+    Ok(buffer)
+  }
+
+  /// An unnamed buffer holding `content`, such as text read from stdin.
+  pub fn from_content(content: &str) -> Self {
+    let mut buffer = Buffer::default();
+    let (content, had_bom) = strip_bom(content);
+    buffer.had_bom = had_bom;
+    buffer.options.bomb = had_bom;
+    if content
+      .split('\n')
+      .next()
+      .is_some_and(|l| l.ends_with('\r'))
+    {
+      buffer.options.fileformat = FileFormat::Dos;
+    }
+    buffer.final_newline = content.is_empty() || content.ends_with('\n');
+    buffer.lines = split_lines(content);
+    buffer.saved = buffer.lines.clone();
+    buffer
+  }
+
+  /// Throws away the in-memory contents and reads the file again.
+  pub fn reload(&mut self) -> io::Result<()> {
+    let path = self.path.clone().ok_or_else(no_file_name)?;
+    let content = fs::read_to_string(&path)?;
+    let (content, had_bom) = strip_bom(&content);
+    self.had_bom = had_bom;
+    self.options.bomb = had_bom;
+    self.final_newline = content.is_empty() || content.ends_with('\n');
+    self.lines = split_lines(content);
+    self.mark_saved();
+    self.mtime = disk_mtime(&path);
+    self.modified = false;
+    // Recorded positions refer to the old text.
+    self.undo.clear();
+    self.annotations = Annotations::default();
+    let len = self.lines.len();
+    self.folds.retain(|f| f.end < len);
+    Ok(())
+  }
+
+  /// Writes the buffer to its path and returns the number of bytes written. With `trimtrailing`
+  /// the trailing whitespace is removed from the buffer first, as an ordinary edit.
+  pub fn save(&mut self) -> io::Result<usize> {
+    let path = self.path.clone().ok_or_else(no_file_name)?;
+    if self.options.trimtrailing {
+      self.trim_trailing_whitespace();
+    }
+    let content = self.contents();
+    fs::write(&path, &content)?;
+    self.mtime = disk_mtime(&path);
+    self.modified = false;
+    self.mark_saved();
+    self.final_newline |= self.options.fixendofline;
+    Ok(content.len())
+  }
+
+  /// Makes the current lines the ones change signs compare against, clearing the signs.
+  pub fn mark_saved(&mut self) {
+    self.saved = self.lines.clone();
+    self.edits += 1;
+    self.signs.clear();
+    self.signs_at = self.edits;
+  }
+
+  /// A number that changes whenever the text does.
+  pub fn version(&self) -> u64 {
+    self.edits
+  }
+
+  /// Replaces the whole text with `content`, as one change that can be undone.
+  pub fn replace_all(&mut self, content: &str) {
+    let last = self.lines.len() - 1;
+    let end = Position::new(last, self.line_len(last));
+    self.delete(Position::new(0, 0), end);
+    let text = split_lines(strip_bom(content).0).join("\n");
+    self.insert(Position::new(0, 0), &text);
+  }
+
+  /// Brings the change signs up to date if there were edits since they were last computed.
+  pub fn update_signs(&mut self) {
+    if self.signs_at != self.edits {
+      self.signs = diff::line_changes(&self.saved, &self.lines);
+      self.signs_at = self.edits;
+    }
+  }
+
+  /// The change sign of `line`, as of the last `update_signs`.
+  pub fn sign(&self, line: usize) -> Option<Change> {
+    self.signs.get(line).copied().flatten()
+  }
+
+  /// The text as it is written to the file, with the buffer's line endings.
+  pub fn contents(&self) -> String {
+    let ending = self.options.fileformat.line_ending();
+    let mut content = String::new();
+    if self.options.bomb && !self.lines[0].starts_with('\u{feff}') {
+      content.push('\u{feff}');
+    }
+    content.push_str(&self.lines.join(ending));
+    if self.final_newline || self.options.fixendofline {
+      content.push_str(ending);
+    }
+    content
+  }
+
+  pub fn trim_trailing_whitespace(&mut self) {
+    for line in 0..self.lines.len() {
+      let text = &self.lines[line];
+      let trimmed = text.trim_end().len();
+      if trimmed < text.len() {
+        let start = Position::new(line, text[..trimmed].chars().count());
+        self.delete(start, Position::new(line, self.line_len(line)));
+      }
+    }
+  }
+
+  /// Current modification time of the file on disk, if there is one.
+  pub fn disk_mtime(&self) -> Option<SystemTime> {
+    self.path.as_deref().and_then(disk_mtime)
+  }
+
+  /// Whether the file was modified by someone else since we last loaded or saved it.
+  pub fn changed_on_disk(&self) -> bool {
+    self.path.is_some() && self.disk_mtime() != self.mtime
+  }
+
+  /// Lines that differ between the buffer and the file on disk, in buffer line numbers.
+  pub fn diff_with_disk(&self) -> io::Result<Vec<usize>> {
+    let path = self.path.clone().ok_or_else(no_file_name)?;
+    let disk = split_lines(strip_bom(&fs::read_to_string(path)?).0);
+    let len = self.lines.len().max(disk.len());
+    Ok(
+      (0..len)
+        .filter(|&i| self.lines.get(i) != disk.get(i))
+        .collect(),
+    )
+  }
+
+  pub fn name(&self) -> String {
+    match &self.path {
+      Some(path) => path.display().to_string(),
+      None => "[No Name]".to_owned(),
+    }
+  }
+
+  pub fn line_len(&self, line: usize) -> usize {
+    self.lines.get(line).map_or(0, |l| l.chars().count())
+  }
+
+  /// Inserts `text` at `pos` and returns the position right after it.
+  pub fn insert(&mut self, pos: Position, text: &str) -> Position {
+    if !text.is_empty() {
+      self.record_change(pos);
+      self.undo.record(Edit::Insert {
+        at: pos,
+        text: text.to_owned(),
+      });
+    }
+    self.insert_raw(pos, text)
+  }
+
+  /// Removes the text between `start` and `end` (exclusive) and returns it.
+  pub fn delete(&mut self, start: Position, end: Position) -> String {
+    let removed = self.delete_raw(start, end);
+    if !removed.is_empty() {
+      self.record_change(start);
+      self.undo.record(Edit::Delete {
+        at: start,
+        text: removed.clone(),
+      });
+    }
+    removed
+  }
+
+  /// Adds `pos` to the change list. A change on the same line as the last one takes its
+  /// place, so typing a word leaves one entry rather than one per char.
+  fn record_change(&mut self, pos: Position) {
+    const MAX_CHANGES: usize = 100;
+    if self
+      .changes
+      .last()
+      .is_some_and(|last| last.line == pos.line)
+    {
+      self.changes.pop();
+    }
+    self.changes.push(pos);
+    if self.changes.len() > MAX_CHANGES {
+      self.changes.remove(0);
+    }
+    self.change_index = self.changes.len();
+  }
+
+  /// Replaces the text of `line`, keeping it as a single recorded change.
+  pub fn replace_line(&mut self, line: usize, text: &str) {
+    let start = Position::new(line, 0);
+    self.delete(start, Position::new(line, self.line_len(line)));
+    self.insert(start, text);
+  }
+
+  /// Reverts the last undo group and returns where the cursor should go.
+  pub fn undo(&mut self) -> Option<Position> {
+    let group = self.undo.undo()?;
+    for edit in group.iter().rev() {
+      self.apply(&edit.inverse());
+    }
+    group.first().map(Edit::at)
+  }
+
+  /// Reapplies the last undone group and returns where the cursor should go.
+  pub fn redo(&mut self) -> Option<Position> {
+    let group = self.undo.redo()?;
+    for edit in &group {
+      self.apply(edit);
+    }
+    group.first().map(Edit::at)
+  }
+
+  /// Undoes and redoes its way to state `id` of the undo tree, on whichever branch it is, and
+  /// returns where the cursor should go.
+  pub fn undo_to(&mut self, id: usize) -> Option<Position> {
+    let (up, down) = self.undo.route(id)?;
+    let mut at = None;
+    for _ in 0..up {
+      at = self.undo().or(at);
+    }
+    for id in down {
+      self.undo.choose(id);
+      at = self.redo().or(at);
+    }
+    at
+  }
+
+  fn apply(&mut self, edit: &Edit) {
+    match edit {
+      Edit::Insert { at, text } => {
+        self.insert_raw(*at, text);
+      }
+      Edit::Delete { at, text } => {
+        let end = end_of(*at, text);
+        self.delete_raw(*at, end);
+      }
+    }
+  }
+
+  fn insert_raw(&mut self, pos: Position, text: &str) -> Position {
+    let line = &mut self.lines[pos.line];
+    let tail = line.split_off(byte_index(line, pos.col));
+    let mut pieces = text.split('\n');
+    let first = pieces.next().unwrap_or_default();
+    line.push_str(first);
+    let mut added: Vec<String> = pieces.map(str::to_owned).collect();
+    let end = match added.last_mut() {
+      Some(last) => {
+        let col = last.chars().count();
+        last.push_str(&tail);
+        Position::new(pos.line + added.len(), col)
+      }
+      None => {
+        line.push_str(&tail);
+        Position::new(pos.line, pos.col + first.chars().count())
+      }
+    };
+    let count = added.len();
+    self.lines.splice(pos.line + 1..pos.line + 1, added);
+    fold::shift(&mut self.folds, pos.line, count as isize);
+    // Breaking a line at its start moves all of it down, and its notes go along.
+    let moved = if pos.col == 0 { pos.line } else { pos.line + 1 };
+    self.annotations.insert_lines(moved, count);
+    self.modified = true;
+    self.edits += 1;
+    end
+  }
+
+  fn delete_raw(&mut self, start: Position, end: Position) -> String {
+    if start >= end {
+      return String::new();
+    }
+    let end_line = self.lines[end.line].clone();
+    let end_at = byte_index(&end_line, end.col);
+    let first = &mut self.lines[start.line];
+    let start_at = byte_index(first, start.col);
+    let mut removed = if start.line == end.line {
+      first[start_at..end_at].to_owned()
+    } else {
+      first[start_at..].to_owned()
+    };
+    first.truncate(start_at);
+    first.push_str(&end_line[end_at..]);
+    if start.line != end.line {
+      for line in self.lines.drain(start.line + 1..=end.line) {
+        removed.push('\n');
+        removed.push_str(&line);
+      }
+      let tail = end_line.len() - end_at;
+      removed.truncate(removed.len() - tail);
+      let count = end.line - start.line;
+      fold::shift(&mut self.folds, start.line, -(count as isize));
+      // Deleting whole lines leaves the line after them where they were, with its notes.
+      let gone = match start.col == 0 && end.col == 0 {
+        true => start.line,
+        false => start.line + 1,
+      };
+      self.annotations.remove_lines(gone, count);
+    }
+    self.modified = true;
+    self.edits += 1;
+    removed
+  }
+}
+
+/// The position just past `text` if it were inserted at `at`.
+pub fn end_of(at: Position, text: &str) -> Position {
+  match text.rsplit_once('\n') {
+    Some((head, tail)) => Position::new(
+      at.line + head.matches('\n').count() + 1,
+      tail.chars().count(),
+    ),
+    None => Position::new(at.line, at.col + text.chars().count()),
+  }
+}
+
+pub fn byte_index(s: &str, col: usize) -> usize {
+  s.char_indices().nth(col).map_or(s.len(), |(i, _)| i)
+}
+
+fn split_lines(content: &str) -> Vec<String> {
+  let content = content.strip_suffix('\n').unwrap_or(content);
+  content
+    .split('\n')
+    .map(|l| l.strip_suffix('\r').unwrap_or(l).to_owned())
+    .collect()
+}
+
+/// `content` without its byte order mark, and whether it had one.
+fn strip_bom(content: &str) -> (&str, bool) {
+  match content.strip_prefix('\u{feff}') {
+    Some(rest) => (rest, true),
+    None => (content, false),
+  }
+}
+
+fn disk_mtime(path: &Path) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn no_file_name() -> io::Error {
+  io::Error::other("no file name")
+}
@@ -0,0 +1,99 @@
+//! `g=`: working out arithmetic written in the text, with `+ - * /`, parentheses and integer
+//! or decimal numbers.
+
+/// The value of `expr`, or what is wrong with it. Spaces and line breaks are skipped.
+pub fn eval(expr: &str) -> Result<f64, String> {
+  let mut parser = Parser {
+    chars: expr.chars().filter(|c| !c.is_whitespace()).collect(),
+    at: 0,
+  };
+  let value = parser.sum()?;
+  match parser.peek() {
+    None if value.is_finite() => Ok(value),
+    None => Err("the result is too large".to_owned()),
+    Some(c) => Err(format!("unexpected {}", c)),
+  }
+}
+
+/// `value` as it goes in the text: whole numbers without a decimal point.
+pub fn format(value: f64) -> String {
+  match value.fract() == 0.0 && value.abs() < 1e15 {
+    true => (value as i64).to_string(),
+    false => value.to_string(),
+  }
+}
+
+struct Parser {
+  chars: Vec<char>,
+  at: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<char> {
+    self.chars.get(self.at).copied()
+  }
+
+  /// Terms added and subtracted, left to right.
+  fn sum(&mut self) -> Result<f64, String> {
+    let mut value = self.product()?;
+    while let Some(op @ ('+' | '-')) = self.peek() {
+      self.at += 1;
+      let term = self.product()?;
+      value = if op == '+' {
+        value + term
+      } else {
+        value - term
+      };
+    }
+    Ok(value)
+  }
+
+  /// Factors multiplied and divided, left to right, so they bind tighter than `+` and `-`.
+  fn product(&mut self) -> Result<f64, String> {
+    let mut value = self.factor()?;
+    while let Some(op @ ('*' | '/')) = self.peek() {
+      self.at += 1;
+      let factor = self.factor()?;
+      value = match op {
+        '*' => value * factor,
+        _ if factor == 0.0 => return Err("division by zero".to_owned()),
+        _ => value / factor,
+      };
+    }
+    Ok(value)
+  }
+
+  /// A number, a signed factor or a sum in parentheses.
+  fn factor(&mut self) -> Result<f64, String> {
+    match self.peek() {
+      Some(sign @ ('-' | '+')) => {
+        self.at += 1;
+        let value = self.factor()?;
+        Ok(if sign == '-' { -value } else { value })
+      }
+      Some('(') => {
+        self.at += 1;
+        let value = self.sum()?;
+        match self.peek() {
+          Some(')') => {
+            self.at += 1;
+            Ok(value)
+          }
+          _ => Err("a ) is missing".to_owned()),
+        }
+      }
+      Some(c) if c.is_ascii_digit() || c == '.' => {
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+          self.at += 1;
+        }
+        let number: String = self.chars[start..self.at].iter().collect();
+        number
+          .parse()
+          .map_err(|_| format!("{} is not a number", number))
+      }
+      Some(c) => Err(format!("unexpected {}", c)),
+      None => Err("a number is missing at the end".to_owned()),
+    }
+  }
+}
@@ -0,0 +1,117 @@
+//! How many colors the terminal can show, and the nearest it has to the ones asked for. RGB
+//! colors such as `yankcolor=#d7875f` come out as they are on a true-color terminal, and as the
+//! closest of the 256 or 16 the terminal has otherwise.
+
+use std::env;
+use tui::style::Color;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Depth {
+  /// Any RGB color.
+  TrueColor,
+  /// The 16 named colors, a 6×6×6 cube and a gray ramp.
+  Indexed,
+  /// The 16 named colors only.
+  Named,
+}
+
+/// The levels of each component in the 6×6×6 cube of the 256-color palette.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The RGB values xterm gives the 16 named colors, in palette order.
+const NAMED: [(Color, (u8, u8, u8)); 16] = [
+  (Color::Black, (0, 0, 0)),
+  (Color::Red, (205, 0, 0)),
+  (Color::Green, (0, 205, 0)),
+  (Color::Yellow, (205, 205, 0)),
+  (Color::Blue, (0, 0, 238)),
+  (Color::Magenta, (205, 0, 205)),
+  (Color::Cyan, (0, 205, 205)),
+  (Color::Gray, (229, 229, 229)),
+  (Color::DarkGray, (127, 127, 127)),
+  (Color::LightRed, (255, 0, 0)),
+  (Color::LightGreen, (0, 255, 0)),
+  (Color::LightYellow, (255, 255, 0)),
+  (Color::LightBlue, (92, 92, 255)),
+  (Color::LightMagenta, (255, 0, 255)),
+  (Color::LightCyan, (0, 255, 255)),
+  (Color::White, (255, 255, 255)),
+];
+
+impl Depth {
+  /// What the terminal says it can do: `COLORTERM=truecolor` or a `TERM` ending in `-direct`
+  /// for any color, a `TERM` with `256color` in it for 256.
+  pub fn detect() -> Depth {
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    let term = env::var("TERM").unwrap_or_default();
+    if matches!(colorterm.as_str(), "truecolor" | "24bit") || term.ends_with("-direct") {
+      Depth::TrueColor
+    } else if term.contains("256color") {
+      Depth::Indexed
+    } else {
+      Depth::Named
+    }
+  }
+
+  /// The nearest color to `color` the terminal can show.
+  pub fn fit(self, color: Color) -> Color {
+    match (self, color) {
+      (Depth::TrueColor, _) => color,
+      (Depth::Indexed, Color::Rgb(r, g, b)) => Color::Indexed(nearest_indexed((r, g, b))),
+      (Depth::Named, Color::Rgb(r, g, b)) => nearest_named((r, g, b)),
+      (Depth::Named, Color::Indexed(i)) => match NAMED.get(i as usize) {
+        Some(&(named, _)) => named,
+        None => nearest_named(indexed_rgb(i)),
+      },
+      _ => color,
+    }
+  }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+  let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+  d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+/// The palette index of the cube color or gray nearest to `rgb`.
+fn nearest_indexed(rgb: (u8, u8, u8)) -> u8 {
+  let level = |c: u8| {
+    (0..CUBE.len())
+      .min_by_key(|&i| (CUBE[i] as i32 - c as i32).abs())
+      .unwrap_or(0) as u8
+  };
+  let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+  let cube = 16 + 36 * r + 6 * g + b;
+  let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+  let gray = 232 + (average.saturating_sub(3) / 10).min(23) as u8;
+  match distance(indexed_rgb(gray), rgb) < distance(indexed_rgb(cube), rgb) {
+    true => gray,
+    false => cube,
+  }
+}
+
+fn nearest_named(rgb: (u8, u8, u8)) -> Color {
+  NAMED
+    .iter()
+    .min_by_key(|(_, named)| distance(*named, rgb))
+    .map_or(Color::Reset, |&(color, _)| color)
+}
+
+/// The RGB value of palette index `i`.
+fn indexed_rgb(i: u8) -> (u8, u8, u8) {
+  match i {
+    0..=15 => NAMED[i as usize].1,
+    16..=231 => {
+      let i = i - 16;
+      (
+        CUBE[(i / 36) as usize],
+        CUBE[(i / 6 % 6) as usize],
+        CUBE[(i % 6) as usize],
+      )
+    }
+    _ => {
+      let level = 8 + 10 * (i - 232);
+      (level, level, level)
+    }
+  }
+}
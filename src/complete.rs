@@ -0,0 +1,125 @@
+//! Tab completion on the `:` command line, and file name completion in insert mode.
+
+use std::fs;
+use std::path::Path;
+
+/// Full names of the commands `Editor::execute` knows, offered when completing a command name.
+pub const COMMANDS: &[&str] = &[
+  "A",
+  "align",
+  "backups",
+  "blame",
+  "buffer",
+  "buffers",
+  "cclose",
+  "cd",
+  "copen",
+  "e!",
+  "earlier",
+  "enew",
+  "fold",
+  "global",
+  "later",
+  "lcd",
+  "ls",
+  "make",
+  "new",
+  "noh",
+  "normal",
+  "only",
+  "pwd",
+  "q",
+  "q!",
+  "qa",
+  "qa!",
+  "reg",
+  "rename",
+  "retab",
+  "send",
+  "set",
+  "sort",
+  "split",
+  "stripctrl",
+  "symbols",
+  "terminal",
+  "tree",
+  "undotree",
+  "vglobal",
+  "vsplit",
+  "w",
+  "wq",
+  "wqa",
+  "wrap",
+  "x",
+  "xa",
+];
+
+/// Commands whose argument is a file name.
+const FILE_COMMANDS: &[&str] = &["sp", "split", "vs", "vsplit", "w", "w!", "wq", "x"];
+
+/// Candidates cycled through by repeated Tabs.
+#[derive(Clone, Debug)]
+pub struct Completion {
+  /// Where the completed word starts: a byte offset in the command line, or in insert mode a
+  /// column of the cursor line.
+  pub start: usize,
+  pub candidates: Vec<String>,
+  /// The candidate now in the command line.
+  pub index: usize,
+}
+
+/// What could replace the word being typed at the end of `line`, if anything.
+pub fn complete(line: &str) -> Option<Completion> {
+  // Skip a range such as `1,5` in front of the command name.
+  let name_start = line
+    .find(|c: char| !(c.is_ascii_digit() || matches!(c, ',' | '.' | '$')))
+    .unwrap_or(line.len());
+  let (start, candidates) = match line[name_start..].split_once(' ') {
+    None => {
+      let typed = &line[name_start..];
+      let names = COMMANDS.iter().filter(|name| name.starts_with(typed));
+      (name_start, names.map(|name| name.to_string()).collect())
+    }
+    Some((name, _)) if FILE_COMMANDS.contains(&name) => {
+      let start = line.rfind(' ').map_or(0, |i| i + 1);
+      (start, files(&line[start..], Path::new(".")))
+    }
+    Some(_) => return None,
+  };
+  match candidates.is_empty() {
+    true => None,
+    false => Some(Completion {
+      start,
+      candidates,
+      index: 0,
+    }),
+  }
+}
+
+/// Paths that start with `typed`, with a `/` after directories. Relative paths are taken from
+/// `base`. Hidden files are only listed once a `.` has been typed.
+pub fn files(typed: &str, base: &Path) -> Vec<String> {
+  let (dir, prefix) = match typed.rfind('/') {
+    Some(i) => typed.split_at(i + 1),
+    None => ("", typed),
+  };
+  let Ok(entries) = fs::read_dir(base.join(dir)) else {
+    return Vec::new();
+  };
+  let mut files: Vec<String> = entries
+    .flatten()
+    .filter_map(|entry| {
+      let name = entry.file_name().into_string().ok()?;
+      if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+        return None;
+      }
+      let slash = match entry.path().is_dir() {
+        true => "/",
+        false => "",
+      };
+      Some(format!("{}{}{}", dir, name, slash))
+    })
+    .collect();
+  files.sort();
+  files
+}
@@ -0,0 +1,132 @@
+//! The user's settings file, `$XDG_CONFIG_HOME/littleeditor/config.json`:
+//!
+//! ```json
+//! {
+//!   "set": ["expandtab", "autoindent"],
+//!   "filetypes": { "yaml": ["tabstop=2"] },
+//!   "statusline": "%f %m%=%y %l:%c %p%%",
+//!   "tabline": "%n:%t%m",
+//!   "onsave": { "rust": ["trim", "rustfmt"] },
+//!   "lsp": { "rust": "rust-analyzer" },
+//!   "alternates": [["%.vert", "%.frag"]],
+//!   "map": { "<leader>w": ":w" }
+//! }
+//! ```
+//!
+//! A project can have its own `set` and `filetypes` in a `.littleeditor.json` at its root,
+//! which apply over these to the files under it; see `Project`.
+//!
+//! Every entry in `set` and `filetypes` is an argument to `:set`. The `statusline` and
+//! `tabline` formats are described in `statusline.rs`, the `onsave` steps in `hook.rs`,
+//! language servers in `lsp.rs`, the `alternates` of `:A` in `alternate.rs` and `map` in
+//! `mapping.rs`.
+
+use crate::error::{EditorError, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{self as stdpath, Path, PathBuf};
+use std::{env, fs, io};
+
+/// Settings that suit a file type better than the defaults. The user's own come after these,
+/// so they win.
+const FILETYPES: &[(&str, &[&str])] = &[
+  ("yaml", &["expandtab", "tabstop=2"]),
+  ("python", &["expandtab", "tabstop=4"]),
+  // Recipes must start with a real tab.
+  ("make", &["noexpandtab"]),
+  // Property names and classes are hyphenated words.
+  ("css", &["iskeyword+=-"]),
+  // Tags are in angle brackets.
+  ("html", &["matchpairs+=<:>"]),
+  // Blocks are keywords rather than braces.
+  ("sh", &["matchwords=if:elif|else:fi,do:done,case:esac"]),
+  (
+    "ruby",
+    &["matchwords=^if|^unless|^while|^until|def|class|module|do|begin|case:elsif|else|when|rescue|ensure:end"],
+  ),
+  ("lua", &["matchwords=function|if|do:elseif|else:end,repeat:until"]),
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+  /// Applied once at startup.
+  pub set: Vec<String>,
+  /// Applied to each buffer whose file type is the key.
+  pub filetypes: BTreeMap<String, Vec<String>>,
+  /// What the status line shows after the mode, instead of the file name and position.
+  pub statusline: Option<String>,
+  /// The label of each buffer's tab, instead of its file name.
+  pub tabline: Option<String>,
+  /// What to do to buffers of the file type that is the key before they are written.
+  pub onsave: BTreeMap<String, Vec<String>>,
+  /// The command that starts the language server for the file type that is the key.
+  pub lsp: BTreeMap<String, String>,
+  /// Groups of related file names for `:A`, looked at before the built-in ones.
+  pub alternates: Vec<Vec<String>>,
+  /// What the keys that are the key do in normal and visual mode.
+  pub map: BTreeMap<String, String>,
+}
+
+impl Config {
+  /// Reads the settings file. A missing file is the same as an empty one.
+  pub fn load() -> Result<Config> {
+    let Some(path) = path() else {
+      return Ok(Config::default());
+    };
+    match fs::read_to_string(&path) {
+      Ok(json) => serde_json::from_str(&json)
+        .map_err(|e| EditorError::Parse(format!("Error in {}: {}", path.display(), e))),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+      Err(e) => Err(EditorError::io("read", &path, e)),
+    }
+  }
+
+  /// The `:set` arguments for buffers of `filetype`, built-in ones first.
+  pub fn filetype_settings<'a>(&'a self, filetype: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let builtin = FILETYPES
+      .iter()
+      .filter(move |(name, _)| *name == filetype)
+      .flat_map(|(_, args)| args.iter().copied());
+    let user = self.filetypes.get(filetype).into_iter().flatten();
+    builtin.chain(user.map(String::as_str))
+  }
+}
+
+/// The project settings file, looked for in the edited file's directory and those above it.
+pub const PROJECT_FILE: &str = ".littleeditor.json";
+
+/// The settings in a `PROJECT_FILE`. Options that every buffer has its own copy of apply to
+/// the buffers of files under its directory; the others apply to the whole editor when it
+/// starts in the project.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Project {
+  pub set: Vec<String>,
+  pub filetypes: BTreeMap<String, Vec<String>>,
+}
+
+impl Project {
+  /// The settings of the project `dir` is in, from the nearest `PROJECT_FILE` up from it,
+  /// and that file's path. `None` outside of any project.
+  pub fn find(dir: &Path) -> Option<Result<(PathBuf, Project)>> {
+    let dir = stdpath::absolute(dir).ok()?;
+    let path = dir
+      .ancestors()
+      .map(|dir| dir.join(PROJECT_FILE))
+      .find(|path| path.is_file())?;
+    Some(match fs::read_to_string(&path) {
+      Ok(json) => serde_json::from_str(&json)
+        .map(|project| (path.clone(), project))
+        .map_err(|e| EditorError::Parse(format!("Error in {}: {}", path.display(), e))),
+      Err(e) => Err(EditorError::io("read", &path, e)),
+    })
+  }
+}
+
+fn path() -> Option<PathBuf> {
+  let base = env::var_os("XDG_CONFIG_HOME")
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+  Some(base.join("littleeditor").join("config.json"))
+}
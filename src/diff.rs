@@ -0,0 +1,69 @@
+//! Line diffs between a buffer and the text it was loaded from.
+
+/// How a line differs from the saved text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+  Added,
+  Changed,
+}
+
+/// Beyond this many line pairs in the differing middle, lines are paired up in order instead
+/// of aligned, to keep big rewrites cheap.
+const MAX_CELLS: usize = 4_000_000;
+
+/// For each line of `new`, how it differs from `old`. Within a run of differences, as many new
+/// lines as replaced old ones count as changed and the rest as added.
+pub fn line_changes(old: &[String], new: &[String]) -> Vec<Option<Change>> {
+  let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+  let suffix = old[prefix..]
+    .iter()
+    .rev()
+    .zip(new[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let old_mid = &old[prefix..old.len() - suffix];
+  let new_mid = &new[prefix..new.len() - suffix];
+  let mut changes = vec![None; new.len()];
+  let mut mark = |new_start: usize, removed: usize, added: usize| {
+    for i in 0..added {
+      changes[prefix + new_start + i] = Some(match i < removed {
+        true => Change::Changed,
+        false => Change::Added,
+      });
+    }
+  };
+  if old_mid.len().saturating_mul(new_mid.len()) > MAX_CELLS {
+    mark(0, old_mid.len(), new_mid.len());
+    return changes;
+  }
+  // `lcs[i][j]` is the longest common subsequence of `old_mid[i..]` and `new_mid[j..]`.
+  let (n, m) = (old_mid.len(), new_mid.len());
+  let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+  for i in (0..n).rev() {
+    for j in (0..m).rev() {
+      lcs[i][j] = match old_mid[i] == new_mid[j] {
+        true => lcs[i + 1][j + 1] + 1,
+        false => lcs[i + 1][j].max(lcs[i][j + 1]),
+      };
+    }
+  }
+  let (mut i, mut j) = (0, 0);
+  let (mut removed, mut added, mut run_start) = (0, 0, 0);
+  while i < n || j < m {
+    if i < n && j < m && old_mid[i] == new_mid[j] {
+      mark(run_start, removed, added);
+      (removed, added) = (0, 0);
+      i += 1;
+      j += 1;
+      run_start = j;
+    } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+      added += 1;
+      j += 1;
+    } else {
+      removed += 1;
+      i += 1;
+    }
+  }
+  mark(run_start, removed, added);
+  changes
+}
@@ -0,0 +1,5257 @@
+use crate::alternate;
+use crate::backup;
+use crate::blame;
+use crate::buffer::{byte_index, Buffer, Position};
+use crate::calc;
+use crate::complete::{self, Completion};
+use crate::config::{Config, Project};
+use crate::diff::Change;
+use crate::editorconfig;
+use crate::error::{EditorError, Result};
+use crate::filetype;
+use crate::fold::{self, Fold, FoldKind};
+use crate::history::History;
+use crate::hook;
+use crate::increment;
+use crate::job::{self, Output};
+use crate::lsp::{self, Incoming};
+use crate::mapping;
+use crate::matchwords;
+use crate::operator::{self, Command, Extent, Parsed, Target};
+use crate::options::{BufferOptions, FoldMethod, Options, Set, WindowOptions};
+use crate::pty::{self, Shell};
+use crate::range;
+use crate::register::{self, Register, Registers};
+use crate::search;
+use crate::sign::{self, Sign};
+use crate::state;
+use crate::substitute::Substitute;
+use crate::symbol::{self, Symbol};
+use crate::tree::{FileTree, TreeInput};
+use crate::ui;
+use crate::undo::Distance;
+use crate::unicode;
+use crate::visual::{BlockInsert, Flash, Selection, VisualKind};
+use crate::window::{Layout, Window};
+use crate::word::Words;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{self, Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{env, fs, io};
+use tui::style::Color;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How long the cursor rests before `highlightcursorword` shows its word elsewhere.
+const CURSOR_WORD_DELAY: Duration = Duration::from_millis(300);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+  Normal,
+  Insert,
+  Command,
+  /// Typing a pattern after `/`.
+  Search,
+  /// Selecting text from `Editor::visual_anchor` to the cursor.
+  Visual(VisualKind),
+}
+
+impl Mode {
+  /// Whether keys are going to the command line in the footer.
+  pub fn on_command_line(self) -> bool {
+    matches!(self, Mode::Command | Mode::Search)
+  }
+}
+
+/// A question waiting for a single-key answer in the footer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Prompt {
+  /// The file changed on disk; `then_save` is set when we found out while saving.
+  DiskChanged { then_save: bool },
+  /// Delete the node selected in the file tree.
+  DeleteNode,
+  /// `:only` found other buffers with unsaved changes.
+  CloseModified,
+  /// `:qa` found that `buffer` has unsaved changes.
+  QuitAll { buffer: usize },
+  /// Run `Editor::confirming`, which changes many lines.
+  Confirm,
+}
+
+impl Prompt {
+  pub fn text(&self) -> &'static str {
+    match self {
+      Prompt::DiskChanged { .. } => "File changed on disk: [r]eload, [k]eep, [d]iff?",
+      Prompt::DeleteNode | Prompt::Confirm => "[y]es, [n]o",
+      Prompt::CloseModified | Prompt::QuitAll { .. } => "[w]rite, [d]iscard, [c]ancel",
+    }
+  }
+}
+
+pub struct Editor {
+  pub buffers: Vec<Buffer>,
+  /// The windows, by the indices the leaves of `layout` hold. Their order says nothing about
+  /// where they are on screen.
+  pub windows: Vec<Window>,
+  pub layout: Layout,
+  /// Index of the window that has the cursor.
+  pub focus: usize,
+  pub options: Options,
+  pub config: Config,
+  pub mode: Mode,
+  pub command_line: String,
+  pub command_history: History,
+  pub search_history: History,
+  /// Candidates being cycled through with Tab on the command line.
+  pub completion: Option<Completion>,
+  /// File names offered by `Ctrl+X Ctrl+F` in insert mode.
+  pub insert_completion: Option<Completion>,
+  /// Set by `Ctrl+X` in insert mode until the next key says what to complete.
+  ctrl_x: bool,
+  /// The signature of the call being typed in insert mode, from the language server.
+  pub signature: Option<lsp::Signature>,
+  /// The pattern `n` and `N` look for.
+  pub last_search: Option<String>,
+  /// Which match of the last search `n` or `N` went to and how many there are, until
+  /// `:nohlsearch`.
+  pub search_count: Option<(usize, usize)>,
+  /// The offset of the last search, which `n` and `N` apply again.
+  search_offset: search::Offset,
+  /// Where the last search left the cursor and the match it went there from, so `n` carries
+  /// on from the match rather than from wherever the offset moved the cursor.
+  search_origin: Option<(Position, Position)>,
+  pub message: Option<String>,
+  pub prompt: Option<Prompt>,
+  /// The buffer the focused window showed before the current one, for `#` in `:ls`.
+  pub alternate: Option<usize>,
+  /// Selected row of the `:ls` popup while it is open.
+  pub buffer_list: Option<usize>,
+  /// The backups listed by `:backups`, newest first, and the selected one.
+  pub backup_list: Option<(Vec<PathBuf>, usize)>,
+  /// The buffers to write once `:qa` has asked about all of them.
+  quit_writes: Vec<usize>,
+  /// The working directory before the last `:cd`, for `:cd -`.
+  previous_dir: Option<PathBuf>,
+  /// Selected row of the `:undotree` popup while it is open.
+  pub undo_list: Option<usize>,
+  /// The code actions offered by the language server, and the selected one.
+  pub code_actions: Option<(Vec<Value>, usize)>,
+  /// What `:symbols` lists, and the selected one.
+  pub symbol_list: Option<(Vec<Symbol>, usize)>,
+  /// The counterparts `:A` found when there is more than one, and the selected one.
+  pub alternate_list: Option<(Vec<PathBuf>, usize)>,
+  /// When the last key or paste came in, for autosave.
+  last_input: Instant,
+  /// The end of the visual selection that stays put while the cursor moves.
+  pub visual_anchor: Position,
+  /// The kind, anchor and cursor of the last visual selection, for `gv`.
+  last_visual: Option<(VisualKind, Position, Position)>,
+  /// Set while typing after `I` or `A` on a visual block.
+  block_insert: Option<BlockInsert>,
+  /// The cursors `gm` puts at the other search matches of the focused buffer, which typing
+  /// goes to along with the window's own.
+  pub cursors: Vec<Position>,
+  /// What yanks and deletes kept, for `p`.
+  pub registers: Registers,
+  /// The register picked with `"` for the next yank, delete or put.
+  register_name: Option<char>,
+  /// The registers `:registers` lists, and the selected one.
+  pub register_list: Option<(Vec<char>, usize)>,
+  /// The ex command that waits for a yes with `confirm` set.
+  confirming: Option<String>,
+  /// The text just yanked, highlighted for `yankflash` milliseconds.
+  pub flash: Option<Flash>,
+  /// The closed fold `zp` opened for a look inside, by buffer and lines. It closes again once
+  /// the cursor leaves it.
+  peek: Option<(usize, usize, usize)>,
+  /// The word under the cursor, highlighted elsewhere with `highlightcursorword`.
+  pub cursor_word: Option<String>,
+  /// What the last `:!` or `:make` printed.
+  pub output: Option<Output>,
+  /// Whether the output panel is shown, and whether keys go to it.
+  pub output_open: bool,
+  pub output_focused: bool,
+  /// The shell `:terminal` runs in the pane below the windows, and whether keys go to it.
+  pub shell: Option<Shell>,
+  pub shell_focused: bool,
+  /// Where the shell's output goes; `:terminal` only works once this is set.
+  pub shell_notify: Option<pty::Notify>,
+  /// Where blames fetched for `:blame` go; it only works once this is set.
+  pub blame_notify: Option<blame::Notify>,
+  /// Set by `--stdout`: `:w` and `:wq` without a file name send the buffer to stdout.
+  pub to_stdout: bool,
+  /// The text last written to `-`, printed on stdout once the terminal is restored.
+  pub stdout: Option<String>,
+  pub tree: Option<FileTree>,
+  /// Whether keys go to the file tree rather than the focused window.
+  pub tree_focused: bool,
+  /// The keys so far of a multi-key normal mode command such as `za` or `d2j`, after its count.
+  pub pending: String,
+  /// The count typed before `pending`, and when its last key came in.
+  pending_count: Option<usize>,
+  pending_at: Instant,
+  /// Count typed before a normal mode command, as in `50%`.
+  pub count: Option<usize>,
+  /// Whether the keys coming in are a mapping being played back, which don't start mappings.
+  replaying: bool,
+  /// Whether the keys coming in are played back by a mapping or `:normal`.
+  feeding: bool,
+  /// Set while `:g` runs its command.
+  in_global: bool,
+  /// The register `q` is recording keys into, and the keys so far.
+  pub recording: Option<(char, String)>,
+  /// The register `@` last played, for `@@`.
+  last_macro: Option<char>,
+  /// Where language servers' messages go; servers are only started once this is set.
+  pub lsp_notify: Option<lsp::Notify>,
+  /// The running language servers, by the file type they are for.
+  lsp: HashMap<&'static str, lsp::Client>,
+  /// File types whose server couldn't be started or exited, so it isn't tried again.
+  lsp_failed: HashSet<&'static str>,
+  /// The diagnostics the language servers last published, by document URI.
+  diagnostics: HashMap<String, Vec<Value>>,
+  pub should_quit: bool,
+}
+
+impl Editor {
+  /// Starts editing `buffer` with the settings from `config`.
+  pub fn new(mut buffer: Buffer, config: Config) -> Self {
+    let mut editor = Editor {
+      buffers: Vec::new(),
+      windows: vec![Window::new(0, WindowOptions::default())],
+      layout: Layout::Window(0),
+      focus: 0,
+      options: Options::default(),
+      config,
+      mode: Mode::Normal,
+      command_line: String::new(),
+      command_history: History::default(),
+      search_history: History::default(),
+      completion: None,
+      insert_completion: None,
+      signature: None,
+      ctrl_x: false,
+      last_search: None,
+      search_count: None,
+      search_offset: search::Offset::default(),
+      search_origin: None,
+      message: None,
+      prompt: None,
+      alternate: None,
+      buffer_list: None,
+      backup_list: None,
+      quit_writes: Vec::new(),
+      previous_dir: None,
+      undo_list: None,
+      code_actions: None,
+      symbol_list: None,
+      alternate_list: None,
+      last_input: Instant::now(),
+      visual_anchor: Position::default(),
+      last_visual: None,
+      block_insert: None,
+      cursors: Vec::new(),
+      registers: Registers::default(),
+      register_name: None,
+      register_list: None,
+      confirming: None,
+      flash: None,
+      peek: None,
+      cursor_word: None,
+      output: None,
+      output_open: false,
+      output_focused: false,
+      shell: None,
+      shell_focused: false,
+      shell_notify: None,
+      blame_notify: None,
+      to_stdout: false,
+      stdout: None,
+      tree: None,
+      tree_focused: false,
+      pending: String::new(),
+      pending_count: None,
+      pending_at: Instant::now(),
+      count: None,
+      replaying: false,
+      feeding: false,
+      in_global: false,
+      recording: None,
+      last_macro: None,
+      lsp_notify: None,
+      lsp: HashMap::new(),
+      lsp_failed: HashSet::new(),
+      diagnostics: HashMap::new(),
+      should_quit: false,
+    };
+    let histories = state::load_history();
+    editor.command_history = History::new(histories.command);
+    editor.search_history = History::new(histories.search);
+    for arg in editor.config.set.clone() {
+      if let Err(e) = editor.options.set_default(&arg) {
+        editor.message = Some(e);
+      }
+    }
+    // The project the editor starts in decides the options that aren't per buffer; buffers
+    // get the rest from the project of their own file.
+    let dir = match buffer.path.as_deref().and_then(Path::parent) {
+      Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+      _ => PathBuf::from("."),
+    };
+    if let Some((path, project)) = editor.project(&dir) {
+      for arg in &project.set {
+        if editor.options.buffer.has(arg) {
+          continue;
+        }
+        if let Err(e) = editor.options.set(arg) {
+          editor.message = Some(format!("{} (in {})", e, path.display()));
+        }
+      }
+    }
+    editor.windows[0].options = editor.options.window.clone();
+    editor.setup_buffer(&mut buffer);
+    editor.buffers.push(buffer);
+    editor
+  }
+
+  /// The project settings for files in `dir`, if it is in a project. A file that can't be
+  /// read is reported and taken as no settings.
+  fn project(&mut self, dir: &Path) -> Option<(PathBuf, Project)> {
+    let dir = match dir.as_os_str().is_empty() {
+      true => Path::new("."),
+      false => dir,
+    };
+    match Project::find(dir)? {
+      Ok(found) => Some(found),
+      Err(e) => {
+        self.message = Some(e.to_string());
+        None
+      }
+    }
+  }
+
+  /// Gives a freshly opened buffer its settings and remembered state. The defaults from `:set`
+  /// come first, then the settings for the file type, then whatever `.editorconfig` says and
+  /// last the project settings file.
+  fn setup_buffer(&mut self, buffer: &mut Buffer) {
+    buffer.options = BufferOptions {
+      // Going by the file's actual line endings beats any default.
+      fileformat: buffer.options.fileformat,
+      // As does whether a file that exists starts with a byte order mark.
+      bomb: match buffer.mtime {
+        Some(_) => buffer.had_bom,
+        None => buffer.had_bom || self.options.buffer.bomb,
+      },
+      ..self.options.buffer.clone()
+    };
+    let Some(path) = buffer.path.clone() else {
+      return;
+    };
+    buffer.filetype = filetype::detect(&path);
+    if let Some(filetype) = buffer.filetype {
+      for arg in self.config.filetype_settings(filetype) {
+        if let Err(e) = buffer.options.set(arg) {
+          self.message = Some(format!("{} (for {} files)", e, filetype));
+        }
+      }
+    }
+    editorconfig::apply(&editorconfig::properties(&path), &mut buffer.options);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    if let Some((file, project)) = self.project(dir) {
+      let filetype = buffer
+        .filetype
+        .and_then(|filetype| project.filetypes.get(filetype));
+      let args = project
+        .set
+        .iter()
+        .filter(|arg| buffer.options.has(arg))
+        .collect::<Vec<_>>();
+      for arg in args.into_iter().chain(filetype.into_iter().flatten()) {
+        if let Err(e) = buffer.options.set(arg) {
+          self.message = Some(format!("{} (in {})", e, file.display()));
+        }
+      }
+    }
+    // Make can't read recipes indented with spaces, whatever the project says.
+    if buffer.filetype == Some("make") {
+      buffer.options.expandtab = false;
+    }
+    state::load_folds(buffer);
+  }
+
+  /// Opens another buffer after the others, with its cursor at line `number` and column
+  /// `col` if given, for when it is first shown.
+  pub fn add_buffer(&mut self, mut buffer: Buffer, position: Option<(usize, Option<usize>)>) {
+    self.setup_buffer(&mut buffer);
+    if let Some((number, col)) = position {
+      buffer.last_cursor = line_position(&buffer, number, col);
+    }
+    self.buffers.push(buffer);
+  }
+
+  pub fn window(&self) -> &Window {
+    &self.windows[self.focus]
+  }
+
+  pub fn buffer(&self) -> &Buffer {
+    &self.buffers[self.window().buffer]
+  }
+
+  fn buffer_mut(&mut self) -> &mut Buffer {
+    let index = self.window().buffer;
+    &mut self.buffers[index]
+  }
+
+  /// The focused window and its buffer, borrowed together for edits.
+  fn focused(&mut self) -> (&mut Buffer, &mut Window) {
+    let window = &mut self.windows[self.focus];
+    (&mut self.buffers[window.buffer], window)
+  }
+
+  pub fn handle_key(&mut self, key: KeyEvent) {
+    let recording = self.recording.is_some() && !self.feeding;
+    self.dispatch_key(key);
+    self.end_peek(false);
+    // Neither the key that starts recording nor the `q` that stops it is recorded.
+    if let (true, Some((_, keys))) = (recording, &mut self.recording) {
+      keys.extend(mapping::notation(key));
+    }
+    if self.cursor_word.is_some()
+      && self.cursor_word != self.words().keyword_at(self.window().cursor)
+    {
+      self.cursor_word = None;
+    }
+  }
+
+  fn dispatch_key(&mut self, key: KeyEvent) {
+    self.last_input = Instant::now();
+    if let Some(prompt) = self.prompt {
+      self.answer_prompt(prompt, key.code);
+      return;
+    }
+    if let Some(selected) = self.buffer_list {
+      self.handle_buffer_list(key, selected);
+      return;
+    }
+    if let Some((backups, selected)) = self.backup_list.take() {
+      self.handle_backup_list(key, backups, selected);
+      return;
+    }
+    if let Some(selected) = self.undo_list.take() {
+      self.handle_undo_list(key, selected);
+      return;
+    }
+    if let Some((actions, selected)) = self.code_actions.take() {
+      self.handle_code_actions(key, actions, selected);
+      return;
+    }
+    if let Some((symbols, selected)) = self.symbol_list.take() {
+      self.handle_symbol_list(key, symbols, selected);
+      return;
+    }
+    if let Some((paths, selected)) = self.alternate_list.take() {
+      self.handle_alternate_list(key, paths, selected);
+      return;
+    }
+    if let Some((names, selected)) = self.register_list.take() {
+      self.handle_register_list(key, names, selected);
+      return;
+    }
+    if self.tree_focused {
+      self.handle_tree(key);
+      return;
+    }
+    if self.output_focused {
+      self.handle_output(key);
+      return;
+    }
+    if self.shell_focused {
+      self.handle_shell(key);
+      return;
+    }
+    match self.mode {
+      Mode::Normal => self.handle_normal(key),
+      Mode::Insert => self.handle_insert(key),
+      Mode::Command | Mode::Search => self.handle_command(key),
+      Mode::Visual(kind) => {
+        // Kept before the key, since keys that end visual mode may move the cursor.
+        self.last_visual = Some((kind, self.visual_anchor, self.window().cursor));
+        self.handle_visual(kind, key)
+      }
+    }
+    // An insert session is one undo group, unless `undobreak` splits it; any other command is
+    // its own. Keys played back are part of the command that plays them.
+    if self.mode != Mode::Insert && !self.feeding {
+      self.commit_undo();
+    } else {
+      // Text is never typed into a closed fold.
+      let (buffer, window) = self.focused();
+      for fold in buffer
+        .folds
+        .iter_mut()
+        .filter(|f| f.contains(window.cursor.line))
+      {
+        fold.closed = false;
+      }
+    }
+    self.refresh_folds();
+  }
+
+  /// Ends the undo group being recorded in every buffer. With `undojoin` set, the first
+  /// buffer to have recorded anything has it joined to its last group instead, which uses the
+  /// setting up.
+  fn commit_undo(&mut self) {
+    for buffer in &mut self.buffers {
+      match self.options.undojoin && buffer.undo.join() {
+        true => self.options.undojoin = false,
+        false => buffer.undo.commit(),
+      }
+      buffer
+        .undo
+        .trim(self.options.undolevels, self.options.undobytes);
+    }
+  }
+
+  /// Recomputes indent folds after edits or a change of `foldmethod`. The language server's
+  /// folds move with the lines until it gives new ones.
+  fn refresh_folds(&mut self) {
+    let method = self.options.foldmethod;
+    for buffer in &mut self.buffers {
+      let indent = match method {
+        FoldMethod::Manual => false,
+        FoldMethod::Indent => true,
+        FoldMethod::Lsp => !buffer.folding.found,
+      };
+      match indent {
+        true => fold::refresh_indent(&mut buffer.folds, &buffer.lines),
+        false => buffer.folds.retain(|f| f.kind != FoldKind::Indent),
+      }
+      if method != FoldMethod::Lsp {
+        buffer.folds.retain(|f| f.kind != FoldKind::Lsp);
+        buffer.folding = fold::Ranges::default();
+      }
+    }
+    self.clamp_all();
+  }
+
+  /// Called a few times a second while the editor is otherwise idle. Change signs are updated
+  /// here rather than after every key.
+  pub fn tick(&mut self) {
+    if self.windows.iter().any(|w| w.options.changesigns) {
+      for buffer in &mut self.buffers {
+        buffer.update_signs();
+      }
+    }
+    // A command left unfinished for `timeoutlen` is given up on.
+    let timeout = Duration::from_millis(self.options.timeoutlen as u64);
+    if !self.pending.is_empty() && self.pending_at.elapsed() >= timeout {
+      let mapped = self.mapped_keys();
+      let command = operator::timed_out(&self.pending, self.options.leader, &mapped);
+      self.pending.clear();
+      let count = self.pending_count.take();
+      if command.is_some() {
+        self.run_command(command, count);
+      }
+    }
+    if self
+      .flash
+      .is_some_and(|flash| flash.until <= Instant::now())
+    {
+      self.flash = None;
+    }
+    if self.options.highlightcursorword
+      && self.cursor_word.is_none()
+      && self.last_input.elapsed() >= CURSOR_WORD_DELAY
+    {
+      let word = self.words().keyword_at(self.window().cursor);
+      self.cursor_word = word;
+    }
+    let autosave = self.options.autosave as u64;
+    if autosave > 0 && self.last_input.elapsed().as_secs() >= autosave {
+      self.autosave();
+    }
+    self.lsp_sync();
+    if self.options.inlayhints {
+      self.request_hints();
+    }
+    self.request_functions();
+    if self.options.foldmethod == FoldMethod::Lsp {
+      self.request_folding_ranges();
+    }
+  }
+
+  /// Starts the language servers the open buffers need and sends them what changed.
+  fn lsp_sync(&mut self) {
+    let Some(notify) = self.lsp_notify.clone() else {
+      return;
+    };
+    for buffer in &self.buffers {
+      let (Some(filetype), Some(path)) = (buffer.filetype, &buffer.path) else {
+        continue;
+      };
+      let Some(command) = self.config.lsp.get(filetype) else {
+        continue;
+      };
+      if self.lsp_failed.contains(filetype) {
+        continue;
+      }
+      if !self.lsp.contains_key(filetype) {
+        match lsp::Client::start(filetype, command, notify.clone()) {
+          Ok(client) => {
+            self.lsp.insert(filetype, client);
+          }
+          Err(e) => {
+            self.message = Some(format!("Can't start \"{}\": {}", command, e));
+            self.lsp_failed.insert(filetype);
+            continue;
+          }
+        }
+      }
+      let client = self.lsp.get_mut(filetype).expect("just started");
+      let path = path::absolute(path).unwrap_or_else(|_| path.clone());
+      let text = || buffer.lines.join("\n") + "\n";
+      if client.sync(&path, buffer.version(), text).is_err() {
+        self.lsp.remove(filetype);
+        self.lsp_failed.insert(filetype);
+        self.message = Some(format!("The {} language server stopped", filetype));
+      }
+    }
+  }
+
+  /// Asks for the inlay hints of the lines in view in each window whose buffer has changed or
+  /// scrolled since they were last asked for.
+  fn request_hints(&mut self) {
+    for window in &self.windows {
+      let buffer = &mut self.buffers[window.buffer];
+      let (Some(filetype), Some(path)) = (buffer.filetype, &buffer.path) else {
+        continue;
+      };
+      let Some(client) = self.lsp.get_mut(filetype) else {
+        continue;
+      };
+      let last_line = buffer.lines.len() - 1;
+      let last = fold::visible_lines(&buffer.folds, window.scroll_offset, buffer.lines.len())
+        .take(window.height.max(1))
+        .last()
+        .unwrap_or(last_line);
+      let asked = (buffer.version(), window.scroll_offset.min(last), last);
+      if client.provider("inlayHintProvider").is_none() || buffer.hints.asked == Some(asked) {
+        continue;
+      }
+      buffer.hints.asked = Some(asked);
+      let path = path::absolute(path).unwrap_or_else(|_| path.clone());
+      let end = Position::new(last, buffer.line_len(last));
+      let params = json!({
+        "textDocument": { "uri": lsp::uri(&path) },
+        "range": {
+          "start": { "line": asked.1, "character": 0 },
+          "end": lsp::position(&buffer.lines, end),
+        },
+      });
+      let request = lsp::Request::InlayHints {
+        path,
+        version: buffer.version(),
+      };
+      let _ = client.request("textDocument/inlayHint", params, request);
+    }
+  }
+
+  /// Asks for the functions of each buffer in a window that has changed since they were last
+  /// asked for, so that `if` and `af` have them ready.
+  fn request_functions(&mut self) {
+    for window in &self.windows {
+      let buffer = &mut self.buffers[window.buffer];
+      let (Some(filetype), Some(path)) = (buffer.filetype, &buffer.path) else {
+        continue;
+      };
+      let Some(client) = self.lsp.get_mut(filetype) else {
+        continue;
+      };
+      let version = buffer.version();
+      if client.provider("documentSymbolProvider").is_none()
+        || buffer.functions.asked == Some(version)
+      {
+        continue;
+      }
+      buffer.functions.asked = Some(version);
+      let path = path::absolute(path).unwrap_or_else(|_| path.clone());
+      let params = json!({ "textDocument": { "uri": lsp::uri(&path) } });
+      let request = lsp::Request::Functions { path, version };
+      let _ = client.request("textDocument/documentSymbol", params, request);
+    }
+  }
+
+  /// Asks for the folding ranges of each buffer in a window that has changed since they were
+  /// last asked for, at most once every `fold::LSP_INTERVAL` while it keeps changing.
+  fn request_folding_ranges(&mut self) {
+    for window in &self.windows {
+      let buffer = &mut self.buffers[window.buffer];
+      let (Some(filetype), Some(path)) = (buffer.filetype, &buffer.path) else {
+        continue;
+      };
+      let Some(client) = self.lsp.get_mut(filetype) else {
+        continue;
+      };
+      let version = buffer.version();
+      let due = match buffer.folding.asked {
+        Some((asked, _)) if asked == version => false,
+        Some((_, at)) => at.elapsed() >= fold::LSP_INTERVAL,
+        None => true,
+      };
+      if client.provider("foldingRangeProvider").is_none() || !due {
+        continue;
+      }
+      buffer.folding.asked = Some((version, Instant::now()));
+      let path = path::absolute(path).unwrap_or_else(|_| path.clone());
+      let params = json!({ "textDocument": { "uri": lsp::uri(&path) } });
+      let request = lsp::Request::FoldingRanges { path, version };
+      let _ = client.request("textDocument/foldingRange", params, request);
+    }
+  }
+
+  /// The language server of the focused buffer, with the buffer's text up to date, and where
+  /// its cursor is in the server's terms.
+  fn lsp_client(&mut self) -> Result<(&mut lsp::Client, Value)> {
+    self.lsp_sync();
+    let buffer = self.buffer();
+    let (Some(filetype), Some(path)) = (buffer.filetype, &buffer.path) else {
+      return Err(EditorError::command("No language server for this buffer"));
+    };
+    let path = path::absolute(path).unwrap_or_else(|_| path.clone());
+    let document = json!({
+      "textDocument": { "uri": lsp::uri(&path) },
+      "position": lsp::position(&buffer.lines, self.window().cursor),
+    });
+    match self.lsp.get_mut(filetype) {
+      Some(client) => Ok((client, document)),
+      None => Err(EditorError::command(format!(
+        "No language server for {} files",
+        filetype
+      ))),
+    }
+  }
+
+  /// Deals with a message from a language server.
+  pub fn lsp_message(&mut self, incoming: Incoming) {
+    let Some(message) = incoming.message else {
+      if self.lsp.remove(incoming.filetype).is_some() {
+        self.message = Some(format!("The {} language server exited", incoming.filetype));
+      }
+      self.lsp_failed.insert(incoming.filetype);
+      return;
+    };
+    let Some(client) = self.lsp.get_mut(incoming.filetype) else {
+      return;
+    };
+    let method = message["method"].as_str();
+    match (message.get("id"), method) {
+      (Some(id), Some("workspace/applyEdit")) => {
+        let id = id.clone();
+        let params = &message["params"];
+        let result = match self.apply_workspace_edit(&params["edit"]) {
+          Ok(_) => {
+            if let Some(label) = params["label"].as_str() {
+              self.message = Some(format!("Applied \"{}\"", label));
+            }
+            json!({ "applied": true })
+          }
+          Err(e) => {
+            self.message = Some(e.to_string());
+            json!({ "applied": false, "failureReason": e.to_string() })
+          }
+        };
+        if let Some(client) = self.lsp.get_mut(incoming.filetype) {
+          let _ = client.respond(&id, result);
+        }
+      }
+      (Some(id), Some(method)) => {
+        if method == "workspace/inlayHint/refresh" {
+          self.buffers.iter_mut().for_each(|b| b.hints.asked = None);
+        }
+        let _ = client.reply(id, method, &message["params"]);
+      }
+      (Some(id), None) => {
+        let Some(request) = id.as_u64().and_then(|id| client.answered(id, &message)) else {
+          return;
+        };
+        self.lsp_response(request, &message);
+      }
+      (None, Some("textDocument/publishDiagnostics")) => {
+        let params = &message["params"];
+        if let (Some(uri), Some(diagnostics)) =
+          (params["uri"].as_str(), params["diagnostics"].as_array())
+        {
+          // Their messages go after the lines they are about.
+          let index = lsp::path(uri).and_then(|path| self.find_buffer(&path));
+          if let Some(index) = index {
+            let notes = diagnostics.iter().filter_map(|d| {
+              let line = d["range"]["start"]["line"].as_u64()?;
+              let message = d["message"].as_str()?.lines().next()?;
+              Some((line as usize, message.to_owned()))
+            });
+            self.buffers[index].annotations.set("diagnostics", notes);
+          }
+          self.diagnostics.insert(uri.to_owned(), diagnostics.clone());
+        }
+      }
+      // Nothing else the servers announce is used yet.
+      (None, _) => (),
+    }
+  }
+
+  fn lsp_response(&mut self, request: lsp::Request, message: &Value) {
+    match request {
+      lsp::Request::Initialize => (),
+      lsp::Request::Rename { name } => {
+        if let Some(error) = lsp::error(message) {
+          self.message = Some(format!("Can't rename: {}", error));
+          return;
+        }
+        let result = &message["result"];
+        self.message = Some(match self.apply_workspace_edit(result) {
+          Ok((0, _)) => "Nothing to rename".to_owned(),
+          Ok((edits, files)) => format!(
+            "Renamed to \"{}\": {} change(s) in {} file(s)",
+            name, edits, files
+          ),
+          Err(e) => e.to_string(),
+        });
+      }
+      lsp::Request::CodeAction => {
+        if let Some(error) = lsp::error(message) {
+          self.message = Some(format!("Can't get code actions: {}", error));
+          return;
+        }
+        let actions = message["result"].as_array().cloned().unwrap_or_default();
+        match actions.is_empty() {
+          true => self.message = Some("No code actions here".to_owned()),
+          false => self.code_actions = Some((actions, 0)),
+        }
+      }
+      lsp::Request::ResolveCodeAction => match lsp::error(message) {
+        Some(error) => self.message = Some(format!("Can't get the code action: {}", error)),
+        None => self.apply_code_action(message["result"].clone(), false),
+      },
+      lsp::Request::ExecuteCommand { title } => {
+        if let Some(error) = lsp::error(message) {
+          self.message = Some(format!("\"{}\" failed: {}", title, error));
+        }
+      }
+      lsp::Request::InlayHints { path, version } => {
+        let Some(index) = self.find_buffer(&path) else {
+          return;
+        };
+        let buffer = &mut self.buffers[index];
+        if lsp::error(message).is_none() && buffer.version() == version {
+          buffer.hints.hints = lsp::Hint::parse(&buffer.lines, &message["result"]);
+          buffer.hints.version = version;
+        }
+      }
+      lsp::Request::Functions { path, version } => {
+        let Some(index) = self.find_buffer(&path) else {
+          return;
+        };
+        let buffer = &mut self.buffers[index];
+        if lsp::error(message).is_none() && buffer.version() == version {
+          buffer.functions.lines = symbol::functions_from_lsp(&buffer.lines, &message["result"]);
+          buffer.functions.version = version;
+        }
+      }
+      lsp::Request::FoldingRanges { path, version } => {
+        let Some(index) = self.find_buffer(&path) else {
+          return;
+        };
+        let buffer = &mut self.buffers[index];
+        if lsp::error(message).is_some()
+          || buffer.version() != version
+          || self.options.foldmethod != FoldMethod::Lsp
+        {
+          return;
+        }
+        let folds = fold::from_lsp(&message["result"], buffer.lines.len());
+        buffer.folding.found = !folds.is_empty();
+        if buffer.folding.found {
+          buffer.folds.retain(|f| f.kind != FoldKind::Indent);
+        }
+        fold::replace(&mut buffer.folds, FoldKind::Lsp, folds);
+        self.refresh_folds();
+      }
+      lsp::Request::SignatureHelp => {
+        if self.mode == Mode::Insert && lsp::error(message).is_none() {
+          self.signature = lsp::Signature::parse(&message["result"]);
+        }
+      }
+      lsp::Request::Symbols => match lsp::error(message) {
+        Some(error) => self.message = Some(format!("Can't list the symbols: {}", error)),
+        None => {
+          let symbols = symbol::from_lsp(&self.buffer().lines, &message["result"]);
+          self.show_symbols(symbols);
+        }
+      },
+      lsp::Request::Format {
+        path,
+        version,
+        on_type,
+      } => {
+        let error = lsp::error(message);
+        let edits = message["result"].as_array();
+        let index = self.find_buffer(&path);
+        let current = index.filter(|&i| self.buffers[i].version() == version);
+        self.message = match (error, edits, current) {
+          (Some(_), _, _) | (_, _, None) if on_type => return,
+          (Some(error), _, _) => Some(format!("Can't format: {}", error)),
+          // Typed over since it was asked for.
+          (None, _, None) => Some("The text changed while it was being formatted".to_owned()),
+          (None, None, _) => Some("Nothing to format".to_owned()),
+          (None, Some(edits), Some(index)) => {
+            let window = &mut self.windows[self.focus];
+            let mut cursor = window.cursor;
+            let count = lsp::apply_edits(&mut self.buffers[index], edits, &mut cursor);
+            if window.buffer == index {
+              window.cursor = cursor;
+            }
+            if !on_type {
+              self.buffers[index].undo.commit();
+            }
+            self.clamp_all();
+            match (count, on_type) {
+              (_, true) => return,
+              (0, _) => Some("Already formatted".to_owned()),
+              (n, _) => Some(format!("{} formatting change(s)", n)),
+            }
+          }
+        };
+      }
+    }
+  }
+
+  /// Sends a request to the language server of the focused buffer.
+  fn lsp_request(&mut self, method: &str, params: Value, request: lsp::Request) -> Result<()> {
+    let (client, _) = self.lsp_client()?;
+    client
+      .request(method, params, request)
+      .map(|_| ())
+      .map_err(|e| EditorError::command(format!("Can't reach the language server: {}", e)))
+  }
+
+  /// `:rename {name}`: asks the language server to rename what is under the cursor.
+  fn rename(&mut self, name: &str) -> Result<()> {
+    if name.is_empty() {
+      return Err(EditorError::Parse("Argument required".to_owned()));
+    }
+    let (_, mut params) = self.lsp_client()?;
+    params["newName"] = json!(name);
+    let request = lsp::Request::Rename {
+      name: name.to_owned(),
+    };
+    self.lsp_request("textDocument/rename", params, request)
+  }
+
+  /// `\ca`: asks the language server what can be done at the cursor, or with the selection
+  /// in visual mode. The diagnostics there go along, so that the fixes for them are offered.
+  fn code_action(&mut self) -> Result<()> {
+    let last = self.buffer().lines.len() - 1;
+    let (start, end) = match self.selection() {
+      Some(selection) if selection.kind == VisualKind::Line => {
+        let end = match selection.end.line < last {
+          true => Position::new(selection.end.line + 1, 0),
+          false => Position::new(last, self.buffer().line_len(last)),
+        };
+        (Position::new(selection.start.line, 0), end)
+      }
+      Some(selection) => (
+        selection.start,
+        Position::new(selection.end.line, selection.end.col + 1),
+      ),
+      None => (self.window().cursor, self.window().cursor),
+    };
+    self.mode = Mode::Normal;
+    let (_, mut params) = self.lsp_client()?;
+    let lines = &self.buffer().lines;
+    let range = json!({ "start": lsp::position(lines, start), "end": lsp::position(lines, end) });
+    let at = |position: &Value| (position["line"].as_u64(), position["character"].as_u64());
+    let diagnostics: Vec<Value> = params["textDocument"]["uri"]
+      .as_str()
+      .and_then(|uri| self.diagnostics.get(uri))
+      .into_iter()
+      .flatten()
+      .filter(|d| {
+        at(&d["range"]["start"]) <= at(&range["end"])
+          && at(&range["start"]) <= at(&d["range"]["end"])
+      })
+      .cloned()
+      .collect();
+    params
+      .as_object_mut()
+      .expect("an object")
+      .remove("position");
+    params["range"] = range;
+    params["context"] = json!({ "diagnostics": diagnostics });
+    self.lsp_request("textDocument/codeAction", params, lsp::Request::CodeAction)
+  }
+
+  /// `\f`: has the language server format the selected lines, or the cursor line outside of
+  /// visual mode. Without a server that can, the lines are reindented by their brackets.
+  fn format_selection(&mut self) {
+    let cursor = self.window().cursor;
+    let (first, last) = match self.selection() {
+      Some(selection) => (selection.start.line, selection.end.line),
+      None => (cursor.line, cursor.line),
+    };
+    self.mode = Mode::Normal;
+    let can_format = self
+      .lsp_client()
+      .is_ok_and(|(client, _)| client.provider("documentRangeFormattingProvider").is_some());
+    if !can_format {
+      let changed = self.reindent(first, last);
+      self.commit_undo();
+      self.clamp_all();
+      self.message = Some(format!("{} line(s) reindented", changed));
+      return;
+    }
+    let end = Position::new(last, self.buffer().line_len(last));
+    let result = self.format_request(
+      "textDocument/rangeFormatting",
+      json!({ "line": first, "character": 0 }),
+      Some(end),
+    );
+    if let Err(e) = result {
+      self.message = Some(e.to_string());
+    }
+  }
+
+  /// With `formatontype`, has the language server format after `c` was typed if it is one of
+  /// the chars the server asks for.
+  fn format_on_type(&mut self, c: char) {
+    if !self.options.formatontype || self.options.paste {
+      return;
+    }
+    let triggered = self.lsp_client().ok().and_then(|(client, _)| {
+      let provider = client.provider("documentOnTypeFormattingProvider")?;
+      let first = provider["firstTriggerCharacter"].as_str();
+      // Only the first is required, so a server may not list any more.
+      let mut triggers = provider["moreTriggerCharacter"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .chain(first);
+      Some(triggers.any(|t| t.chars().eq([c])))
+    });
+    if triggered == Some(true) {
+      let _ = self.format_request("textDocument/onTypeFormatting", json!(c.to_string()), None);
+    }
+  }
+
+  /// Asks the language server for the signature of the call being typed when `c` is one of the
+  /// chars it asks for, and puts it away once `)` closes the call.
+  fn signature_help(&mut self, c: char) {
+    if c == ')' {
+      self.signature = None;
+      return;
+    }
+    let document = self.lsp_client().ok().and_then(|(client, document)| {
+      let provider = client.provider("signatureHelpProvider")?;
+      let mut triggers = ["triggerCharacters", "retriggerCharacters"]
+        .iter()
+        .filter_map(|name| provider[name].as_array())
+        .flatten()
+        .filter_map(Value::as_str);
+      triggers.any(|t| t.chars().eq([c])).then_some(document)
+    });
+    let Some(mut params) = document else {
+      return;
+    };
+    params["context"] = json!({
+      "triggerKind": 2,
+      "triggerCharacter": c.to_string(),
+      "isRetrigger": self.signature.is_some(),
+    });
+    let _ = self.lsp_request(
+      "textDocument/signatureHelp",
+      params,
+      lsp::Request::SignatureHelp,
+    );
+  }
+
+  /// Asks for the edits that format the focused buffer: from `at` up to `end` for a range
+  /// formatting, or around the cursor after typing `at` without an `end`.
+  fn format_request(&mut self, method: &str, at: Value, end: Option<Position>) -> Result<()> {
+    let options = &self.buffer().options;
+    let options = json!({ "tabSize": options.tabstop, "insertSpaces": options.expandtab });
+    let (_, mut params) = self.lsp_client()?;
+    match end {
+      Some(end) => {
+        let params = params.as_object_mut().expect("an object");
+        params.remove("position");
+        let end = lsp::position(&self.buffer().lines, end);
+        params.insert("range".to_owned(), json!({ "start": at, "end": end }));
+      }
+      None => params["ch"] = at,
+    }
+    params["options"] = options;
+    let buffer = self.buffer();
+    let path = buffer.path.as_deref().unwrap_or(Path::new(""));
+    let request = lsp::Request::Format {
+      path: path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+      version: buffer.version(),
+      on_type: end.is_none(),
+    };
+    self.lsp_request(method, params, request)
+  }
+
+  /// Indents lines `first` to `last` by their brackets: one level deeper than the line above
+  /// when it ends with an opening one, and one less when the line starts with a closing one.
+  /// Returns how many lines changed.
+  fn reindent(&mut self, first: usize, last: usize) -> usize {
+    let buffer = self.buffer_mut();
+    let tabstop = buffer.options.tabstop.max(1);
+    let expandtab = buffer.options.expandtab;
+    let above = buffer.lines[..first]
+      .iter()
+      .rev()
+      .find(|line| !line.trim().is_empty());
+    let width_of =
+      |line: &str| ui::render_col(line, leading_whitespace(line).chars().count(), tabstop);
+    let mut prev = above.map(|line| (width_of(line), line.trim_end().to_owned()));
+    let mut changed = 0;
+    for l in first..=last {
+      let line = buffer.lines[l].clone();
+      let text = line.trim_start();
+      if text.is_empty() {
+        continue;
+      }
+      let mut width =
+        prev
+          .as_ref()
+          .map_or(0, |(width, prev)| match prev.ends_with(['{', '(', '[']) {
+            true => width + tabstop,
+            false => *width,
+          });
+      if text.starts_with(['}', ')', ']']) {
+        width = width.saturating_sub(tabstop);
+      }
+      let indent = indent_to(width, tabstop, expandtab);
+      let old = leading_whitespace(&line);
+      if indent != old {
+        let end = Position::new(l, old.chars().count());
+        buffer.delete(Position::new(l, 0), end);
+        buffer.insert(Position::new(l, 0), &indent);
+        changed += 1;
+      }
+      prev = Some((width, text.trim_end().to_owned()));
+    }
+    changed
+  }
+
+  /// `:symbols`: lists what the language server finds in the buffer to jump to one, or without
+  /// a server that can, the lines that start an indented block.
+  fn list_symbols(&mut self) {
+    let document = self
+      .lsp_client()
+      .ok()
+      .and_then(|(client, document)| client.provider("documentSymbolProvider").map(|_| document));
+    let Some(mut params) = document else {
+      let symbols = symbol::outline(&self.buffer().lines);
+      return self.show_symbols(symbols);
+    };
+    params
+      .as_object_mut()
+      .expect("an object")
+      .remove("position");
+    let request = lsp::Request::Symbols;
+    if let Err(e) = self.lsp_request("textDocument/documentSymbol", params, request) {
+      self.message = Some(e.to_string());
+    }
+  }
+
+  /// Opens the `:symbols` popup on the nearest symbol at or above the cursor.
+  fn show_symbols(&mut self, symbols: Vec<Symbol>) {
+    if symbols.is_empty() {
+      self.message = Some("No symbols".to_owned());
+      return;
+    }
+    let cursor = self.window().cursor;
+    let selected = symbols
+      .iter()
+      .enumerate()
+      .filter(|(_, symbol)| symbol.position.line <= cursor.line)
+      .max_by_key(|(_, symbol)| symbol.position.line)
+      .map_or(0, |(i, _)| i);
+    self.symbol_list = Some((symbols, selected));
+  }
+
+  fn handle_symbol_list(&mut self, key: KeyEvent, symbols: Vec<Symbol>, selected: usize) {
+    let last = symbols.len() - 1;
+    let selected = match key.code {
+      KeyCode::Char('j') | KeyCode::Down => (selected + 1).min(last),
+      KeyCode::Char('k') | KeyCode::Up => selected.saturating_sub(1),
+      KeyCode::Enter => {
+        self.windows[self.focus].cursor = symbols[selected].position;
+        self.clamp_all();
+        self.frame_cursor_line('z');
+        return;
+      }
+      KeyCode::Esc | KeyCode::Char('q') => return,
+      _ => selected,
+    };
+    self.symbol_list = Some((symbols, selected));
+  }
+
+  /// Keys for the `:registers` popup: `Enter` or `p` puts the selected register after the
+  /// cursor and `P` before it.
+  fn handle_register_list(&mut self, key: KeyEvent, names: Vec<char>, selected: usize) {
+    let last = names.len() - 1;
+    let selected = match key.code {
+      KeyCode::Char('j') | KeyCode::Down => (selected + 1).min(last),
+      KeyCode::Char('k') | KeyCode::Up => selected.saturating_sub(1),
+      KeyCode::Enter | KeyCode::Char('p' | 'P') => {
+        self.register_name = Some(names[selected]);
+        self.put(key.code != KeyCode::Char('P'), 1);
+        self.clamp_all();
+        return;
+      }
+      KeyCode::Esc | KeyCode::Char('q') => return,
+      _ => selected,
+    };
+    self.register_list = Some((names, selected));
+  }
+
+  fn handle_code_actions(&mut self, key: KeyEvent, actions: Vec<Value>, selected: usize) {
+    let last = actions.len() - 1;
+    let selected = match key.code {
+      KeyCode::Char('j') | KeyCode::Down => (selected + 1).min(last),
+      KeyCode::Char('k') | KeyCode::Up => selected.saturating_sub(1),
+      KeyCode::Enter => {
+        self.apply_code_action(actions[selected].clone(), true);
+        return;
+      }
+      KeyCode::Esc | KeyCode::Char('q') => return,
+      _ => selected,
+    };
+    self.code_actions = Some((actions, selected));
+  }
+
+  /// Carries out a code action: its edit, then its command, which the server runs and which
+  /// may send edits of its own. An action that comes with neither is resolved first, if
+  /// `resolve` is set and the server can.
+  fn apply_code_action(&mut self, action: Value, resolve: bool) {
+    let title = action["title"].as_str().unwrap_or_default().to_owned();
+    // A bare command rather than an action.
+    if action["command"].is_string() {
+      return self.execute_command(title, &action);
+    }
+    if action.get("edit").is_none() && action.get("command").is_none() {
+      let resolvable = self.lsp_client().ok().and_then(|(client, _)| {
+        client.provider("codeActionProvider")?["resolveProvider"].as_bool()
+      });
+      if resolve && resolvable == Some(true) {
+        let request = lsp::Request::ResolveCodeAction;
+        if let Err(e) = self.lsp_request("codeAction/resolve", action, request) {
+          self.message = Some(e.to_string());
+        }
+        return;
+      }
+      self.message = Some(format!("\"{}\" has nothing to do", title));
+      return;
+    }
+    if let Some(edit) = action.get("edit") {
+      match self.apply_workspace_edit(edit) {
+        Ok(_) => self.message = Some(format!("Applied \"{}\"", title)),
+        Err(e) => {
+          self.message = Some(e.to_string());
+          return;
+        }
+      }
+    }
+    if let Some(command) = action.get("command") {
+      self.execute_command(title, command);
+    }
+  }
+
+  /// Has the language server run `command`, for the code action called `title`.
+  fn execute_command(&mut self, title: String, command: &Value) {
+    let mut params = json!({ "command": command["command"] });
+    if let Some(arguments) = command.get("arguments") {
+      params["arguments"] = arguments.clone();
+    }
+    let request = lsp::Request::ExecuteCommand { title };
+    if let Err(e) = self.lsp_request("workspace/executeCommand", params, request) {
+      self.message = Some(e.to_string());
+    }
+  }
+
+  /// `\rn`: puts `:rename` on the command line with the word under the cursor to edit.
+  fn ask_rename(&mut self) {
+    let cursor = self.window().cursor;
+    let word = self.words().object(cursor, true).map(|(start, end)| {
+      let line = &self.buffer().lines[cursor.line];
+      line
+        .chars()
+        .skip(start)
+        .take(end - start)
+        .collect::<String>()
+    });
+    self.mode = Mode::Command;
+    self.command_line = format!("rename {}", word.unwrap_or_default().trim());
+  }
+
+  /// Applies a workspace edit from a language server, opening the files it changes that
+  /// aren't open. The edits to each file become one undo group. Nothing is changed if any
+  /// part of it can't be applied. Returns the number of edits and files changed.
+  fn apply_workspace_edit(&mut self, edit: &Value) -> Result<(usize, usize)> {
+    let mut files: Vec<(PathBuf, &[Value])> = Vec::new();
+    let unsupported =
+      || EditorError::command("The edit creates, renames or deletes files, which isn't supported");
+    let file = |uri: &Value| {
+      uri
+        .as_str()
+        .and_then(lsp::path)
+        .ok_or_else(|| EditorError::command(format!("Can't edit {}", uri)))
+    };
+    if let Some(changes) = edit["documentChanges"].as_array() {
+      for change in changes {
+        if change.get("kind").is_some() {
+          return Err(unsupported());
+        }
+        let edits = change["edits"].as_array().map_or(&[][..], Vec::as_slice);
+        files.push((file(&change["textDocument"]["uri"])?, edits));
+      }
+    } else if let Some(changes) = edit["changes"].as_object() {
+      for (uri, edits) in changes {
+        let edits = edits.as_array().map_or(&[][..], Vec::as_slice);
+        files.push((file(&json!(uri))?, edits));
+      }
+    }
+    files.retain(|(_, edits)| !edits.is_empty());
+    let mut indices = Vec::new();
+    for (path, _) in &files {
+      indices.push(self.open_for_edit(path)?);
+    }
+    let mut count = 0;
+    for ((_, edits), index) in files.iter().zip(indices) {
+      let buffer = &mut self.buffers[index];
+      let window = &mut self.windows[self.focus];
+      let mut cursor = window.cursor;
+      count += lsp::apply_edits(buffer, edits, &mut cursor);
+      if window.buffer == index {
+        window.cursor = cursor;
+      }
+      buffer.undo.commit();
+    }
+    self.clamp_all();
+    Ok((count, files.len()))
+  }
+
+  /// The index of the buffer for `path`, opening it after the others if there is none.
+  fn open_for_edit(&mut self, path: &Path) -> Result<usize> {
+    if let Some(index) = self.find_buffer(path) {
+      return Ok(index);
+    }
+    let buffer = Buffer::open(path).map_err(|e| EditorError::io("open", path, e))?;
+    self.add_buffer(buffer, None);
+    Ok(self.buffers.len() - 1)
+  }
+
+  /// The index of the buffer for the absolute `path`, if it is open.
+  fn find_buffer(&self, path: &Path) -> Option<usize> {
+    self.buffers.iter().position(|buffer| {
+      let open = buffer.path.as_deref().and_then(|p| path::absolute(p).ok());
+      open.as_deref() == Some(path)
+    })
+  }
+
+  /// Saves every modified buffer with a file, or with `backup` writes a backup of it instead.
+  /// Each version of a buffer is only autosaved once.
+  fn autosave(&mut self) {
+    let backupdir = self.options.backupdir.clone().or_else(backup::default_dir);
+    for buffer in &mut self.buffers {
+      let Some(path) = buffer.path.clone() else {
+        continue;
+      };
+      if !buffer.modified || buffer.autosaved == buffer.version() {
+        continue;
+      }
+      buffer.autosaved = buffer.version();
+      let result = match (&backupdir, self.options.backup) {
+        (Some(dir), true) => {
+          let contents = buffer.contents();
+          backup::write(dir, &path, &contents, self.options.backupcount).map(|_| ())
+        }
+        (None, true) => continue,
+        // Changes made on disk by something else are never overwritten unasked.
+        (_, false) if buffer.changed_on_disk() => continue,
+        (_, false) => buffer.save().map(|_| ()),
+      };
+      if let Err(e) = result {
+        self.message = Some(format!("Can't autosave \"{}\": {}", buffer.name(), e));
+      }
+    }
+    self.clamp_all();
+  }
+
+  /// Writes a backup of every modified buffer with a file, for when the editor has to exit
+  /// without asking. Returns how many were written.
+  pub fn back_up_unsaved(&mut self) -> usize {
+    let Some(dir) = self.options.backupdir.clone().or_else(backup::default_dir) else {
+      return 0;
+    };
+    let keep = self.options.backupcount;
+    self
+      .buffers
+      .iter()
+      .filter(|b| b.modified)
+      .filter_map(|b| Some((b.path.as_ref()?, b.contents())))
+      .filter(|(path, contents)| backup::write(&dir, path, contents, keep).is_ok())
+      .count()
+  }
+
+  /// `:backups`: lists the backups of the current buffer's file to pick one to restore.
+  fn list_backups(&mut self) {
+    let Some(path) = self.buffer().path.clone() else {
+      self.message = Some("The buffer has no file name".to_owned());
+      return;
+    };
+    let dir = self.options.backupdir.clone().or_else(backup::default_dir);
+    let backups = dir.map_or(Vec::new(), |dir| backup::list(&dir, &path));
+    match backups.is_empty() {
+      true => self.message = Some(format!("No backups of \"{}\"", path.display())),
+      false => self.backup_list = Some((backups, 0)),
+    }
+  }
+
+  fn handle_backup_list(&mut self, key: KeyEvent, backups: Vec<PathBuf>, selected: usize) {
+    let last = backups.len() - 1;
+    let selected = match key.code {
+      KeyCode::Char('j') | KeyCode::Down => (selected + 1).min(last),
+      KeyCode::Char('k') | KeyCode::Up => selected.saturating_sub(1),
+      KeyCode::Enter => {
+        let backup = &backups[selected];
+        let name = backup.file_name().unwrap_or_default().to_string_lossy();
+        self.message = Some(match fs::read_to_string(backup) {
+          Ok(content) => {
+            self.buffer_mut().replace_all(&content);
+            self.commit_undo();
+            self.clamp_all();
+            format!("Restored the backup from {}", name)
+          }
+          Err(e) => format!("Can't read the backup from {}: {}", name, e),
+        });
+        return;
+      }
+      KeyCode::Esc | KeyCode::Char('q') => return,
+      _ => selected,
+    };
+    self.backup_list = Some((backups, selected));
+  }
+
+  /// `:earlier` and `:later`: goes back or forward through the undo tree by a number of changes
+  /// or an amount of time.
+  fn travel(&mut self, arg: &str, forward: bool) -> Result<()> {
+    let distance = Distance::parse(arg)
+      .ok_or_else(|| EditorError::Parse(format!("Invalid argument: {}", arg)))?;
+    let undo = &mut self.buffers[self.windows[self.focus].buffer].undo;
+    undo.commit();
+    let id = undo.travel(distance, forward);
+    if id == undo.current() {
+      self.message = Some(match forward {
+        true => "Already at newest change".to_owned(),
+        false => "Already at oldest change".to_owned(),
+      });
+      return Ok(());
+    }
+    self.go_to_undo_state(id);
+    Ok(())
+  }
+
+  /// Makes the focused buffer what it was at state `id` of its undo tree.
+  fn go_to_undo_state(&mut self, id: usize) {
+    let buffer = self.buffer_mut();
+    let at = buffer.undo_to(id);
+    let time = buffer.undo.time(id).map(|time| time.format("%H:%M:%S"));
+    self.message = Some(match (id, time) {
+      (0, _) => "At the original text".to_owned(),
+      (_, Some(time)) => format!("At change {} from {}", id, time),
+      (_, None) => format!("At change {}", id),
+    });
+    if let Some(at) = at {
+      self.windows[self.focus].cursor = at;
+    }
+    self.clamp_all();
+  }
+
+  /// `:undotree`: lists every state of the undo tree, to pick one to go back or forward to.
+  fn list_undo_states(&mut self) {
+    let undo = &mut self.buffers[self.windows[self.focus].buffer].undo;
+    undo.commit();
+    let current = undo.current();
+    self.undo_list = undo.entries().iter().position(|e| e.id == current);
+  }
+
+  fn handle_undo_list(&mut self, key: KeyEvent, selected: usize) {
+    let entries = self.buffer().undo.entries();
+    let selected = match key.code {
+      KeyCode::Char('j') | KeyCode::Down => (selected + 1).min(entries.len() - 1),
+      KeyCode::Char('k') | KeyCode::Up => selected.saturating_sub(1),
+      KeyCode::Enter => {
+        self.go_to_undo_state(entries[selected].id);
+        return;
+      }
+      KeyCode::Esc | KeyCode::Char('q') => return,
+      _ => selected,
+    };
+    self.undo_list = Some(selected);
+  }
+
+  /// Called when the terminal regains focus.
+  pub fn focus_gained(&mut self) {
+    self.check_disk(false);
+  }
+
+  /// Called when the terminal loses focus, as when switching to another window.
+  pub fn focus_lost(&mut self) {
+    if self.options.autosavefocus {
+      self.autosave();
+    }
+  }
+
+  /// Called when the watcher sees `path` change on disk. An unmodified buffer simply takes the
+  /// new contents; a modified one asks what to do if it is in view.
+  pub fn file_changed(&mut self, path: &Path) {
+    let Some(index) = self
+      .buffers
+      .iter()
+      .position(|b| b.path.as_deref() == Some(path))
+    else {
+      return;
+    };
+    let buffer = &mut self.buffers[index];
+    // Our own writes update `mtime`, so they don't count.
+    if !buffer.changed_on_disk() {
+      return;
+    }
+    if !buffer.modified {
+      let name = buffer.name();
+      self.message = Some(match buffer.reload() {
+        Ok(()) => format!("\"{}\" changed on disk and was reloaded", name),
+        Err(e) => format!("Can't reload \"{}\": {}", name, e),
+      });
+      self.refresh_folds();
+    } else if index == self.window().buffer {
+      self.check_disk(false);
+    }
+  }
+
+  pub fn handle_mouse(&mut self, mouse: MouseEvent) {
+    let (MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left)) =
+      mouse.kind
+    else {
+      return;
+    };
+    self.cursors.clear();
+    // A click on a fold marker opens or closes that fold.
+    let hit = self.windows.iter().position(|w| {
+      let area = w.area;
+      mouse.column >= area.x
+        && mouse.column < area.x + w.options.foldcolumn as u16
+        && mouse.row >= area.y
+        && mouse.row < area.bottom()
+    });
+    if let Some(index) = hit {
+      if mouse.kind == MouseEventKind::Down(MouseButton::Left) {
+        self.focus_window(index);
+        self.toggle_fold_at_row((mouse.row - self.window().area.y) as usize);
+      }
+      return;
+    }
+    // Clicking or dragging on a scrollbar jumps that window to the matching part of the file.
+    let hit = self.windows.iter().position(|w| {
+      let area = w.area;
+      w.options.scrollbar
+        && mouse.column + 1 == area.right()
+        && mouse.row >= area.y
+        && mouse.row < area.bottom()
+    });
+    if let Some(index) = hit {
+      self.focus_window(index);
+      let area = self.window().area;
+      let total = self.buffer().lines.len();
+      let target = (mouse.row - area.y) as usize * total / area.height.max(1) as usize;
+      let delta = target as isize - self.window().scroll_offset as isize;
+      self.scroll_view(delta);
+    }
+  }
+
+  fn toggle_fold_at_row(&mut self, row: usize) {
+    let (buffer, window) = self.focused();
+    let last = buffer.lines.len() - 1;
+    let line = fold::rows_down(&buffer.folds, window.scroll_offset, row, last);
+    if fold::rows_between(&buffer.folds, window.scroll_offset, line) == row
+      && fold::toggle(&mut buffer.folds, line)
+    {
+      window.cursor = Position::new(line, 0);
+      self.clamp_all();
+    }
+  }
+
+  /// Fits window `index` to a viewport `height` lines tall.
+  ///
+  /// The focused window scrolls to keep its cursor visible; with `scrollbind` the other windows
+  /// follow by the same amount. Unfocused windows instead move their cursor into view.
+  pub fn scroll(&mut self, index: usize, height: usize) {
+    let window = &mut self.windows[index];
+    let buffer = &self.buffers[window.buffer];
+    let (folds, last) = (&buffer.folds, buffer.lines.len() - 1);
+    window.height = height;
+    let limit = scroll_limit(folds, last, height, self.options.scrollpastend);
+    if index != self.focus {
+      window.scroll_offset = fold::visible_start(folds, window.scroll_offset.min(limit));
+      let bottom = fold::rows_down(folds, window.scroll_offset, height.max(1) - 1, last);
+      let line = window.cursor.line.clamp(window.scroll_offset, bottom);
+      window.cursor.line = fold::visible_start(folds, line);
+      return;
+    }
+    let before = window.scroll_offset;
+    window.scroll_offset = fold::visible_start(folds, window.scroll_offset.min(limit));
+    if window.cursor.line < window.scroll_offset {
+      window.scroll_offset = fold::visible_start(folds, window.cursor.line);
+    }
+    if height > 0 && fold::rows_between(folds, window.scroll_offset, window.cursor.line) >= height {
+      window.scroll_offset = fold::rows_up(folds, window.cursor.line, height - 1);
+    }
+    let delta = window.scroll_offset as isize - before as isize;
+    self.scroll_bound(delta);
+  }
+
+  /// Scrolls the focused window by `delta` lines, dragging the cursor along if it leaves the view.
+  fn scroll_view(&mut self, delta: isize) {
+    let past_end = self.options.scrollpastend;
+    let (buffer, window) = self.focused();
+    let (folds, last) = (&buffer.folds, buffer.lines.len() - 1);
+    let limit = scroll_limit(folds, last, window.height, past_end);
+    let before = window.scroll_offset;
+    window.scroll_offset = match delta {
+      0.. => fold::rows_down(folds, before, delta as usize, last).min(limit.max(before)),
+      _ => fold::rows_up(folds, before, delta.unsigned_abs()),
+    };
+    let bottom = fold::rows_down(folds, window.scroll_offset, window.height.max(1) - 1, last);
+    window.cursor.line = window.cursor.line.clamp(window.scroll_offset, bottom);
+    let delta = window.scroll_offset as isize - before as isize;
+    self.scroll_bound(delta);
+    self.clamp_cursor();
+  }
+
+  /// Applies a scroll of the focused window to the others when `scrollbind` is set. Each window
+  /// clamps to its own document, so files of different lengths stay usable.
+  fn scroll_bound(&mut self, delta: isize) {
+    if !self.options.scrollbind || delta == 0 {
+      return;
+    }
+    for (i, window) in self.windows.iter_mut().enumerate() {
+      if i != self.focus {
+        let buffer = &self.buffers[window.buffer];
+        let (folds, last) = (&buffer.folds, buffer.lines.len() - 1);
+        let limit = scroll_limit(folds, last, window.height, self.options.scrollpastend);
+        window.scroll_offset = window.scroll_offset.saturating_add_signed(delta).min(limit);
+      }
+    }
+  }
+
+  fn focus_window(&mut self, index: usize) {
+    let previous = self.window().buffer;
+    self.focus = index.min(self.windows.len() - 1);
+    self.left_buffer(previous);
+    self.clamp_all();
+  }
+
+  /// Focuses the nearest window in a direction on screen, going by where windows were last
+  /// drawn. Returns whether there was one.
+  fn focus_neighbour(&mut self, dx: i32, dy: i32) -> bool {
+    let from = self.window().area;
+    let (x, y) = (from.x as i32, from.y as i32);
+    let nearest = self
+      .windows
+      .iter()
+      .enumerate()
+      .filter_map(|(i, w)| {
+        let a = w.area;
+        let (left, top, right, bottom) =
+          (a.x as i32, a.y as i32, a.right() as i32, a.bottom() as i32);
+        let distance = match (dx, dy) {
+          (1, _) if left >= from.right() as i32 && (top..bottom).contains(&y) => left - x,
+          (-1, _) if right <= x && (top..bottom).contains(&y) => x - left,
+          (_, 1) if top >= from.bottom() as i32 && (left..right).contains(&x) => top - y,
+          (_, -1) if bottom <= y && (left..right).contains(&x) => y - top,
+          _ => return None,
+        };
+        Some((distance, i))
+      })
+      .min();
+    match nearest {
+      Some((_, index)) => {
+        self.focus_window(index);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Makes `previous` the alternate buffer if the focused window no longer shows it.
+  fn left_buffer(&mut self, previous: usize) {
+    if previous != self.window().buffer {
+      self.alternate = Some(previous);
+    }
+  }
+
+  fn handle_normal(&mut self, key: KeyEvent) {
+    self.message = None;
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    if !self.pending.is_empty() {
+      match key.code {
+        KeyCode::Char(c) if !ctrl => self.pending.push(c),
+        // As their control chars, for `g Ctrl+A` and `g Ctrl+X`.
+        KeyCode::Char(c @ ('a' | 'x')) => self.pending.push(control(c)),
+        // Esc and the like cancel the command.
+        _ => self.pending.clear(),
+      }
+      self.run_pending();
+      return;
+    }
+    if let KeyCode::Char(c @ '0'..='9') = key.code {
+      // A leading 0 is the motion to the start of the line, not part of a count.
+      if !ctrl && (c != '0' || self.count.is_some()) {
+        let digit = c as usize - '0' as usize;
+        self.count = Some(
+          self
+            .count
+            .unwrap_or(0)
+            .saturating_mul(10)
+            .saturating_add(digit),
+        );
+        return;
+      }
+    }
+    let count = self.count.take();
+    let n = count.unwrap_or(1) as isize;
+    match key.code {
+      KeyCode::Char('e') if ctrl => self.scroll_view(1),
+      KeyCode::Char(c @ ('a' | 'x')) if ctrl => {
+        self.increment(if c == 'a' { n } else { -n } as i64)
+      }
+      KeyCode::Char('y') if ctrl => self.scroll_view(-1),
+      // Going left from the leftmost window enters the file tree.
+      KeyCode::Char('h') if ctrl => {
+        self.tree_focused = !self.focus_neighbour(-1, 0) && self.tree.is_some()
+      }
+      // Going down from the bottom window enters the output panel, or the terminal pane
+      // without one.
+      KeyCode::Char('j') if ctrl => {
+        let bottom = !self.focus_neighbour(0, 1);
+        self.output_focused = bottom && self.output_open;
+        self.shell_focused = bottom && !self.output_open && self.shell.is_some();
+      }
+      KeyCode::Char('k') if ctrl => {
+        self.focus_neighbour(0, -1);
+      }
+      KeyCode::Char('l') if ctrl => {
+        self.focus_neighbour(1, 0);
+      }
+      // Terminals send Ctrl+^ as Ctrl+6.
+      KeyCode::Char('^' | '6') if ctrl => match count {
+        Some(n) => {
+          if let Err(e) = self.buffer_command(&n.to_string()) {
+            self.message = Some(e.to_string());
+          }
+        }
+        None => match self.alternate {
+          Some(alternate) => self.switch_buffer(alternate),
+          None => self.message = Some("No alternate file".to_owned()),
+        },
+      },
+      KeyCode::Char('}') => self.move_paragraphs(n),
+      KeyCode::Char('{') => self.move_paragraphs(-n),
+      KeyCode::Down if ctrl => self.move_paragraphs(n),
+      KeyCode::Up if ctrl => self.move_paragraphs(-n),
+      KeyCode::Char('h') | KeyCode::Left => self.move_cursor(0, -n),
+      KeyCode::Char('j') | KeyCode::Down => self.move_cursor(n, 0),
+      KeyCode::Char('k') | KeyCode::Up => self.move_cursor(-n, 0),
+      KeyCode::Char('l') | KeyCode::Right => self.move_cursor(0, n),
+      KeyCode::Char('%') => match count {
+        Some(percent) => self.goto_percent(percent),
+        None => {
+          let (buffer, window) = self.focused();
+          let options = &buffer.options;
+          let keyword = matchwords::matching(
+            &buffer.lines,
+            window.cursor,
+            &options.matchwords,
+            &options.matchpairs,
+          );
+          match keyword {
+            Some(Some(pos)) => window.cursor = pos,
+            Some(None) => self.message = Some("No matching keyword".to_owned()),
+            None => match matching_bracket(&buffer.lines, window.cursor, &options.matchpairs) {
+              Some(pos) => window.cursor = pos,
+              None => self.message = Some("No matching bracket".to_owned()),
+            },
+          }
+          self.clamp_all();
+        }
+      },
+      KeyCode::Char(c @ ('w' | 'b' | 'e')) => {
+        let words = self.words();
+        let cursor = (0..n).fold(self.window().cursor, |pos, _| match c {
+          'w' => words.next_start(pos),
+          'b' => words.prev_start(pos),
+          _ => words.next_end(pos),
+        });
+        drop(words);
+        self.windows[self.focus].cursor = cursor;
+        self.clamp_all();
+      }
+      KeyCode::Char('0') | KeyCode::Home => self.windows[self.focus].cursor.col = 0,
+      KeyCode::Char('$') | KeyCode::End => {
+        let (buffer, window) = self.focused();
+        window.cursor.col = buffer.line_len(window.cursor.line).saturating_sub(1);
+        window.goal_column = Some((window.cursor, usize::MAX));
+      }
+      KeyCode::Char('i') => self.mode = Mode::Insert,
+      KeyCode::Char('v') if ctrl => self.toggle_visual(VisualKind::Block),
+      KeyCode::Char('v') => self.toggle_visual(VisualKind::Char),
+      KeyCode::Char('V') => self.toggle_visual(VisualKind::Line),
+      KeyCode::Char('a') => {
+        let (buffer, window) = self.focused();
+        window.cursor.col = (window.cursor.col + 1).min(buffer.line_len(window.cursor.line));
+        self.mode = Mode::Insert;
+      }
+      KeyCode::Char('A') => {
+        let (buffer, window) = self.focused();
+        window.cursor.col = buffer.line_len(window.cursor.line);
+        self.mode = Mode::Insert;
+      }
+      KeyCode::Char('I') => {
+        let (buffer, window) = self.focused();
+        let line = &buffer.lines[window.cursor.line];
+        window.cursor.col = leading_whitespace(line).chars().count();
+        self.mode = Mode::Insert;
+      }
+      KeyCode::Char('o') => {
+        let (buffer, window) = self.focused();
+        window.cursor.col = buffer.line_len(window.cursor.line);
+        self.newline();
+        self.mode = Mode::Insert;
+      }
+      KeyCode::Char('x') => {
+        let (buffer, window) = self.focused();
+        if buffer.line_len(window.cursor.line) > 0 {
+          let len = buffer.line_len(window.cursor.line);
+          let end = Position::new(
+            window.cursor.line,
+            (window.cursor.col + n as usize).min(len),
+          );
+          let removed = buffer.delete(window.cursor, end);
+          let name = self.register_name.take();
+          self.registers.deleted(
+            name,
+            Register {
+              lines: vec![removed],
+              kind: VisualKind::Char,
+            },
+          );
+          self.clamp_cursor();
+        }
+      }
+      KeyCode::Char(c) if c == self.options.leader && !ctrl => {
+        self.pending.push(c);
+        self.pending_count = count;
+        self.pending_at = Instant::now();
+      }
+      KeyCode::Char('q') if self.recording.is_some() => self.stop_recording(),
+      KeyCode::Char(c @ ('z' | 'g' | 'c' | 'd' | 'y' | '"' | 'm' | '\'' | '`' | 'q' | '@')) => {
+        self.pending.push(c);
+        self.pending_count = count;
+        self.pending_at = Instant::now();
+      }
+      KeyCode::Char('G') => {
+        let last = self.buffer().lines.len();
+        self.goto_line(count.unwrap_or(last));
+      }
+      KeyCode::Char('Y') => self.yank_lines(n as usize),
+      KeyCode::Char(c @ ('p' | 'P')) => self.put(c == 'p', n as usize),
+      KeyCode::Char('u') => self.undo(),
+      KeyCode::Char('r') if ctrl => self.redo(),
+      KeyCode::Char(':') => {
+        self.command_line.clear();
+        self.mode = Mode::Command;
+      }
+      KeyCode::Char('/') => {
+        self.command_line.clear();
+        self.mode = Mode::Search;
+      }
+      KeyCode::Char('n') => self.search_next(true),
+      KeyCode::Char('N') => self.search_next(false),
+      _ => (),
+    }
+  }
+
+  fn handle_visual(&mut self, kind: VisualKind, key: KeyEvent) {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    // Leader commands work on the selection, `"a` picks the register for the next yank or
+    // delete of it and `g Ctrl+A` numbers its lines.
+    if self.pending == "z" && key.code == KeyCode::Char('f') {
+      self.pending.clear();
+      if let Some(selection) = self.selection() {
+        self.fold_extent(Extent::Lines(selection.start.line, selection.end.line));
+      }
+      self.mode = Mode::Normal;
+      return;
+    }
+    if !self.pending.is_empty() {
+      return self.handle_normal(key);
+    }
+    match key.code {
+      KeyCode::Esc => self.mode = Mode::Normal,
+      KeyCode::Char('"' | 'g' | 'z') if !ctrl => self.handle_normal(key),
+      KeyCode::Char(c @ ('a' | 'x')) if ctrl => {
+        let step = self.count.take().unwrap_or(1) as i64;
+        self.increment_selection(if c == 'a' { step } else { -step }, false);
+      }
+      KeyCode::Char(c) if c == self.options.leader && !ctrl => self.handle_normal(key),
+      // Ctrl+Shift+D, which most terminals send as Ctrl+D.
+      KeyCode::Char('d' | 'D') if ctrl => {
+        if let Some(selection) = self.selection() {
+          self.duplicate(selection);
+        }
+        self.mode = Mode::Normal;
+        self.clamp_all();
+      }
+      KeyCode::Char('v') if ctrl => self.toggle_visual(VisualKind::Block),
+      KeyCode::Char('v') => self.toggle_visual(VisualKind::Char),
+      KeyCode::Char('V') => self.toggle_visual(VisualKind::Line),
+      KeyCode::Char('o') => {
+        std::mem::swap(
+          &mut self.visual_anchor,
+          &mut self.windows[self.focus].cursor,
+        );
+      }
+      KeyCode::Char('d' | 'x') => {
+        if let Some(selection) = self.selection() {
+          self.delete_selection(selection);
+        }
+        self.mode = Mode::Normal;
+        self.clamp_all();
+      }
+      KeyCode::Char(':') => {
+        // Ex commands get the selection as their range.
+        if self.selection().is_none() {
+          return;
+        }
+        self.command_line = range::VISUAL.to_owned();
+        self.mode = Mode::Command;
+      }
+      KeyCode::Char(c @ ('I' | 'A')) if kind == VisualKind::Block => {
+        self.start_block_insert(c == 'A')
+      }
+      KeyCode::Char('y') if !ctrl => {
+        if let Some(selection) = self.selection() {
+          self.yank(selection);
+          if self.options.stickyvisual {
+            return;
+          }
+          self.windows[self.focus].cursor = selection.start;
+        }
+        self.mode = Mode::Normal;
+        self.clamp_all();
+      }
+      KeyCode::Char('h' | 'j' | 'k' | 'l' | '0'..='9' | '$' | '%' | 'n' | 'N')
+      | KeyCode::Char('e' | 'y')
+        if ctrl =>
+      {
+        self.handle_normal(key)
+      }
+      KeyCode::Char('h' | 'j' | 'k' | 'l' | '0'..='9' | '$' | '%' | 'n' | 'N')
+      | KeyCode::Char('w' | 'b' | 'e' | '{' | '}')
+      | KeyCode::Left
+      | KeyCode::Down
+      | KeyCode::Up
+      | KeyCode::Right
+      | KeyCode::Home
+      | KeyCode::End => self.handle_normal(key),
+      _ => (),
+    }
+  }
+
+  /// `}` and `{`: moves the cursor `n` paragraphs down, or up for a negative `n`.
+  fn move_paragraphs(&mut self, n: isize) {
+    let (buffer, window) = self.focused();
+    window.cursor = paragraphs(&buffer.lines, window.cursor, n);
+    self.clamp_all();
+  }
+
+  /// Copies `selection` of the focused buffer into the register and briefly highlights it.
+  fn yank(&mut self, selection: Selection) {
+    let lines = selection.text(&self.buffer().lines);
+    if lines.len() > 2 {
+      self.message = Some(format!("{} lines yanked", lines.len()));
+    }
+    let name = self.register_name.take();
+    self.registers.yanked(
+      name,
+      Register {
+        lines,
+        kind: selection.kind,
+      },
+    );
+    if self.options.yankflash > 0 {
+      self.flash = Some(Flash {
+        buffer: self.window().buffer,
+        selection,
+        until: Instant::now() + Duration::from_millis(self.options.yankflash as u64),
+      });
+    }
+  }
+
+  /// Carries out `pending` if it makes up a whole command, and forgets it unless it is still
+  /// the start of one.
+  fn run_pending(&mut self) {
+    self.pending_at = Instant::now();
+    let mapped = self.mapped_keys();
+    let command = match operator::parse(&self.pending, self.options.leader, &mapped) {
+      Parsed::Pending => return,
+      Parsed::Invalid => None,
+      Parsed::Command(command) => Some(command),
+    };
+    self.pending.clear();
+    let count = self.pending_count.take();
+    self.run_command(command, count);
+  }
+
+  /// The keys after the leader of the user's mappings, none while a mapping is played back.
+  fn mapped_keys(&self) -> Vec<&str> {
+    match self.replaying {
+      true => Vec::new(),
+      false => mapping::leader_keys(&self.config.map)
+        .into_iter()
+        .map(|(keys, _)| keys)
+        .collect(),
+    }
+  }
+
+  fn run_command(&mut self, command: Option<Command>, count: Option<usize>) {
+    match command {
+      Some(Command::Prefixed('z', c @ ('z' | 't' | 'b'))) => self.frame_cursor_line(c),
+      Some(Command::Prefixed('z', c)) => self.fold_command(c),
+      Some(Command::Prefixed('g', 'a')) => self.char_info(),
+      Some(Command::Prefixed('g', 'g')) => self.goto_line(count.unwrap_or(1)),
+      Some(Command::Prefixed('g', 'v')) => self.reselect(),
+      Some(Command::Prefixed('g', 'm')) => self.cursors_at_matches(),
+      Some(Command::Prefixed('g', c @ (';' | ','))) => self.goto_change(c == ';', count),
+      Some(Command::Prefixed('g', '=')) => self.calculate(),
+      Some(Command::Prefixed('g', c @ ('\x01' | '\x18'))) => {
+        let step = count.unwrap_or(1) as i64;
+        let delta = if c == '\x01' { step } else { -step };
+        match self.mode {
+          Mode::Visual(_) => self.increment_selection(delta, true),
+          _ => self.increment(delta),
+        }
+      }
+      Some(Command::Prefixed('m', name)) if name.is_ascii_lowercase() => {
+        let cursor = self.window().cursor;
+        self.buffer_mut().marks.insert(name, cursor);
+      }
+      Some(Command::Prefixed(c @ ('\'' | '`'), name)) => self.goto_mark(name, c == '`'),
+      Some(Command::Prefixed('q', name)) if name.is_ascii_alphabetic() => {
+        self.recording = Some((name, String::new()));
+      }
+      Some(Command::Prefixed('@', name)) => self.play_macro(name, count.unwrap_or(1)),
+      // The count before `"a` is for the command after it.
+      Some(Command::Prefixed('"', name)) if register::valid(name) => {
+        self.register_name = Some(name);
+        self.count = count;
+      }
+      Some(Command::Leader("rn")) => self.ask_rename(),
+      Some(Command::Leader("ca")) => {
+        if let Err(e) = self.code_action() {
+          self.message = Some(e.to_string());
+        }
+      }
+      Some(Command::Leader("f")) => self.format_selection(),
+      Some(Command::Mapped(i)) => self.run_mapping(i, count),
+      Some(Command::Operator {
+        op,
+        count: inner,
+        target,
+      }) => {
+        // In `2d3j` the counts multiply, but `G` and `gg` take a line number.
+        let line_number = inner.or(count);
+        let n = count.unwrap_or(1) * inner.unwrap_or(1);
+        match self.target(op, target, n, line_number) {
+          Some(extent) if op == 'z' => self.fold_extent(extent),
+          Some(extent) => self.apply_operator(op, extent),
+          None => (),
+        }
+      }
+      _ => (),
+    }
+    self.clamp_all();
+  }
+
+  /// Carries out the user's mapping with index `i`: runs its command, or plays back its keys
+  /// after `count`.
+  fn run_mapping(&mut self, i: usize, count: Option<usize>) {
+    let Some(to) = mapping::leader_keys(&self.config.map)
+      .get(i)
+      .map(|(_, to)| to.to_string())
+    else {
+      return;
+    };
+    if let Some(command) = to.strip_prefix(':') {
+      if let Err(e) = self.execute(command.trim()) {
+        self.message = Some(e.to_string());
+      }
+      return;
+    }
+    self.count = count;
+    self.feed_keys(&mapping::keys(&to), false);
+  }
+
+  /// Plays `keys` back as if typed, in one undo group. Unless `remap`, they don't start any
+  /// of the user's mappings.
+  fn feed_keys(&mut self, keys: &[KeyEvent], remap: bool) {
+    let (replaying, feeding) = (self.replaying, self.feeding);
+    self.replaying = replaying || !remap;
+    self.feeding = true;
+    for &key in keys {
+      self.handle_key(key);
+    }
+    self.replaying = replaying;
+    self.feeding = feeding;
+  }
+
+  /// `:{range}normal keys`: runs `keys` in normal mode from the start of each line of `range`.
+  /// Whatever the keys leave unfinished on a line is given up on, as with `Esc`, before the
+  /// next. `:normal!` doesn't use the user's mappings.
+  fn normal(&mut self, range: &str, keys: &str, remap: bool) -> Result<()> {
+    if keys.is_empty() {
+      return Err(EditorError::Parse("Argument required".to_owned()));
+    }
+    let (first, last) = self.range_lines(range)?;
+    let keys = mapping::keys(keys);
+    let escape = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+    // Lines the keys add or take away move the ones after them.
+    let mut shift = 0isize;
+    for line in first..=last {
+      let len = self.buffer().lines.len();
+      let Some(line) = line.checked_add_signed(shift).filter(|&line| line < len) else {
+        break;
+      };
+      self.windows[self.focus].cursor = Position::new(line, 0);
+      self.mode = Mode::Normal;
+      self.feed_keys(&keys, remap);
+      self.pending.clear();
+      self.count = None;
+      if self.mode != Mode::Normal {
+        self.feed_keys(&[escape], false);
+      }
+      shift += self.buffer().lines.len() as isize - len as isize;
+    }
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `'a` and `` `a ``: goes to the line of mark `a`, or with `exact` to where it was set.
+  fn goto_mark(&mut self, name: char, exact: bool) {
+    let Some(&pos) = self.buffer().marks.get(&name) else {
+      self.message = Some(format!("Mark not set: {}", name));
+      return;
+    };
+    let line = pos.line.min(self.buffer().lines.len() - 1);
+    let col = match exact {
+      true => pos.col,
+      false => leading_whitespace(&self.buffer().lines[line])
+        .chars()
+        .count(),
+    };
+    self.windows[self.focus].cursor = Position::new(line, col);
+    self.clamp_all();
+  }
+
+  /// The `q` that ends `qa`: keeps the keys typed since in register `a`, or after what it
+  /// already holds for `qA`.
+  fn stop_recording(&mut self) {
+    let Some((name, keys)) = self.recording.take() else {
+      return;
+    };
+    let register = Register {
+      lines: vec![keys],
+      kind: VisualKind::Char,
+    };
+    self.registers.set(name, register);
+  }
+
+  /// `@a`: plays the keys in register `a` back `count` times; `@@` plays the last one again.
+  fn play_macro(&mut self, name: char, count: usize) {
+    let name = match name {
+      '@' => match self.last_macro {
+        Some(name) => name,
+        None => {
+          self.message = Some("No previous macro".to_owned());
+          return;
+        }
+      },
+      name => name,
+    };
+    let Some(register) = self.registers.get(Some(name)) else {
+      self.message = Some(format!("Nothing in register {}", name));
+      return;
+    };
+    // Lines in a register are played with a line break between them, as if typed.
+    let keys = mapping::keys(&register.lines.join("<CR>"));
+    self.last_macro = Some(name);
+    for _ in 0..count {
+      self.feed_keys(&keys, true);
+    }
+  }
+
+  /// `Ctrl+A` and `Ctrl+X`: adds `delta` to the number under or after the cursor.
+  fn increment(&mut self, delta: i64) {
+    let (buffer, window) = self.focused();
+    let cursor = window.cursor;
+    let Some((text, last)) =
+      increment::add(&buffer.lines[cursor.line], cursor.col..usize::MAX, delta)
+    else {
+      return;
+    };
+    buffer.replace_line(cursor.line, &text);
+    window.cursor.col = last;
+  }
+
+  /// `Ctrl+A` and `Ctrl+X` in visual mode: adds `delta` to the first number in the selection
+  /// on each of its lines. With `progressive`, for `g Ctrl+A`, the second line with a number
+  /// gets twice `delta`, the third three times and so on.
+  fn increment_selection(&mut self, delta: i64, progressive: bool) {
+    let Some(selection) = self.selection() else {
+      return;
+    };
+    let (buffer, window) = self.focused();
+    let mut times = 1;
+    for line in selection.start.line..=selection.end.line {
+      let cols = match selection.kind {
+        VisualKind::Line => 0..usize::MAX,
+        VisualKind::Block => selection.start.col..selection.end.col + 1,
+        VisualKind::Char => {
+          let start = if line == selection.start.line {
+            selection.start.col
+          } else {
+            0
+          };
+          let end = if line == selection.end.line {
+            selection.end.col + 1
+          } else {
+            usize::MAX
+          };
+          start..end
+        }
+      };
+      if let Some((text, _)) =
+        increment::add(&buffer.lines[line], cols, delta.saturating_mul(times))
+      {
+        buffer.replace_line(line, &text);
+        if progressive {
+          times += 1;
+        }
+      }
+    }
+    window.cursor = selection.start;
+    self.mode = Mode::Normal;
+    self.clamp_all();
+  }
+
+  /// `g=`: replaces the arithmetic in the selection, or on the cursor line after its indent,
+  /// with its value. A line ending with `=` keeps its sum and gets the value after the `=`.
+  fn calculate(&mut self) {
+    let selection = self.selection();
+    self.mode = Mode::Normal;
+    let (buffer, window) = self.focused();
+    // Appending after `=` leaves a space before the value if there is one before the `=`.
+    let (start, end, expr, space) = match selection {
+      Some(selection) if selection.kind == VisualKind::Block => {
+        self.message = Some("Can't calculate over a block selection".to_owned());
+        return;
+      }
+      Some(selection) => {
+        let (start, end) = match selection.kind {
+          VisualKind::Char => (selection.start, selection.end),
+          _ => (
+            Position::new(selection.start.line, 0),
+            Position::new(selection.end.line, usize::MAX),
+          ),
+        };
+        let end = Position::new(end.line, (end.col + 1).min(buffer.line_len(end.line)));
+        let expr = selection.text(&buffer.lines).join("\n");
+        (start, end, expr, "")
+      }
+      None => {
+        let line = window.cursor.line;
+        let text = &buffer.lines[line];
+        let len = buffer.line_len(line);
+        match text.trim_end().strip_suffix('=') {
+          Some(sum) => {
+            let end = Position::new(line, sum.chars().count() + 1);
+            let space = if sum.ends_with(' ') { " " } else { "" };
+            (end, end, sum.to_owned(), space)
+          }
+          None => {
+            let sum = text.trim_start();
+            let start = Position::new(line, len - sum.chars().count());
+            (start, Position::new(line, len), sum.to_owned(), "")
+          }
+        }
+      }
+    };
+    match calc::eval(&expr) {
+      Ok(value) => {
+        let text = format!("{}{}", space, calc::format(value));
+        buffer.delete(start, end);
+        let after = buffer.insert(start, &text);
+        window.cursor = Position::new(after.line, after.col.saturating_sub(1));
+      }
+      Err(e) => self.message = Some(format!("Can't calculate: {}", e)),
+    }
+    self.clamp_all();
+  }
+
+  /// `g;` and `g,`: goes `count` entries back or, unless `older`, forward through the
+  /// buffer's change list.
+  fn goto_change(&mut self, older: bool, count: Option<usize>) {
+    let n = count.unwrap_or(1);
+    let (buffer, window) = self.focused();
+    if buffer.changes.is_empty() {
+      self.message = Some("Change list is empty".to_owned());
+      return;
+    }
+    let index = match older {
+      true if buffer.change_index == 0 => {
+        self.message = Some("At start of change list".to_owned());
+        return;
+      }
+      true => buffer.change_index.saturating_sub(n),
+      false if buffer.change_index + 1 >= buffer.changes.len() => {
+        self.message = Some("At end of change list".to_owned());
+        return;
+      }
+      false => (buffer.change_index + n).min(buffer.changes.len() - 1),
+    };
+    buffer.change_index = index;
+    window.cursor = buffer.changes[index];
+    self.message = Some(format!(
+      "Change {} of {}",
+      index + 1,
+      self.buffer().changes.len()
+    ));
+    self.clamp_all();
+  }
+
+  /// What operator `op`'s `target` covers from the cursor, if anything.
+  fn target(
+    &self,
+    op: char,
+    target: Target,
+    n: usize,
+    line_number: Option<usize>,
+  ) -> Option<Extent> {
+    let cursor = self.window().cursor;
+    let buffer = self.buffer();
+    let words = self.words();
+    let last = buffer.lines.len() - 1;
+    let len = buffer.line_len(cursor.line);
+    let line_of = |number: usize| number.clamp(1, last + 1) - 1;
+    let at = |col: usize| Position::new(cursor.line, col);
+    Some(match target {
+      Target::Lines => Extent::Lines(cursor.line, (cursor.line + n - 1).min(last)),
+      Target::Motion('j') if cursor.line < last => {
+        Extent::Lines(cursor.line, (cursor.line + n).min(last))
+      }
+      Target::Motion('k') if cursor.line > 0 => {
+        Extent::Lines(cursor.line.saturating_sub(n), cursor.line)
+      }
+      Target::Motion(c @ ('G' | 'g')) => {
+        let line = match (line_number, c) {
+          (Some(number), _) => line_of(number),
+          (None, 'G') => last,
+          (None, _) => 0,
+        };
+        Extent::Lines(line.min(cursor.line), line.max(cursor.line))
+      }
+      Target::Motion('h') if cursor.col > 0 => {
+        Extent::Chars(at(cursor.col.saturating_sub(n)), cursor)
+      }
+      Target::Motion('l') if len > 0 => Extent::Chars(cursor, at((cursor.col + n).min(len))),
+      Target::Motion('0') if cursor.col > 0 => Extent::Chars(at(0), cursor),
+      Target::Motion('$') if len > 0 => Extent::Chars(cursor, at(len)),
+      Target::Motion('w') if op == 'c' && words.end_of_word(cursor).is_some() => {
+        // `cw` on a word changes up to its end, leaving the blanks after it.
+        let first = words.end_of_word(cursor)?;
+        let end = (1..n).fold(first, |pos, _| words.next_end(pos));
+        Extent::Chars(cursor, Position::new(end.line, end.col + 1))
+      }
+      Target::Motion('w') => {
+        let mut end = (0..n).fold(cursor, |pos, _| words.next_start(pos));
+        // The last word of a line is deleted without joining the next line on.
+        if end.line > cursor.line && len > 0 {
+          end = at(len);
+        }
+        Extent::Chars(cursor, end)
+      }
+      Target::Motion('e') => {
+        let end = (0..n).fold(cursor, |pos, _| words.next_end(pos));
+        Extent::Chars(cursor, Position::new(end.line, end.col + 1))
+      }
+      Target::Motion('b') => {
+        let start = (0..n).fold(cursor, |pos, _| words.prev_start(pos));
+        match start == cursor {
+          true => return None,
+          false => Extent::Chars(start, cursor),
+        }
+      }
+      Target::Motion('}') => Extent::Chars(cursor, paragraphs(&buffer.lines, cursor, n as isize)),
+      Target::Motion('{') => {
+        let start = paragraphs(&buffer.lines, cursor, -(n as isize));
+        match start == cursor {
+          true => return None,
+          false => Extent::Chars(start, cursor),
+        }
+      }
+      Target::Word { inner } => {
+        let (start, end) = words.object(cursor, inner)?;
+        Extent::Chars(at(start), at(end))
+      }
+      Target::Object { inner, delimiter } => {
+        let (start, end) = operator::object(&buffer.lines, cursor, inner, delimiter)?;
+        Extent::Chars(start, end)
+      }
+      Target::Function { inner } => {
+        let (first, last) = self.function(cursor.line)?;
+        let (first, last) = match inner {
+          true => symbol::body(&buffer.lines, first, last)?,
+          false => (first, last),
+        };
+        Extent::Lines(first, last)
+      }
+      Target::Motion(_) => return None,
+    })
+  }
+
+  /// The first and last line of the function around `line` in the focused buffer: the
+  /// innermost one the language server found while that is for the text as it is, or else the
+  /// indented block that looks like one.
+  fn function(&self, line: usize) -> Option<(usize, usize)> {
+    let buffer = self.buffer();
+    let functions = &buffer.functions;
+    let found = match functions.version == buffer.version() {
+      true => symbol::enclosing(&functions.lines, line),
+      false => None,
+    };
+    found.or_else(|| symbol::function(&buffer.lines, line))
+  }
+
+  /// The sign each line of window `index` shows in its sign column, from the change signs if
+  /// `changesigns` is set, the language server's diagnostics and the buffer's marks.
+  pub fn signs(&self, index: usize) -> BTreeMap<usize, Sign> {
+    let window = &self.windows[index];
+    let buffer = &self.buffers[window.buffer];
+    let mut signs = Vec::new();
+    if window.options.changesigns {
+      signs.extend((0..buffer.lines.len()).filter_map(|line| {
+        let sign = match buffer.sign(line)? {
+          Change::Added => Sign::new('+', Color::Green, sign::CHANGE),
+          Change::Changed => Sign::new('~', Color::Blue, sign::CHANGE),
+        };
+        Some((line, sign))
+      }));
+    }
+    let diagnostics = buffer.path.as_ref().and_then(|path| {
+      let path = path::absolute(path).unwrap_or_else(|_| path.clone());
+      self.diagnostics.get(&lsp::uri(&path))
+    });
+    for diagnostic in diagnostics.into_iter().flatten() {
+      let Some(line) = diagnostic["range"]["start"]["line"].as_u64() else {
+        continue;
+      };
+      let sign = match diagnostic["severity"].as_u64() {
+        Some(2) => Sign::new('W', Color::Yellow, sign::WARNING),
+        Some(3 | 4) => Sign::new('I', Color::Cyan, sign::HINT),
+        _ => Sign::new('E', Color::Red, sign::ERROR),
+      };
+      signs.push((line as usize, sign));
+    }
+    for (&name, at) in buffer
+      .marks
+      .iter()
+      .filter(|(name, _)| name.is_ascii_lowercase())
+    {
+      signs.push((at.line, Sign::new(name, Color::Magenta, sign::MARK)));
+    }
+    signs.retain(|&(line, _)| line < buffer.lines.len());
+    sign::merge(signs)
+  }
+
+  /// The lines `stickyheader` pins above the text of window `index`: the first lines of the
+  /// blocks its top line is in, by the functions the language server found or else by
+  /// indentation. Only as many as leave the cursor line in view are kept, so the cursor never
+  /// goes under them.
+  pub fn sticky_lines(&self, index: usize) -> Vec<usize> {
+    let window = &self.windows[index];
+    let buffer = &self.buffers[window.buffer];
+    let top = window.scroll_offset;
+    let functions = &buffer.functions;
+    let mut lines = match functions.version == buffer.version() && functions.asked.is_some() {
+      true => {
+        let mut starts: Vec<usize> = functions
+          .lines
+          .iter()
+          .filter(|&&(first, last)| first < top && top <= last)
+          .map(|&(first, _)| first)
+          .collect();
+        starts.sort_unstable();
+        starts.dedup();
+        starts
+      }
+      false => symbol::context(&buffer.lines, top),
+    };
+    let room = match window.cursor.line >= top {
+      true => fold::rows_between(&buffer.folds, top, window.cursor.line),
+      false => 0,
+    };
+    lines.truncate(room.min(window.height / 2));
+    lines
+  }
+
+  /// Yanks (`y`), deletes (`d`) or changes (`c`) `extent` of the focused buffer.
+  fn apply_operator(&mut self, op: char, extent: Extent) {
+    let buffer = self.buffer();
+    let selection = match extent {
+      Extent::Lines(first, last) => Selection::new(
+        VisualKind::Line,
+        Position::new(first, 0),
+        Position::new(last, 0),
+      ),
+      Extent::Chars(start, end) if start == end => {
+        // Nothing to work on, as in `ci(` on `()`, but a change still starts typing there.
+        self.windows[self.focus].cursor = start;
+        if op == 'c' {
+          self.mode = Mode::Insert;
+        }
+        return;
+      }
+      Extent::Chars(start, end) => {
+        // Selections include their last char, or the line break when past the end of a line.
+        let last = match end.col {
+          0 => Position::new(end.line - 1, buffer.line_len(end.line - 1)),
+          col => Position::new(end.line, col - 1),
+        };
+        Selection::new(VisualKind::Char, start, last)
+      }
+    };
+    match (op, extent) {
+      ('y', _) => {
+        self.yank(selection);
+        let cursor = &mut self.windows[self.focus].cursor;
+        *cursor = match extent {
+          Extent::Lines(first, _) => Position::new(first, cursor.col),
+          Extent::Chars(start, _) => start,
+        };
+      }
+      // Changing lines keeps one empty line to type on.
+      ('c', Extent::Lines(first, last)) => {
+        let name = self.register_name.take();
+        let lines = selection.text(&self.buffer().lines);
+        self.registers.deleted(
+          name,
+          Register {
+            lines,
+            kind: VisualKind::Line,
+          },
+        );
+        let (buffer, window) = self.focused();
+        let end = Position::new(last, buffer.line_len(last));
+        buffer.delete(Position::new(first, 0), end);
+        window.cursor = Position::new(first, 0);
+        self.mode = Mode::Insert;
+      }
+      ('c', _) => {
+        self.delete_selection(selection);
+        self.mode = Mode::Insert;
+      }
+      _ => self.delete_selection(selection),
+    }
+  }
+
+  /// Puts the cursor on line `number` and, if given, column `col`, both counting from 1 and
+  /// clamped to the text: a position given on the command line as `+42` or `file:42:10`.
+  pub fn go_to(&mut self, number: usize, col: Option<usize>) {
+    let (buffer, window) = self.focused();
+    window.cursor = line_position(buffer, number, col);
+    self.clamp_all();
+  }
+
+  /// The focused buffer's text as words, going by its `iskeyword`.
+  fn words(&self) -> Words<'_, impl Fn(char) -> bool + '_> {
+    let buffer = self.buffer();
+    Words {
+      lines: &buffer.lines,
+      is_keyword: |c| buffer.options.is_keyword(c),
+    }
+  }
+
+  /// `gg` and `G`: goes to line `number`, counting from 1, at its first non-blank char.
+  fn goto_line(&mut self, number: usize) {
+    self.go_to(number, None);
+  }
+
+  /// `Y`: yanks `count` lines from the cursor line down.
+  fn yank_lines(&mut self, count: usize) {
+    let cursor = self.window().cursor;
+    let last = (cursor.line + count.max(1) - 1).min(self.buffer().lines.len() - 1);
+    self.yank(Selection::new(
+      VisualKind::Line,
+      cursor,
+      Position::new(last, 0),
+    ));
+  }
+
+  /// `p` and `P`: puts the register `count` times after or before the cursor, or for whole
+  /// lines below or above the cursor line.
+  fn put(&mut self, after: bool, count: usize) {
+    let name = self.register_name.take();
+    let Some(register) = self.registers.get(name).cloned() else {
+      self.message = Some(match name {
+        Some(name) => format!("Nothing in register {}", name),
+        None => "Nothing to put".to_owned(),
+      });
+      return;
+    };
+    let count = count.max(1);
+    let (buffer, window) = self.focused();
+    let cursor = window.cursor;
+    // Text goes after the char under the cursor, if there is one.
+    let col = match after && buffer.line_len(cursor.line) > 0 {
+      true => cursor.col + 1,
+      false => cursor.col,
+    };
+    match register.kind {
+      VisualKind::Line => {
+        let text = vec![register.lines.join("\n"); count].join("\n");
+        let line = match after {
+          true => {
+            let end = Position::new(cursor.line, buffer.line_len(cursor.line));
+            buffer.insert(end, &format!("\n{}", text));
+            cursor.line + 1
+          }
+          false => {
+            buffer.insert(Position::new(cursor.line, 0), &format!("{}\n", text));
+            cursor.line
+          }
+        };
+        window.cursor = Position::new(line, 0);
+      }
+      VisualKind::Char => {
+        let text = register.lines.join("\n").repeat(count);
+        let end = buffer.insert(Position::new(cursor.line, col), &text);
+        window.cursor = Position::new(end.line, end.col.saturating_sub(1));
+      }
+      VisualKind::Block => {
+        let width = register.lines.iter().map(|l| l.chars().count()).max();
+        let at = Position::new(cursor.line, col);
+        insert_block(buffer, &register.lines, at, width.unwrap_or(0), count);
+        window.cursor = Position::new(cursor.line, col);
+      }
+    }
+    self.clamp_all();
+  }
+
+  /// Puts a copy of `selection` right after it: lines below its last line, text after its last
+  /// char and a block to the right of it. The cursor goes to the start of the copy.
+  fn duplicate(&mut self, selection: Selection) {
+    let (buffer, window) = self.focused();
+    let lines = selection.text(&buffer.lines);
+    let (start, end) = (selection.start, selection.end);
+    window.cursor = match selection.kind {
+      VisualKind::Line => {
+        let at = Position::new(end.line, buffer.line_len(end.line));
+        buffer.insert(at, &format!("\n{}", lines.join("\n")));
+        Position::new(end.line + 1, 0)
+      }
+      VisualKind::Char => {
+        let at = Position::new(end.line, (end.col + 1).min(buffer.line_len(end.line)));
+        buffer.insert(at, &lines.join("\n"));
+        at
+      }
+      VisualKind::Block => {
+        let at = Position::new(start.line, end.col + 1);
+        insert_block(buffer, &lines, at, end.col + 1 - start.col, 1);
+        at
+      }
+    };
+  }
+
+  /// `v`, `V` and `Ctrl+V`: starts selecting, switches to another kind of selection, or stops
+  /// when the kind is the current one.
+  fn toggle_visual(&mut self, kind: VisualKind) {
+    self.mode = match self.mode {
+      Mode::Visual(current) if current == kind => Mode::Normal,
+      Mode::Visual(_) => Mode::Visual(kind),
+      _ => {
+        self.visual_anchor = self.window().cursor;
+        Mode::Visual(kind)
+      }
+    };
+  }
+
+  /// `gv`: selects the last visual selection again.
+  fn reselect(&mut self) {
+    let Some((kind, anchor, cursor)) = self.last_visual else {
+      return;
+    };
+    self.visual_anchor = anchor;
+    self.windows[self.focus].cursor = cursor;
+    self.mode = Mode::Visual(kind);
+  }
+
+  /// The visual selection in the focused window, if visual mode is on.
+  pub fn selection(&self) -> Option<Selection> {
+    let Mode::Visual(kind) = self.mode else {
+      return None;
+    };
+    let last = self.buffer().lines.len() - 1;
+    let anchor = Position::new(self.visual_anchor.line.min(last), self.visual_anchor.col);
+    Some(Selection::new(kind, anchor, self.window().cursor))
+  }
+
+  /// Deletes `selection` from the focused buffer, keeping it in the register.
+  fn delete_selection(&mut self, selection: Selection) {
+    let name = self.register_name.take();
+    let lines = selection.text(&self.buffer().lines);
+    self.registers.deleted(
+      name,
+      Register {
+        lines,
+        kind: selection.kind,
+      },
+    );
+    let (start, end) = (selection.start, selection.end);
+    let (buffer, window) = self.focused();
+    let last = buffer.lines.len() - 1;
+    match selection.kind {
+      VisualKind::Char => {
+        // The selection includes the char under its end, or the line break past an empty line.
+        let end = match end.col < buffer.line_len(end.line) {
+          true => Position::new(end.line, end.col + 1),
+          false if end.line < last => Position::new(end.line + 1, 0),
+          false => Position::new(end.line, buffer.line_len(end.line)),
+        };
+        buffer.delete(start, end);
+        window.cursor = start;
+      }
+      VisualKind::Line => {
+        let (from, to) = match (end.line < last, start.line > 0) {
+          (true, _) => (Position::new(start.line, 0), Position::new(end.line + 1, 0)),
+          (false, true) => (
+            Position::new(start.line - 1, buffer.line_len(start.line - 1)),
+            Position::new(end.line, buffer.line_len(end.line)),
+          ),
+          (false, false) => (
+            Position::new(0, 0),
+            Position::new(end.line, buffer.line_len(end.line)),
+          ),
+        };
+        buffer.delete(from, to);
+        window.cursor = Position::new(start.line.min(buffer.lines.len() - 1), 0);
+      }
+      VisualKind::Block => {
+        for line in start.line..=end.line {
+          let len = buffer.line_len(line);
+          if len > start.col {
+            let to = Position::new(line, (end.col + 1).min(len));
+            buffer.delete(Position::new(line, start.col), to);
+          }
+        }
+        window.cursor = start;
+      }
+    }
+  }
+
+  /// `I` and `A` on a block: types on the block's first line, to be copied to the rest by
+  /// `finish_block_insert`.
+  fn start_block_insert(&mut self, append: bool) {
+    let Some(selection) = self.selection() else {
+      return;
+    };
+    let (start, end) = (selection.start, selection.end);
+    let (buffer, window) = self.focused();
+    let col = match append {
+      true => end.col + 1,
+      false => start.col,
+    };
+    let len = buffer.line_len(start.line);
+    if len < col {
+      buffer.insert(Position::new(start.line, len), &" ".repeat(col - len));
+    }
+    window.cursor = Position::new(start.line, col);
+    self.block_insert = Some(BlockInsert {
+      at: window.cursor,
+      last: end.line,
+      append,
+    });
+    self.mode = Mode::Insert;
+  }
+
+  /// `gm`: starts typing at every match of the last search at once. The window's cursor goes
+  /// to the first match from the cursor on, and every other match gets a cursor of its own.
+  fn cursors_at_matches(&mut self) {
+    let Some(pattern) = self.last_search.clone() else {
+      self.message = Some("No previous search pattern".to_owned());
+      return;
+    };
+    let (buffer, window) = self.focused();
+    let mut matches = search::all(&buffer.lines, &pattern);
+    if matches.is_empty() {
+      self.message = Some(format!("Pattern not found: {}", pattern));
+      return;
+    }
+    let primary = matches
+      .iter()
+      .position(|&m| m >= window.cursor)
+      .unwrap_or(0);
+    window.cursor = matches.remove(primary);
+    self.message = Some(format!("{} cursors", matches.len() + 1));
+    self.cursors = matches;
+    self.mode = Mode::Insert;
+  }
+
+  /// Typing with the cursors of `gm`: a char goes in before each cursor, and backspace takes
+  /// out the char before each. Any other key leaves just the window's cursor, and returns
+  /// false for the key to be handled as usual.
+  fn type_at_cursors(&mut self, key: KeyEvent) -> bool {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let typed = match key.code {
+      KeyCode::Char(c) if !ctrl => Some(c),
+      KeyCode::Backspace => None,
+      _ => {
+        self.cursors.clear();
+        return false;
+      }
+    };
+    let mut cursors = std::mem::take(&mut self.cursors);
+    let (buffer, window) = self.focused();
+    cursors.insert(0, window.cursor);
+    let mut order: Vec<usize> = (0..cursors.len()).collect();
+    // The last first, so no edit moves a cursor still to be typed at.
+    order.sort_by_key(|&i| std::cmp::Reverse(cursors[i]));
+    for &i in &order {
+      let at = cursors[i];
+      match typed {
+        Some(c) => {
+          buffer.insert(at, &c.to_string());
+        }
+        None if at.col > 0 => {
+          buffer.delete(Position::new(at.line, at.col - 1), at);
+        }
+        None => (),
+      }
+    }
+    let moved: Vec<Position> = cursors
+      .iter()
+      .map(|&at| {
+        // Each edit before a cursor on its line moves it by a char.
+        let edits = cursors
+          .iter()
+          .filter(|other| other.line == at.line && other.col <= at.col)
+          .filter(|other| typed.is_some() || other.col > 0)
+          .count();
+        match typed {
+          Some(_) => Position::new(at.line, at.col + edits),
+          None => Position::new(at.line, at.col - edits),
+        }
+      })
+      .collect();
+    window.cursor = moved[0];
+    let primary = moved[0];
+    self.cursors = moved[1..].to_vec();
+    self.cursors.sort();
+    self.cursors.dedup();
+    self.cursors.retain(|&at| at != primary);
+    true
+  }
+
+  /// Copies what was typed on the first line of a block to its other lines. Text is only
+  /// copied if it was typed on that line without moving away from where it started; `I` skips
+  /// lines too short to reach the block.
+  fn finish_block_insert(&mut self) {
+    let Some(block) = self.block_insert.take() else {
+      return;
+    };
+    let (buffer, window) = self.focused();
+    let at = block.at;
+    if window.cursor.line != at.line || window.cursor.col <= at.col {
+      return;
+    }
+    let text: String = buffer.lines[at.line]
+      .chars()
+      .skip(at.col)
+      .take(window.cursor.col - at.col)
+      .collect();
+    for line in at.line + 1..=block.last.min(buffer.lines.len() - 1) {
+      let len = buffer.line_len(line);
+      if len < at.col {
+        if !block.append {
+          continue;
+        }
+        buffer.insert(Position::new(line, len), &" ".repeat(at.col - len));
+      }
+      buffer.insert(Position::new(line, at.col), &text);
+    }
+  }
+
+  /// Keys for the output panel, which only scrolls.
+  fn handle_output(&mut self, key: KeyEvent) {
+    let Some(output) = &mut self.output else {
+      self.output_focused = false;
+      return;
+    };
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    // Scrolling stops once the last line is at the bottom of the panel.
+    let last = output.lines.len().saturating_sub(output.height.max(1));
+    let scroll = output.scroll;
+    output.scroll = match key.code {
+      KeyCode::Char('k') if ctrl => {
+        self.output_focused = false;
+        return;
+      }
+      KeyCode::Char('j') if ctrl => {
+        self.output_focused = self.shell.is_none();
+        self.shell_focused = self.shell.is_some();
+        return;
+      }
+      KeyCode::Char('d') if ctrl => scroll + 10,
+      KeyCode::Char('u') if ctrl => scroll.saturating_sub(10),
+      KeyCode::Char('j') | KeyCode::Down => scroll + 1,
+      KeyCode::Char('k') | KeyCode::Up => scroll.saturating_sub(1),
+      KeyCode::PageDown => scroll + 10,
+      KeyCode::PageUp => scroll.saturating_sub(10),
+      KeyCode::Char('g') | KeyCode::Home => 0,
+      KeyCode::Char('G') | KeyCode::End => last,
+      KeyCode::Esc | KeyCode::Char('q') => {
+        self.output_focused = false;
+        return;
+      }
+      _ => scroll,
+    }
+    .min(last);
+  }
+
+  /// Keys for the terminal pane, which all go to the shell but Ctrl-K. That leaves for the
+  /// output panel above, or the windows without one.
+  fn handle_shell(&mut self, key: KeyEvent) {
+    let Some(shell) = &mut self.shell else {
+      self.shell_focused = false;
+      return;
+    };
+    if key.code == KeyCode::Char('k') && key.modifiers.contains(KeyModifiers::CONTROL) {
+      self.shell_focused = false;
+      self.output_focused = self.output_open && self.output.is_some();
+      return;
+    }
+    if let Err(e) = shell.key(key) {
+      self.message = Some(format!("Can't write to the terminal: {}", e));
+    }
+  }
+
+  /// Opens the terminal pane with a shell in the working directory, or focuses the one that
+  /// is open.
+  fn open_shell(&mut self) -> Result<()> {
+    if self.shell.is_none() {
+      let Some(notify) = self.shell_notify.clone() else {
+        return Err(EditorError::command("No terminal here"));
+      };
+      // The pane gives it its real size once drawn.
+      let shell = Shell::spawn(24, 80, &self.cwd(), notify)
+        .map_err(|e| EditorError::command(format!("Can't start a shell: {}", e)))?;
+      self.shell = Some(shell);
+    }
+    self.output_focused = false;
+    self.tree_focused = false;
+    self.shell_focused = true;
+    Ok(())
+  }
+
+  /// `:{range}send`: types the lines of `range`, the cursor line by default, into the shell
+  /// of the terminal pane, each ended as by Enter so a REPL there runs them.
+  fn send_to_shell(&mut self, range: &str) -> Result<()> {
+    let (start, end) = self.range_lines(range)?;
+    let lines = &self.buffers[self.window().buffer].lines[start..=end];
+    let text: String = lines.iter().map(|line| format!("{}\r", line)).collect();
+    let Some(shell) = &mut self.shell else {
+      return Err(EditorError::command("No terminal open, see :terminal"));
+    };
+    shell
+      .paste(&text)
+      .map_err(|e| EditorError::command(format!("Can't write to the terminal: {}", e)))?;
+    self.message = Some(match end - start {
+      0 => "Sent 1 line to the terminal".to_owned(),
+      n => format!("Sent {} lines to the terminal", n + 1),
+    });
+    Ok(())
+  }
+
+  /// Output from the shell, which closes its pane once it exits.
+  pub fn shell_output(&mut self, incoming: pty::Incoming) {
+    match (incoming, &mut self.shell) {
+      (pty::Incoming::Output(bytes), Some(shell)) => shell.output(&bytes),
+      (pty::Incoming::Exited, _) => {
+        self.shell = None;
+        self.shell_focused = false;
+      }
+      _ => (),
+    }
+  }
+
+  /// `:blame`: shows who last changed each line of the buffer after it, or hides that again.
+  /// Git is asked in the background, and a file it doesn't track gets nothing.
+  fn toggle_blame(&mut self) -> Result<()> {
+    if self.buffer().annotations.has("blame") {
+      self.buffer_mut().annotations.clear("blame");
+      return Ok(());
+    }
+    let Some(notify) = self.blame_notify.clone() else {
+      return Err(EditorError::command("Can't run git here"));
+    };
+    let buffer = self.buffer();
+    let Some(path) = buffer.path.as_deref() else {
+      return Err(EditorError::command("The buffer has no file name"));
+    };
+    let path = path::absolute(path).map_err(|e| EditorError::io("blame", path, e))?;
+    blame::spawn(path, buffer.contents(), notify);
+    Ok(())
+  }
+
+  /// Puts a blame fetched by `:blame` on its buffer, if that is still open.
+  pub fn blame_arrived(&mut self, blame: blame::Blame) {
+    if let Some(index) = self.find_buffer(&blame.path) {
+      self.buffers[index].annotations.set("blame", blame.lines);
+    }
+  }
+
+  /// Runs a shell command, keeping its output for `:copen`.
+  fn run_job(&mut self, command: &str) {
+    self.message = Some(match job::run(command, &self.cwd()) {
+      Ok(output) => {
+        let status = match output.status {
+          Some(0) => "finished".to_owned(),
+          Some(code) => format!("exited with {}", code),
+          None => "was killed".to_owned(),
+        };
+        let message = format!(
+          "\"{}\" {}, {} line(s) of output (:copen to see)",
+          command,
+          status,
+          output.lines.len()
+        );
+        self.output = Some(output);
+        message
+      }
+      Err(e) => format!("Can't run \"{}\": {}", command, e),
+    });
+  }
+
+  fn handle_tree(&mut self, key: KeyEvent) {
+    let Some(tree) = &mut self.tree else {
+      self.tree_focused = false;
+      return;
+    };
+    if let Some((_, name)) = &mut tree.input {
+      match key.code {
+        KeyCode::Esc => tree.input = None,
+        KeyCode::Enter => {
+          if let Some((input, name)) = tree.input.take() {
+            self.finish_tree_input(input, &name);
+          }
+        }
+        KeyCode::Backspace => {
+          name.pop();
+        }
+        KeyCode::Char(c) => name.push(c),
+        _ => (),
+      }
+      return;
+    }
+    self.message = None;
+    match key.code {
+      KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+        self.tree_focused = false
+      }
+      KeyCode::Char('j') | KeyCode::Down => tree.move_selection(1),
+      KeyCode::Char('k') | KeyCode::Up => tree.move_selection(-1),
+      KeyCode::Char('h') | KeyCode::Left => tree.collapse(),
+      KeyCode::Enter | KeyCode::Char('l') | KeyCode::Char('o') | KeyCode::Right => {
+        match tree.selected() {
+          Some(entry) if !entry.is_dir => {
+            let path = relative_to_cwd(&entry.path);
+            match self.buffer_for(&path) {
+              Ok(index) => {
+                self.switch_buffer(index);
+                self.tree_focused = false;
+              }
+              Err(e) => self.message = Some(e.to_string()),
+            }
+          }
+          _ => tree.toggle(),
+        }
+      }
+      KeyCode::Char('a') => tree.input = Some((TreeInput::Create, String::new())),
+      KeyCode::Char('r') => {
+        let name = tree
+          .selected()
+          .and_then(|e| e.path.file_name())
+          .map(|name| name.to_string_lossy().into_owned());
+        if let Some(name) = name {
+          tree.input = Some((TreeInput::Rename, name));
+        }
+      }
+      KeyCode::Char('d') => {
+        if let Some(entry) = tree.selected() {
+          let name = tree.relative(&entry.path).display();
+          self.message = Some(format!("Delete \"{}\"?", name));
+          self.prompt = Some(Prompt::DeleteNode);
+        }
+      }
+      // `m` picks the node to move, and `m` again on the target directory moves it there.
+      KeyCode::Char('m') if tree.moving.is_some() => {
+        let result = tree.move_to_target();
+        self.after_relocate(result, "move");
+      }
+      KeyCode::Char('m') => {
+        if let Some(entry) = tree.selected() {
+          let name = tree.relative(&entry.path).display();
+          self.message = Some(format!(
+            "Moving \"{}\": select a directory and press m",
+            name
+          ));
+          tree.moving = Some(entry.path.clone());
+        }
+      }
+      KeyCode::Esc => tree.moving = None,
+      KeyCode::Char('q') => {
+        self.tree = None;
+        self.tree_focused = false;
+      }
+      _ => (),
+    }
+  }
+
+  fn finish_tree_input(&mut self, input: TreeInput, name: &str) {
+    let Some(tree) = &mut self.tree else {
+      return;
+    };
+    if name.is_empty() {
+      return;
+    }
+    match input {
+      TreeInput::Create => {
+        self.message = Some(match tree.create(name) {
+          Ok(path) => format!("Created \"{}\"", tree.relative(&path).display()),
+          Err(e) => format!("Can't create \"{}\": {}", name, e),
+        })
+      }
+      TreeInput::Rename => {
+        let result = tree.rename(name);
+        self.after_relocate(result, "rename");
+      }
+    }
+  }
+
+  /// Reports a rename or move done in the file tree and points open buffers at the new path.
+  fn after_relocate(&mut self, result: io::Result<(PathBuf, PathBuf)>, verb: &str) {
+    let Some(tree) = &self.tree else {
+      return;
+    };
+    let (from, to) = match result {
+      Ok(paths) => paths,
+      Err(e) => {
+        self.message = Some(format!("Can't {}: {}", verb, e));
+        return;
+      }
+    };
+    for buffer in &mut self.buffers {
+      let Some(old) = buffer.path.as_deref().and_then(|p| path::absolute(p).ok()) else {
+        continue;
+      };
+      if let Ok(rest) = old.strip_prefix(&from) {
+        let new = match rest.as_os_str().is_empty() {
+          true => to.clone(),
+          false => to.join(rest),
+        };
+        buffer.path = Some(tree.relative(&new).to_path_buf());
+      }
+    }
+    self.message = Some(format!(
+      "\"{}\" -> \"{}\"",
+      tree.relative(&from).display(),
+      tree.relative(&to).display()
+    ));
+  }
+
+  /// `N%`: goes to the line N percent of the way through the buffer, on its first non-blank.
+  fn goto_percent(&mut self, percent: usize) {
+    if percent > 100 {
+      self.message = Some("Percentage must be at most 100".to_owned());
+      return;
+    }
+    let (buffer, window) = self.focused();
+    let len = buffer.lines.len();
+    // Rounded up, so that 1% of a short file is still its first line and 100% its last.
+    let line = (percent * len).div_ceil(100).clamp(1, len) - 1;
+    let indent = leading_whitespace(&buffer.lines[line]).chars().count();
+    window.cursor = Position::new(line, indent);
+    self.clamp_all();
+  }
+
+  /// `ga`: describes the character under the cursor, every code point of it if it is made of
+  /// several, like a letter with combining accents.
+  fn char_info(&mut self) {
+    let cursor = self.window().cursor;
+    let line = &self.buffer().lines[cursor.line];
+    let at = byte_index(line, cursor.col);
+    let Some(cluster) = line
+      .grapheme_indices(true)
+      .find(|&(i, g)| i <= at && at < i + g.len())
+      .map(|(_, g)| g)
+    else {
+      self.message = Some("NUL".to_owned());
+      return;
+    };
+    let described: Vec<String> = cluster
+      .chars()
+      .enumerate()
+      .map(|(i, c)| {
+        let shown = match c {
+          // Gives a combining mark something to combine with other than the bracket.
+          _ if i > 0 => format!(" {}", c),
+          _ if c.is_control() => format!("^{}", char::from_u32(c as u32 ^ 0x40).unwrap_or('?')),
+          _ => c.to_string(),
+        };
+        let code = c as u32;
+        let name = unicode::name(c).map_or(String::new(), |name| format!(" {}", name));
+        format!(
+          "<{}> U+{:04X}{} ({}, 0x{:02X})",
+          shown, code, name, code, code
+        )
+      })
+      .collect();
+    self.message = Some(described.join(", "));
+  }
+
+  /// `zz`, `zt` and `zb`: scrolls so the cursor line sits in the middle, at the top or at the
+  /// bottom of the window, as far as the start of the buffer and `scrollpastend` allow.
+  fn frame_cursor_line(&mut self, c: char) {
+    let past_end = self.options.scrollpastend;
+    let (buffer, window) = self.focused();
+    let limit = scroll_limit(
+      &buffer.folds,
+      buffer.lines.len() - 1,
+      window.height,
+      past_end,
+    );
+    let above = match c {
+      'z' => window.height.saturating_sub(1) / 2,
+      't' => 0,
+      _ => window.height.saturating_sub(1),
+    };
+    let before = window.scroll_offset;
+    window.scroll_offset = fold::rows_up(&buffer.folds, window.cursor.line, above).min(limit);
+    let delta = window.scroll_offset as isize - before as isize;
+    self.scroll_bound(delta);
+  }
+
+  fn handle_buffer_list(&mut self, key: KeyEvent, selected: usize) {
+    let last = self.buffers.len() - 1;
+    match key.code {
+      KeyCode::Char('j') | KeyCode::Down => self.buffer_list = Some((selected + 1).min(last)),
+      KeyCode::Char('k') | KeyCode::Up => self.buffer_list = Some(selected.saturating_sub(1)),
+      KeyCode::Enter => {
+        self.buffer_list = None;
+        self.switch_buffer(selected);
+      }
+      KeyCode::Esc | KeyCode::Char('q') => self.buffer_list = None,
+      _ => (),
+    }
+  }
+
+  /// Shows buffer `index` in the focused window, back where its cursor was last time.
+  fn switch_buffer(&mut self, index: usize) {
+    let current = self.window().buffer;
+    if current == index {
+      return;
+    }
+    self.buffers[current].last_cursor = self.window().cursor;
+    let cursor = self.buffers[index].last_cursor;
+    let window = &mut self.windows[self.focus];
+    window.buffer = index;
+    window.cursor = cursor;
+    window.scroll_offset = 0;
+    window.col_offset = 0;
+    self.left_buffer(current);
+    self.clamp_all();
+  }
+
+  /// `:b N`: switches to the buffer numbered N in `:ls`.
+  fn buffer_command(&mut self, arg: &str) -> Result<()> {
+    let n: usize = arg
+      .parse()
+      .map_err(|_| EditorError::Parse("Buffer number required".to_owned()))?;
+    if !(1..=self.buffers.len()).contains(&n) {
+      return Err(EditorError::command(format!("Buffer {} does not exist", n)));
+    }
+    self.switch_buffer(n - 1);
+    Ok(())
+  }
+
+  /// `:A`: switches to the file related to the buffer's, like its header or its tests, asking
+  /// which one if there are several.
+  fn alternate(&mut self) -> Result<()> {
+    let Some(path) = self.buffer().path.clone() else {
+      return Err(EditorError::command("The buffer has no file name"));
+    };
+    let mut found = alternate::find(&path, &self.config.alternates);
+    match found.len() {
+      0 => Err(EditorError::command(format!(
+        "No alternate file for \"{}\"",
+        path.display()
+      ))),
+      1 => self.open_alternate(&found.remove(0)),
+      _ => {
+        self.alternate_list = Some((found, 0));
+        Ok(())
+      }
+    }
+  }
+
+  fn open_alternate(&mut self, path: &Path) -> Result<()> {
+    let index = self.buffer_for(path)?;
+    self.switch_buffer(index);
+    Ok(())
+  }
+
+  fn handle_alternate_list(&mut self, key: KeyEvent, paths: Vec<PathBuf>, selected: usize) {
+    let last = paths.len() - 1;
+    let selected = match key.code {
+      KeyCode::Char('j') | KeyCode::Down => (selected + 1).min(last),
+      KeyCode::Char('k') | KeyCode::Up => selected.saturating_sub(1),
+      KeyCode::Enter => {
+        if let Err(e) = self.open_alternate(&paths[selected]) {
+          self.message = Some(e.to_string());
+        }
+        return;
+      }
+      KeyCode::Esc | KeyCode::Char('q') => return,
+      _ => selected,
+    };
+    self.alternate_list = Some((paths, selected));
+  }
+
+  /// The directory commands run in and relative paths are taken from: the focused buffer's
+  /// `:lcd` directory, or the working directory.
+  pub fn cwd(&self) -> PathBuf {
+    match &self.buffer().local_dir {
+      Some(dir) => dir.clone(),
+      None => env::current_dir().unwrap_or_default(),
+    }
+  }
+
+  /// A path typed in a command, with `~` for the home directory and relative to `cwd`.
+  fn resolve(&self, path: &str) -> PathBuf {
+    let path = expand_home(path);
+    match &self.buffer().local_dir {
+      Some(dir) if path.is_relative() => normalize(&dir.join(path)),
+      _ => path,
+    }
+  }
+
+  /// `:cd {dir}`: changes the working directory, to the home directory without `dir` or back
+  /// to the one before with `-`. Buffers keep their files: their paths are made relative to
+  /// the new directory if they are in it, and absolute if not.
+  fn change_dir(&mut self, arg: &str) -> Result<()> {
+    let old = env::current_dir().map_err(|e| EditorError::io("read", Path::new("."), e))?;
+    let dir = match arg {
+      "" => expand_home("~"),
+      "-" => self
+        .previous_dir
+        .clone()
+        .ok_or_else(|| EditorError::command("No previous directory"))?,
+      dir => self.resolve(dir),
+    };
+    env::set_current_dir(&dir).map_err(|e| EditorError::io("change to", &dir, e))?;
+    for buffer in &mut self.buffers {
+      if let Some(path) = &buffer.path {
+        buffer.path = Some(relative_to_cwd(&normalize(&old.join(path))));
+      }
+    }
+    self.previous_dir = Some(old);
+    self.message = Some(self.cwd().display().to_string());
+    Ok(())
+  }
+
+  /// `:lcd {dir}`: gives the focused buffer a working directory of its own.
+  fn local_dir(&mut self, arg: &str) -> Result<()> {
+    let dir = match arg {
+      "" => expand_home("~"),
+      dir => self.resolve(dir),
+    };
+    let dir = path::absolute(&dir).map_err(|e| EditorError::io("read", &dir, e))?;
+    if !dir.is_dir() {
+      return Err(EditorError::command(format!(
+        "\"{}\" is not a directory",
+        dir.display()
+      )));
+    }
+    self.message = Some(dir.display().to_string());
+    self.buffer_mut().local_dir = Some(normalize(&dir));
+    Ok(())
+  }
+
+  /// `:only`: closes every buffer but the current one, and the windows showing them.
+  fn only(&mut self, force: bool) {
+    let current = self.window().buffer;
+    let modified = (0..self.buffers.len())
+      .filter(|&i| i != current && self.buffers[i].modified)
+      .count();
+    if modified > 0 && !force {
+      self.message = Some(format!(
+        "{} other buffer(s) have unsaved changes:",
+        modified
+      ));
+      self.prompt = Some(Prompt::CloseModified);
+      return;
+    }
+    let buffer = self.buffers.swap_remove(current);
+    state::save_folds(&self.buffers);
+    self.buffers = vec![buffer];
+    for i in (0..self.windows.len()).rev() {
+      if self.windows[i].buffer != current {
+        self.windows.remove(i);
+        self.layout.remove(i);
+        if i < self.focus {
+          self.focus -= 1;
+        }
+      }
+    }
+    for window in &mut self.windows {
+      window.buffer = 0;
+    }
+    self.alternate = None;
+    self.clamp_all();
+  }
+
+  /// The `z` fold commands.
+  fn fold_command(&mut self, c: char) {
+    if c == 'p' {
+      return self.peek();
+    }
+    let (buffer, window) = self.focused();
+    let (folds, line) = (&mut buffer.folds, window.cursor.line);
+    let found = match c {
+      'a' => fold::toggle(folds, line),
+      'o' => fold::set_closed(folds, line, false),
+      'c' => fold::set_closed(folds, line, true),
+      'd' => {
+        let manual = folds
+          .iter()
+          .enumerate()
+          .filter(|(_, f)| f.kind == FoldKind::Manual && f.contains(line))
+          .min_by_key(|(_, f)| f.end - f.start)
+          .map(|(i, _)| i);
+        manual.map(|i| folds.remove(i)).is_some()
+      }
+      'R' | 'M' => {
+        if c == 'R' {
+          folds.retain(|f| f.kind != FoldKind::Search);
+        }
+        folds.iter_mut().for_each(|f| f.closed = c == 'M');
+        true
+      }
+      _ => true,
+    };
+    if !found {
+      self.message = Some("No fold found".to_owned());
+    }
+    self.clamp_all();
+  }
+
+  fn handle_insert(&mut self, key: KeyEvent) {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    if !self.cursors.is_empty() && self.type_at_cursors(key) {
+      return;
+    }
+    if self.insert_completion.is_some() && self.insert_completion_key(key) {
+      return;
+    }
+    if std::mem::take(&mut self.ctrl_x) && ctrl && key.code == KeyCode::Char('f') {
+      self.complete_path();
+      return;
+    }
+    let smarttab = self.options.smarttab;
+    let (buffer, window) = self.focused();
+    match key.code {
+      KeyCode::Char('x') if ctrl => self.ctrl_x = true,
+      KeyCode::Esc => {
+        self.finish_block_insert();
+        self.signature = None;
+        self.mode = Mode::Normal;
+        self.clamp_cursor();
+      }
+      KeyCode::Enter => {
+        if self.options.undobreak.newline {
+          self.commit_undo();
+        }
+        self.newline();
+      }
+      KeyCode::Tab => {
+        let text = match buffer.options.expandtab {
+          true => {
+            let tabstop = buffer.options.tabstop.max(1);
+            let line = &buffer.lines[window.cursor.line];
+            let x = ui::render_col(line, window.cursor.col, tabstop);
+            " ".repeat(tabstop - x % tabstop)
+          }
+          false => "\t".to_owned(),
+        };
+        window.cursor = buffer.insert(window.cursor, &text);
+      }
+      KeyCode::Backspace => {
+        let cursor = window.cursor;
+        let tabstop = buffer.options.tabstop.max(1);
+        let in_indent = buffer.lines[cursor.line]
+          .chars()
+          .take(cursor.col)
+          .all(|c| c == ' ');
+        if cursor.col > 0 && in_indent && smarttab {
+          // Back to the previous tab stop, as if the spaces were a tab.
+          let start = Position::new(cursor.line, (cursor.col - 1) / tabstop * tabstop);
+          buffer.delete(start, cursor);
+          window.cursor = start;
+        } else if cursor.col > 0 {
+          let start = Position::new(cursor.line, cursor.col - 1);
+          buffer.delete(start, cursor);
+          window.cursor = start;
+        } else if cursor.line > 0 {
+          let start = Position::new(cursor.line - 1, buffer.line_len(cursor.line - 1));
+          buffer.delete(start, cursor);
+          window.cursor = start;
+        }
+      }
+      KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down => {
+        if self.options.undobreak.jump {
+          self.commit_undo();
+        }
+        match key.code {
+          KeyCode::Left => self.move_cursor(0, -1),
+          KeyCode::Right => self.move_cursor(0, 1),
+          KeyCode::Up => self.move_cursor(-1, 0),
+          _ => self.move_cursor(1, 0),
+        }
+      }
+      KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+        self.type_char(c);
+        self.format_on_type(c);
+        self.signature_help(c);
+      }
+      _ => (),
+    }
+  }
+
+  /// `Ctrl+X Ctrl+F`: completes the file path before the cursor, relative to the buffer's
+  /// directory.
+  fn complete_path(&mut self) {
+    let (buffer, window) = self.focused();
+    let cursor = window.cursor;
+    let before: Vec<char> = buffer.lines[cursor.line].chars().take(cursor.col).collect();
+    let is_path_char = |c: char| c.is_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | '+');
+    let start = before
+      .iter()
+      .rposition(|&c| !is_path_char(c))
+      .map_or(0, |i| i + 1);
+    let typed: String = before[start..].iter().collect();
+    let base = buffer
+      .path
+      .as_deref()
+      .and_then(Path::parent)
+      .filter(|dir| !dir.as_os_str().is_empty())
+      .unwrap_or(Path::new("."));
+    let candidates = complete::files(&typed, base);
+    if candidates.is_empty() {
+      self.message = Some("No matching files".to_owned());
+      return;
+    }
+    self.show_insert_completion(Completion {
+      start,
+      candidates,
+      index: 0,
+    });
+  }
+
+  /// Puts the current candidate of `completion` in place of the word being completed. A single
+  /// candidate is simply accepted.
+  fn show_insert_completion(&mut self, completion: Completion) {
+    let (buffer, window) = self.focused();
+    let start = Position::new(window.cursor.line, completion.start);
+    buffer.delete(start, window.cursor);
+    window.cursor = buffer.insert(start, &completion.candidates[completion.index]);
+    self.insert_completion = match completion.candidates.len() > 1 {
+      true => Some(completion),
+      false => None,
+    };
+  }
+
+  /// Keys while the insert completion popup is open. Returns false for keys that close it and
+  /// then do what they normally do.
+  fn insert_completion_key(&mut self, key: KeyEvent) -> bool {
+    let Some(mut completion) = self.insert_completion.take() else {
+      return false;
+    };
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let len = completion.candidates.len();
+    let accept = key.code == KeyCode::Enter || (ctrl && key.code == KeyCode::Char('y'));
+    match key.code {
+      // Accepting a directory goes straight on to completing inside it.
+      _ if accept => {
+        if completion.candidates[completion.index].ends_with('/') {
+          self.complete_path();
+        }
+        return true;
+      }
+      KeyCode::Char('n' | 'f') if ctrl => completion.index = (completion.index + 1) % len,
+      KeyCode::Down | KeyCode::Tab => completion.index = (completion.index + 1) % len,
+      KeyCode::Char('p') if ctrl => completion.index = (completion.index + len - 1) % len,
+      KeyCode::Up | KeyCode::BackTab => completion.index = (completion.index + len - 1) % len,
+      _ => return false,
+    }
+    self.show_insert_completion(completion);
+    true
+  }
+
+  /// Inserts pasted text exactly as given, bypassing autoindent and autopairs.
+  pub fn paste(&mut self, text: &str) {
+    self.last_input = Instant::now();
+    if let Some(shell) = self.shell.as_mut().filter(|_| self.shell_focused) {
+      if let Err(e) = shell.paste(text) {
+        self.message = Some(format!("Can't write to the terminal: {}", e));
+      }
+      return;
+    }
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    if self.mode.on_command_line() {
+      self
+        .command_line
+        .push_str(text.lines().next().unwrap_or(""));
+      return;
+    }
+    // Keep the paste in a group of its own, apart from anything typed around it.
+    self.commit_undo();
+    let (buffer, window) = self.focused();
+    window.cursor = buffer.insert(window.cursor, &text);
+    self.commit_undo();
+    self.clamp_cursor();
+  }
+
+  /// Splits the line at the cursor, carrying its indent over with `autoindent`.
+  fn newline(&mut self) {
+    let smartindent = self.options.smartindent && !self.options.paste;
+    let autoindent = (self.options.autoindent || smartindent) && !self.options.paste;
+    let (buffer, window) = self.focused();
+    let line = &buffer.lines[window.cursor.line];
+    let (before, after) = line.split_at(byte_index(line, window.cursor.col));
+    let mut indent = String::new();
+    if autoindent {
+      indent.push_str(leading_whitespace(line));
+    }
+    let opened = smartindent && before.trim_end().ends_with('{');
+    if !opened {
+      window.cursor = buffer.insert(window.cursor, &format!("\n{}", indent));
+      return;
+    }
+    let tabstop = buffer.options.tabstop.max(1);
+    let width = ui::render_col(&indent, indent.chars().count(), tabstop);
+    let inner = indent_to(width + tabstop, tabstop, buffer.options.expandtab);
+    // Between a pair of braces, the closer goes on a line of its own below the cursor.
+    let text = match after.trim_start().starts_with('}') {
+      true => format!("\n{}\n{}", inner, indent),
+      false => format!("\n{}", inner),
+    };
+    let at = window.cursor;
+    buffer.insert(at, &text);
+    window.cursor = Position::new(at.line + 1, inner.chars().count());
+  }
+
+  /// With `smartindent`, a `}` typed as the first thing on a line takes one indent level off it.
+  fn smart_dedent(&mut self) {
+    let (buffer, window) = self.focused();
+    let line = &buffer.lines[window.cursor.line];
+    let indent = leading_whitespace(line);
+    let len = indent.chars().count();
+    if len == 0 || len != window.cursor.col {
+      return;
+    }
+    let tabstop = buffer.options.tabstop.max(1);
+    let width = ui::render_col(line, len, tabstop);
+    let dedented = indent_to(
+      (width - 1) / tabstop * tabstop,
+      tabstop,
+      buffer.options.expandtab,
+    );
+    let line_no = window.cursor.line;
+    buffer.delete(Position::new(line_no, 0), Position::new(line_no, len));
+    window.cursor = buffer.insert(Position::new(line_no, 0), &dedented);
+  }
+
+  /// Inserts a typed char. With `autopairs`, openers get their closer and typing a closer that
+  /// is already next to the cursor steps over it.
+  fn type_char(&mut self, c: char) {
+    let autopairs = self.options.autopairs && !self.options.paste;
+    let (buffer, window) = self.focused();
+    let next = buffer.lines[window.cursor.line]
+      .chars()
+      .nth(window.cursor.col);
+    if autopairs && next == Some(c) && matches!(c, ')' | ']' | '}' | '"' | '\'') {
+      window.cursor.col += 1;
+      return;
+    }
+    if c == '}' && self.options.smartindent && !self.options.paste {
+      self.smart_dedent();
+    }
+    let (buffer, window) = self.focused();
+    window.cursor = buffer.insert(window.cursor, c.encode_utf8(&mut [0; 4]));
+    let closer = match c {
+      '(' => ")",
+      '[' => "]",
+      '{' => "}",
+      '"' => "\"",
+      '\'' => "'",
+      _ => return,
+    };
+    if autopairs {
+      buffer.insert(window.cursor, closer);
+    }
+  }
+
+  fn handle_command(&mut self, key: KeyEvent) {
+    let search = self.mode == Mode::Search;
+    if !matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+      self.completion = None;
+    }
+    let history = match search {
+      true => &mut self.search_history,
+      false => &mut self.command_history,
+    };
+    match key.code {
+      KeyCode::Esc => {
+        history.reset();
+        self.mode = Mode::Normal;
+      }
+      KeyCode::Enter => {
+        self.mode = Mode::Normal;
+        let line = std::mem::take(&mut self.command_line);
+        history.push(&line);
+        match search {
+          true => self.search(&line),
+          false => self.execute_typed(line.trim_start()),
+        }
+      }
+      KeyCode::Tab | KeyCode::BackTab if !search => self.complete(key.code == KeyCode::Tab),
+      KeyCode::Up => {
+        if let Some(line) = history.older(&self.command_line) {
+          self.command_line = line;
+        }
+      }
+      KeyCode::Down => {
+        if let Some(line) = history.newer() {
+          self.command_line = line;
+        }
+      }
+      KeyCode::Backspace if self.command_line.is_empty() => {
+        history.reset();
+        self.mode = Mode::Normal;
+      }
+      KeyCode::Backspace => {
+        history.reset();
+        self.command_line.pop();
+      }
+      KeyCode::Char(c) => {
+        history.reset();
+        self.command_line.push(c);
+      }
+      _ => (),
+    }
+  }
+
+  /// Tab on the command line: completes the word being typed, or moves on to the next candidate.
+  fn complete(&mut self, forward: bool) {
+    let completion = match self.completion.take() {
+      Some(mut completion) => {
+        let len = completion.candidates.len();
+        completion.index = match forward {
+          true => (completion.index + 1) % len,
+          false => (completion.index + len - 1) % len,
+        };
+        completion
+      }
+      None => match complete::complete(&self.command_line) {
+        Some(mut completion) => {
+          if !forward {
+            completion.index = completion.candidates.len() - 1;
+          }
+          completion
+        }
+        None => return,
+      },
+    };
+    self.command_line.truncate(completion.start);
+    self
+      .command_line
+      .push_str(&completion.candidates[completion.index]);
+    // A single candidate is simply accepted, so the next Tab can complete further into it.
+    if completion.candidates.len() > 1 {
+      self.completion = Some(completion);
+    }
+  }
+
+  /// Runs a `/` search. An empty pattern repeats the last one, and nothing at all its offset as
+  /// well.
+  fn search(&mut self, input: &str) {
+    if !input.is_empty() {
+      let (pattern, offset) = match search::parse(input) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+          self.message = Some(e);
+          return;
+        }
+      };
+      if !pattern.is_empty() {
+        self.last_search = Some(pattern);
+      }
+      self.search_offset = offset;
+    }
+    self.search_next(true);
+  }
+
+  /// `n` and `N`: goes to the next or previous match of the last search.
+  fn search_next(&mut self, forward: bool) {
+    let Some(pattern) = self.last_search.clone() else {
+      self.message = Some("No previous search pattern".to_owned());
+      return;
+    };
+    let offset = self.search_offset;
+    let origin = self.search_origin;
+    let (buffer, window) = self.focused();
+    let from = match origin {
+      Some((left, at)) if left == window.cursor => at,
+      _ => window.cursor,
+    };
+    match search::find(&buffer.lines, from, &pattern, forward) {
+      Some((pos, wrapped)) => {
+        window.cursor = search::apply(&buffer.lines, pos, pattern.chars().count(), offset);
+        let count = search::count(&buffer.lines, &pattern, pos);
+        self.search_origin = Some((window.cursor, pos));
+        self.search_count = Some(count);
+        if wrapped {
+          self.message = Some(match forward {
+            true => "Search hit BOTTOM, continuing at TOP".to_owned(),
+            false => "Search hit TOP, continuing at BOTTOM".to_owned(),
+          });
+        }
+      }
+      None => {
+        self.search_count = None;
+        self.message = Some(format!("Pattern not found: {}", pattern));
+      }
+    }
+    self.clamp_all();
+  }
+
+  /// Runs an ex command typed on the command line. With `confirm` set, one that changes more
+  /// than `confirmlines` lines waits for a yes first.
+  fn execute_typed(&mut self, command: &str) {
+    let changed = match self.options.confirm && !self.feeding {
+      true => self.lines_changed(command),
+      false => None,
+    };
+    if let Some(n) = changed.filter(|&n| n > self.options.confirmlines) {
+      self.message = Some(format!(":{} changes {} lines. Run it?", command, n));
+      self.confirming = Some(command.to_owned());
+      self.prompt = Some(Prompt::Confirm);
+      return;
+    }
+    if let Err(e) = self.execute(command) {
+      self.message = Some(e.to_string());
+    }
+  }
+
+  /// How many lines ex command `command` would change: those `:s` substitutes on, those `:g`
+  /// and `:v` run their command on, and the range of `:d`, `:>`, `:<`, `:normal` and
+  /// `:retab`. `None` for other commands.
+  fn lines_changed(&self, command: &str) -> Option<usize> {
+    let lines = &self.buffer().lines;
+    if let Some(sub) = Substitute::parse(command) {
+      let (first, last) = self.range_lines(&sub.range).ok()?;
+      let changed = (first..=last).filter(|&l| !sub.apply(&lines[l]).1.is_empty());
+      return Some(changed.count());
+    }
+    let (range, rest) = range::split(command);
+    let (first, last) = match range {
+      "" if parse_global(rest).is_some() => (0, lines.len() - 1),
+      range => self.range_lines(range).ok()?,
+    };
+    if let Some((matching, pattern, command)) = parse_global(rest) {
+      let pattern = match pattern.as_str() {
+        "" => self.last_search.clone()?,
+        _ => pattern,
+      };
+      let on = (first..=last).filter(|&l| lines[l].contains(&pattern) == matching);
+      return (!command.trim().is_empty()).then(|| on.count());
+    }
+    let name = rest.split_once(' ').map_or(rest, |(name, _)| name);
+    let shift =
+      !name.is_empty() && (name.chars().all(|c| c == '>') || name.chars().all(|c| c == '<'));
+    let changes = shift
+      || matches!(
+        name,
+        "d"
+          | "de"
+          | "delete"
+          | "ret"
+          | "retab"
+          | "ret!"
+          | "retab!"
+          | "sor"
+          | "sort"
+          | "stripctrl"
+          | "wrap"
+          | "norm"
+          | "normal"
+          | "norm!"
+          | "normal!"
+      );
+    changes.then_some(last - first + 1)
+  }
+
+  /// Runs an ex command. Failures come back as errors, for the caller to report.
+  fn execute(&mut self, command: &str) -> Result<()> {
+    if let Some(sub) = Substitute::parse(command) {
+      return self.substitute(&sub);
+    }
+    let (range, rest) = range::split(command);
+    if let Some((matching, pattern, command)) = parse_global(rest) {
+      return self.global(range, matching, &pattern, command);
+    }
+    if let Some(shell) = rest.strip_prefix('!') {
+      if !range.is_empty() {
+        return Err(EditorError::Parse("No range allowed".to_owned()));
+      }
+      self.run_job(shell.trim());
+      return Ok(());
+    }
+    let (name, arg) = match rest.split_once(' ') {
+      Some((name, arg)) => (name, arg.trim()),
+      None => (rest, ""),
+    };
+    match name {
+      "fo" | "fold" if arg.starts_with('/') => return self.fold_matching(range, arg),
+      "fo" | "fold" => return self.create_fold(range),
+      "ret" | "retab" => return self.retab(range, false),
+      "align" => return self.align(range, arg),
+      "wrap" => return self.wrap(range, arg),
+      "sor" | "sort" => return self.sort(range, arg),
+      "stripctrl" => return self.strip_control(range),
+      "send" => return self.send_to_shell(range),
+      "ret!" | "retab!" => return self.retab(range, true),
+      "d" | "de" | "delete" => return self.delete_range(range, arg),
+      // Spaces in the keys count, even at the end.
+      "norm" | "normal" | "norm!" | "normal!" => {
+        let keys = rest.split_once(' ').map_or("", |(_, keys)| keys);
+        return self.normal(range, keys, !name.ends_with('!'));
+      }
+      "y" | "ya" | "yank" => return self.yank_range(range, arg),
+      _ if !name.is_empty() && name.chars().all(|c| c == '>') => {
+        return self.shift_range(range, name.len() as isize)
+      }
+      _ if !name.is_empty() && name.chars().all(|c| c == '<') => {
+        return self.shift_range(range, -(name.len() as isize))
+      }
+      // A range alone goes to its last line.
+      "" if !range.is_empty() => {
+        let (_, last) = self.range_lines(range)?;
+        self.goto_line(last + 1);
+      }
+      _ if !range.is_empty() => return Err(EditorError::Parse("No range allowed".to_owned())),
+      "" => (),
+      "w" | "w!" | "wq" | "x" if arg == "-" || (arg.is_empty() && self.to_stdout) => {
+        self.write_stdout();
+        if matches!(name, "wq" | "x") {
+          self.quit(false);
+        }
+      }
+      "w" | "w!" if arg.is_empty() && self.buffer().path.is_none() => self.ask_file_name(name),
+      "w" => match arg {
+        "" => self.save(),
+        path => self.save_as(&self.resolve(path), false)?,
+      },
+      "w!" => self.save_as(&self.resolve(arg), true)?,
+      "q" => self.quit(false),
+      "q!" => self.quit(true),
+      "qa" | "qall" | "quita" | "quitall" => self.quit_all(false),
+      "qa!" | "qall!" | "quita!" | "quitall!" => self.quit_all(true),
+      "wqa" | "wqall" | "xa" | "xall" => self.write_quit_all()?,
+      "wq" | "x" if arg.is_empty() && self.buffer().path.is_none() => self.ask_file_name(name),
+      "wq" | "x" => {
+        match arg {
+          "" => self.save(),
+          path => self.save_as(&self.resolve(path), false)?,
+        }
+        if self.prompt.is_none() && !self.buffer().modified {
+          self.quit(false);
+        }
+      }
+      "new" => {
+        let mut buffer = Buffer::default();
+        self.setup_buffer(&mut buffer);
+        self.buffers.push(buffer);
+        self.switch_buffer(self.buffers.len() - 1);
+      }
+      "ene" | "enew" if self.buffer().modified => {
+        return Err(EditorError::command(
+          "No write since last change (add ! to override)",
+        ))
+      }
+      "ene" | "enew" | "ene!" | "enew!" => {
+        let mut buffer = Buffer::default();
+        self.setup_buffer(&mut buffer);
+        let current = self.window().buffer;
+        self.buffers[current] = buffer;
+        for window in self.windows.iter_mut().filter(|w| w.buffer == current) {
+          *window = Window::new(current, window.options.clone());
+        }
+      }
+      "e!" => self.reload(),
+      "vs" | "vsplit" => self.split(arg, true)?,
+      "sp" | "split" => self.split(arg, false)?,
+      "backups" => self.list_backups(),
+      "blame" => self.toggle_blame()?,
+      "mak" | "make" => match arg {
+        "" => self.run_job("make"),
+        arg => self.run_job(&format!("make {}", arg)),
+      },
+      "cope" | "copen" => match self.output {
+        Some(_) => {
+          self.output_open = true;
+          self.output_focused = true;
+        }
+        None => return Err(EditorError::command("No command output yet")),
+      },
+      "ter" | "terminal" => self.open_shell()?,
+      "ccl" | "cclose" => {
+        self.output_open = false;
+        self.output_focused = false;
+      }
+      "ls" | "buffers" => self.buffer_list = Some(self.window().buffer),
+      "undotree" => self.list_undo_states(),
+      "symbols" => self.list_symbols(),
+      "noh" | "nohlsearch" => self.search_count = None,
+      "reg" | "registers" | "di" | "display" => {
+        let names: Vec<char> = self
+          .registers
+          .list()
+          .iter()
+          .map(|(name, _)| *name)
+          .collect();
+        if names.is_empty() {
+          return Err(EditorError::command("All registers are empty"));
+        }
+        self.register_list = Some((names, 0));
+      }
+      "A" | "alternate" => self.alternate()?,
+      "rename" => self.rename(arg)?,
+      "ea" | "earlier" => self.travel(arg, false)?,
+      "lat" | "later" => self.travel(arg, true)?,
+      "b" | "buffer" => self.buffer_command(arg)?,
+      "cd" | "chdir" => self.change_dir(arg)?,
+      "lcd" | "lchdir" => self.local_dir(arg)?,
+      "pw" | "pwd" => self.message = Some(self.cwd().display().to_string()),
+      "on" | "only" => self.only(false),
+      "tree" => {
+        let dir = self.cwd();
+        self.tree.get_or_insert_with(|| FileTree::new(dir));
+        self.tree_focused = true;
+      }
+      "se" | "set" => {
+        for option in arg.split_whitespace() {
+          // Buffer and window settings change for this buffer or window and become the
+          // default for new ones.
+          if self.options.buffer.has(option) {
+            self
+              .buffer_mut()
+              .options
+              .set(option)
+              .and_then(|()| self.options.set_default(option))
+          } else if self.options.window.has(option) {
+            self.windows[self.focus]
+              .options
+              .set(option)
+              .and_then(|()| self.options.set(option))
+          } else {
+            self.options.set(option)
+          }
+          .map_err(EditorError::Parse)?;
+        }
+      }
+      _ => {
+        return Err(EditorError::Parse(format!(
+          "Not an editor command: {}",
+          command
+        )))
+      }
+    }
+    Ok(())
+  }
+
+  /// `:{range}fold`: folds the lines of `range`, by default just the cursor line.
+  fn create_fold(&mut self, range: &str) -> Result<()> {
+    let (start, end) = self.range_lines(range)?;
+    self.add_fold(start, end)
+  }
+
+  /// `zf` and a motion or text object, or `zf` in visual mode: folds the lines `extent` spans.
+  /// As with vim's exclusive motions, one that ends at the start of a line leaves that line
+  /// out, so `zf}` stops short of the blank line.
+  fn fold_extent(&mut self, extent: Extent) {
+    let (start, end) = match extent {
+      Extent::Lines(start, end) => (start, end),
+      Extent::Chars(start, end) if end.col == 0 && end.line > start.line => {
+        (start.line, end.line - 1)
+      }
+      Extent::Chars(start, end) => (start.line, end.line),
+    };
+    if let Err(e) = self.add_fold(start, end) {
+      self.message = Some(e.to_string());
+    }
+  }
+
+  /// `zp`: opens the closed fold under the cursor until the cursor leaves it, or closes the
+  /// one it opened again.
+  fn peek(&mut self) {
+    if self.peek.is_some() {
+      return self.end_peek(true);
+    }
+    let buffer_index = self.window().buffer;
+    let (buffer, window) = self.focused();
+    let line = window.cursor.line;
+    let fold = buffer
+      .folds
+      .iter_mut()
+      .filter(|f| f.closed && f.contains(line))
+      .min_by_key(|f| f.end - f.start);
+    match fold {
+      Some(fold) => {
+        fold.closed = false;
+        self.peek = Some((buffer_index, fold.start, fold.end));
+      }
+      None => self.message = Some("No closed fold here".to_owned()),
+    }
+  }
+
+  /// Closes the fold `zp` opened once the cursor has left it, or with `now` at once.
+  fn end_peek(&mut self, now: bool) {
+    let Some((index, start, end)) = self.peek else {
+      return;
+    };
+    let window = self.window();
+    let inside = window.buffer == index && (start..=end).contains(&window.cursor.line);
+    if inside && !now {
+      return;
+    }
+    self.peek = None;
+    let folds = self.buffers.get_mut(index).map(|b| &mut b.folds);
+    // A fold an edit has moved or removed since is left as it is.
+    if let Some(fold) = folds
+      .into_iter()
+      .flatten()
+      .find(|f| (f.start, f.end) == (start, end))
+    {
+      fold.closed = true;
+    }
+    self.clamp_all();
+  }
+
+  /// Adds a closed manual fold over `start..=end`, with the cursor on its first line.
+  fn add_fold(&mut self, start: usize, end: usize) -> Result<()> {
+    if start == end {
+      return Err(EditorError::command("A fold needs at least two lines"));
+    }
+    let (buffer, window) = self.focused();
+    let mut new = Fold::new(start, end, FoldKind::Manual);
+    new.closed = true;
+    buffer.folds.push(new);
+    window.cursor = Position::new(start, 0);
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `:{range}fold /pattern/`: folds away the lines of `range`, by default the whole buffer,
+  /// that don't contain `pattern`, leaving the matches and a line around each in view. An
+  /// empty pattern is the last search.
+  fn fold_matching(&mut self, range: &str, arg: &str) -> Result<()> {
+    let pattern = arg[1..].strip_suffix('/').unwrap_or(&arg[1..]);
+    let pattern = match pattern {
+      "" => self
+        .last_search
+        .clone()
+        .ok_or_else(|| EditorError::command("No previous search pattern"))?,
+      pattern => pattern.to_owned(),
+    };
+    let (start, end) = match range {
+      "" => (0, self.buffer().lines.len() - 1),
+      range => self.range_lines(range)?,
+    };
+    let buffer = self.buffer_mut();
+    let matches = buffer.lines[start..=end]
+      .iter()
+      .filter(|l| l.contains(&pattern))
+      .count();
+    if matches == 0 {
+      return Err(EditorError::command(format!(
+        "Pattern not found: {}",
+        pattern
+      )));
+    }
+    buffer.folds.retain(|f| f.kind != FoldKind::Search);
+    let folds = fold::search_folds(&buffer.lines, &pattern, start, end);
+    buffer.folds.extend(folds);
+    self.message = Some(format!("{} matching line(s)", matches));
+    self.last_search = Some(pattern);
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// While a range alone, like the `42` of `:42`, is being typed, where the focused window
+  /// would scroll to show the line it goes to in its middle. `None` while that line is in
+  /// view anyway.
+  pub fn line_preview(&self) -> Option<usize> {
+    if self.mode != Mode::Command {
+      return None;
+    }
+    let (range, rest) = range::split(self.command_line.trim_start());
+    if range.is_empty() || !rest.is_empty() {
+      return None;
+    }
+    let (_, line) = self.range_lines(range).ok()?;
+    let (buffer, window) = (self.buffer(), self.window());
+    let folds = &buffer.folds;
+    let rows = fold::rows_between(folds, window.scroll_offset, line);
+    if line >= window.scroll_offset && rows < window.height {
+      return None;
+    }
+    Some(fold::rows_up(
+      folds,
+      line,
+      window.height.saturating_sub(1) / 2,
+    ))
+  }
+
+  /// The first and last line of a range such as `3,$`, or of the cursor line if it is empty.
+  /// `'<` and `'>` are the first and last line of the last selection.
+  pub fn range_lines(&self, range: &str) -> Result<(usize, usize)> {
+    let selection = self.last_selection();
+    let buffer = self.buffer();
+    let mark = |name| match name {
+      '<' => selection.map(|s| s.start.line),
+      '>' => selection.map(|s| s.end.line),
+      name => buffer.marks.get(&name).map(|pos| pos.line),
+    };
+    let cursor = self.window().cursor.line;
+    range::lines(range, cursor, buffer.lines.len() - 1, mark).map_err(EditorError::Parse)
+  }
+
+  /// `:{range}g/pattern/command`: runs `command` on each line of `range`, all of them by
+  /// default, that contains `pattern`, or unless `matching` on each line that doesn't. The
+  /// lines are picked before any of them change. Without a command it counts them.
+  fn global(&mut self, range: &str, matching: bool, pattern: &str, command: &str) -> Result<()> {
+    if self.in_global {
+      return Err(EditorError::command("Cannot do :global inside :global"));
+    }
+    let pattern = match pattern {
+      "" => self
+        .last_search
+        .clone()
+        .ok_or_else(|| EditorError::command("No previous search pattern"))?,
+      pattern => pattern.to_owned(),
+    };
+    let (first, last) = match range {
+      "" => (0, self.buffer().lines.len() - 1),
+      range => self.range_lines(range)?,
+    };
+    let lines: Vec<usize> = (first..=last)
+      .filter(|&l| self.buffer().lines[l].contains(&pattern) == matching)
+      .collect();
+    if lines.is_empty() {
+      return Err(EditorError::command(format!(
+        "Pattern not found: {}",
+        pattern
+      )));
+    }
+    let command = command.trim_start();
+    if command.is_empty() {
+      self.message = Some(format!("{} matching line(s)", lines.len()));
+      self.last_search = Some(pattern);
+      return Ok(());
+    }
+    self.in_global = true;
+    // Lines the command adds or takes away move the ones after it.
+    let mut shift = 0isize;
+    let mut error = None;
+    for line in lines {
+      let len = self.buffer().lines.len();
+      let Some(line) = line.checked_add_signed(shift).filter(|&line| line < len) else {
+        break;
+      };
+      self.windows[self.focus].cursor = Position::new(line, 0);
+      if let Err(e) = self.execute(command) {
+        error.get_or_insert(e);
+      }
+      shift += self.buffer().lines.len() as isize - len as isize;
+    }
+    self.in_global = false;
+    self.last_search = Some(pattern);
+    self.clamp_all();
+    error.map_or(Ok(()), Err)
+  }
+
+  /// `:{range}d [x]`: deletes the lines of `range` into register `x` or the unnamed one.
+  fn delete_range(&mut self, range: &str, register: &str) -> Result<()> {
+    let selection = self.range_selection(range, register)?;
+    self.delete_selection(selection);
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `:{range}y [x]`: yanks the lines of `range` into register `x` or the unnamed one.
+  fn yank_range(&mut self, range: &str, register: &str) -> Result<()> {
+    let selection = self.range_selection(range, register)?;
+    self.yank(selection);
+    Ok(())
+  }
+
+  /// The lines of `range` as a selection, with the register named by `register` picked for
+  /// the command on them.
+  fn range_selection(&mut self, range: &str, register: &str) -> Result<Selection> {
+    let (first, last) = self.range_lines(range)?;
+    let mut name = register.chars();
+    self.register_name = match (name.next(), name.next()) {
+      (None, _) => None,
+      (Some(c), None) if register::valid(c) => Some(c),
+      _ => {
+        return Err(EditorError::Parse(format!(
+          "Invalid register: {}",
+          register
+        )))
+      }
+    };
+    Ok(Selection::new(
+      VisualKind::Line,
+      Position::new(first, 0),
+      Position::new(last, 0),
+    ))
+  }
+
+  /// `:{range}>` and `:{range}<`: indents the lines of `range` by `levels` tab stops, or for a
+  /// negative `levels` takes that many away. Empty lines are left alone.
+  fn shift_range(&mut self, range: &str, levels: isize) -> Result<()> {
+    let (first, last) = self.range_lines(range)?;
+    let buffer = self.buffer_mut();
+    let tabstop = buffer.options.tabstop.max(1);
+    let expandtab = buffer.options.expandtab;
+    for l in first..=last {
+      let line = &buffer.lines[l];
+      if line.trim().is_empty() {
+        continue;
+      }
+      let old = leading_whitespace(line);
+      let width = ui::render_col(line, old.chars().count(), tabstop);
+      let width = (width as isize + levels * tabstop as isize).max(0) as usize;
+      let indent = indent_to(width, tabstop, expandtab);
+      if indent != old {
+        let end = Position::new(l, old.chars().count());
+        buffer.delete(Position::new(l, 0), end);
+        buffer.insert(Position::new(l, 0), &indent);
+      }
+    }
+    let lines = last - first + 1;
+    self.message = Some(format!(
+      "{} line(s) {}ed {} time(s)",
+      lines,
+      if levels > 0 { '>' } else { '<' },
+      levels.unsigned_abs()
+    ));
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `:{range}retab`: turns leading tabs into spaces, or with `!` leading spaces into tabs
+  /// where they fill a whole tab stop. Works on the whole buffer unless given a range.
+  fn retab(&mut self, range: &str, to_tabs: bool) -> Result<()> {
+    let (start, end) = match range {
+      "" => (0, self.buffer().lines.len() - 1),
+      range => self.range_lines(range)?,
+    };
+    let buffer = self.buffer_mut();
+    let tabstop = buffer.options.tabstop.max(1);
+    let mut changed = 0;
+    for line in start..=end {
+      let text = &buffer.lines[line];
+      let indent = leading_whitespace(text);
+      let len = indent.chars().count();
+      let width = ui::render_col(text, len, tabstop);
+      let new = indent_to(width, tabstop, !to_tabs);
+      if new != indent {
+        buffer.delete(Position::new(line, 0), Position::new(line, len));
+        buffer.insert(Position::new(line, 0), &new);
+        changed += 1;
+      }
+    }
+    self.message = Some(format!("{} line(s) retabbed", changed));
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `:{range}sort [flags]`: sorts the lines of `range`, all of them by default. With `i` case
+  /// is ignored, and with `u` only the first of lines that are the same is kept.
+  fn sort(&mut self, range: &str, flags: &str) -> Result<()> {
+    let (mut unique, mut ignore_case) = (false, false);
+    for flag in flags.chars().filter(|c| !c.is_whitespace()) {
+      match flag {
+        'u' => unique = true,
+        'i' => ignore_case = true,
+        _ => return Err(EditorError::Parse(format!("Invalid flag: {}", flag))),
+      }
+    }
+    let (start, end) = match range {
+      "" => (0, self.buffer().lines.len() - 1),
+      range => self.range_lines(range)?,
+    };
+    let buffer = self.buffer_mut();
+    let key = |line: &String| match ignore_case {
+      true => line.to_lowercase(),
+      false => line.clone(),
+    };
+    // The sort is stable, so the first of equal lines is still the first.
+    let mut lines = buffer.lines[start..=end].to_vec();
+    lines.sort_by_cached_key(key);
+    if unique {
+      lines.dedup_by(|line, before| key(line) == key(before));
+    }
+    let removed = end - start + 1 - lines.len();
+    if lines[..] != buffer.lines[start..=end] {
+      let last = Position::new(end, buffer.line_len(end));
+      buffer.delete(Position::new(start, 0), last);
+      buffer.insert(Position::new(start, 0), &lines.join("\n"));
+    }
+    self.message = Some(match removed {
+      0 => format!("{} line(s) sorted", lines.len()),
+      n => format!("{} line(s) sorted, {} duplicate(s) removed", lines.len(), n),
+    });
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `:{range}wrap {text}`: puts the last selection, if `range` is `range::VISUAL`, or else the
+  /// lines of `range` after their indent between `text` and what closes it. `foo(` wraps them
+  /// in a call and `<div>` in a tag. Other text is a call in code and a tag in markup. Each row
+  /// of a block is wrapped on its own. The cursor goes to the start of what was put before.
+  fn wrap(&mut self, range: &str, text: &str) -> Result<()> {
+    if text.is_empty() {
+      return Err(EditorError::Parse("Usage: :wrap {text}".to_owned()));
+    }
+    let selection = self.last_selection().filter(|_| range == range::VISUAL);
+    let (first, last) = self.range_lines(range)?;
+    let markup = matches!(self.buffer().filetype, Some("html" | "xml"));
+    let (open, close) = wrapping(text, markup);
+    let buffer = self.buffer_mut();
+    let spans: Vec<(Position, Position)> = match selection {
+      Some(s) if s.kind == VisualKind::Block => (s.start.line..=s.end.line)
+        .filter(|&line| buffer.line_len(line) > s.start.col)
+        .map(|line| {
+          let end = (s.end.col + 1).min(buffer.line_len(line));
+          (Position::new(line, s.start.col), Position::new(line, end))
+        })
+        .collect(),
+      Some(s) if s.kind == VisualKind::Char => {
+        let end = (s.end.col + 1).min(buffer.line_len(s.end.line));
+        vec![(s.start, Position::new(s.end.line, end))]
+      }
+      _ => {
+        let indent = leading_whitespace(&buffer.lines[first]).chars().count();
+        vec![(
+          Position::new(first, indent),
+          Position::new(last, buffer.line_len(last)),
+        )]
+      }
+    };
+    // The end first, so the start is still where it was.
+    for &(start, end) in &spans {
+      buffer.insert(end, &close);
+      buffer.insert(start, &open);
+    }
+    if let Some(&(start, _)) = spans.first() {
+      self.windows[self.focus].cursor = start;
+    }
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `:{range}stripctrl`: removes the chars `showcontrol` marks from the lines of `range`, or
+  /// of the whole file without one.
+  fn strip_control(&mut self, range: &str) -> Result<()> {
+    let (start, end) = match range {
+      "" => (0, self.buffer().lines.len() - 1),
+      range => self.range_lines(range)?,
+    };
+    let buffer = self.buffer_mut();
+    let mut removed = 0;
+    for line in start..=end {
+      let text = &buffer.lines[line];
+      let kept: String = text
+        .chars()
+        .filter(|&c| ui::control_glyphs(c).is_none())
+        .collect();
+      if kept.len() != text.len() {
+        removed += text.chars().count() - kept.chars().count();
+        buffer.replace_line(line, &kept);
+      }
+    }
+    self.message = Some(match removed {
+      0 => "No control chars found".to_owned(),
+      n => format!("{} control char(s) removed", n),
+    });
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// `:{range}align {char}`: pads the lines of `range` with spaces before their first `char`
+  /// so that it is in the same column on all of them. Lines without it are left alone.
+  /// Without a range, the lines around the cursor line that have it.
+  fn align(&mut self, range: &str, arg: &str) -> Result<()> {
+    let mut chars = arg.chars();
+    let (Some(target), None) = (chars.next(), chars.next()) else {
+      return Err(EditorError::Parse("Usage: :align {char}".to_owned()));
+    };
+    let (start, end) = match range {
+      "" => {
+        let lines = &self.buffer().lines;
+        let has = |l: &usize| lines[*l].contains(target);
+        let cursor = self.window().cursor.line;
+        if !has(&cursor) {
+          return Err(EditorError::command(format!(
+            "No \"{}\" on this line",
+            target
+          )));
+        }
+        let start = (0..cursor).rev().take_while(has).last().unwrap_or(cursor);
+        let end = (cursor + 1..lines.len())
+          .take_while(has)
+          .last()
+          .unwrap_or(cursor);
+        (start, end)
+      }
+      range => self.range_lines(range)?,
+    };
+    let buffer = self.buffer_mut();
+    let tabstop = buffer.options.tabstop.max(1);
+    let found: Vec<(usize, usize, usize)> = (start..=end)
+      .filter_map(|line| {
+        let text = &buffer.lines[line];
+        let col = text.chars().position(|c| c == target)?;
+        Some((line, col, ui::render_col(text, col, tabstop)))
+      })
+      .collect();
+    let Some(column) = found.iter().map(|&(_, _, width)| width).max() else {
+      return Err(EditorError::command(format!(
+        "Pattern not found: {}",
+        target
+      )));
+    };
+    let mut changed = 0;
+    for (line, col, width) in found {
+      if width < column {
+        buffer.insert(Position::new(line, col), &" ".repeat(column - width));
+        changed += 1;
+      }
+    }
+    self.message = Some(format!("{} line(s) aligned", changed));
+    self.clamp_all();
+    Ok(())
+  }
+
+  /// The last selection, whether or not it is still showing.
+  fn last_selection(&self) -> Option<Selection> {
+    self
+      .last_visual
+      .map(|(kind, anchor, cursor)| Selection::new(kind, anchor, cursor))
+  }
+
+  /// The columns `range` limits a substitution to: those of the last selection, if `range` is
+  /// `range::VISUAL` and it was a block.
+  pub fn range_columns(&self, range: &str) -> Option<std::ops::Range<usize>> {
+    let selection = self.last_selection()?;
+    match range == range::VISUAL && selection.kind == VisualKind::Block {
+      true => Some(selection.start.col..selection.end.col + 1),
+      false => None,
+    }
+  }
+
+  fn substitute(&mut self, sub: &Substitute) -> Result<()> {
+    if sub.pattern.is_empty() {
+      self.message = Some("Empty search pattern".to_owned());
+      return Ok(());
+    }
+    let (first, last) = self.range_lines(&sub.range)?;
+    let columns = self.range_columns(&sub.range);
+    let (buffer, window) = self.focused();
+    let (mut count, mut changed) = (0, 0);
+    for line in first..=last {
+      let (text, ranges) = match &columns {
+        Some(columns) => sub.apply_within(&buffer.lines[line], columns),
+        None => sub.apply(&buffer.lines[line]),
+      };
+      if ranges.is_empty() {
+        continue;
+      }
+      buffer.replace_line(line, &text);
+      window.cursor = Position::new(line, 0);
+      count += ranges.len();
+      changed += 1;
+    }
+    self.message = Some(match count {
+      0 => format!("Pattern not found: {}", sub.pattern),
+      _ => format!("{} substitution(s) on {} line(s)", count, changed),
+    });
+    Ok(())
+  }
+
+  /// Opens a new window left of the focused one, or above it unless `vertical`, on `path` or on
+  /// the same buffer.
+  fn split(&mut self, path: &str, vertical: bool) -> Result<()> {
+    let mut window = self.window().clone();
+    if !path.is_empty() {
+      window = Window::new(self.buffer_for(&self.resolve(path))?, window.options);
+    }
+    let previous = self.window().buffer;
+    self.windows.insert(self.focus, window);
+    self.layout.split(self.focus, vertical);
+    self.left_buffer(previous);
+    Ok(())
+  }
+
+  /// The buffer showing `path`, opened now if no buffer has it yet.
+  fn buffer_for(&mut self, path: &Path) -> Result<usize> {
+    if let Some(index) = self
+      .buffers
+      .iter()
+      .position(|b| b.path.as_deref() == Some(path))
+    {
+      return Ok(index);
+    }
+    let mut buffer = Buffer::open(path).map_err(|e| EditorError::io("open", path, e))?;
+    self.setup_buffer(&mut buffer);
+    self.buffers.push(buffer);
+    Ok(self.buffers.len() - 1)
+  }
+
+  /// Closes the focused window, or exits when it is the last one.
+  fn quit(&mut self, force: bool) {
+    if self.windows.len() > 1 {
+      let previous = self.windows.remove(self.focus).buffer;
+      self.layout.remove(self.focus);
+      self.focus = self.focus.min(self.windows.len() - 1);
+      self.left_buffer(previous);
+      self.clamp_all();
+      return;
+    }
+    match self.buffers.iter().find(|b| b.modified) {
+      Some(buffer) if !force => {
+        self.message = Some(format!(
+          "No write since last change for \"{}\" (add ! to override)",
+          buffer.name()
+        ))
+      }
+      _ => self.exit(),
+    }
+  }
+
+  /// `:qa`: quits, first asking about each buffer with unsaved changes whether to write it.
+  /// Nothing is written unless the user gets through all of them without cancelling.
+  fn quit_all(&mut self, force: bool) {
+    self.quit_writes.clear();
+    match force {
+      true => self.exit(),
+      false => self.ask_quit_all(0),
+    }
+  }
+
+  /// Asks about the next buffer from `from` on with unsaved changes, or once there are none
+  /// left, writes the ones the user said to and quits.
+  fn ask_quit_all(&mut self, from: usize) {
+    if let Some(buffer) = (from..self.buffers.len()).find(|&i| self.buffers[i].modified) {
+      self.message = Some(format!(
+        "\"{}\" has unsaved changes:",
+        self.buffers[buffer].name()
+      ));
+      self.prompt = Some(Prompt::QuitAll { buffer });
+      return;
+    }
+    let writes = std::mem::take(&mut self.quit_writes);
+    match self.write_buffers(&writes) {
+      Ok(()) => self.exit(),
+      Err(e) => self.message = Some(e.to_string()),
+    }
+  }
+
+  /// `:wqa`: saves every buffer with unsaved changes and quits.
+  fn write_quit_all(&mut self) -> Result<()> {
+    let modified: Vec<usize> = (0..self.buffers.len())
+      .filter(|&i| self.buffers[i].modified)
+      .collect();
+    self.write_buffers(&modified)?;
+    self.exit();
+    Ok(())
+  }
+
+  /// Ends the editor, keeping folds and histories for next time.
+  fn exit(&mut self) {
+    state::save_folds(&self.buffers);
+    state::save_history(&state::Histories {
+      command: self.command_history.entries().to_vec(),
+      search: self.search_history.entries().to_vec(),
+    });
+    self.should_quit = true;
+  }
+
+  /// Puts `:{command} ` back on the command line for the user to type a file name after.
+  fn ask_file_name(&mut self, command: &str) {
+    self.mode = Mode::Command;
+    self.command_line = format!("{} ", command);
+  }
+
+  /// `:w {path}`: names an unnamed buffer and saves it, or writes a copy of a named one.
+  fn save_as(&mut self, path: &Path, force: bool) -> Result<()> {
+    if !force && path.exists() {
+      return Err(EditorError::command("File exists (add ! to override)"));
+    }
+    if self.buffer().path.is_none() {
+      let buffer = self.buffer_mut();
+      buffer.path = Some(path.to_path_buf());
+      buffer.filetype = filetype::detect(path);
+      self.write();
+      return Ok(());
+    }
+    let buffer = self.buffer();
+    let content = buffer.contents();
+    fs::write(path, &content).map_err(|e| EditorError::io("write", path, e))?;
+    self.message = Some(format!(
+      "\"{}\" {}L, {}B written",
+      path.display(),
+      buffer.lines.len(),
+      content.len()
+    ));
+    Ok(())
+  }
+
+  /// `:w -`: keeps the buffer's text to print on stdout at exit, for use in a pipeline.
+  fn write_stdout(&mut self) {
+    let buffer = self.buffer_mut();
+    let content = buffer.contents();
+    buffer.modified = false;
+    buffer.mark_saved();
+    self.message = Some(format!(
+      "\"-\" {}L, {}B written to stdout on exit",
+      buffer.lines.len(),
+      content.len()
+    ));
+    self.stdout = Some(content);
+  }
+
+  fn save(&mut self) {
+    if self.check_disk(true) {
+      return;
+    }
+    self.write();
+  }
+
+  fn write(&mut self) {
+    if let Err(e) = self.run_save_hooks(self.window().buffer) {
+      self.message = Some(format!("{}; not written", e));
+      self.clamp_all();
+      return;
+    }
+    let buffer = self.buffer_mut();
+    let message = match buffer.save() {
+      Ok(bytes) => {
+        state::save_folds(std::slice::from_ref(buffer));
+        format!(
+          "\"{}\" {}L, {}B written",
+          buffer.name(),
+          buffer.lines.len(),
+          bytes
+        )
+      }
+      Err(e) => format!("Can't write \"{}\": {}", buffer.name(), e),
+    };
+    self.message = Some(message);
+    // Trimming trailing whitespace or formatting may have left cursors past the end of the text.
+    self.clamp_all();
+  }
+
+  /// Runs the `onsave` steps of buffer `index`. Cursors stay on the same line and column, as
+  /// far as the new text allows.
+  fn run_save_hooks(&mut self, index: usize) -> Result<()> {
+    let Some(steps) = self.buffers[index]
+      .filetype
+      .and_then(|filetype| self.config.onsave.get(filetype))
+      .cloned()
+    else {
+      return Ok(());
+    };
+    hook::run(&steps, &mut self.buffers[index])
+  }
+
+  /// Saves the buffers at `indices`, stopping at the first that can't be.
+  fn write_buffers(&mut self, indices: &[usize]) -> Result<()> {
+    for &index in indices {
+      let Some(path) = self.buffers[index].path.clone() else {
+        return Err(EditorError::command(format!(
+          "No file name for buffer {}",
+          index + 1
+        )));
+      };
+      let result = self.run_save_hooks(index);
+      self.clamp_all();
+      result?;
+      let buffer = &mut self.buffers[index];
+      buffer
+        .save()
+        .map_err(|e| EditorError::io("write", &path, e))?;
+      state::save_folds(std::slice::from_ref(buffer));
+    }
+    self.clamp_all();
+    Ok(())
+  }
+
+  fn reload(&mut self) {
+    let buffer = self.buffer_mut();
+    let message = match buffer.reload() {
+      Ok(()) => format!("\"{}\" {}L reloaded", buffer.name(), buffer.lines.len()),
+      Err(e) => format!("Can't reload \"{}\": {}", buffer.name(), e),
+    };
+    self.message = Some(message);
+    self.clamp_all();
+  }
+
+  /// Asks what to do if the file changed under us. Returns whether a prompt is now open.
+  fn check_disk(&mut self, then_save: bool) -> bool {
+    if self.prompt.is_none() && self.buffer().changed_on_disk() {
+      self.prompt = Some(Prompt::DiskChanged { then_save });
+    }
+    self.prompt.is_some()
+  }
+
+  fn answer_prompt(&mut self, prompt: Prompt, code: KeyCode) {
+    match prompt {
+      Prompt::DiskChanged { then_save } => match code {
+        KeyCode::Char('r') => {
+          self.prompt = None;
+          self.reload();
+        }
+        KeyCode::Char('k') | KeyCode::Esc => {
+          self.prompt = None;
+          // Adopt the new mtime so we don't ask again until the next external change.
+          let buffer = self.buffer_mut();
+          buffer.mtime = buffer.disk_mtime();
+          if then_save {
+            self.write();
+          }
+        }
+        KeyCode::Char('d') => match self.buffer().diff_with_disk() {
+          Ok(lines) if lines.is_empty() => {
+            self.message = Some("Contents are identical to the file on disk".to_owned())
+          }
+          Ok(lines) => {
+            let (buffer, window) = self.focused();
+            window.cursor = Position::new(lines[0].min(buffer.lines.len() - 1), 0);
+            self.message = Some(format!(
+              "{} line(s) differ from disk, first at line {}",
+              lines.len(),
+              lines[0] + 1
+            ));
+          }
+          Err(e) => self.message = Some(format!("Can't diff: {}", e)),
+        },
+        _ => (),
+      },
+      Prompt::Confirm => {
+        self.prompt = None;
+        self.message = None;
+        let Some(command) = self.confirming.take() else {
+          return;
+        };
+        if code == KeyCode::Char('y') {
+          if let Err(e) = self.execute(&command) {
+            self.message = Some(e.to_string());
+          }
+        }
+      }
+      Prompt::DeleteNode => {
+        self.prompt = None;
+        let Some(tree) = &mut self.tree else {
+          return;
+        };
+        self.message = match code {
+          KeyCode::Char('y') => Some(match tree.delete() {
+            Ok(path) => format!("Deleted \"{}\"", tree.relative(&path).display()),
+            Err(e) => format!("Can't delete: {}", e),
+          }),
+          _ => None,
+        };
+      }
+      Prompt::CloseModified => {
+        self.prompt = None;
+        match code {
+          KeyCode::Char('w') => {
+            let current = self.window().buffer;
+            let modified: Vec<usize> = (0..self.buffers.len())
+              .filter(|&i| i != current && self.buffers[i].modified)
+              .collect();
+            match self.write_buffers(&modified) {
+              Ok(()) => self.only(true),
+              Err(e) => self.message = Some(e.to_string()),
+            }
+          }
+          KeyCode::Char('d') => self.only(true),
+          _ => self.message = None,
+        }
+      }
+      Prompt::QuitAll { buffer } => {
+        self.prompt = None;
+        match code {
+          KeyCode::Char('w') => {
+            self.quit_writes.push(buffer);
+            self.ask_quit_all(buffer + 1);
+          }
+          KeyCode::Char('d') => self.ask_quit_all(buffer + 1),
+          _ => {
+            self.quit_writes.clear();
+            self.message = None;
+          }
+        }
+      }
+    }
+  }
+
+  fn undo(&mut self) {
+    match self.buffer_mut().undo() {
+      Some(at) => self.windows[self.focus].cursor = at,
+      None => self.message = Some("Already at oldest change".to_owned()),
+    }
+    self.clamp_all();
+  }
+
+  fn redo(&mut self) {
+    match self.buffer_mut().redo() {
+      Some(at) => self.windows[self.focus].cursor = at,
+      None => self.message = Some("Already at newest change".to_owned()),
+    }
+    self.clamp_all();
+  }
+
+  /// Moves by `lines` screen rows, so a closed fold counts as one line.
+  fn move_cursor(&mut self, lines: isize, cols: isize) {
+    let (buffer, window) = self.focused();
+    let (folds, last) = (&buffer.folds, buffer.lines.len() - 1);
+    let goal = match window.goal_column {
+      Some((at, goal)) if at == window.cursor => goal,
+      _ => window.cursor.col,
+    };
+    window.cursor.line = match lines {
+      0.. => fold::rows_down(folds, window.cursor.line, lines as usize, last),
+      _ => fold::rows_up(folds, window.cursor.line, lines.unsigned_abs()),
+    };
+    window.cursor.col = match cols {
+      0 => goal,
+      _ => window.cursor.col.saturating_add_signed(cols),
+    };
+    self.clamp_cursor();
+    if cols == 0 {
+      let window = &mut self.windows[self.focus];
+      window.goal_column = Some((window.cursor, goal));
+    }
+  }
+
+  fn clamp_cursor(&mut self) {
+    let insert = self.mode == Mode::Insert;
+    let (buffer, window) = self.focused();
+    clamp(buffer, window, insert);
+  }
+
+  /// Clamps every window, for edits that may have shortened a buffer shown more than once.
+  fn clamp_all(&mut self) {
+    let insert = self.mode == Mode::Insert;
+    for (i, window) in self.windows.iter_mut().enumerate() {
+      clamp(
+        &self.buffers[window.buffer],
+        window,
+        insert && i == self.focus,
+      );
+    }
+  }
+}
+
+/// Inserts `rows` as a block `width` chars wide with its top left corner at `at`, each row
+/// repeated `count` times. Lines too short to reach it are padded with spaces, and lines are
+/// added after the last one if the block runs past it.
+fn insert_block(buffer: &mut Buffer, rows: &[String], at: Position, width: usize, count: usize) {
+  for (i, row) in rows.iter().enumerate() {
+    let line = at.line + i;
+    if line == buffer.lines.len() {
+      let last = line - 1;
+      buffer.insert(Position::new(last, buffer.line_len(last)), "\n");
+    }
+    let len = buffer.line_len(line);
+    if len < at.col {
+      buffer.insert(Position::new(line, len), &" ".repeat(at.col - len));
+    }
+    // Rows are padded to the block's width when text follows, to keep it in a column.
+    let row = match len > at.col {
+      true => format!("{:<1$}", row, width),
+      false => row.clone(),
+    };
+    buffer.insert(Position::new(line, at.col), &row.repeat(count));
+  }
+}
+
+/// The furthest down a window `height` rows tall may scroll: to the last line with
+/// `scrollpastend`, or to where the last line is at the bottom without.
+fn scroll_limit(folds: &[Fold], last: usize, height: usize, past_end: bool) -> usize {
+  match past_end {
+    true => last,
+    false => fold::rows_up(folds, last, height.saturating_sub(1)),
+  }
+}
+
+/// Where `n` paragraph motions from `pos` end: each goes past any blank lines and then the
+/// paragraph after them, to the blank line beyond it. Down, a negative `n` up. One that runs
+/// out of lines stops at the end or the start of the buffer.
+fn paragraphs(lines: &[String], pos: Position, n: isize) -> Position {
+  let blank = |l: usize| lines[l].trim().is_empty();
+  let last = lines.len() - 1;
+  let mut line = pos.line;
+  for _ in 0..n.unsigned_abs() {
+    match n > 0 {
+      true => {
+        while line < last && blank(line) {
+          line += 1;
+        }
+        while line < last && !blank(line) {
+          line += 1;
+        }
+      }
+      false => {
+        while line > 0 && blank(line) {
+          line -= 1;
+        }
+        while line > 0 && !blank(line) {
+          line -= 1;
+        }
+      }
+    }
+  }
+  match (blank(line), n > 0) {
+    (false, true) => Position::new(line, lines[line].chars().count()),
+    _ => Position::new(line, 0),
+  }
+}
+
+/// `%` without a count: the bracket matching the first one of `pairs` at or after `pos` on
+/// its line.
+fn matching_bracket(lines: &[String], pos: Position, pairs: &[(char, char)]) -> Option<Position> {
+  let pair = |c: char| {
+    pairs
+      .iter()
+      .copied()
+      .find(|&(open, close)| c == open || c == close)
+  };
+  let (col, (open, close), c) = lines[pos.line]
+    .chars()
+    .enumerate()
+    .skip(pos.col)
+    .find_map(|(col, c)| Some((col, pair(c)?, c)))?;
+  let mut depth = 0;
+  if c == open {
+    for (l, line) in lines.iter().enumerate().skip(pos.line) {
+      let skip = if l == pos.line { col } else { 0 };
+      for (i, ch) in line.chars().enumerate().skip(skip) {
+        if ch == open {
+          depth += 1;
+        } else if ch == close {
+          depth -= 1;
+          if depth == 0 {
+            return Some(Position::new(l, i));
+          }
+        }
+      }
+    }
+  } else {
+    for l in (0..=pos.line).rev() {
+      let chars: Vec<char> = lines[l].chars().collect();
+      let end = if l == pos.line { col + 1 } else { chars.len() };
+      for i in (0..end).rev() {
+        if chars[i] == close {
+          depth += 1;
+        } else if chars[i] == open {
+          depth -= 1;
+          if depth == 0 {
+            return Some(Position::new(l, i));
+          }
+        }
+      }
+    }
+  }
+  None
+}
+
+/// `path` with a leading `~` standing for the home directory.
+fn expand_home(path: &str) -> PathBuf {
+  let home = env::var_os("HOME").map(PathBuf::from);
+  match (path, path.strip_prefix("~/"), home) {
+    ("~", _, Some(home)) => home,
+    (_, Some(rest), Some(home)) => home.join(rest),
+    _ => PathBuf::from(path),
+  }
+}
+
+/// `path` without `.` and with each `..` taking off the name before it.
+fn normalize(path: &Path) -> PathBuf {
+  let mut normal = PathBuf::new();
+  for component in path.components() {
+    match component {
+      path::Component::CurDir => (),
+      path::Component::ParentDir if normal.file_name().is_some() => {
+        normal.pop();
+      }
+      component => normal.push(component),
+    }
+  }
+  normal
+}
+
+/// An absolute `path` relative to the working directory if it is in it.
+fn relative_to_cwd(path: &Path) -> PathBuf {
+  let cwd = env::current_dir().unwrap_or_default();
+  path.strip_prefix(&cwd).unwrap_or(path).to_path_buf()
+}
+
+fn leading_whitespace(line: &str) -> &str {
+  &line[..line.len() - line.trim_start().len()]
+}
+
+/// Whitespace reaching screen column `width`, in tabs and spaces or, with `expandtab`, just
+/// spaces.
+fn indent_to(width: usize, tabstop: usize, expandtab: bool) -> String {
+  match expandtab {
+    true => " ".repeat(width),
+    false => "\t".repeat(width / tabstop) + &" ".repeat(width % tabstop),
+  }
+}
+
+/// Column `col` of line `number` of `buffer`, or its first non-blank char, counting from 1.
+fn line_position(buffer: &Buffer, number: usize, col: Option<usize>) -> Position {
+  let line = number.clamp(1, buffer.lines.len()) - 1;
+  let col = match col {
+    Some(col) => col.saturating_sub(1),
+    None => leading_whitespace(&buffer.lines[line]).chars().count(),
+  };
+  Position::new(line, col)
+}
+
+fn clamp(buffer: &Buffer, window: &mut Window, insert: bool) {
+  let line = window.cursor.line.min(buffer.lines.len() - 1);
+  // A closed fold shows as its first line, so that is where the cursor sits.
+  window.cursor.line = fold::visible_start(&buffer.folds, line);
+  let len = buffer.line_len(window.cursor.line);
+  let max = if insert { len } else { len.saturating_sub(1) };
+  window.cursor.col = window.cursor.col.min(max);
+}
+
+/// What `:wrap text` puts before and after what it wraps.
+fn wrapping(text: &str, markup: bool) -> (String, String) {
+  let bracket = |open| match open {
+    '(' => Some(')'),
+    '[' => Some(']'),
+    '{' => Some('}'),
+    _ => None,
+  };
+  if let Some(close) = text.chars().last().and_then(bracket) {
+    return (text.to_owned(), close.to_string());
+  }
+  let tag = text.strip_prefix('<').map(|tag| tag.trim_end_matches('>'));
+  match tag.or(markup.then_some(text)) {
+    Some(tag) => {
+      let name = tag.split_whitespace().next().unwrap_or(tag);
+      (format!("<{}>", tag), format!("</{}>", name))
+    }
+    None => (format!("{}(", text), ")".to_owned()),
+  }
+}
+
+/// The control char `Ctrl+c` stands for, like `\x01` for `Ctrl+A`.
+fn control(c: char) -> char {
+  char::from(c.to_ascii_uppercase() as u8 & 0x1f)
+}
+
+/// The parts of a `:g/pattern/command`, `:g!/pattern/command` or `:v/pattern/command`:
+/// whether it is for the lines that match, the pattern and the command. The pattern ends at
+/// the next delimiter not escaped with `\`.
+fn parse_global(command: &str) -> Option<(bool, String, &str)> {
+  let (matching, rest) = match command {
+    _ if command.starts_with("g!") => (false, &command[2..]),
+    _ if command.starts_with("global!") => (false, &command[7..]),
+    _ if command.starts_with("global") => (true, &command[6..]),
+    _ if command.starts_with("vglobal") => (false, &command[7..]),
+    _ if command.starts_with('g') => (true, &command[1..]),
+    _ if command.starts_with('v') => (false, &command[1..]),
+    _ => return None,
+  };
+  let delimiter = rest.chars().next()?;
+  if delimiter.is_alphanumeric() || delimiter.is_whitespace() || delimiter == '\\' {
+    return None;
+  }
+  let mut pattern = String::new();
+  let mut chars = rest[delimiter.len_utf8()..].char_indices();
+  let after = delimiter.len_utf8();
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '\\' if rest[after + i + 1..].starts_with(delimiter) => {
+        pattern.push(delimiter);
+        chars.next();
+      }
+      _ if c == delimiter => return Some((matching, pattern, &rest[after + i + c.len_utf8()..])),
+      _ => pattern.push(c),
+    }
+  }
+  Some((matching, pattern, ""))
+}
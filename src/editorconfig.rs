@@ -0,0 +1,164 @@
+//! Reads `.editorconfig` files, see <https://editorconfig.org>.
+//!
+//! Files are looked up from the directory of the edited file towards the root, stopping at one
+//! that says `root = true`. Closer files win over farther ones, and later sections win over
+//! earlier ones in the same file.
+
+use crate::options::{BufferOptions, FileFormat};
+use std::fs;
+use std::path::{self, Path};
+
+/// The properties that apply to `path`, lowercased, in the order they should be applied.
+pub fn properties(path: &Path) -> Vec<(String, String)> {
+  let Ok(path) = path::absolute(path) else {
+    return Vec::new();
+  };
+  let mut files = Vec::new();
+  for dir in path.ancestors().skip(1) {
+    let Ok(text) = fs::read_to_string(dir.join(".editorconfig")) else {
+      continue;
+    };
+    let root = parse(&text).0;
+    files.push((dir.to_path_buf(), text));
+    if root {
+      break;
+    }
+  }
+  let mut properties = Vec::new();
+  for (dir, text) in files.iter().rev() {
+    let Ok(relative) = path.strip_prefix(dir) else {
+      continue;
+    };
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    for (pattern, section) in parse(text).1 {
+      if section_matches(&pattern, &relative) {
+        properties.extend(section);
+      }
+    }
+  }
+  properties
+}
+
+/// Applies EditorConfig properties to a buffer's settings, ignoring ones we don't support.
+pub fn apply(properties: &[(String, String)], options: &mut BufferOptions) {
+  let mut tab_width = None;
+  let mut indent_size = None;
+  for (key, value) in properties {
+    match (key.as_str(), value.as_str()) {
+      ("indent_style", "tab") => options.expandtab = false,
+      ("indent_style", "space") => options.expandtab = true,
+      ("indent_size", size) => indent_size = size.parse().ok(),
+      ("tab_width", width) => tab_width = width.parse().ok(),
+      ("end_of_line", "lf") => options.fileformat = FileFormat::Unix,
+      ("end_of_line", "crlf") => options.fileformat = FileFormat::Dos,
+      ("charset", "utf-8") => options.bomb = false,
+      ("charset", "utf-8-bom") => options.bomb = true,
+      ("trim_trailing_whitespace", flag) => options.trimtrailing = flag == "true",
+      ("insert_final_newline", flag) => options.fixendofline = flag == "true",
+      _ => (),
+    }
+  }
+  // There is a single width for tabs and indents, so `tab_width` wins if both are given.
+  if let Some(width) = tab_width.or(indent_size).filter(|&w: &usize| w > 0) {
+    options.tabstop = width;
+  }
+}
+
+type Section = (String, Vec<(String, String)>);
+
+/// Splits a file into its `root` flag and its sections.
+fn parse(text: &str) -> (bool, Vec<Section>) {
+  let mut root = false;
+  let mut sections: Vec<Section> = Vec::new();
+  for line in text.lines().map(str::trim) {
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+      continue;
+    }
+    if let Some(pattern) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+      sections.push((pattern.to_owned(), Vec::new()));
+      continue;
+    }
+    let Some((key, value)) = line.split_once('=') else {
+      continue;
+    };
+    let key = key.trim().to_lowercase();
+    let value = value.trim().to_lowercase();
+    match sections.last_mut() {
+      Some((_, properties)) => properties.push((key, value)),
+      None if key == "root" => root = value == "true",
+      None => (),
+    }
+  }
+  (root, sections)
+}
+
+/// Whether a section header matches `path`, given relative to the `.editorconfig`. Patterns
+/// without a `/` match the file name in any directory.
+fn section_matches(pattern: &str, path: &str) -> bool {
+  let pattern = match pattern.strip_prefix('/') {
+    Some(anchored) => anchored.to_owned(),
+    None if pattern.contains('/') => pattern.to_owned(),
+    None => format!("**/{}", pattern),
+  };
+  let path: Vec<char> = path.chars().collect();
+  expand_braces(&pattern).iter().any(|p| {
+    let p: Vec<char> = p.chars().collect();
+    glob(&p, &path)
+  })
+}
+
+/// Expands `{a,b}` alternatives into separate patterns.
+fn expand_braces(pattern: &str) -> Vec<String> {
+  let Some(open) = pattern.find('{') else {
+    return vec![pattern.to_owned()];
+  };
+  let Some(close) = pattern[open..].find('}').map(|i| open + i) else {
+    return vec![pattern.to_owned()];
+  };
+  let (head, tail) = (&pattern[..open], &pattern[close + 1..]);
+  pattern[open + 1..close]
+    .split(',')
+    .flat_map(|choice| expand_braces(&format!("{}{}{}", head, choice, tail)))
+    .collect()
+}
+
+/// Matches `*` (within a path component), `**` (across components), `?` and `[...]` sets.
+fn glob(pattern: &[char], path: &[char]) -> bool {
+  match pattern {
+    [] => path.is_empty(),
+    ['*', '*', '/', rest @ ..] => {
+      glob(rest, path) || (0..path.len()).any(|i| path[i] == '/' && glob(rest, &path[i + 1..]))
+    }
+    ['*', '*', rest @ ..] => (0..=path.len()).any(|i| glob(rest, &path[i..])),
+    ['*', rest @ ..] => (0..=path.len())
+      .take_while(|&i| i == 0 || path[i - 1] != '/')
+      .any(|i| glob(rest, &path[i..])),
+    ['?', rest @ ..] => matches!(path, [c, ..] if *c != '/') && glob(rest, &path[1..]),
+    ['[', rest @ ..] => match rest.iter().position(|&c| c == ']') {
+      Some(end) => {
+        let (set, rest) = (&rest[..end], &rest[end + 1..]);
+        let Some(&c) = path.first() else {
+          return false;
+        };
+        let (negated, set) = match set {
+          ['!', set @ ..] => (true, set),
+          set => (false, set),
+        };
+        let mut found = false;
+        let mut i = 0;
+        while i < set.len() {
+          if i + 2 < set.len() && set[i + 1] == '-' {
+            found |= (set[i]..=set[i + 2]).contains(&c);
+            i += 3;
+          } else {
+            found |= set[i] == c;
+            i += 1;
+          }
+        }
+        found != negated && glob(rest, &path[1..])
+      }
+      None => path.first() == Some(&'[') && glob(rest, &path[1..]),
+    },
+    ['\\', c, rest @ ..] | [c, rest @ ..] => path.first() == Some(c) && glob(rest, &path[1..]),
+  }
+}
@@ -0,0 +1,38 @@
+//! Failures reported to the user in the footer instead of ending the editor.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EditorError {
+  /// Reading or writing a file or directory failed.
+  #[error("Can't {action} \"{}\": {source}", path.display())]
+  Io {
+    action: &'static str,
+    path: PathBuf,
+    source: io::Error,
+  },
+  /// Something the user wrote, on the command line or in a settings file, doesn't parse.
+  #[error("{0}")]
+  Parse(String),
+  /// A command that can't be carried out as asked.
+  #[error("{0}")]
+  Command(String),
+}
+
+impl EditorError {
+  pub fn io(action: &'static str, path: &Path, source: io::Error) -> Self {
+    EditorError::Io {
+      action,
+      path: path.to_path_buf(),
+      source,
+    }
+  }
+
+  pub fn command(message: impl Into<String>) -> Self {
+    EditorError::Command(message.into())
+  }
+}
+
+pub type Result<T, E = EditorError> = std::result::Result<T, E>;
@@ -0,0 +1,36 @@
+//! Working out what language a file is in from its name.
+
+use std::path::Path;
+
+/// The file type of `path`, named like Vim names them, e.g. `rust`, `python` or `make`.
+pub fn detect(path: &Path) -> Option<&'static str> {
+  let name = path.file_name()?.to_str()?;
+  match name {
+    "Makefile" | "makefile" | "GNUmakefile" => return Some("make"),
+    "Dockerfile" => return Some("dockerfile"),
+    "CMakeLists.txt" => return Some("cmake"),
+    _ => (),
+  }
+  let extension = name.rsplit_once('.')?.1;
+  Some(match extension {
+    "rs" => "rust",
+    "py" | "pyi" => "python",
+    "yaml" | "yml" => "yaml",
+    "toml" => "toml",
+    "json" => "json",
+    "md" | "markdown" => "markdown",
+    "c" | "h" => "c",
+    "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => "cpp",
+    "go" => "go",
+    "js" | "mjs" | "cjs" => "javascript",
+    "ts" => "typescript",
+    "html" | "htm" => "html",
+    "css" => "css",
+    "sh" | "bash" => "sh",
+    "mk" => "make",
+    "lua" => "lua",
+    "rb" => "ruby",
+    "java" => "java",
+    _ => return None,
+  })
+}
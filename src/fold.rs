@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FoldKind {
+  /// Created by the user; remembered across sessions.
+  Manual,
+  /// Derived from indentation with `foldmethod=indent`; recomputed after edits.
+  Indent,
+  /// From the language server with `foldmethod=lsp`; asked for again after edits.
+  Lsp,
+  /// Hides lines that don't match a `:fold /pattern/`; `zR` removes them.
+  Search,
+}
+
+/// A range of lines, `start..=end`, that can be collapsed into its first line.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fold {
+  pub start: usize,
+  pub end: usize,
+  pub closed: bool,
+  pub kind: FoldKind,
+}
+
+impl Fold {
+  pub fn new(start: usize, end: usize, kind: FoldKind) -> Self {
+    Fold {
+      start,
+      end,
+      closed: false,
+      kind,
+    }
+  }
+
+  pub fn contains(&self, line: usize) -> bool {
+    (self.start..=self.end).contains(&line)
+  }
+}
+
+/// How long after asking for a buffer's folding ranges edits wait before they are asked for
+/// again.
+pub const LSP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The folding ranges asked for from the language server for a buffer.
+#[derive(Clone, Debug, Default)]
+pub struct Ranges {
+  /// The version of the buffer they were last asked for, and when.
+  pub asked: Option<(u64, Instant)>,
+  /// Whether the server has given any. Until it does, the buffer is folded by indentation.
+  pub found: bool,
+}
+
+/// The folds of an answer to `textDocument/foldingRange`, for a buffer of `len` lines. Ranges
+/// of a single line are left out.
+pub fn from_lsp(result: &Value, len: usize) -> Vec<Fold> {
+  let mut folds: Vec<Fold> = result
+    .as_array()
+    .into_iter()
+    .flatten()
+    .filter_map(|range| {
+      let start = range["startLine"].as_u64()? as usize;
+      let end = range["endLine"].as_u64()? as usize;
+      (start < end && end < len).then(|| Fold::new(start, end, FoldKind::Lsp))
+    })
+    .collect();
+  folds.sort_by_key(|fold| (fold.start, fold.end));
+  folds.dedup_by_key(|fold| (fold.start, fold.end));
+  folds
+}
+
+/// Puts `new` in place of the folds of `kind`, keeping closed the ones that start where a
+/// closed one did.
+pub fn replace(folds: &mut Vec<Fold>, kind: FoldKind, new: Vec<Fold>) {
+  let closed: Vec<usize> = folds
+    .iter()
+    .filter(|f| f.kind == kind && f.closed)
+    .map(|f| f.start)
+    .collect();
+  folds.retain(|f| f.kind != kind);
+  for mut fold in new {
+    fold.closed = closed.contains(&fold.start);
+    folds.push(fold);
+  }
+}
+
+/// One fold for every line followed by more deeply indented lines, nested as the indentation is.
+/// Blank lines trailing a block stay outside its fold.
+pub fn indent_folds(lines: &[String]) -> Vec<Fold> {
+  let indent = |line: &String| match line.trim().is_empty() {
+    true => None,
+    false => Some(line.len() - line.trim_start().len()),
+  };
+  let mut folds = Vec::new();
+  for (start, line) in lines.iter().enumerate() {
+    let Some(level) = indent(line) else {
+      continue;
+    };
+    let mut end = start;
+    for (i, inner) in lines.iter().enumerate().skip(start + 1) {
+      match indent(inner) {
+        Some(inner_level) if inner_level > level => end = i,
+        Some(_) => break,
+        None => (),
+      }
+    }
+    if end > start {
+      folds.push(Fold::new(start, end, FoldKind::Indent));
+    }
+  }
+  folds
+}
+
+/// Lines of context kept around each match by `search_folds`.
+const SEARCH_CONTEXT: usize = 1;
+
+/// Closed folds over the runs of lines in `start..=end` that neither contain `pattern` nor are
+/// next to a line that does. A run of a single line is left visible, since a fold summary
+/// would take its row anyway.
+pub fn search_folds(lines: &[String], pattern: &str, start: usize, end: usize) -> Vec<Fold> {
+  let mut visible = vec![false; end + 1];
+  for (i, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+    if line.contains(pattern) {
+      let from = i.saturating_sub(SEARCH_CONTEXT).max(start);
+      let to = (i + SEARCH_CONTEXT).min(end);
+      visible[from..=to].iter_mut().for_each(|v| *v = true);
+    }
+  }
+  let mut folds = Vec::new();
+  let mut run_start = None;
+  for line in start..=end + 1 {
+    match (visible.get(line).copied().unwrap_or(true), run_start) {
+      (false, None) => run_start = Some(line),
+      (true, Some(from)) => {
+        if line - 1 > from {
+          let mut fold = Fold::new(from, line - 1, FoldKind::Search);
+          fold.closed = true;
+          folds.push(fold);
+        }
+        run_start = None;
+      }
+      _ => (),
+    }
+  }
+  folds
+}
+
+/// The line that stands for `line` on screen: the start of the outermost closed fold hiding it,
+/// or `line` itself.
+pub fn visible_start(folds: &[Fold], line: usize) -> usize {
+  folds
+    .iter()
+    .filter(|f| f.closed && f.contains(line))
+    .map(|f| f.start)
+    .min()
+    .unwrap_or(line)
+}
+
+/// The last line collapsed into the screen row of `line`.
+pub fn visible_end(folds: &[Fold], line: usize) -> usize {
+  let start = visible_start(folds, line);
+  folds
+    .iter()
+    .filter(|f| f.closed && f.start == start)
+    .map(|f| f.end)
+    .max()
+    .unwrap_or(start)
+}
+
+/// The first line of the screen row after the one showing `line`.
+pub fn next_visible(folds: &[Fold], line: usize) -> usize {
+  visible_end(folds, line) + 1
+}
+
+/// The first line of the screen row before the one showing `line`.
+pub fn prev_visible(folds: &[Fold], line: usize) -> Option<usize> {
+  let start = visible_start(folds, line);
+  start.checked_sub(1).map(|l| visible_start(folds, l))
+}
+
+/// Index of the smallest fold containing `line`.
+pub fn innermost(folds: &[Fold], line: usize) -> Option<usize> {
+  folds
+    .iter()
+    .enumerate()
+    .filter(|(_, f)| f.contains(line))
+    .min_by_key(|(_, f)| f.end - f.start)
+    .map(|(i, _)| i)
+}
+
+/// Keeps folds on the same text when `delta` lines are inserted (or removed, if negative) just
+/// after `line`. Folds that lose all their lines are dropped.
+pub fn shift(folds: &mut Vec<Fold>, line: usize, delta: isize) {
+  if delta == 0 {
+    return;
+  }
+  if delta > 0 {
+    let delta = delta as usize;
+    for fold in folds.iter_mut() {
+      if fold.start > line {
+        fold.start += delta;
+      }
+      if fold.end >= line {
+        fold.end += delta;
+      }
+    }
+    return;
+  }
+  // Lines `line + 1..=line + removed` are gone.
+  let removed = delta.unsigned_abs();
+  let first = line + 1;
+  let map = |l: usize| match l {
+    l if l < first => l,
+    l if l < first + removed => line,
+    l => l - removed,
+  };
+  for fold in folds.iter_mut() {
+    fold.start = map(fold.start);
+    fold.end = map(fold.end);
+  }
+  folds.retain(|f| f.end > f.start);
+}
+
+/// The lines that start each screen row from the one showing `from`, up to `len`.
+pub fn visible_lines(folds: &[Fold], from: usize, len: usize) -> impl Iterator<Item = usize> + '_ {
+  std::iter::successors(Some(visible_start(folds, from)), |&line| {
+    Some(next_visible(folds, line))
+  })
+  .take_while(move |&line| line < len)
+}
+
+/// The line `rows` screen rows below the one showing `line`, stopping at `last`.
+pub fn rows_down(folds: &[Fold], line: usize, rows: usize, last: usize) -> usize {
+  let mut line = visible_start(folds, line);
+  for _ in 0..rows {
+    let next = next_visible(folds, line);
+    if next > last {
+      break;
+    }
+    line = next;
+  }
+  line
+}
+
+/// The line `rows` screen rows above the one showing `line`, stopping at the top.
+pub fn rows_up(folds: &[Fold], line: usize, rows: usize) -> usize {
+  let mut line = visible_start(folds, line);
+  for _ in 0..rows {
+    match prev_visible(folds, line) {
+      Some(prev) => line = prev,
+      None => break,
+    }
+  }
+  line
+}
+
+/// Screen rows from the one showing `from` down to the one showing `to`.
+pub fn rows_between(folds: &[Fold], from: usize, to: usize) -> usize {
+  let mut line = visible_start(folds, from);
+  let target = visible_start(folds, to);
+  let mut rows = 0;
+  while line < target {
+    line = next_visible(folds, line);
+    rows += 1;
+  }
+  rows
+}
+
+/// `za`: opens the outermost closed fold at `line`, or else closes the innermost one around it.
+/// Returns false when there is no fold there.
+pub fn toggle(folds: &mut [Fold], line: usize) -> bool {
+  set_closed(folds, line, false) || set_closed(folds, line, true)
+}
+
+/// Opens or closes the innermost fold around `line`. Returns false when there is none.
+pub fn set_closed(folds: &mut [Fold], line: usize, closed: bool) -> bool {
+  let candidates = folds.iter().enumerate().filter(|(_, f)| f.contains(line));
+  // Opening starts from the outermost closed fold so the line actually becomes visible.
+  let index = match closed {
+    true => innermost(folds, line),
+    false => candidates
+      .filter(|(_, f)| f.closed)
+      .min_by_key(|(_, f)| (f.start, usize::MAX - f.end))
+      .map(|(i, _)| i),
+  };
+  match index {
+    Some(i) => {
+      folds[i].closed = closed;
+      true
+    }
+    None => false,
+  }
+}
+
+/// Replaces the indent folds with ones computed from `lines`, keeping closed the ones that start
+/// where a closed fold started before. Other folds are left alone.
+pub fn refresh_indent(folds: &mut Vec<Fold>, lines: &[String]) {
+  replace(folds, FoldKind::Indent, indent_folds(lines));
+}
@@ -0,0 +1,79 @@
+/// Lines entered on the command line, oldest first, recalled with Up and Down.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+  entries: Vec<String>,
+  /// The entry being shown while recalling, or `entries.len()` when not recalling.
+  index: usize,
+  /// What was typed before recalling started. Only entries starting with it are recalled, and
+  /// it comes back after the newest one.
+  typed: Option<String>,
+}
+
+/// Entries kept per history; older ones are forgotten.
+const MAX_ENTRIES: usize = 200;
+
+impl History {
+  pub fn new(mut entries: Vec<String>) -> Self {
+    let excess = entries.len().saturating_sub(MAX_ENTRIES);
+    entries.drain(..excess);
+    History {
+      index: entries.len(),
+      entries,
+      typed: None,
+    }
+  }
+
+  pub fn entries(&self) -> &[String] {
+    &self.entries
+  }
+
+  /// Records an entered line, unless it repeats the last one.
+  pub fn push(&mut self, entry: &str) {
+    self.reset();
+    if entry.is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+      return;
+    }
+    if self.entries.len() == MAX_ENTRIES {
+      self.entries.remove(0);
+    }
+    self.entries.push(entry.to_owned());
+    self.index = self.entries.len();
+  }
+
+  /// Stops recalling, e.g. because the line was edited.
+  pub fn reset(&mut self) {
+    self.index = self.entries.len();
+    self.typed = None;
+  }
+
+  /// The next older entry starting with what was typed, if any.
+  pub fn older(&mut self, line: &str) -> Option<String> {
+    let typed = self.typed.get_or_insert_with(|| line.to_owned());
+    let index = self.entries[..self.index]
+      .iter()
+      .rposition(|e| e.starts_with(typed.as_str()))?;
+    self.index = index;
+    Some(self.entries[index].clone())
+  }
+
+  /// The next newer entry starting with what was typed, or what was typed once past the newest.
+  pub fn newer(&mut self) -> Option<String> {
+    let typed = self.typed.clone()?;
+    if self.index == self.entries.len() {
+      return None;
+    }
+    let next = self.entries[self.index + 1..]
+      .iter()
+      .position(|e| e.starts_with(typed.as_str()));
+    match next {
+      Some(offset) => {
+        self.index += 1 + offset;
+        Some(self.entries[self.index].clone())
+      }
+      None => {
+        self.reset();
+        Some(typed)
+      }
+    }
+  }
+}
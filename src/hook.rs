@@ -0,0 +1,56 @@
+//! What happens to a buffer on `:w` before it is written, set per file type under `onsave` in
+//! the settings file:
+//!
+//! ```json
+//! { "onsave": { "rust": ["trim", "eol", "rustfmt --edition 2021"] } }
+//! ```
+//!
+//! The steps run in order. `trim` removes trailing whitespace and `eol` makes sure the file ends
+//! with a newline. Anything else is a shell command that gets the text on stdin and prints it
+//! back formatted, like `rustfmt` or `prettier --stdin-filepath x.js`.
+
+use crate::buffer::Buffer;
+use crate::error::{EditorError, Result};
+use crate::job;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Step<'a> {
+  Trim,
+  FinalNewline,
+  Format(&'a str),
+}
+
+impl<'a> Step<'a> {
+  fn parse(step: &'a str) -> Self {
+    match step.trim() {
+      "trim" => Step::Trim,
+      "eol" => Step::FinalNewline,
+      command => Step::Format(command),
+    }
+  }
+}
+
+/// Runs `steps` on `buffer`, as ordinary edits that can be undone. Stops at the first
+/// formatter that fails, whose error the save should be abandoned with.
+pub fn run(steps: &[String], buffer: &mut Buffer) -> Result<()> {
+  for step in steps {
+    match Step::parse(step) {
+      Step::Trim => buffer.trim_trailing_whitespace(),
+      Step::FinalNewline => buffer.final_newline = true,
+      Step::Format(command) => {
+        let text = buffer.lines.join("\n") + "\n";
+        let formatted = job::filter(command, &text)?;
+        if formatted.is_empty() && !text.trim().is_empty() {
+          return Err(EditorError::command(format!(
+            "\"{}\" printed nothing",
+            command
+          )));
+        }
+        if formatted != text {
+          buffer.replace_all(&formatted);
+        }
+      }
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,31 @@
+//! `Ctrl+A` and `Ctrl+X`: adding to the decimal numbers in the text.
+
+use std::ops::Range;
+
+/// `line` with `delta` added to the number at or after char `cols.start`, if one starts before
+/// `cols.end`, and the column of the new number's last digit. A `-` right before the digits
+/// makes the number negative.
+pub fn add(line: &str, cols: Range<usize>, delta: i64) -> Option<(String, usize)> {
+  let chars: Vec<char> = line.chars().collect();
+  let end_col = cols.end.min(chars.len());
+  let mut start = (cols.start..end_col).find(|&i| chars[i].is_ascii_digit())?;
+  // From the middle of a number, the whole of it.
+  if start == cols.start {
+    while start > 0 && chars[start - 1].is_ascii_digit() {
+      start -= 1;
+    }
+  }
+  let end = (start..chars.len())
+    .find(|&i| !chars[i].is_ascii_digit())
+    .unwrap_or(chars.len());
+  if start > 0 && chars[start - 1] == '-' {
+    start -= 1;
+  }
+  let digits: String = chars[start..end].iter().collect();
+  let number = digits.parse::<i64>().ok()?.saturating_add(delta);
+  let number = number.to_string();
+  let before: String = chars[..start].iter().collect();
+  let after: String = chars[end..].iter().collect();
+  let last = start + number.chars().count() - 1;
+  Some((format!("{}{}{}", before, number, after), last))
+}
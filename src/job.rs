@@ -0,0 +1,80 @@
+//! Shell commands run from the command line, like `:!cmd` and `:make`, and formatters run on
+//! save.
+
+use crate::error::{EditorError, Result};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// What the last command printed, kept for the output panel.
+#[derive(Clone, Debug)]
+pub struct Output {
+  pub command: String,
+  /// Its stdout and stderr, interleaved as they were written.
+  pub lines: Vec<String>,
+  /// The exit code, or `None` if it was killed by a signal.
+  pub status: Option<i32>,
+  /// First line shown in the panel.
+  pub scroll: usize,
+  /// Lines the panel showed at the last render.
+  pub height: usize,
+}
+
+/// Runs `command` with `sh` in `dir`, waiting for it to finish.
+pub fn run(command: &str, dir: &Path) -> io::Result<Output> {
+  // The braces send stderr to the same pipe for the whole command, not just its last part.
+  let output = Command::new("sh")
+    .arg("-c")
+    .arg(format!("{{ {}\n}} 2>&1", command))
+    .current_dir(dir)
+    .stdin(Stdio::null())
+    .output()?;
+  let text = String::from_utf8_lossy(&output.stdout);
+  Ok(Output {
+    command: command.to_owned(),
+    lines: text.lines().map(str::to_owned).collect(),
+    status: output.status.code(),
+    scroll: 0,
+    height: 0,
+  })
+}
+
+/// Runs `command` with `sh`, giving it `input` on stdin, and returns what it printed on stdout.
+/// If it fails, the error carries the first line it printed on stderr.
+pub fn filter(command: &str, input: &str) -> Result<String> {
+  let mut child = Command::new("sh")
+    .arg("-c")
+    .arg(command)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| EditorError::io("run", Path::new(command), e))?;
+  // Written from another thread so a command that prints as it reads can't fill its stdout
+  // pipe while we are still blocked on its stdin.
+  let mut stdin = child.stdin.take().expect("stdin is piped");
+  let input = input.to_owned();
+  let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+  let output = child
+    .wait_with_output()
+    .map_err(|e| EditorError::io("run", Path::new(command), e))?;
+  // A command that exits without reading all of its input breaks the pipe; its status says
+  // whether that was a failure.
+  let _ = writer.join();
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let status = match output.status.code() {
+      Some(code) => format!("exited with {}", code),
+      None => "was killed".to_owned(),
+    };
+    return Err(EditorError::command(
+      match stderr.lines().find(|l| !l.trim().is_empty()) {
+        Some(line) => format!("\"{}\" {}: {}", command, status, line.trim()),
+        None => format!("\"{}\" {}", command, status),
+      },
+    ));
+  }
+  String::from_utf8(output.stdout)
+    .map_err(|_| EditorError::command(format!("\"{}\" printed text that isn't UTF-8", command)))
+}
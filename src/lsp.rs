@@ -0,0 +1,513 @@
+//! A client for language servers, set per file type under `lsp` in the settings file:
+//!
+//! ```json
+//! { "lsp": { "rust": "rust-analyzer", "python": "pylsp" } }
+//! ```
+//!
+//! Each server runs as a child process speaking JSON-RPC on its stdin and stdout. Its messages
+//! are read on a thread of their own and handed to the editor as they come, so a slow server
+//! never holds up typing. Documents are kept in sync by sending their whole text on each change.
+
+use crate::buffer::{Buffer, Position};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+/// A message from the server of file type `filetype`, or `None` once it has exited.
+#[derive(Debug)]
+pub struct Incoming {
+  pub filetype: &'static str,
+  pub message: Option<Value>,
+}
+
+/// Hands a message to the editor. Reading stops when it returns false.
+pub type Notify = Arc<dyn Fn(Incoming) -> bool + Send + Sync>;
+
+/// What the answer to a request is for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+  Initialize,
+  /// `textDocument/rename`, renaming to `name`.
+  Rename {
+    name: String,
+  },
+  /// `textDocument/codeAction`, for the actions to choose from.
+  CodeAction,
+  /// `codeAction/resolve`, for the edit of an action that came without one.
+  ResolveCodeAction,
+  /// `workspace/executeCommand`, run for the action called `title`.
+  ExecuteCommand {
+    title: String,
+  },
+  /// `textDocument/rangeFormatting` or `textDocument/onTypeFormatting` for version `version`
+  /// of the document at `path`. Only a range formatting says how it went.
+  Format {
+    path: PathBuf,
+    version: u64,
+    on_type: bool,
+  },
+  /// `textDocument/documentSymbol`, for `:symbols`.
+  Symbols,
+  /// `textDocument/documentSymbol` for the functions in version `version` of the document at
+  /// `path`.
+  Functions {
+    path: PathBuf,
+    version: u64,
+  },
+  /// `textDocument/foldingRange` for version `version` of the document at `path`.
+  FoldingRanges {
+    path: PathBuf,
+    version: u64,
+  },
+  /// `textDocument/signatureHelp`, for the popup while typing a call.
+  SignatureHelp,
+  /// `textDocument/inlayHint` for version `version` of the document at `path`.
+  InlayHints {
+    path: PathBuf,
+    version: u64,
+  },
+}
+
+/// A label the language server has shown in the text without it being part of it, such as a
+/// parameter name or an inferred type. It goes before the char at `position`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hint {
+  pub position: Position,
+  pub label: String,
+}
+
+/// The inlay hints of a buffer.
+#[derive(Clone, Debug, Default)]
+pub struct Hints {
+  /// The version of the buffer and the lines they were last asked for.
+  pub asked: Option<(u64, usize, usize)>,
+  /// The version they are for. They aren't shown once the text has changed.
+  pub version: u64,
+  /// By position.
+  pub hints: Vec<Hint>,
+}
+
+impl Hint {
+  /// The hints in an answer to `textDocument/inlayHint`, by position.
+  pub fn parse(lines: &[String], result: &Value) -> Vec<Hint> {
+    let mut hints: Vec<Hint> = result
+      .as_array()
+      .into_iter()
+      .flatten()
+      .filter_map(|hint| {
+        let position = from_position(lines, &hint["position"])?;
+        let mut label = match &hint["label"] {
+          Value::String(label) => label.clone(),
+          Value::Array(parts) => parts.iter().filter_map(|p| p["value"].as_str()).collect(),
+          _ => return None,
+        };
+        if hint["paddingLeft"] == true {
+          label.insert(0, ' ');
+        }
+        if hint["paddingRight"] == true {
+          label.push(' ');
+        }
+        Some(Hint { position, label })
+      })
+      .collect();
+    hints.sort_by_key(|hint| hint.position);
+    hints
+  }
+}
+
+/// The signature of the call being typed, and the part of it naming the parameter the cursor
+/// is at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+  pub label: String,
+  /// Where the active parameter is in `label`, in chars, from its start up to its end.
+  pub active: Option<(usize, usize)>,
+}
+
+impl Signature {
+  /// The active signature of an answer to `textDocument/signatureHelp`, if there is one.
+  pub fn parse(result: &Value) -> Option<Signature> {
+    let signatures = result["signatures"].as_array()?;
+    let index = result["activeSignature"].as_u64().unwrap_or(0) as usize;
+    let signature = signatures.get(index).or(signatures.first())?;
+    let label = signature["label"].as_str()?.to_owned();
+    let parameter = signature["activeParameter"]
+      .as_u64()
+      .or(result["activeParameter"].as_u64())
+      .unwrap_or(0) as usize;
+    let parameter = &signature["parameters"][parameter];
+    let active = match &parameter["label"] {
+      Value::String(name) => label.find(name.as_str()).map(|start| {
+        let start = label[..start].chars().count();
+        (start, start + name.chars().count())
+      }),
+      // Offsets into the label, in UTF-16 code units.
+      Value::Array(offsets) => {
+        let offset = |i: usize| offsets.get(i)?.as_u64().map(|o| o as usize);
+        let (start, end) = (offset(0)?, offset(1)?);
+        let chars = |units: usize| {
+          let mut counted = 0;
+          label
+            .chars()
+            .take_while(|c| {
+              counted += c.len_utf16();
+              counted <= units
+            })
+            .count()
+        };
+        Some((chars(start), chars(end)))
+      }
+      _ => None,
+    };
+    Some(Signature { label, active })
+  }
+}
+
+pub struct Client {
+  pub filetype: &'static str,
+  child: Child,
+  stdin: ChildStdin,
+  next_id: u64,
+  /// Requests sent and not answered yet, by id.
+  pending: HashMap<u64, Request>,
+  /// What the server said it can do, once it has answered `initialize`.
+  pub capabilities: Option<Value>,
+  /// Messages held back until the server is initialized.
+  queue: Vec<Value>,
+  /// The version of each open document last sent, by path.
+  versions: HashMap<PathBuf, u64>,
+}
+
+impl Client {
+  /// Starts `command` with `sh` and asks it to initialize, with the current directory as the
+  /// workspace.
+  pub fn start(filetype: &'static str, command: &str, notify: Notify) -> io::Result<Client> {
+    let mut child = Command::new("sh")
+      .arg("-c")
+      .arg(format!("exec {}", command))
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null())
+      .spawn()?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+    thread::spawn(move || {
+      let mut reader = BufReader::new(stdout);
+      loop {
+        let message = read_message(&mut reader);
+        let done = message.is_none();
+        if !notify(Incoming { filetype, message }) || done {
+          return;
+        }
+      }
+    });
+    let mut client = Client {
+      filetype,
+      child,
+      stdin,
+      next_id: 0,
+      pending: HashMap::new(),
+      capabilities: None,
+      queue: Vec::new(),
+      versions: HashMap::new(),
+    };
+    let root = std::env::current_dir().unwrap_or_default();
+    let params = json!({
+      "processId": std::process::id(),
+      "rootUri": uri(&root),
+      "workspaceFolders": [{ "uri": uri(&root), "name": root.display().to_string() }],
+      "capabilities": {
+        "general": { "positionEncodings": ["utf-16"] },
+        "workspace": {
+          "applyEdit": true,
+          "workspaceEdit": { "documentChanges": true },
+          "workspaceFolders": true,
+          "executeCommand": { "dynamicRegistration": false },
+          "inlayHint": { "refreshSupport": true },
+        },
+        "textDocument": {
+          "synchronization": { "dynamicRegistration": false },
+          "rename": { "prepareSupport": false },
+          "codeAction": {
+            "codeActionLiteralSupport": { "codeActionKind": { "valueSet": [
+              "", "quickfix", "refactor", "refactor.extract", "refactor.inline",
+              "refactor.rewrite", "source", "source.organizeImports",
+            ]}},
+            "resolveSupport": { "properties": ["edit"] },
+          },
+          "publishDiagnostics": {},
+          "rangeFormatting": { "dynamicRegistration": false },
+          "onTypeFormatting": { "dynamicRegistration": false },
+          "documentSymbol": { "hierarchicalDocumentSymbolSupport": true },
+          "inlayHint": { "dynamicRegistration": false },
+          "foldingRange": { "lineFoldingOnly": true },
+          "signatureHelp": { "signatureInformation": {
+            "parameterInformation": { "labelOffsetSupport": true },
+            "activeParameterSupport": true,
+          }},
+        },
+      },
+    });
+    let id = client.next_id();
+    client.pending.insert(id, Request::Initialize);
+    client
+      .write(&json!({ "jsonrpc": "2.0", "id": id, "method": "initialize", "params": params }))?;
+    Ok(client)
+  }
+
+  /// Sends a request, returning its id.
+  pub fn request(&mut self, method: &str, params: Value, request: Request) -> io::Result<u64> {
+    let id = self.next_id();
+    self.pending.insert(id, request);
+    self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+    Ok(id)
+  }
+
+  pub fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+    self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+  }
+
+  /// Sends the document at `path` if the server hasn't seen this version of it yet. Its text
+  /// is only made then.
+  pub fn sync(
+    &mut self,
+    path: &Path,
+    version: u64,
+    text: impl FnOnce() -> String,
+  ) -> io::Result<()> {
+    let sent = self.versions.insert(path.to_path_buf(), version);
+    if sent == Some(version) {
+      return Ok(());
+    }
+    let text = text();
+    match sent {
+      Some(_) => self.notify(
+        "textDocument/didChange",
+        json!({
+          "textDocument": { "uri": uri(path), "version": version },
+          "contentChanges": [{ "text": text }],
+        }),
+      ),
+      None => self.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": {
+          "uri": uri(path),
+          "languageId": language(self.filetype),
+          "version": version,
+          "text": text,
+        }}),
+      ),
+    }
+  }
+
+  /// Takes the answer to request `id` off the list of those waiting, saying what it is for.
+  /// The answer to `initialize` is dealt with here.
+  pub fn answered(&mut self, id: u64, response: &Value) -> Option<Request> {
+    let request = self.pending.remove(&id)?;
+    if request == Request::Initialize {
+      self.capabilities = Some(response["result"]["capabilities"].clone());
+      self.initialized();
+    }
+    Some(request)
+  }
+
+  /// The `capabilities` entry for `name`, such as `"renameProvider"`, if the server has it.
+  pub fn provider(&self, name: &str) -> Option<&Value> {
+    match self.capabilities.as_ref()?.get(name)? {
+      Value::Null | Value::Bool(false) => None,
+      provider => Some(provider),
+    }
+  }
+
+  /// Answers a request the server made that the editor has nothing to say to, in the least
+  /// disruptive way.
+  pub fn reply(&mut self, id: &Value, method: &str, params: &Value) -> io::Result<()> {
+    let result = match method {
+      // One null per item asked for means the defaults.
+      "workspace/configuration" => {
+        let items = params["items"].as_array().map_or(0, Vec::len);
+        Value::Array(vec![Value::Null; items])
+      }
+      _ => Value::Null,
+    };
+    self.respond(id, result)
+  }
+
+  /// Answers request `id` from the server with `result`.
+  pub fn respond(&mut self, id: &Value, result: Value) -> io::Result<()> {
+    self.write(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+  }
+
+  fn initialized(&mut self) {
+    let _ = self.write(&json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }));
+    for message in std::mem::take(&mut self.queue) {
+      let _ = self.write(&message);
+    }
+  }
+
+  fn next_id(&mut self) -> u64 {
+    self.next_id += 1;
+    self.next_id
+  }
+
+  fn send(&mut self, message: Value) -> io::Result<()> {
+    match self.capabilities {
+      Some(_) => self.write(&message),
+      None => {
+        self.queue.push(message);
+        Ok(())
+      }
+    }
+  }
+
+  fn write(&mut self, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    self.stdin.flush()
+  }
+}
+
+impl Drop for Client {
+  fn drop(&mut self) {
+    let _ = self.write(&json!({ "jsonrpc": "2.0", "method": "exit" }));
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+/// The LSP name of a file type, which is mostly the same.
+fn language(filetype: &str) -> &str {
+  match filetype {
+    "sh" => "shellscript",
+    _ => filetype,
+  }
+}
+
+/// Reads one message, or `None` at the end of the stream or if it doesn't parse.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+  let mut length = None;
+  loop {
+    let mut header = String::new();
+    if reader.read_line(&mut header).ok()? == 0 {
+      return None;
+    }
+    let header = header.trim_end();
+    if header.is_empty() {
+      break;
+    }
+    if let Some((name, value)) = header.split_once(':') {
+      if name.eq_ignore_ascii_case("content-length") {
+        length = value.trim().parse::<usize>().ok();
+      }
+    }
+  }
+  let mut body = vec![0; length?];
+  reader.read_exact(&mut body).ok()?;
+  serde_json::from_slice(&body).ok()
+}
+
+/// The `file://` URI of `path`, which should be absolute.
+pub fn uri(path: &Path) -> String {
+  let mut uri = "file://".to_owned();
+  for byte in path.to_string_lossy().bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+        uri.push(byte as char)
+      }
+      _ => uri.push_str(&format!("%{:02X}", byte)),
+    }
+  }
+  uri
+}
+
+/// The path a `file://` URI names.
+pub fn path(uri: &str) -> Option<PathBuf> {
+  let encoded = uri.strip_prefix("file://")?.as_bytes();
+  let mut bytes = Vec::with_capacity(encoded.len());
+  let mut i = 0;
+  while i < encoded.len() {
+    let hex = encoded
+      .get(i + 1..i + 3)
+      .and_then(|h| std::str::from_utf8(h).ok());
+    match (encoded[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+      (b'%', Some(byte)) => {
+        bytes.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        bytes.push(byte);
+        i += 1;
+      }
+    }
+  }
+  Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+/// `pos` as an LSP position, which counts UTF-16 code units along the line.
+pub fn position(lines: &[String], pos: Position) -> Value {
+  let character: usize = lines.get(pos.line).map_or(0, |line| {
+    line.chars().take(pos.col).map(char::len_utf16).sum()
+  });
+  json!({ "line": pos.line, "character": character })
+}
+
+/// An LSP position as a position in `lines`, clamped to the text.
+pub fn from_position(lines: &[String], value: &Value) -> Option<Position> {
+  let line = value["line"].as_u64()? as usize;
+  let character = value["character"].as_u64()? as usize;
+  let Some(text) = lines.get(line) else {
+    let last = lines.len() - 1;
+    return Some(Position::new(last, lines[last].chars().count()));
+  };
+  let mut units = 0;
+  let mut col = 0;
+  for c in text.chars() {
+    if units >= character {
+      break;
+    }
+    units += c.len_utf16();
+    col += 1;
+  }
+  Some(Position::new(line, col))
+}
+
+/// Applies text edits to `buffer`, moving `cursor` along with the text around it. Returns
+/// how many were applied.
+pub fn apply_edits(buffer: &mut Buffer, edits: &[Value], cursor: &mut Position) -> usize {
+  let mut changes: Vec<(Position, Position, &str)> = edits
+    .iter()
+    .filter_map(|edit| {
+      let start = from_position(&buffer.lines, &edit["range"]["start"])?;
+      let end = from_position(&buffer.lines, &edit["range"]["end"])?;
+      Some((start, end, edit["newText"].as_str()?))
+    })
+    .collect();
+  // From the bottom up, so each edit leaves the positions of those still to come alone.
+  changes.sort_by_key(|change| std::cmp::Reverse(change.0));
+  for &(start, end, text) in &changes {
+    buffer.delete(start, end);
+    let new_end = buffer.insert(start, &text.replace("\r\n", "\n"));
+    if *cursor >= end {
+      *cursor = match cursor.line == end.line {
+        true => Position::new(new_end.line, new_end.col + cursor.col - end.col),
+        false => Position::new(cursor.line + new_end.line - end.line, cursor.col),
+      };
+    } else if *cursor > start {
+      *cursor = start;
+    }
+  }
+  changes.len()
+}
+
+/// The `error` of a response as a message for the user, if it is one.
+pub fn error(message: &Value) -> Option<String> {
+  let error = message.get("error")?;
+  Some(match error["message"].as_str() {
+    Some(text) => text.to_owned(),
+    None => error.to_string(),
+  })
+}
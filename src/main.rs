@@ -1,60 +1,286 @@
 #![allow(non_snake_case)]
 
-use crossterm::event::DisableMouseCapture;
-use crossterm::execute;
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode, LeaveAlternateScreen};
-use std::io::{self, Read};
-use tui::backend::Backend;
-use tui::layout::{Constraint, Direction, Layout};
-use tui::widgets::{Block, Borders};
-use tui::{backend::CrosstermBackend, Frame, Terminal};
-
-fn ui<B: Backend>(f: &mut Frame<B>) {
-  let chunks = Layout::default()
-    .direction(Direction::Vertical)
-    .margin(1)
-    .constraints(
-      [
-        Constraint::Percentage(10),
-        Constraint::Percentage(80),
-        Constraint::Percentage(10),
-      ]
-      .as_ref(),
-    )
-    .split(f.size());
-  let block = Block::default().title("Block").borders(Borders::ALL);
-  f.render_widget(block, chunks[0]);
-  let block = Block::default().title("Block 2").borders(Borders::ALL);
-  f.render_widget(block, chunks[1]);
-  let block = Block::default().title("Block 3").borders(Borders::ALL);
-  f.render_widget(block, chunks[2]);
+mod alternate;
+mod annotation;
+mod backup;
+mod blame;
+mod buffer;
+mod calc;
+mod color;
+mod complete;
+mod config;
+mod diff;
+mod editor;
+mod editorconfig;
+mod error;
+mod filetype;
+mod fold;
+mod history;
+mod hook;
+mod increment;
+mod job;
+mod lsp;
+mod mapping;
+mod matchwords;
+mod operator;
+mod options;
+mod pty;
+mod range;
+mod register;
+mod search;
+mod sign;
+mod state;
+mod statusline;
+mod substitute;
+mod symbol;
+mod terminal;
+mod tree;
+mod ui;
+mod undo;
+mod unicode;
+mod visual;
+mod vt;
+mod watch;
+mod window;
+mod word;
+
+use buffer::Buffer;
+use config::Config;
+use crossterm::event::{self, Event as CEvent, MouseEvent};
+use editor::Editor;
+use error::EditorError;
+use signal_hook::consts::{SIGHUP, SIGQUIT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use std::{env, io, thread};
+use tui::{backend::CrosstermBackend, Terminal};
+
+enum Event<I> {
+  Input(I),
+  Mouse(MouseEvent),
+  Paste(String),
+  FocusGained,
+  FocusLost,
+  /// A watched file changed on disk.
+  FileChanged(PathBuf),
+  Lsp(lsp::Incoming),
+  /// What the shell of `:terminal` printed.
+  Shell(pty::Incoming),
+  /// Who last changed the lines of a file, for `:blame`.
+  Blame(blame::Blame),
+  Tick,
+  /// Something failed outside of a command, to be shown in the footer.
+  Error(EditorError),
+  /// The process was asked to exit by a signal, or its terminal went away.
+  Terminate,
 }
 
-fn main() -> Result<(), io::Error> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  // Neither a file that can't be read nor a broken settings file should keep anyone from
+  // editing, so they are only reported.
+  let mut errors = Vec::new();
+  let mut args: Vec<String> = env::args().skip(1).collect();
+  let to_stdout = args.iter().any(|a| a == "--stdout");
+  args.retain(|a| a != "--stdout");
+  // `+42` puts the cursor of the first file on line 42. Any file can have its own position,
+  // as in `file.rs:42` or `file.rs:42:10`.
+  let line_arg = |a: &str| a.strip_prefix('+')?.parse::<usize>().ok();
+  let line = args
+    .iter()
+    .find_map(|a| line_arg(a))
+    .map(|line| (line, None));
+  args.retain(|a| line_arg(a).is_none());
+  let mut files: Vec<_> = args
+    .into_iter()
+    .map(|arg| match split_position(&arg) {
+      Some((path, line, col)) => (path, Some((line, col))),
+      None => (arg, None),
+    })
+    .collect();
+  // Text piped in is read before the terminal is set up; keys then come from the terminal
+  // itself, which crossterm opens when stdin isn't one.
+  let stdin = match files.first() {
+    Some((path, _)) => path == "-",
+    None => !io::stdin().is_terminal(),
+  };
+  if stdin && !files.is_empty() {
+    files.remove(0);
+  }
+  let mut buffers = Vec::new();
+  if stdin {
+    buffers.push((read_stdin(&mut errors), None));
+  }
+  for (path, position) in files {
+    match Buffer::open(Path::new(&path)) {
+      Ok(buffer) => buffers.push((buffer, position)),
+      Err(e) => errors.push(EditorError::io("open", Path::new(&path), e)),
+    }
+  }
+  if buffers.is_empty() {
+    buffers.push((Buffer::default(), None));
+  }
+  if let Some((_, position @ None)) = buffers.first_mut() {
+    *position = line;
+  }
+  let config = Config::load().unwrap_or_else(|e| {
+    errors.push(e);
+    Config::default()
+  });
+  let mut buffers = buffers.into_iter();
+  let (first, position) = buffers.next().expect("there is always a first buffer");
+  let mut editor = Editor::new(first, config);
+  editor.to_stdout = to_stdout;
+  if let Some((line, col)) = position {
+    editor.go_to(line, col);
+  }
+  for (buffer, position) in buffers {
+    editor.add_buffer(buffer, position);
+  }
+  if !errors.is_empty() {
+    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    editor.message = Some(errors.join("; "));
+  }
+
   // setup terminal
-  enable_raw_mode()?;
-  let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+  let guard = terminal::Guard::enter()?;
+  let backend = CrosstermBackend::new(terminal::output());
+  let depth = color::Depth::detect();
+  let mut terminal = Terminal::new(terminal::Fitted::new(backend, depth))?;
   terminal.clear()?;
 
-  loop {
-    terminal.draw(|f| ui(f))?;
+  // setup event loop
+  let (tx, rx) = mpsc::channel();
+  let watched = watch::Watched::default();
+  let watch_tx = tx.clone();
+  watch::spawn(watched.clone(), move |path| {
+    watch_tx.send(Event::FileChanged(path)).is_ok()
+  });
+  let lsp_tx = tx.clone();
+  editor.lsp_notify = Some(Arc::new(move |incoming| {
+    lsp_tx.send(Event::Lsp(incoming)).is_ok()
+  }));
+  let shell_tx = tx.clone();
+  editor.shell_notify = Some(Arc::new(move |incoming| {
+    shell_tx.send(Event::Shell(incoming)).is_ok()
+  }));
+  let blame_tx = tx.clone();
+  editor.blame_notify = Some(Arc::new(move |blame| {
+    blame_tx.send(Event::Blame(blame)).is_ok()
+  }));
+  let mut signals = Signals::new([SIGTERM, SIGHUP, SIGQUIT])?;
+  let signal_tx = tx.clone();
+  thread::spawn(move || {
+    if signals.forever().next().is_some() {
+      let _ = signal_tx.send(Event::Terminate);
+    }
+  });
+  let tick_rate = Duration::from_millis(200);
+  thread::spawn(move || {
+    let mut last_tick = Instant::now();
+    loop {
+      let timeout = tick_rate
+        .checked_sub(last_tick.elapsed())
+        .unwrap_or_else(|| Duration::from_secs(0));
+
+      // Terminal read errors are reported and retried on the next tick, not fatal.
+      let read = event::poll(timeout).and_then(|ready| ready.then(event::read).transpose());
+      if let Some(event) = read.transpose() {
+        let event = match event {
+          Ok(CEvent::Key(key)) => Event::Input(key),
+          Ok(CEvent::Mouse(mouse)) => Event::Mouse(mouse),
+          Ok(CEvent::Paste(text)) => Event::Paste(text),
+          Ok(CEvent::FocusGained) => Event::FocusGained,
+          Ok(CEvent::FocusLost) => Event::FocusLost,
+          Ok(_) => continue,
+          Err(e) => {
+            thread::sleep(tick_rate);
+            Event::Error(EditorError::io("read", Path::new("the terminal"), e))
+          }
+        };
+        if tx.send(event).is_err() {
+          break;
+        }
+      }
 
-    let mut ch = [0u8];
-    let sz = io::stdin().read(&mut ch)?;
-    if sz == 0 || ch[0] == b'q' {
-      terminal.clear()?;
-      break;
+      if last_tick.elapsed() >= tick_rate && tx.send(Event::Tick).is_ok() {
+        last_tick = Instant::now();
+      }
+    }
+  });
+
+  while !editor.should_quit {
+    terminal.draw(|f| ui::ui(f, &mut editor))?;
+    if let Ok(mut files) = watched.lock() {
+      *files = editor
+        .buffers
+        .iter()
+        .filter_map(|b| b.path.clone())
+        .collect();
+    }
+
+    match rx.recv()? {
+      Event::Input(key) => editor.handle_key(key),
+      Event::Mouse(mouse) => editor.handle_mouse(mouse),
+      Event::Paste(text) => editor.paste(&text),
+      Event::FocusGained => editor.focus_gained(),
+      Event::FocusLost => editor.focus_lost(),
+      Event::FileChanged(path) => editor.file_changed(&path),
+      Event::Lsp(incoming) => editor.lsp_message(incoming),
+      Event::Shell(incoming) => editor.shell_output(incoming),
+      Event::Blame(blame) => editor.blame_arrived(blame),
+      Event::Tick => editor.tick(),
+      Event::Error(e) => editor.message = Some(e.to_string()),
+      Event::Terminate => {
+        let saved = editor.back_up_unsaved();
+        terminal.clear()?;
+        drop(guard);
+        if saved > 0 {
+          eprintln!("{} unsaved buffer(s) were backed up, see :backups", saved);
+        }
+        return Ok(());
+      }
     }
   }
 
   // restore terminal
-  disable_raw_mode()?;
-  execute!(
-    terminal.backend_mut(),
-    LeaveAlternateScreen,
-    DisableMouseCapture
-  )?;
-  terminal.show_cursor()?;
+  terminal.clear()?;
+  drop(guard);
+  if let Some(text) = editor.stdout {
+    io::stdout().write_all(text.as_bytes())?;
+  }
 
   Ok(())
 }
+
+/// Splits `file.rs:42` or `file.rs:42:10`, as compilers and grep print positions, into the
+/// path, line and column. A file that really has such a name is opened as it is.
+fn split_position(arg: &str) -> Option<(String, usize, Option<usize>)> {
+  if Path::new(arg).exists() {
+    return None;
+  }
+  let number = |s: &str| s.parse::<usize>().ok();
+  let (rest, last) = arg.rsplit_once(':')?;
+  let last = number(last)?;
+  match rest.rsplit_once(':') {
+    Some((path, line)) if number(line).is_some() && !path.is_empty() => {
+      Some((path.to_owned(), number(line)?, Some(last)))
+    }
+    _ if !rest.is_empty() => Some((rest.to_owned(), last, None)),
+    _ => None,
+  }
+}
+
+/// An unnamed buffer with everything on stdin.
+fn read_stdin(errors: &mut Vec<EditorError>) -> Buffer {
+  let mut content = String::new();
+  match io::stdin().read_to_string(&mut content) {
+    Ok(_) => Buffer::from_content(&content),
+    Err(e) => {
+      errors.push(EditorError::io("read", Path::new("stdin"), e));
+      Buffer::default()
+    }
+  }
+}
@@ -0,0 +1,81 @@
+//! The user's own commands after the leader key, under `map` in the settings file:
+//!
+//! ```json
+//! { "map": { "<leader>w": ":w", "<leader>t": ":tree", "<leader>o": "o<Esc>k" } }
+//! ```
+//!
+//! A mapping to `:` and a command runs the command. Anything else is keys played back as if
+//! typed, with `<CR>`, `<Esc>`, `<Tab>`, `<BS>`, `<Space>`, `<lt>` and `<C-x>` for the keys
+//! that are not plain chars. Played back keys never start a mapping themselves. The leader is
+//! `\` unless `:set leader` says otherwise, such as `:set leader=space`.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::BTreeMap;
+
+/// The keys after `<leader>` of each mapping and what they map to. Mappings that do not start
+/// with `<leader>` are left out.
+pub fn leader_keys(map: &BTreeMap<String, String>) -> Vec<(&str, &str)> {
+  map
+    .iter()
+    .filter_map(|(keys, to)| Some((keys.strip_prefix("<leader>")?, to.as_str())))
+    .filter(|(keys, _)| !keys.is_empty())
+    .collect()
+}
+
+/// The key events `notation` stands for. A `<` that does not start a key name is itself.
+pub fn keys(notation: &str) -> Vec<KeyEvent> {
+  let mut keys = Vec::new();
+  let mut rest = notation;
+  while let Some(c) = rest.chars().next() {
+    let named = rest
+      .strip_prefix('<')
+      .and_then(|after| after.split_once('>'))
+      .and_then(|(name, after)| Some((named(name)?, after)));
+    match named {
+      Some((key, after)) => {
+        keys.push(key);
+        rest = after;
+      }
+      None => {
+        keys.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+      }
+    }
+  }
+  keys
+}
+
+/// How `key` is written in `keys` notation, or `None` for keys it has no name for.
+pub fn notation(key: KeyEvent) -> Option<String> {
+  let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+  Some(match key.code {
+    KeyCode::Char(c) if ctrl => format!("<C-{}>", c),
+    KeyCode::Char('<') => "<lt>".to_owned(),
+    KeyCode::Char(c) => c.to_string(),
+    KeyCode::Enter => "<CR>".to_owned(),
+    KeyCode::Esc => "<Esc>".to_owned(),
+    KeyCode::Tab => "<Tab>".to_owned(),
+    KeyCode::Backspace => "<BS>".to_owned(),
+    _ => return None,
+  })
+}
+
+fn named(name: &str) -> Option<KeyEvent> {
+  let code = match name.to_ascii_lowercase().as_str() {
+    "cr" | "enter" | "return" => KeyCode::Enter,
+    "esc" => KeyCode::Esc,
+    "tab" => KeyCode::Tab,
+    "bs" => KeyCode::Backspace,
+    "space" => KeyCode::Char(' '),
+    "lt" => KeyCode::Char('<'),
+    lower => {
+      let c = lower.strip_prefix("c-")?;
+      let mut chars = c.chars();
+      return match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)),
+        _ => None,
+      };
+    }
+  };
+  Some(KeyEvent::new(code, KeyModifiers::NONE))
+}
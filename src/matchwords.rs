@@ -0,0 +1,179 @@
+//! `%` on keywords that pair up like brackets do, such as `if`, `else` and `fi` in shell
+//! scripts, as the `matchwords` option describes them for the buffer's file type.
+//!
+//! `:set matchwords=if:elif|else:fi,do:done` makes two groups, split by commas. The words of a
+//! group are split by colons: those that open it come first, those that close it last, and any
+//! in between go in its middle. Alternatives for each are split by `|`, and one starting with
+//! `^` only counts as the first word on its line, which keeps Ruby's `return if done` from
+//! opening a block. Words are only told apart from the text around them, so ones in strings and
+//! comments count too.
+
+use crate::buffer::Position;
+
+/// A word of a group, and whether it only counts at the start of a line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keyword {
+  pub word: String,
+  pub line_start: bool,
+}
+
+/// Keywords that nest like a pair of brackets, with maybe some that go between them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+  pub open: Vec<Keyword>,
+  pub middle: Vec<Keyword>,
+  pub close: Vec<Keyword>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Role {
+  Open,
+  Middle,
+  Close,
+}
+
+impl Group {
+  fn role(&self, word: &str, first: bool) -> Option<Role> {
+    let is = |keywords: &[Keyword]| {
+      keywords
+        .iter()
+        .any(|k| k.word == word && (first || !k.line_start))
+    };
+    if is(&self.open) {
+      Some(Role::Open)
+    } else if is(&self.middle) {
+      Some(Role::Middle)
+    } else if is(&self.close) {
+      Some(Role::Close)
+    } else {
+      None
+    }
+  }
+}
+
+/// Reads a `matchwords` value, or `None` if it isn't one.
+pub fn parse(value: &str) -> Option<Vec<Group>> {
+  let keywords = |item: &str| -> Option<Vec<Keyword>> {
+    item
+      .split('|')
+      .map(|alternative| {
+        let word = alternative.strip_prefix('^').unwrap_or(alternative);
+        let valid = !word.is_empty() && word.chars().all(is_word_char);
+        valid.then(|| Keyword {
+          word: word.to_owned(),
+          line_start: word.len() < alternative.len(),
+        })
+      })
+      .collect()
+  };
+  value
+    .split(',')
+    .map(|group| {
+      let items: Vec<&str> = group.split(':').collect();
+      let (open, rest) = items.split_first()?;
+      let (close, middle) = rest.split_last()?;
+      Some(Group {
+        open: keywords(open)?,
+        middle: middle
+          .iter()
+          .map(|item| keywords(item))
+          .collect::<Option<Vec<_>>>()?
+          .concat(),
+        close: keywords(close)?,
+      })
+    })
+    .collect()
+}
+
+/// Where `%` goes from `pos` by keyword. `None` if no keyword of `groups` is under the cursor or
+/// after it on its line before any of the brackets of `pairs`, which `%` is then for. Otherwise
+/// the start of the keyword it goes to if there is one: from one that opens or goes in the
+/// middle, the next one in the middle or the one that closes, and from that back to the one
+/// that opens.
+pub fn matching(
+  lines: &[String],
+  pos: Position,
+  groups: &[Group],
+  pairs: &[(char, char)],
+) -> Option<Option<Position>> {
+  let bracket = lines[pos.line]
+    .chars()
+    .enumerate()
+    .skip(pos.col)
+    .find(|&(_, c)| pairs.iter().any(|&(open, close)| c == open || c == close))
+    .map_or(usize::MAX, |(col, _)| col);
+  let (col, group, role) = words(&lines[pos.line])
+    .into_iter()
+    .filter(|(col, word, _)| col + word.chars().count() > pos.col)
+    .take_while(|&(col, _, _)| col <= pos.col || col < bracket)
+    .find_map(|(col, word, first)| {
+      groups
+        .iter()
+        .find_map(|group| Some((col, group, group.role(&word, first)?)))
+    })?;
+  let at = Position::new(pos.line, col);
+  let mut depth = 0;
+  // From a closing keyword back to the one that opened it.
+  if role == Role::Close {
+    for line in (0..=at.line).rev() {
+      for (col, word, first) in words(&lines[line]).into_iter().rev() {
+        if line == at.line && col >= at.col {
+          continue;
+        }
+        match group.role(&word, first) {
+          Some(Role::Close) => depth += 1,
+          Some(Role::Open) if depth == 0 => return Some(Some(Position::new(line, col))),
+          Some(Role::Open) => depth -= 1,
+          _ => (),
+        }
+      }
+    }
+    return Some(None);
+  }
+  for (line, text) in lines.iter().enumerate().skip(at.line) {
+    for (col, word, first) in words(text) {
+      if line == at.line && col <= at.col {
+        continue;
+      }
+      match group.role(&word, first) {
+        Some(Role::Open) => depth += 1,
+        Some(Role::Middle | Role::Close) if depth == 0 => {
+          return Some(Some(Position::new(line, col)))
+        }
+        Some(Role::Close) => depth -= 1,
+        _ => (),
+      }
+    }
+  }
+  Some(None)
+}
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+/// The words of `line`: the column each starts at, the word, and whether it is the first thing
+/// on the line.
+fn words(line: &str) -> Vec<(usize, String, bool)> {
+  let mut words = Vec::new();
+  let mut word = String::new();
+  let mut start = 0;
+  let mut blank_before = true;
+  for (col, c) in line.chars().chain([' ']).enumerate() {
+    if is_word_char(c) {
+      if word.is_empty() {
+        start = col;
+      }
+      word.push(c);
+      continue;
+    }
+    if !word.is_empty() {
+      let first = blank_before && words.is_empty();
+      words.push((start, std::mem::take(&mut word), first));
+    }
+    if !c.is_whitespace() {
+      blank_before = false;
+    }
+  }
+  words
+}
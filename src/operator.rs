@@ -0,0 +1,242 @@
+//! Normal mode commands of more than one key: an operator such as `d` followed by a motion or
+//! a text object, as in `d2j` or `ci(`, and two-key commands such as `gg`, `za` and `"a`.
+
+use crate::buffer::Position;
+
+/// What the keys typed so far add up to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parsed {
+  /// The start of a command; more keys are needed.
+  Pending,
+  /// Not a command. The keys are dropped.
+  Invalid,
+  Command(Command),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+  /// `z`, `g`, `"`, `m`, `'`, `` ` ``, `q` or `@` followed by another key.
+  Prefixed(char, char),
+  /// The leader key followed by one of `LEADER_COMMANDS`.
+  Leader(&'static str),
+  /// The leader key followed by the keys of the user's mapping with this index.
+  Mapped(usize),
+  /// `c`, `d`, `y`, or `z` for `zf`, with the count typed between it and its target.
+  Operator {
+    op: char,
+    count: Option<usize>,
+    target: Target,
+  },
+}
+
+/// The text an operator ends up working on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extent {
+  /// From a position up to but not including another.
+  Chars(Position, Position),
+  /// Whole lines, first and last.
+  Lines(usize, usize),
+}
+
+/// What an operator works on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+  /// The operator key again, as in `dd`: whole lines from the cursor line down.
+  Lines,
+  /// `h`, `j`, `k`, `l`, `0`, `$`, `G`, `w`, `b`, `e`, `{`, `}`, or `g` for `gg`.
+  Motion(char),
+  /// `iw` or `aw`: the word under the cursor, or with `aw` the blanks around it too.
+  Word { inner: bool },
+  /// `i` or `a` and a bracket or quote: what is between the pair, or with `a` the pair too.
+  Object { inner: bool, delimiter: char },
+  /// `if` or `af`: the body of the function around the cursor, or with `af` all its lines.
+  Function { inner: bool },
+}
+
+/// What can follow the leader key: `rn` renames with the language server, `ca` offers its
+/// code actions and `f` formats.
+const LEADER_COMMANDS: &[&str] = &["rn", "ca", "f"];
+
+/// Reads the keys typed after any count, such as `"d2j"`. `leader` is the leader key and
+/// `mapped` the keys the user has mapped after it.
+pub fn parse(keys: &str, leader: char, mapped: &[&str]) -> Parsed {
+  let mut chars = keys.chars();
+  let Some(first) = chars.next() else {
+    return Parsed::Pending;
+  };
+  let rest = chars.as_str();
+  match first {
+    _ if first == leader => parse_leader(rest, mapped, false),
+    'z' if rest.starts_with('f') => parse_operator('z', &rest[1..]),
+    'z' | 'g' | '"' | 'm' | '\'' | '`' | 'q' | '@' => match rest.chars().next() {
+      None => Parsed::Pending,
+      Some(c) => Parsed::Command(Command::Prefixed(first, c)),
+    },
+    'c' | 'd' | 'y' => parse_operator(first, rest),
+    _ => Parsed::Invalid,
+  }
+}
+
+/// The command `keys` make up once no more are coming. Only leader keys that name one
+/// command and start a longer one, like `\f` next to a mapped `\fo`, wait for that.
+pub fn timed_out(keys: &str, leader: char, mapped: &[&str]) -> Option<Command> {
+  match parse_leader(keys.strip_prefix(leader)?, mapped, true) {
+    Parsed::Command(command) => Some(command),
+    _ => None,
+  }
+}
+
+/// The keys after the leader. The user's mappings come before `LEADER_COMMANDS`.
+fn parse_leader(keys: &str, mapped: &[&str], timed_out: bool) -> Parsed {
+  let exact = match mapped.iter().position(|m| *m == keys) {
+    Some(i) => Some(Command::Mapped(i)),
+    None => LEADER_COMMANDS
+      .iter()
+      .find(|c| **c == keys)
+      .map(|c| Command::Leader(c)),
+  };
+  let longer = mapped
+    .iter()
+    .chain(LEADER_COMMANDS)
+    .any(|c| c.len() > keys.len() && c.starts_with(keys));
+  match (exact, longer && !timed_out) {
+    (_, true) => Parsed::Pending,
+    (Some(command), false) => Parsed::Command(command),
+    (None, false) => Parsed::Invalid,
+  }
+}
+
+fn parse_operator(op: char, keys: &str) -> Parsed {
+  // A leading 0 is the motion, not the start of a count.
+  let digits = match keys.starts_with('0') {
+    true => 0,
+    false => keys.len() - keys.trim_start_matches(|c: char| c.is_ascii_digit()).len(),
+  };
+  let count = keys[..digits].parse().ok();
+  let mut rest = keys[digits..].chars();
+  let target = match (rest.next(), rest.next()) {
+    (None, _) => return Parsed::Pending,
+    (Some(c), None) if c == op => Target::Lines,
+    (Some(c @ ('h' | 'j' | 'k' | 'l' | '0' | '$' | 'G' | 'w' | 'b' | 'e' | '{' | '}')), None) => {
+      Target::Motion(c)
+    }
+    (Some('g' | 'i' | 'a'), None) => return Parsed::Pending,
+    (Some('g'), Some('g')) => Target::Motion('g'),
+    (Some(c @ ('i' | 'a')), Some('w')) => Target::Word { inner: c == 'i' },
+    (Some(c @ ('i' | 'a')), Some('f')) => Target::Function { inner: c == 'i' },
+    (Some(c @ ('i' | 'a')), Some(delimiter)) if pair(delimiter).is_some() => Target::Object {
+      inner: c == 'i',
+      delimiter,
+    },
+    _ => return Parsed::Invalid,
+  };
+  Parsed::Command(Command::Operator { op, count, target })
+}
+
+/// The opening and closing char of the pair a text object names, like `b` for `()`.
+fn pair(delimiter: char) -> Option<(char, char)> {
+  Some(match delimiter {
+    '(' | ')' | 'b' => ('(', ')'),
+    '[' | ']' => ('[', ']'),
+    '{' | '}' | 'B' => ('{', '}'),
+    '<' | '>' => ('<', '>'),
+    '"' | '\'' | '`' => (delimiter, delimiter),
+    _ => return None,
+  })
+}
+
+/// The text a text object covers around `cursor`, from its start up to but not including its
+/// end. Brackets may span lines and nest; quotes are looked for on the cursor line only.
+pub fn object(
+  lines: &[String],
+  cursor: Position,
+  inner: bool,
+  delimiter: char,
+) -> Option<(Position, Position)> {
+  let (open, close) = pair(delimiter)?;
+  let (start, end) = match open == close {
+    true => quotes(&lines[cursor.line], cursor.col, open).map(|(start, end)| {
+      (
+        Position::new(cursor.line, start),
+        Position::new(cursor.line, end),
+      )
+    })?,
+    false => brackets(lines, cursor, open, close)?,
+  };
+  Some(match inner {
+    true => (after(lines, start), end),
+    false => (start, after(lines, end)),
+  })
+}
+
+/// The columns of the quotes around `col`, or of the first quoted text after it.
+fn quotes(line: &str, col: usize, quote: char) -> Option<(usize, usize)> {
+  let marks: Vec<usize> = line
+    .chars()
+    .enumerate()
+    .filter(|&(_, c)| c == quote)
+    .map(|(i, _)| i)
+    .collect();
+  marks
+    .chunks_exact(2)
+    .map(|pair| (pair[0], pair[1]))
+    .find(|&(_, end)| end >= col)
+}
+
+/// The innermost unmatched `open` at or before `cursor` and the `close` matching it.
+fn brackets(
+  lines: &[String],
+  cursor: Position,
+  open: char,
+  close: char,
+) -> Option<(Position, Position)> {
+  let mut depth = 0usize;
+  let mut start = None;
+  'search: for l in (0..=cursor.line).rev() {
+    let chars: Vec<char> = lines[l].chars().collect();
+    let end = match l == cursor.line {
+      true => (cursor.col + 1).min(chars.len()),
+      false => chars.len(),
+    };
+    for i in (0..end).rev() {
+      // A closer under the cursor belongs to the pair being looked for.
+      if chars[i] == close && !(l == cursor.line && i == cursor.col) {
+        depth += 1;
+      } else if chars[i] == open {
+        if depth == 0 {
+          start = Some(Position::new(l, i));
+          break 'search;
+        }
+        depth -= 1;
+      }
+    }
+  }
+  let start = start?;
+  let mut depth = 0usize;
+  for (l, line) in lines.iter().enumerate().skip(start.line) {
+    let skip = match l == start.line {
+      true => start.col + 1,
+      false => 0,
+    };
+    for (i, c) in line.chars().enumerate().skip(skip) {
+      if c == open {
+        depth += 1;
+      } else if c == close {
+        if depth == 0 {
+          return Some((start, Position::new(l, i)));
+        }
+        depth -= 1;
+      }
+    }
+  }
+  None
+}
+
+/// The position after the char at `pos`, which is the start of the next line after the last
+/// char of a line.
+fn after(lines: &[String], pos: Position) -> Position {
+  match pos.col + 1 >= lines[pos.line].chars().count() && pos.line + 1 < lines.len() {
+    true => Position::new(pos.line + 1, 0),
+    false => Position::new(pos.line, pos.col + 1),
+  }
+}
@@ -0,0 +1,537 @@
+use crate::matchwords::{self, Group};
+use std::path::PathBuf;
+use tui::style::Color;
+
+/// Where folds come from. Folds made by hand work under either method.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FoldMethod {
+  Manual,
+  /// Also fold every block of more deeply indented lines.
+  Indent,
+  /// Also fold the ranges the language server gives, or by indentation without one.
+  Lsp,
+}
+
+/// When a window shows its sign column.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SignColumn {
+  /// While something in the buffer has a sign.
+  #[default]
+  Auto,
+  Yes,
+  No,
+}
+
+/// The line ending a buffer is written with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+  Unix,
+  Dos,
+}
+
+impl FileFormat {
+  pub fn line_ending(self) -> &'static str {
+    match self {
+      FileFormat::Unix => "\n",
+      FileFormat::Dos => "\r\n",
+    }
+  }
+}
+
+/// Where an insert session is split into more undo groups than the one it makes as a whole.
+/// Leaving insert mode always ends a group, and any other command is a group of its own.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UndoBreak {
+  /// Each line break typed starts a new group, so undo takes back one line at a time.
+  pub newline: bool,
+  /// Moving the cursor with the arrow keys starts a new group, after the text typed before it.
+  pub jump: bool,
+}
+
+/// Settings changed with `:set`.
+#[derive(Clone, Debug)]
+pub struct Options {
+  /// Scroll all windows together.
+  pub scrollbind: bool,
+  /// Let windows scroll on until the last line is at the top, rather than stopping once it is
+  /// at the bottom.
+  pub scrollpastend: bool,
+  /// Start new lines with the indent of the line above.
+  pub autoindent: bool,
+  /// Like `autoindent`, and also indent after a line ending in `{` and dedent a line starting
+  /// with `}`.
+  pub smartindent: bool,
+  /// Insert the closing bracket or quote along with the opening one.
+  pub autopairs: bool,
+  /// Have backspace in an indent of spaces take out a whole `tabstop` of them.
+  pub smarttab: bool,
+  /// Have the language server reformat around the cursor after typing one of the chars it
+  /// asks for, such as `;` or `}`.
+  pub formatontype: bool,
+  /// Show the parameter names and inferred types the language server adds to the text.
+  pub inlayhints: bool,
+  /// Shade the lines around the cursor that are at its line's indent, up to where the
+  /// indentation drops below it.
+  pub blockhighlight: bool,
+  /// Highlight the other places the word under the cursor is in view once the cursor rests.
+  pub highlightcursorword: bool,
+  /// Stay in visual mode after yanking, to work on the same text again.
+  pub stickyvisual: bool,
+  /// Take typed text literally, as if it were being pasted: no autoindent, smartindent or
+  /// autopairs.
+  pub paste: bool,
+  /// Ask before running an ex command that changes more than `confirmlines` lines.
+  pub confirm: bool,
+  pub confirmlines: usize,
+  /// How long a multi-key command like `d2j` waits for its next key, in milliseconds.
+  pub timeoutlen: usize,
+  /// The key that starts `\rn`, `\ca`, `\f` and the user's mappings; `space` for the space
+  /// bar.
+  pub leader: char,
+  /// How many undo groups each buffer keeps.
+  pub undolevels: usize,
+  /// How much text each buffer's undo history may hold, in bytes.
+  pub undobytes: usize,
+  /// `mode`, or a comma-separated list of `newline` and `jump`.
+  pub undobreak: UndoBreak,
+  /// Makes the next change part of the undo group before it; it unsets itself once used.
+  pub undojoin: bool,
+  /// Save modified buffers after this many seconds without input; 0 turns autosave off.
+  pub autosave: usize,
+  /// Also autosave as soon as the terminal loses focus.
+  pub autosavefocus: bool,
+  /// Have autosave write timestamped backups to `backupdir` instead of saving the file.
+  pub backup: bool,
+  /// Where backups go, `backups` in the state directory if unset.
+  pub backupdir: Option<PathBuf>,
+  /// How many backups are kept per file; older ones are removed.
+  pub backupcount: usize,
+  pub foldmethod: FoldMethod,
+  /// How long yanked text stays highlighted, in milliseconds; 0 turns the highlight off.
+  pub yankflash: usize,
+  /// The background of the yank highlight.
+  pub yankcolor: Color,
+  /// What new buffers start with.
+  pub buffer: BufferOptions,
+  /// The settings new windows start with.
+  pub window: WindowOptions,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      scrollbind: false,
+      scrollpastend: true,
+      autoindent: false,
+      smartindent: false,
+      autopairs: false,
+      formatontype: false,
+      inlayhints: false,
+      blockhighlight: false,
+      highlightcursorword: false,
+      stickyvisual: false,
+      paste: false,
+      smarttab: false,
+      confirm: false,
+      confirmlines: 50,
+      timeoutlen: 1000,
+      leader: '\\',
+      undolevels: 1000,
+      undobytes: 64 << 20,
+      undobreak: UndoBreak::default(),
+      undojoin: false,
+      autosave: 0,
+      autosavefocus: false,
+      backup: false,
+      backupdir: None,
+      backupcount: 10,
+      foldmethod: FoldMethod::Manual,
+      yankflash: 300,
+      yankcolor: Color::Yellow,
+      buffer: BufferOptions::default(),
+      window: WindowOptions::default(),
+    }
+  }
+}
+
+impl Options {
+  /// Like `set`, but buffer settings only change the default for buffers opened later, as
+  /// window settings always do here.
+  pub fn set_default(&mut self, arg: &str) -> Result<(), String> {
+    match self.buffer.has(arg) {
+      true => self.buffer.set(arg),
+      false => self.set(arg),
+    }
+  }
+}
+
+/// Settings each window has its own copy of, since they are about how it shows its buffer. Two
+/// windows on the same buffer can differ in them. A split starts with those of the window it
+/// was split from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WindowOptions {
+  /// Show a scrollbar on the right of the window.
+  pub scrollbar: bool,
+  /// Shade the scrollbar track by line length.
+  pub minimap: bool,
+  /// Put a sign on lines added (`+`) or changed (`~`) since the last save.
+  pub changesigns: bool,
+  /// Whether to show the sign column left of the text, with change signs, diagnostics and
+  /// marks.
+  pub signcolumn: SignColumn,
+  /// Width of the fold marker column left of the text; 0 hides it.
+  pub foldcolumn: usize,
+  /// Pin the first lines of the blocks the top line of the window is in above the text.
+  pub stickyheader: bool,
+  /// Draw control chars as `^M` and the like, in a color of their own, so they can be seen.
+  pub showcontrol: bool,
+}
+
+impl Set for WindowOptions {
+  fn flag(&mut self, name: &str) -> Option<&mut bool> {
+    match name {
+      "scrollbar" => Some(&mut self.scrollbar),
+      "minimap" => Some(&mut self.minimap),
+      "changesigns" => Some(&mut self.changesigns),
+      "stickyheader" => Some(&mut self.stickyheader),
+      "showcontrol" => Some(&mut self.showcontrol),
+      _ => None,
+    }
+  }
+
+  fn number(&mut self, name: &str) -> Option<&mut usize> {
+    match name {
+      "foldcolumn" | "fdc" => Some(&mut self.foldcolumn),
+      _ => None,
+    }
+  }
+
+  fn word(&mut self, name: &str, value: &str) -> Option<Result<(), String>> {
+    match name {
+      "signcolumn" | "scl" => {
+        self.signcolumn = match value {
+          "auto" => SignColumn::Auto,
+          "yes" => SignColumn::Yes,
+          "no" => SignColumn::No,
+          _ => return Some(Err(invalid(name, value))),
+        };
+        Some(Ok(()))
+      }
+      _ => None,
+    }
+  }
+}
+
+/// Settings each buffer has its own copy of, since they describe how its file is laid out.
+/// Besides `:set`, they can come from an `.editorconfig`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BufferOptions {
+  /// Indent with spaces instead of tab characters.
+  pub expandtab: bool,
+  /// Columns between tab stops.
+  pub tabstop: usize,
+  pub fileformat: FileFormat,
+  /// Strip whitespace from the end of lines when writing.
+  pub trimtrailing: bool,
+  /// End the file with a line ending even if it didn't have one.
+  pub fixendofline: bool,
+  /// Start the file with a UTF-8 byte order mark. Files that exist keep whatever they had
+  /// unless this is set for the buffer itself.
+  pub bomb: bool,
+  /// The chars besides letters and digits that make up words, for word motions and `iw`.
+  /// `:set iskeyword+=-` adds to them and `iskeyword-=` takes away.
+  pub iskeyword: String,
+  /// The brackets `%` jumps between, open and close. `:set matchpairs=(:),<:>` sets them and
+  /// `matchpairs+=` and `matchpairs-=` add and take away.
+  pub matchpairs: Vec<(char, char)>,
+  /// The keywords `%` also jumps between, as `matchwords.rs` describes. `matchwords+=` adds
+  /// groups.
+  pub matchwords: Vec<Group>,
+}
+
+impl Default for BufferOptions {
+  fn default() -> Self {
+    BufferOptions {
+      expandtab: false,
+      tabstop: 4,
+      fileformat: FileFormat::Unix,
+      trimtrailing: false,
+      fixendofline: true,
+      bomb: false,
+      iskeyword: "_".to_owned(),
+      matchpairs: vec![('(', ')'), ('[', ']'), ('{', '}')],
+      matchwords: Vec::new(),
+    }
+  }
+}
+
+impl BufferOptions {
+  /// Whether `c` is part of words.
+  pub fn is_keyword(&self, c: char) -> bool {
+    c.is_alphanumeric() || self.iskeyword.contains(c)
+  }
+}
+
+/// A group of settings that can be changed with `:set`.
+pub trait Set {
+  fn flag(&mut self, name: &str) -> Option<&mut bool>;
+
+  fn number(&mut self, name: &str) -> Option<&mut usize>;
+
+  /// Sets an option that takes one of a few words, like `foldmethod=indent`. Returns `None` if
+  /// there is no such option. An empty value is never valid and changes nothing.
+  fn word(&mut self, name: &str, value: &str) -> Option<Result<(), String>>;
+
+  /// Whether `arg` refers to one of these options, whatever it asks to do with it.
+  fn has(&mut self, arg: &str) -> bool {
+    let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+    let name = name.strip_suffix('!').unwrap_or(name);
+    self.flag(name).is_some()
+      || name
+        .strip_prefix("no")
+        .is_some_and(|n| self.flag(n).is_some())
+      || self.number(name).is_some()
+      || self.word(name, "").is_some()
+  }
+
+  /// Applies one `:set` argument: `name`, `noname`, `name!` or `name=value`.
+  fn set(&mut self, arg: &str) -> Result<(), String> {
+    if let Some((name, value)) = arg.split_once('=') {
+      if let Some(result) = self.word(name, value) {
+        return result;
+      }
+      let number = self
+        .number(name)
+        .ok_or_else(|| format!("Unknown option: {}", name))?;
+      *number = value
+        .parse()
+        .map_err(|_| format!("Number required after =: {}", arg))?;
+      return Ok(());
+    }
+    if let Some(flag) = self.flag(arg) {
+      *flag = true;
+      return Ok(());
+    }
+    if let Some(flag) = arg.strip_prefix("no").and_then(|name| self.flag(name)) {
+      *flag = false;
+      return Ok(());
+    }
+    if let Some(flag) = arg.strip_suffix('!').and_then(|name| self.flag(name)) {
+      *flag = !*flag;
+      return Ok(());
+    }
+    Err(format!("Unknown option: {}", arg))
+  }
+}
+
+impl Set for Options {
+  fn flag(&mut self, name: &str) -> Option<&mut bool> {
+    match name {
+      "scrollbind" | "scb" => Some(&mut self.scrollbind),
+      "scrollpastend" => Some(&mut self.scrollpastend),
+      "autoindent" | "ai" => Some(&mut self.autoindent),
+      "smartindent" | "si" => Some(&mut self.smartindent),
+      "autopairs" => Some(&mut self.autopairs),
+      "smarttab" | "sta" => Some(&mut self.smarttab),
+      "formatontype" => Some(&mut self.formatontype),
+      "inlayhints" => Some(&mut self.inlayhints),
+      "blockhighlight" => Some(&mut self.blockhighlight),
+      "highlightcursorword" => Some(&mut self.highlightcursorword),
+      "stickyvisual" => Some(&mut self.stickyvisual),
+      "paste" => Some(&mut self.paste),
+      "confirm" | "cf" => Some(&mut self.confirm),
+      "autosavefocus" => Some(&mut self.autosavefocus),
+      "backup" | "bk" => Some(&mut self.backup),
+      "undojoin" => Some(&mut self.undojoin),
+      _ => self.window.flag(name),
+    }
+  }
+
+  fn number(&mut self, name: &str) -> Option<&mut usize> {
+    match name {
+      "timeoutlen" | "tm" => Some(&mut self.timeoutlen),
+      "confirmlines" => Some(&mut self.confirmlines),
+      "undolevels" | "ul" => Some(&mut self.undolevels),
+      "undobytes" => Some(&mut self.undobytes),
+      "autosave" => Some(&mut self.autosave),
+      "backupcount" => Some(&mut self.backupcount),
+      "yankflash" => Some(&mut self.yankflash),
+      _ => self.window.number(name),
+    }
+  }
+
+  fn word(&mut self, name: &str, value: &str) -> Option<Result<(), String>> {
+    match name {
+      "foldmethod" | "fdm" => {
+        self.foldmethod = match value {
+          "manual" => FoldMethod::Manual,
+          "indent" => FoldMethod::Indent,
+          "lsp" => FoldMethod::Lsp,
+          _ => return Some(Err(invalid(name, value))),
+        };
+        Some(Ok(()))
+      }
+      "backupdir" | "bdir" => {
+        if value.is_empty() {
+          return Some(Err(invalid(name, value)));
+        }
+        self.backupdir = Some(PathBuf::from(value));
+        Some(Ok(()))
+      }
+      "leader" | "mapleader" => {
+        self.leader = match value {
+          "space" | "<space>" | "<Space>" => ' ',
+          _ => match (value.chars().next(), value.chars().nth(1)) {
+            (Some(c), None) => c,
+            _ => return Some(Err(invalid(name, value))),
+          },
+        };
+        Some(Ok(()))
+      }
+      "yankcolor" => {
+        self.yankcolor = match color(value) {
+          Some(color) => color,
+          None => return Some(Err(invalid(name, value))),
+        };
+        Some(Ok(()))
+      }
+      "undobreak" | "ub" => {
+        let mut breaks = UndoBreak::default();
+        for rule in value.split(',') {
+          match rule {
+            "mode" if value == "mode" => (),
+            "newline" => breaks.newline = true,
+            "jump" => breaks.jump = true,
+            _ => return Some(Err(invalid(name, value))),
+          }
+        }
+        self.undobreak = breaks;
+        Some(Ok(()))
+      }
+      _ => self.window.word(name, value),
+    }
+  }
+}
+
+impl Set for BufferOptions {
+  fn flag(&mut self, name: &str) -> Option<&mut bool> {
+    match name {
+      "expandtab" | "et" => Some(&mut self.expandtab),
+      "trimtrailing" => Some(&mut self.trimtrailing),
+      "fixendofline" | "fixeol" => Some(&mut self.fixendofline),
+      "bomb" => Some(&mut self.bomb),
+      _ => None,
+    }
+  }
+
+  fn number(&mut self, name: &str) -> Option<&mut usize> {
+    match name {
+      "tabstop" | "ts" => Some(&mut self.tabstop),
+      _ => None,
+    }
+  }
+
+  fn word(&mut self, name: &str, value: &str) -> Option<Result<(), String>> {
+    match name {
+      "fileformat" | "ff" => {
+        self.fileformat = match value {
+          "unix" => FileFormat::Unix,
+          "dos" => FileFormat::Dos,
+          _ => return Some(Err(invalid(name, value))),
+        };
+        Some(Ok(()))
+      }
+      "iskeyword" | "isk" | "iskeyword+" | "isk+" | "iskeyword-" | "isk-" => {
+        if value.is_empty() || value.contains(char::is_whitespace) {
+          return Some(Err(invalid(name, value)));
+        }
+        match name.chars().last() {
+          Some('+') => {
+            let added: String = value
+              .chars()
+              .filter(|&c| !self.iskeyword.contains(c))
+              .collect();
+            self.iskeyword.push_str(&added);
+          }
+          Some('-') => self.iskeyword.retain(|c| !value.contains(c)),
+          _ => self.iskeyword = value.to_owned(),
+        }
+        Some(Ok(()))
+      }
+      "matchpairs" | "mps" | "matchpairs+" | "mps+" | "matchpairs-" | "mps-" => {
+        let Some(pairs) = match_pairs(value) else {
+          return Some(Err(invalid(name, value)));
+        };
+        match name.chars().last() {
+          Some('+') => {
+            let added: Vec<_> = pairs
+              .into_iter()
+              .filter(|pair| !self.matchpairs.contains(pair))
+              .collect();
+            self.matchpairs.extend(added);
+          }
+          Some('-') => self.matchpairs.retain(|pair| !pairs.contains(pair)),
+          _ => self.matchpairs = pairs,
+        }
+        Some(Ok(()))
+      }
+      "matchwords" | "matchwords+" => {
+        let Some(groups) = matchwords::parse(value) else {
+          return Some(Err(invalid(name, value)));
+        };
+        match name.ends_with('+') {
+          true => self.matchwords.extend(groups),
+          false => self.matchwords = groups,
+        }
+        Some(Ok(()))
+      }
+      _ => None,
+    }
+  }
+}
+
+/// The pairs of a `matchpairs` value: an open and a close char with `:` between, separated by
+/// commas, as in `(:),<:>`.
+fn match_pairs(value: &str) -> Option<Vec<(char, char)>> {
+  value
+    .split(',')
+    .map(|pair| {
+      let mut chars = pair.chars();
+      match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some(open), Some(':'), Some(close), None) if open != close => Some((open, close)),
+        _ => None,
+      }
+    })
+    .collect()
+}
+
+/// One of the terminal's named colors, or `#rrggbb`.
+fn color(name: &str) -> Option<Color> {
+  Some(match name {
+    "black" => Color::Black,
+    "red" => Color::Red,
+    "green" => Color::Green,
+    "yellow" => Color::Yellow,
+    "blue" => Color::Blue,
+    "magenta" => Color::Magenta,
+    "cyan" => Color::Cyan,
+    "gray" => Color::Gray,
+    "darkgray" => Color::DarkGray,
+    "lightred" => Color::LightRed,
+    "lightgreen" => Color::LightGreen,
+    "lightyellow" => Color::LightYellow,
+    "lightblue" => Color::LightBlue,
+    "lightmagenta" => Color::LightMagenta,
+    "lightcyan" => Color::LightCyan,
+    "white" => Color::White,
+    _ if name.len() == 7 && name.starts_with('#') => {
+      let component = |i: usize| u8::from_str_radix(name.get(i..i + 2)?, 16).ok();
+      Color::Rgb(component(1)?, component(3)?, component(5)?)
+    }
+    _ => return None,
+  })
+}
+
+fn invalid(name: &str, value: &str) -> String {
+  format!("Invalid argument: {}={}", name, value)
+}
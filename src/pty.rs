@@ -0,0 +1,235 @@
+//! The shell `:terminal` opens, running on a pseudo-terminal of its own so it behaves as it
+//! would in a terminal window. What it prints is read on a thread of its own and handed to the
+//! editor as it comes, and keys typed in its pane are sent to it as a terminal would send them.
+//! Without the `terminal` feature, `:terminal` only says it isn't there.
+
+use crate::vt::Screen;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// What the shell printed, or `Exited` once it is gone.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "terminal"), allow(dead_code))]
+pub enum Incoming {
+  Output(Vec<u8>),
+  Exited,
+}
+
+/// Hands output to the editor. Reading stops when it returns false.
+pub type Notify = Arc<dyn Fn(Incoming) -> bool + Send + Sync>;
+
+/// A shell and its screen.
+pub struct Shell {
+  pub screen: Screen,
+  pty: Pty,
+}
+
+impl Shell {
+  /// Starts `$SHELL`, or `sh` without one, in `dir` on a terminal of `rows` by `cols`.
+  pub fn spawn(rows: usize, cols: usize, dir: &Path, notify: Notify) -> io::Result<Shell> {
+    let screen = Screen::new(rows, cols);
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_owned());
+    let pty = Pty::spawn(&shell, screen.rows, screen.cols, dir, notify)?;
+    Ok(Shell { screen, pty })
+  }
+
+  /// Takes in what the shell printed.
+  pub fn output(&mut self, bytes: &[u8]) {
+    self.screen.feed(bytes);
+  }
+
+  /// Sends the bytes of `key`, if it has any.
+  pub fn key(&mut self, key: KeyEvent) -> io::Result<()> {
+    match key_bytes(key, self.screen.app_cursor) {
+      Some(bytes) => self.pty.write(&bytes),
+      None => Ok(()),
+    }
+  }
+
+  /// Sends `text` as if typed.
+  pub fn paste(&mut self, text: &str) -> io::Result<()> {
+    self.pty.write(text.as_bytes())
+  }
+
+  /// Makes the screen `rows` by `cols` and tells the shell, if that is a change.
+  pub fn resize(&mut self, rows: usize, cols: usize) {
+    if (rows.max(1), cols.max(1)) != (self.screen.rows, self.screen.cols) {
+      self.screen.resize(rows, cols);
+      self.pty.resize(self.screen.rows, self.screen.cols);
+    }
+  }
+}
+
+/// The bytes a terminal sends for `key`. With `app_cursor`, as full-screen programs ask for,
+/// the arrow keys are sent as `ESC O` and a letter.
+fn key_bytes(key: KeyEvent, app_cursor: bool) -> Option<Vec<u8>> {
+  let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+  let alt = key.modifiers.contains(KeyModifiers::ALT);
+  let arrow = |c: char| match app_cursor {
+    true => format!("\x1bO{}", c),
+    false => format!("\x1b[{}", c),
+  };
+  let bytes = match key.code {
+    KeyCode::Char(c) if ctrl && c.is_ascii() => match c {
+      ' ' | '@' | '2' => vec![0],
+      '[' | '3' => vec![0x1b],
+      '\\' | '4' => vec![0x1c],
+      ']' | '5' => vec![0x1d],
+      '^' | '6' => vec![0x1e],
+      '_' | '7' | '/' => vec![0x1f],
+      c => vec![c.to_ascii_lowercase() as u8 & 0x1f],
+    },
+    KeyCode::Char(c) => c.to_string().into_bytes(),
+    KeyCode::Enter => vec![b'\r'],
+    KeyCode::Backspace => vec![0x7f],
+    KeyCode::Tab => vec![b'\t'],
+    KeyCode::BackTab => b"\x1b[Z".to_vec(),
+    KeyCode::Esc => vec![0x1b],
+    KeyCode::Up => arrow('A').into_bytes(),
+    KeyCode::Down => arrow('B').into_bytes(),
+    KeyCode::Right => arrow('C').into_bytes(),
+    KeyCode::Left => arrow('D').into_bytes(),
+    KeyCode::Home => arrow('H').into_bytes(),
+    KeyCode::End => arrow('F').into_bytes(),
+    KeyCode::Insert => b"\x1b[2~".to_vec(),
+    KeyCode::Delete => b"\x1b[3~".to_vec(),
+    KeyCode::PageUp => b"\x1b[5~".to_vec(),
+    KeyCode::PageDown => b"\x1b[6~".to_vec(),
+    KeyCode::F(n @ 1..=4) => format!("\x1bO{}", (b'P' + n - 1) as char).into_bytes(),
+    KeyCode::F(n @ 5..=12) => {
+      let code = [15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5];
+      format!("\x1b[{}~", code).into_bytes()
+    }
+    _ => return None,
+  };
+  // Alt sends an escape first.
+  Some(match alt {
+    true => [vec![0x1b], bytes].concat(),
+    false => bytes,
+  })
+}
+
+#[cfg(feature = "terminal")]
+struct Pty {
+  master: std::fs::File,
+  child: std::process::Child,
+}
+
+#[cfg(feature = "terminal")]
+impl Pty {
+  fn spawn(shell: &str, rows: usize, cols: usize, dir: &Path, notify: Notify) -> io::Result<Pty> {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let check = |result: libc::c_int| match result {
+      -1 => Err(io::Error::last_os_error()),
+      _ => Ok(()),
+    };
+    // SAFETY: the descriptor is checked before it is owned, and `ptsname`'s static buffer is
+    // copied out at once.
+    let (master, slave) = unsafe {
+      let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+      check(fd)?;
+      let master = File::from_raw_fd(fd);
+      check(libc::grantpt(fd))?;
+      check(libc::unlockpt(fd))?;
+      let name = libc::ptsname(fd);
+      if name.is_null() {
+        return Err(io::Error::last_os_error());
+      }
+      let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+      (master, File::options().read(true).write(true).open(name)?)
+    };
+    let mut command = Command::new(shell);
+    command
+      .current_dir(dir)
+      .env("TERM", "xterm-256color")
+      .stdin(Stdio::from(slave.try_clone()?))
+      .stdout(Stdio::from(slave.try_clone()?))
+      .stderr(Stdio::from(slave));
+    // SAFETY: only async-signal-safe calls, between fork and exec. The shell leads a session
+    // of its own, with the pseudo-terminal as the terminal that controls it.
+    unsafe {
+      command.pre_exec(|| {
+        if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+          return Err(io::Error::last_os_error());
+        }
+        Ok(())
+      });
+    }
+    let pty = Pty {
+      master,
+      child: command.spawn()?,
+    };
+    pty.resize(rows, cols);
+    let mut reader = pty.master.try_clone()?;
+    std::thread::spawn(move || {
+      let mut buf = [0; 4096];
+      loop {
+        // Reading fails with EIO once the shell has closed its side.
+        match reader.read(&mut buf) {
+          Ok(0) | Err(_) => {
+            notify(Incoming::Exited);
+            return;
+          }
+          Ok(n) => {
+            if !notify(Incoming::Output(buf[..n].to_vec())) {
+              return;
+            }
+          }
+        }
+      }
+    });
+    Ok(pty)
+  }
+
+  fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    self.master.write_all(bytes)
+  }
+
+  fn resize(&self, rows: usize, cols: usize) {
+    use std::os::unix::io::AsRawFd;
+    let size = libc::winsize {
+      ws_row: rows as u16,
+      ws_col: cols as u16,
+      ws_xpixel: 0,
+      ws_ypixel: 0,
+    };
+    // SAFETY: `size` outlives the call. A failure leaves the old size, which is harmless.
+    unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+  }
+}
+
+#[cfg(feature = "terminal")]
+impl Drop for Pty {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+#[cfg(not(feature = "terminal"))]
+struct Pty;
+
+#[cfg(not(feature = "terminal"))]
+impl Pty {
+  fn spawn(_: &str, _: usize, _: usize, _: &Path, _: Notify) -> io::Result<Pty> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "built without the terminal feature",
+    ))
+  }
+
+  fn write(&mut self, _: &[u8]) -> io::Result<()> {
+    Ok(())
+  }
+
+  fn resize(&self, _: usize, _: usize) {}
+}
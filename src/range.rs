@@ -0,0 +1,88 @@
+//! The line ranges that go before ex commands, as in `:10,20d` or `:.,+3>`. A range is one
+//! address or two separated by `,`, or `%` for every line. An address is a line number, `.`
+//! for the cursor line, `$` for the last line or `'a` for the line of mark `a`, followed by any
+//! number of `+N` and `-N` offsets. An address of offsets alone counts from the cursor line.
+
+/// The range `:` starts the command line with in visual mode: the lines of the selection, and
+/// for a block selection only its columns.
+pub const VISUAL: &str = "'<,'>";
+
+/// Splits an ex command into its range and the rest.
+pub fn split(command: &str) -> (&str, &str) {
+  let mut chars = command.char_indices();
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '0'..='9' | '.' | '$' | '%' | ',' | '+' | '-' => (),
+      // The mark name.
+      '\'' => {
+        if chars.next().is_none() {
+          return (command, "");
+        }
+      }
+      _ => return command.split_at(i),
+    }
+  }
+  (command, "")
+}
+
+/// The first and last line of `range`, counted from 0, for a buffer whose last line is `last`.
+/// `mark` says on which line a mark is.
+pub fn lines(
+  range: &str,
+  cursor: usize,
+  last: usize,
+  mark: impl Fn(char) -> Option<usize>,
+) -> Result<(usize, usize), String> {
+  if range == "%" {
+    return Ok((0, last));
+  }
+  let line = |spec: &str| address(spec, cursor, last, &mark);
+  let (from, to) = match range.split_once(',') {
+    Some((from, to)) => (line(from), line(to)),
+    None => (line(range), line(range)),
+  };
+  let (Some(from), Some(to)) = (from, to) else {
+    return Err(format!("Invalid range: {}", range));
+  };
+  Ok((from.min(to), from.max(to)))
+}
+
+fn address(
+  spec: &str,
+  cursor: usize,
+  last: usize,
+  mark: &impl Fn(char) -> Option<usize>,
+) -> Option<usize> {
+  let offsets = spec.find(['+', '-']).unwrap_or(spec.len());
+  let (base, mut rest) = spec.split_at(offsets);
+  let mut line = match base {
+    "" | "." => cursor,
+    "$" => last,
+    _ if base.starts_with('\'') => {
+      let mut name = base[1..].chars();
+      match (name.next(), name.next()) {
+        (Some(name), None) => mark(name)?,
+        _ => return None,
+      }
+    }
+    n => n.parse::<usize>().ok()?.saturating_sub(1),
+  };
+  while let Some(sign) = rest.chars().next() {
+    let after = &rest[1..];
+    let digits = after
+      .find(|c: char| !c.is_ascii_digit())
+      .unwrap_or(after.len());
+    // `+` and `-` alone are 1.
+    let n = match digits {
+      0 => 1,
+      _ => after[..digits].parse::<usize>().ok()?,
+    };
+    line = match sign {
+      '+' => line.saturating_add(n),
+      '-' => line.checked_sub(n)?,
+      _ => return None,
+    };
+    rest = &after[digits..];
+  }
+  Some(line.min(last))
+}
@@ -0,0 +1,117 @@
+//! The registers that yanks and deletes fill and `p` puts back. Without a name they go to the
+//! unnamed register; `"a` to `"z` name one to use instead, and `"A` to `"Z` add to it. Every
+//! yank is also kept in `"0`, and every delete of lines in `"1`, with the older ones moving up
+//! to `"9`. A delete within a line goes to `"-` instead.
+
+use crate::visual::VisualKind;
+use std::collections::BTreeMap;
+
+/// Yanked or deleted text, one entry per line it spans.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+  pub lines: Vec<String>,
+  /// How the text was taken, which decides how it is put: inside a line, as whole lines or as
+  /// a column.
+  pub kind: VisualKind,
+}
+
+#[derive(Debug, Default)]
+pub struct Registers {
+  /// What was last yanked or deleted.
+  unnamed: Option<Register>,
+  /// `a` to `z`, `0` to `9` and `-`.
+  named: BTreeMap<char, Register>,
+}
+
+/// Whether `"` followed by `name` picks a register.
+pub fn valid(name: char) -> bool {
+  name.is_ascii_alphanumeric() || matches!(name, '"' | '-')
+}
+
+impl Registers {
+  /// The register called `name`, or the unnamed one for `None` or `"`.
+  pub fn get(&self, name: Option<char>) -> Option<&Register> {
+    match name {
+      None | Some('"') => self.unnamed.as_ref(),
+      Some(name) => self.named.get(&name.to_ascii_lowercase()),
+    }
+  }
+
+  /// Keeps yanked text in the unnamed register, in `"0` and in the register called `name`.
+  pub fn yanked(&mut self, name: Option<char>, register: Register) {
+    if name.is_none() {
+      self.named.insert('0', register.clone());
+    }
+    self.store(name, register);
+  }
+
+  /// Keeps deleted text in the unnamed register, in the register called `name`, and in the
+  /// delete history if it spans lines or in `"-` if not.
+  pub fn deleted(&mut self, name: Option<char>, register: Register) {
+    let small = register.kind != VisualKind::Line && register.lines.len() == 1;
+    if small && name.is_none() {
+      self.named.insert('-', register.clone());
+    } else if name.is_none() {
+      for n in (1..9).rev() {
+        let digit = char::from(b'0' + n);
+        if let Some(older) = self.named.remove(&digit) {
+          self.named.insert(char::from(b'1' + n), older);
+        }
+      }
+      self.named.insert('1', register.clone());
+    }
+    self.store(name, register);
+  }
+
+  fn store(&mut self, name: Option<char>, register: Register) {
+    let register = match name {
+      Some(name) if name != '"' => self.set(name, register),
+      _ => register,
+    };
+    self.unnamed = Some(register);
+  }
+
+  /// Puts `register` in the one called `name`, or for an uppercase name after what the
+  /// lowercase one holds, and returns what it holds now. The unnamed register is left alone,
+  /// as it is for the keys `qa` records.
+  pub fn set(&mut self, name: char, register: Register) -> Register {
+    let register = match name {
+      'A'..='Z' => match self.named.remove(&name.to_ascii_lowercase()) {
+        Some(existing) => append(existing, register),
+        None => register,
+      },
+      _ => register,
+    };
+    self
+      .named
+      .insert(name.to_ascii_lowercase(), register.clone());
+    register
+  }
+
+  /// The registers that hold something, the unnamed one first and then by name.
+  pub fn list(&self) -> Vec<(char, &Register)> {
+    let unnamed = self.unnamed.iter().map(|register| ('"', register));
+    let named = self.named.iter().map(|(&name, register)| (name, register));
+    unnamed.chain(named).collect()
+  }
+}
+
+/// `register` added to the end of `existing`. Text goes on the same line as the text before
+/// it; with lines on either side the two become lines.
+fn append(mut existing: Register, register: Register) -> Register {
+  match (existing.kind, register.kind) {
+    (VisualKind::Char, VisualKind::Char) => {
+      let mut lines = register.lines.into_iter();
+      if let (Some(last), Some(first)) = (existing.lines.last_mut(), lines.next()) {
+        last.push_str(&first);
+      }
+      existing.lines.extend(lines);
+    }
+    (VisualKind::Line, _) | (_, VisualKind::Line) => {
+      existing.kind = VisualKind::Line;
+      existing.lines.extend(register.lines);
+    }
+    _ => existing.lines.extend(register.lines),
+  }
+  existing
+}
@@ -0,0 +1,150 @@
+//! Searching with `/`. Like `:s`, patterns are matched literally. An offset after a second
+//! `/` moves the cursor away from the match.
+
+use crate::buffer::Position;
+
+/// The next match of `pattern` after `from`, or the previous one when not `forward`, wrapping
+/// around the ends of the buffer. Also says whether it wrapped.
+pub fn find(
+  lines: &[String],
+  from: Position,
+  pattern: &str,
+  forward: bool,
+) -> Option<(Position, bool)> {
+  if pattern.is_empty() {
+    return None;
+  }
+  let len = lines.len() as isize;
+  // One step more than there are lines, to come back round to the start of `from.line`.
+  for step in 0..=len {
+    let line = match forward {
+      true => from.line as isize + step,
+      false => from.line as isize - step,
+    };
+    let wrapped = !(0..len).contains(&line);
+    let line = line.rem_euclid(len) as usize;
+    let text = &lines[line];
+    let mut cols = text
+      .match_indices(pattern)
+      .map(|(i, _)| text[..i].chars().count())
+      .filter(|&col| match (step, forward) {
+        (0, true) => col > from.col,
+        (0, false) => col < from.col,
+        _ => true,
+      });
+    let col = match forward {
+      true => cols.next(),
+      false => cols.last(),
+    };
+    if let Some(col) = col {
+      return Some((Position::new(line, col), wrapped));
+    }
+  }
+  None
+}
+
+/// Where the cursor goes relative to a match, from the end of a search like `/pattern/e`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Offset {
+  /// `s` or `b`, or nothing: this many chars from the start of the match.
+  Start(isize),
+  /// `e`: this many chars from the last char of the match.
+  End(isize),
+  /// `+2`, `-1` and the like: the start of the line this many lines from the match. A bare
+  /// `+` or `-` is one line.
+  Lines(isize),
+}
+
+impl Default for Offset {
+  fn default() -> Self {
+    Offset::Start(0)
+  }
+}
+
+/// Splits what was typed after `/` into the pattern and its offset. An unescaped `/` ends the
+/// pattern, and `\/` stands for a slash in it.
+pub fn parse(input: &str) -> Result<(String, Offset), String> {
+  let mut pattern = String::new();
+  let mut chars = input.char_indices();
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '\\' if input[i + 1..].starts_with('/') => {
+        pattern.push('/');
+        chars.next();
+      }
+      '/' => {
+        let offset = &input[i + 1..];
+        let invalid = || format!("Invalid search offset: {}", offset);
+        let number = |s: &str| match s {
+          "" => Some(0),
+          "+" => Some(1),
+          "-" => Some(-1),
+          s => s.strip_prefix('+').unwrap_or(s).parse().ok(),
+        };
+        let offset = match offset.chars().next() {
+          None => Offset::Start(0),
+          Some('e') => Offset::End(number(&offset[1..]).ok_or_else(invalid)?),
+          Some('s' | 'b') => Offset::Start(number(&offset[1..]).ok_or_else(invalid)?),
+          Some(_) => Offset::Lines(number(offset).ok_or_else(invalid)?),
+        };
+        return Ok((pattern, offset));
+      }
+      c => pattern.push(c),
+    }
+  }
+  Ok((pattern, Offset::default()))
+}
+
+/// Where `offset` puts the cursor for a match of `len` chars at `at`, kept inside the buffer.
+pub fn apply(lines: &[String], at: Position, len: usize, offset: Offset) -> Position {
+  let line_len = |line: usize| lines[line].chars().count();
+  let col = |col: isize| col.clamp(0, line_len(at.line).saturating_sub(1) as isize) as usize;
+  match offset {
+    Offset::Start(n) => Position::new(at.line, col(at.col as isize + n)),
+    Offset::End(n) => Position::new(at.line, col((at.col + len) as isize - 1 + n)),
+    Offset::Lines(n) => {
+      let line = (at.line as isize + n).clamp(0, lines.len() as isize - 1);
+      Position::new(line as usize, 0)
+    }
+  }
+}
+
+/// Where each match of `pattern` starts.
+pub fn all(lines: &[String], pattern: &str) -> Vec<Position> {
+  if pattern.is_empty() {
+    return Vec::new();
+  }
+  lines
+    .iter()
+    .enumerate()
+    .flat_map(|(l, text)| {
+      text
+        .match_indices(pattern)
+        .map(move |(i, _)| Position::new(l, text[..i].chars().count()))
+    })
+    .collect()
+}
+
+/// How many matches `count` looks for before it gives up.
+pub const MAX_COUNT: usize = 999;
+
+/// Which match of `pattern` the one at `at` is, counting from 1, and how many matches there
+/// are. Counting stops at `MAX_COUNT`, which leaves the index 0 if `at` wasn't reached.
+pub fn count(lines: &[String], pattern: &str, at: Position) -> (usize, usize) {
+  let (mut index, mut total) = (0, 0);
+  if pattern.is_empty() {
+    return (index, total);
+  }
+  for (l, text) in lines.iter().enumerate() {
+    for (i, _) in text.match_indices(pattern) {
+      total += 1;
+      if l == at.line && text[..i].chars().count() == at.col {
+        index = total;
+      }
+      if total == MAX_COUNT {
+        return (index, total);
+      }
+    }
+  }
+  (index, total)
+}
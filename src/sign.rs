@@ -0,0 +1,48 @@
+//! The sign column: a char in the gutter for each line something marks, such as a change
+//! since the last save, a diagnostic or a mark. Each source gives its signs a priority, and
+//! where several mark the same line only the highest shows.
+
+use std::collections::BTreeMap;
+use tui::style::Color;
+
+/// A char drawn in `color` beside a line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sign {
+  pub text: char,
+  pub color: Color,
+  pub priority: u8,
+}
+
+impl Sign {
+  pub fn new(text: char, color: Color, priority: u8) -> Self {
+    Sign {
+      text,
+      color,
+      priority,
+    }
+  }
+}
+
+/// The sources' priorities, so an error hides a change sign and a change sign hides a mark.
+pub const ERROR: u8 = 40;
+pub const WARNING: u8 = 30;
+pub const HINT: u8 = 20;
+pub const CHANGE: u8 = 10;
+pub const MARK: u8 = 5;
+
+/// The sign that shows on each line of `signs`: the highest priority one, or of those that tie
+/// the first.
+pub fn merge(signs: impl IntoIterator<Item = (usize, Sign)>) -> BTreeMap<usize, Sign> {
+  let mut merged: BTreeMap<usize, Sign> = BTreeMap::new();
+  for (line, sign) in signs {
+    merged
+      .entry(line)
+      .and_modify(|shown| {
+        if sign.priority > shown.priority {
+          *shown = sign;
+        }
+      })
+      .or_insert(sign);
+  }
+  merged
+}
@@ -0,0 +1,100 @@
+//! Per-file state remembered between sessions, kept under `$XDG_STATE_HOME/littleeditor`.
+//! Failing to read or write it is never worth interrupting the user, so errors are ignored.
+
+use crate::buffer::Buffer;
+use crate::fold::{Fold, FoldKind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+type FoldTable = BTreeMap<String, Vec<Fold>>;
+
+pub fn dir() -> Option<PathBuf> {
+  let base = env::var_os("XDG_STATE_HOME")
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+  Some(base.join("littleeditor"))
+}
+
+/// The key a file is remembered under, stable however it was named on the command line.
+fn key(path: &Path) -> String {
+  fs::canonicalize(path)
+    .unwrap_or_else(|_| path.to_path_buf())
+    .display()
+    .to_string()
+}
+
+/// Command lines and searches from earlier sessions, oldest first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Histories {
+  pub command: Vec<String>,
+  pub search: Vec<String>,
+}
+
+pub fn load_history() -> Histories {
+  dir()
+    .and_then(|dir| fs::read_to_string(dir.join("history.json")).ok())
+    .and_then(|json| serde_json::from_str(&json).ok())
+    .unwrap_or_default()
+}
+
+pub fn save_history(histories: &Histories) {
+  let Some(dir) = dir() else {
+    return;
+  };
+  if fs::create_dir_all(&dir).is_ok() {
+    if let Ok(json) = serde_json::to_string(histories) {
+      let _ = fs::write(dir.join("history.json"), json);
+    }
+  }
+}
+
+fn read_folds() -> FoldTable {
+  dir()
+    .and_then(|dir| fs::read_to_string(dir.join("folds.json")).ok())
+    .and_then(|json| serde_json::from_str(&json).ok())
+    .unwrap_or_default()
+}
+
+/// Restores the manual folds saved for the buffer's file, dropping any that no longer fit.
+pub fn load_folds(buffer: &mut Buffer) {
+  let Some(path) = &buffer.path else {
+    return;
+  };
+  let len = buffer.lines.len();
+  if let Some(folds) = read_folds().remove(&key(path)) {
+    buffer
+      .folds
+      .extend(folds.into_iter().filter(|f| f.end < len && f.start < f.end));
+  }
+}
+
+/// Remembers the manual folds of every buffer that has a file.
+pub fn save_folds(buffers: &[Buffer]) {
+  let Some(dir) = dir() else {
+    return;
+  };
+  let mut table = read_folds();
+  for buffer in buffers {
+    let Some(path) = &buffer.path else {
+      continue;
+    };
+    let manual: Vec<Fold> = buffer
+      .folds
+      .iter()
+      .filter(|f| f.kind == FoldKind::Manual)
+      .cloned()
+      .collect();
+    match manual.is_empty() {
+      true => table.remove(&key(path)),
+      false => table.insert(key(path), manual),
+    };
+  }
+  if fs::create_dir_all(&dir).is_ok() {
+    if let Ok(json) = serde_json::to_string(&table) {
+      let _ = fs::write(dir.join("folds.json"), json);
+    }
+  }
+}
@@ -0,0 +1,122 @@
+//! The `statusline` and `tabline` formats from the settings file, like `"%f %m%=%l:%c %p%%"`.
+//!
+//! `%f` is the file's path relative to the working directory, `%t` just its name, `%n` the
+//! buffer number, `%l` and `%c` the cursor line and column, `%m` `[+]` when the buffer is
+//! modified, `%y` the file type in brackets, `%p` how far through the file the cursor is as a
+//! percentage, `%d` the working directory, or the buffer's own from `:lcd`, `%s` which match
+//! of the last search the cursor is on out of how many, like `[3/17]`, `%P` how far through
+//! the file the window is, as `Top`, `Bot`, `All` or a percentage like vim's ruler, `%B` the
+//! same as a bar, and `%%` a percent sign. What comes after `%=` is aligned to the right, or in
+//! a tab label just follows the rest. `%P` and `%B` are empty in tab labels.
+
+/// What the placeholders stand for.
+pub struct Fields<'a> {
+  pub name: String,
+  /// The buffer's number in `:ls`.
+  pub number: usize,
+  pub modified: bool,
+  pub filetype: Option<&'a str>,
+  /// The cursor position, counting from 1.
+  pub line: usize,
+  pub col: usize,
+  pub lines: usize,
+  /// The working directory for the buffer, with the home directory as `~`.
+  pub dir: String,
+  /// The search count, or empty without one.
+  pub search: String,
+  /// The first and last line in view, counting from 0, for the status line of a window.
+  pub view: Option<(usize, usize)>,
+}
+
+/// How many cells wide `%B` is.
+const BAR_WIDTH: usize = 10;
+
+/// `%P`: `All` when every line is in view, `Top` or `Bot` when the first or last is, and
+/// otherwise the lines above the view as a percentage of those that don't fit in it.
+pub fn ruler((top, bottom): (usize, usize), lines: usize) -> String {
+  let last = lines.saturating_sub(1);
+  match (top == 0, bottom >= last) {
+    (true, true) => "All".to_owned(),
+    (true, false) => "Top".to_owned(),
+    (false, true) => "Bot".to_owned(),
+    (false, false) => {
+      let hidden = lines - (bottom - top + 1);
+      format!("{}%", top * 100 / hidden.max(1))
+    }
+  }
+}
+
+/// `%B`: the part of the file in view as a bar, like `[░░███░░░░░]`.
+fn bar((top, bottom): (usize, usize), lines: usize) -> String {
+  let lines = lines.max(1);
+  let start = top * BAR_WIDTH / lines;
+  let end = ((bottom + 1) * BAR_WIDTH)
+    .div_ceil(lines)
+    .clamp(start + 1, BAR_WIDTH);
+  let cells: String = (0..BAR_WIDTH)
+    .map(|i| match (start..end).contains(&i) {
+      true => '█',
+      false => '░',
+    })
+    .collect();
+  format!("[{}]", cells)
+}
+
+/// Fills in `format`, returning the parts before and after `%=`. Unknown placeholders are kept
+/// as they are.
+pub fn expand(format: &str, fields: &Fields) -> (String, String) {
+  let (mut left, mut right) = (String::new(), String::new());
+  let mut aligned_right = false;
+  let mut chars = format.chars();
+  while let Some(c) = chars.next() {
+    let out = match aligned_right {
+      true => &mut right,
+      false => &mut left,
+    };
+    if c != '%' {
+      out.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('f') => out.push_str(&fields.name),
+      Some('t') => {
+        let tail = fields.name.rsplit('/').next().unwrap_or_default();
+        out.push_str(tail);
+      }
+      Some('n') => out.push_str(&fields.number.to_string()),
+      Some('d') => out.push_str(&fields.dir),
+      Some('s') => out.push_str(&fields.search),
+      Some('l') => out.push_str(&fields.line.to_string()),
+      Some('c') => out.push_str(&fields.col.to_string()),
+      Some('m') if fields.modified => out.push_str("[+]"),
+      Some('m') => (),
+      Some('y') => {
+        if let Some(filetype) = fields.filetype {
+          out.push_str(&format!("[{}]", filetype));
+        }
+      }
+      Some('p') => {
+        let percent = fields.line * 100 / fields.lines.max(1);
+        out.push_str(&percent.to_string());
+      }
+      Some('P') => {
+        if let Some(view) = fields.view {
+          out.push_str(&ruler(view, fields.lines));
+        }
+      }
+      Some('B') => {
+        if let Some(view) = fields.view {
+          out.push_str(&bar(view, fields.lines));
+        }
+      }
+      Some('%') => out.push('%'),
+      Some('=') if !aligned_right => aligned_right = true,
+      Some(other) => {
+        out.push('%');
+        out.push(other);
+      }
+      None => out.push('%'),
+    }
+  }
+  (left, right)
+}
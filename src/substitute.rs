@@ -0,0 +1,147 @@
+//! The `:s/pattern/replacement/flags` command. Patterns are matched literally. The `g` flag
+//! replaces every match on a line, and `p` matches regardless of case and gives each
+//! replacement the case of its match, so `:s/foo/bar/p` turns `Foo` into `Bar` and `FOO` into
+//! `BAR`.
+
+use crate::buffer::byte_index;
+use crate::range;
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitute {
+  pub pattern: String,
+  pub replacement: String,
+  /// Replace every match on a line instead of only the first.
+  pub global: bool,
+  /// Match without regard to case, and replace in the case of the match.
+  pub preserve_case: bool,
+  /// The lines to run on, as described in `range.rs`; empty for the cursor line.
+  pub range: String,
+}
+
+impl Substitute {
+  /// Parses a command line such as `s/foo/bar/g` or `%s#a#b#`. Missing trailing parts are allowed
+  /// so the command can be previewed while it is still being typed.
+  pub fn parse(command: &str) -> Option<Substitute> {
+    let (range, rest) = range::split(command);
+    let rest = rest.strip_prefix('s')?;
+    let delimiter = rest.chars().next()?;
+    if delimiter.is_alphanumeric() || delimiter.is_whitespace() || delimiter == '\\' {
+      return None;
+    }
+    let mut parts = split_unescaped(&rest[delimiter.len_utf8()..], delimiter).into_iter();
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let flags = parts.next().unwrap_or_default();
+    Some(Substitute {
+      pattern,
+      replacement,
+      global: flags.contains('g'),
+      preserve_case: flags.contains('p'),
+      range: range.to_owned(),
+    })
+  }
+
+  /// Applies the substitution to one line. Returns the new text and the char ranges of the
+  /// inserted replacements within it.
+  pub fn apply(&self, line: &str) -> (String, Vec<Range<usize>>) {
+    let mut result = String::with_capacity(line.len());
+    let mut ranges = Vec::new();
+    let mut rest = line;
+    let mut col = 0;
+    if self.pattern.is_empty() {
+      return (line.to_owned(), ranges);
+    }
+    while let Some((at, end)) = self.find(rest) {
+      let before = &rest[..at];
+      result.push_str(before);
+      col += before.chars().count();
+      let replacement = match self.preserve_case {
+        true => in_case_of(&rest[at..end], &self.replacement),
+        false => self.replacement.clone(),
+      };
+      result.push_str(&replacement);
+      let len = replacement.chars().count();
+      ranges.push(col..col + len);
+      col += len;
+      rest = &rest[end..];
+      if !self.global {
+        break;
+      }
+    }
+    result.push_str(rest);
+    (result, ranges)
+  }
+
+  /// The byte range of the first match in `text`.
+  fn find(&self, text: &str) -> Option<(usize, usize)> {
+    if !self.preserve_case {
+      let at = text.find(&self.pattern)?;
+      return Some((at, at + self.pattern.len()));
+    }
+    let len = self.pattern.chars().count();
+    text.char_indices().find_map(|(at, _)| {
+      let mut chars = text[at..].char_indices();
+      let same = self.pattern.chars().all(|p| {
+        chars
+          .next()
+          .is_some_and(|(_, c)| c.to_lowercase().eq(p.to_lowercase()))
+      });
+      let end = text[at..]
+        .char_indices()
+        .nth(len)
+        .map_or(text.len(), |(i, _)| at + i);
+      same.then_some((at, end))
+    })
+  }
+
+  /// Like `apply`, but only within the chars in `cols`.
+  pub fn apply_within(&self, line: &str, cols: &Range<usize>) -> (String, Vec<Range<usize>>) {
+    let start = byte_index(line, cols.start);
+    let end = byte_index(line, cols.end);
+    let (text, ranges) = self.apply(&line[start..end]);
+    let shift = |r: Range<usize>| r.start + cols.start..r.end + cols.start;
+    (
+      format!("{}{}{}", &line[..start], text, &line[end..]),
+      ranges.into_iter().map(shift).collect(),
+    )
+  }
+}
+
+/// `replacement` in the case of `matched`: all capitals if it is, or with the first letter a
+/// capital if only its first is, or lowercase if it is. Otherwise as it is.
+fn in_case_of(matched: &str, replacement: &str) -> String {
+  let letters: Vec<char> = matched.chars().filter(|c| c.is_alphabetic()).collect();
+  let upper = |c: &char| c.is_uppercase();
+  let with_first = |first: fn(char) -> String| {
+    let mut chars = replacement.chars();
+    chars
+      .next()
+      .map_or(String::new(), |c| first(c) + chars.as_str())
+  };
+  match letters.split_first() {
+    Some((first, rest)) if upper(first) && !rest.is_empty() && rest.iter().all(upper) => {
+      replacement.to_uppercase()
+    }
+    Some((first, _)) if upper(first) => with_first(|c| c.to_uppercase().collect()),
+    Some(_) if !letters.iter().any(upper) => with_first(|c| c.to_lowercase().collect()),
+    _ => replacement.to_owned(),
+  }
+}
+
+/// Splits on `delimiter`, treating `\<delimiter>` as a literal delimiter.
+fn split_unescaped(s: &str, delimiter: char) -> Vec<String> {
+  let mut parts = vec![String::new()];
+  let mut chars = s.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c == '\\' && chars.peek() == Some(&delimiter) {
+      parts.last_mut().unwrap().push(delimiter);
+      chars.next();
+    } else if c == delimiter {
+      parts.push(String::new());
+    } else {
+      parts.last_mut().unwrap().push(c);
+    }
+  }
+  parts
+}
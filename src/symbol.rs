@@ -0,0 +1,251 @@
+//! The outline `:symbols` lists: the document symbols of the language server, or without one
+//! the lines that start an indented block. The functions among them are what `if` and `af`
+//! work on.
+
+use crate::buffer::Position;
+use crate::fold;
+use crate::lsp;
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+  pub name: String,
+  /// A short name for its kind, like `fn`. Empty in an indentation outline.
+  pub kind: &'static str,
+  /// How many symbols it is inside of.
+  pub depth: usize,
+  pub position: Position,
+}
+
+/// The functions the language server found in a buffer.
+#[derive(Clone, Debug, Default)]
+pub struct Functions {
+  /// The version of the buffer they were last asked for.
+  pub asked: Option<u64>,
+  /// The version they are for. They aren't used once the text has changed.
+  pub version: u64,
+  /// The first and last line of each.
+  pub lines: Vec<(usize, usize)>,
+}
+
+/// LSP symbol kinds that are functions: methods, constructors and functions.
+const FUNCTION_KINDS: &[u64] = &[6, 9, 12];
+
+/// Words that start a function in one language or another.
+const FUNCTION_KEYWORDS: &[&str] = &["fn", "def", "defp", "function", "func", "fun", "sub"];
+
+/// Words that start a block that is not a function, even with parentheses like one.
+const CONTROL_KEYWORDS: &[&str] = &[
+  "if", "else", "elif", "for", "foreach", "while", "do", "switch", "match", "case", "catch", "try",
+  "with", "return",
+];
+
+/// LSP symbol kinds in the order they are listed in, each kind together. Kinds not in here
+/// come last.
+const ORDER: &[u64] = &[2, 3, 4, 5, 23, 10, 11, 26, 9, 12, 6, 14, 13, 8, 7, 22];
+
+/// The symbols of an answer to `textDocument/documentSymbol`, each followed by those inside
+/// it.
+pub fn from_lsp(lines: &[String], result: &Value) -> Vec<Symbol> {
+  let mut symbols = Vec::new();
+  if let Some(items) = result.as_array() {
+    add(lines, items, 0, &mut symbols);
+  }
+  symbols
+}
+
+fn add(lines: &[String], items: &[Value], depth: usize, symbols: &mut Vec<Symbol>) {
+  let kind = |item: &Value| item["kind"].as_u64().unwrap_or(0);
+  let mut items: Vec<&Value> = items.iter().collect();
+  items.sort_by_key(|item| {
+    ORDER
+      .iter()
+      .position(|&k| k == kind(item))
+      .unwrap_or(ORDER.len())
+  });
+  for item in items {
+    // A `DocumentSymbol` has the range of its name, a `SymbolInformation` only a location.
+    let range = match item.get("selectionRange") {
+      Some(range) => range,
+      None => &item["location"]["range"],
+    };
+    let Some(position) = lsp::from_position(lines, &range["start"]) else {
+      continue;
+    };
+    symbols.push(Symbol {
+      name: item["name"].as_str().unwrap_or_default().to_owned(),
+      kind: label(kind(item)),
+      depth,
+      position,
+    });
+    if let Some(children) = item["children"].as_array() {
+      add(lines, children, depth + 1, symbols);
+    }
+  }
+}
+
+fn label(kind: u64) -> &'static str {
+  match kind {
+    1 => "file",
+    2 => "mod",
+    3 => "ns",
+    4 => "pkg",
+    5 => "class",
+    6 => "method",
+    7 => "prop",
+    8 => "field",
+    9 => "new",
+    10 => "enum",
+    11 => "iface",
+    12 => "fn",
+    13 => "var",
+    14 => "const",
+    22 => "variant",
+    23 => "struct",
+    24 => "event",
+    25 => "op",
+    26 => "type",
+    _ => "value",
+  }
+}
+
+/// The lines followed by more deeply indented ones, nested as the indentation is.
+pub fn outline(lines: &[String]) -> Vec<Symbol> {
+  let folds = fold::indent_folds(lines);
+  folds
+    .iter()
+    .map(|fold| {
+      let line = &lines[fold.start];
+      let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+      Symbol {
+        name: line.trim().to_owned(),
+        kind: "",
+        depth: folds
+          .iter()
+          .filter(|outer| outer.start < fold.start && outer.end >= fold.end)
+          .count(),
+        position: Position::new(fold.start, indent),
+      }
+    })
+    .collect()
+}
+
+/// The first and last line of each function in an answer to `textDocument/documentSymbol`.
+pub fn functions_from_lsp(lines: &[String], result: &Value) -> Vec<(usize, usize)> {
+  let mut functions = Vec::new();
+  if let Some(items) = result.as_array() {
+    add_functions(lines, items, &mut functions);
+  }
+  functions
+}
+
+fn add_functions(lines: &[String], items: &[Value], functions: &mut Vec<(usize, usize)>) {
+  for item in items {
+    let range = match item.get("range") {
+      Some(range) => range,
+      None => &item["location"]["range"],
+    };
+    let start = lsp::from_position(lines, &range["start"]);
+    let end = lsp::from_position(lines, &range["end"]);
+    let kind = item["kind"].as_u64().unwrap_or(0);
+    if let (Some(start), Some(end), true) = (start, end, FUNCTION_KINDS.contains(&kind)) {
+      functions.push((start.line, end.line));
+    }
+    if let Some(children) = item["children"].as_array() {
+      add_functions(lines, children, functions);
+    }
+  }
+}
+
+/// The innermost of `functions` that `line` is in.
+pub fn enclosing(functions: &[(usize, usize)], line: usize) -> Option<(usize, usize)> {
+  functions
+    .iter()
+    .copied()
+    .filter(|&(first, last)| (first..=last).contains(&line))
+    .min_by_key(|&(first, last)| last - first)
+}
+
+/// The first lines of the blocks `line` is in that start above it, going by indentation and
+/// outermost first. A blank line is in the blocks of the next line that isn't.
+pub fn context(lines: &[String], line: usize) -> Vec<usize> {
+  let indent = |l: usize| lines[l].len() - lines[l].trim_start().len();
+  let blank = |l: usize| lines[l].trim().is_empty();
+  let Some(first) = (line..lines.len()).find(|&l| !blank(l)) else {
+    return Vec::new();
+  };
+  let mut level = indent(first);
+  let mut found = Vec::new();
+  for l in (0..line).rev() {
+    if level == 0 {
+      break;
+    }
+    if !blank(l) && indent(l) < level {
+      found.push(l);
+      level = indent(l);
+    }
+  }
+  found.reverse();
+  found
+}
+
+/// The first and last line of the function `line` is in, going by indentation: the innermost
+/// indented block whose first line looks like it starts a function, with the `}` or `end` line
+/// that closes it.
+pub fn function(lines: &[String], line: usize) -> Option<(usize, usize)> {
+  let folds = fold::indent_folds(lines);
+  let indent = |l: usize| lines[l].len() - lines[l].trim_start().len();
+  folds
+    .iter()
+    .filter(|fold| fold.contains(line))
+    .filter_map(|fold| {
+      // A signature over several lines is a block of its own, ending before the `)` line.
+      let first = folds
+        .iter()
+        .find(|before| {
+          before.end + 1 == fold.start
+            && indent(before.start) == indent(fold.start)
+            && lines[fold.start].trim_start().starts_with(')')
+        })
+        .map_or(fold.start, |before| before.start);
+      let closer = lines.get(fold.end + 1).filter(|closer| {
+        let trimmed = closer.trim();
+        indent(fold.end + 1) == indent(first) && (trimmed.starts_with('}') || trimmed == "end")
+      });
+      let last = fold.end + usize::from(closer.is_some());
+      starts_function(&lines[first]).then_some((first, last))
+    })
+    .max_by_key(|&(first, _)| first)
+}
+
+fn starts_function(line: &str) -> bool {
+  let words: Vec<&str> = line
+    .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+    .filter(|word| !word.is_empty())
+    .collect();
+  if words.iter().any(|word| FUNCTION_KEYWORDS.contains(word)) {
+    return true;
+  }
+  // A C-like signature: a name and parameters, not a statement.
+  let trimmed = line.trim();
+  let opens = trimmed.ends_with('{') || trimmed.ends_with(')');
+  let control = words
+    .first()
+    .is_some_and(|word| CONTROL_KEYWORDS.contains(word));
+  trimmed.contains('(') && opens && !control && !trimmed.starts_with('}')
+}
+
+/// The lines of function `first..=last` between the line its body opens on and the line that
+/// closes it, if there are any.
+pub fn body(lines: &[String], first: usize, last: usize) -> Option<(usize, usize)> {
+  let open = (first..=last)
+    .find(|&l| {
+      let trimmed = lines[l].trim_end();
+      trimmed.ends_with('{') || trimmed.ends_with(':')
+    })
+    .unwrap_or(first);
+  let trimmed = lines[last].trim();
+  let closed = last > open && (trimmed.starts_with('}') || trimmed == "end");
+  let end = last - usize::from(closed);
+  (open < end).then_some((open + 1, end))
+}
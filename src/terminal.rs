@@ -0,0 +1,131 @@
+//! Putting the terminal into the editor's mode and, however the editor ends, back again, and
+//! drawing to it in the colors it has.
+
+use crate::color::Depth;
+use crossterm::cursor::Show;
+use crossterm::event::{
+  DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+  EnableFocusChange, EnableMouseCapture,
+};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::panic;
+use tui::backend::Backend;
+use tui::buffer::Cell;
+use tui::layout::Rect;
+
+/// Restores the terminal when dropped, including when the main loop returns early with an
+/// error or unwinds from a panic.
+pub struct Guard;
+
+impl Guard {
+  /// Enters raw mode with mouse, focus and bracketed paste reporting. A panic anywhere restores
+  /// the terminal before its message is printed, so the message stays readable.
+  pub fn enter() -> io::Result<Guard> {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+      restore();
+      default_hook(info);
+    }));
+    enable_raw_mode()?;
+    let guard = Guard;
+    execute!(
+      output(),
+      EnableFocusChange,
+      EnableMouseCapture,
+      EnableBracketedPaste
+    )?;
+    Ok(guard)
+  }
+}
+
+impl Drop for Guard {
+  fn drop(&mut self) {
+    restore();
+  }
+}
+
+/// Undoes `Guard::enter`. Safe to call more than once, and errors are ignored since there is
+/// nothing better to do with them on the way out.
+pub fn restore() {
+  let _ = disable_raw_mode();
+  let _ = execute!(
+    output(),
+    DisableFocusChange,
+    DisableMouseCapture,
+    DisableBracketedPaste,
+    Show
+  );
+}
+
+/// Where the editor draws: stdout, unless that goes somewhere else such as a pipe taking the
+/// buffer at exit, in which case the terminal itself so the two never mix.
+pub fn output() -> Box<dyn Write> {
+  if !io::stdout().is_terminal() {
+    if let Ok(tty) = OpenOptions::new().write(true).open("/dev/tty") {
+      return Box::new(BufWriter::new(tty));
+    }
+  }
+  Box::new(io::stdout())
+}
+
+/// A backend that draws every color as the nearest one the terminal can show.
+pub struct Fitted<B> {
+  backend: B,
+  depth: Depth,
+}
+
+impl<B: Backend> Fitted<B> {
+  pub fn new(backend: B, depth: Depth) -> Self {
+    Fitted { backend, depth }
+  }
+}
+
+impl<B: Backend> Backend for Fitted<B> {
+  fn draw<'a, I>(&mut self, content: I) -> io::Result<()>
+  where
+    I: Iterator<Item = (u16, u16, &'a Cell)>,
+  {
+    let cells: Vec<(u16, u16, Cell)> = content
+      .map(|(x, y, cell)| {
+        let mut cell = cell.clone();
+        cell.fg = self.depth.fit(cell.fg);
+        cell.bg = self.depth.fit(cell.bg);
+        (x, y, cell)
+      })
+      .collect();
+    self
+      .backend
+      .draw(cells.iter().map(|(x, y, cell)| (*x, *y, cell)))
+  }
+
+  fn hide_cursor(&mut self) -> io::Result<()> {
+    self.backend.hide_cursor()
+  }
+
+  fn show_cursor(&mut self) -> io::Result<()> {
+    self.backend.show_cursor()
+  }
+
+  fn get_cursor(&mut self) -> io::Result<(u16, u16)> {
+    self.backend.get_cursor()
+  }
+
+  fn set_cursor(&mut self, x: u16, y: u16) -> io::Result<()> {
+    self.backend.set_cursor(x, y)
+  }
+
+  fn clear(&mut self) -> io::Result<()> {
+    self.backend.clear()
+  }
+
+  fn size(&self) -> io::Result<Rect> {
+    self.backend.size()
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.backend.flush()
+  }
+}
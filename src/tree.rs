@@ -0,0 +1,225 @@
+//! The file tree sidebar and the file operations it offers.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+  pub path: PathBuf,
+  pub depth: usize,
+  pub is_dir: bool,
+}
+
+/// A name being typed in the sidebar.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TreeInput {
+  /// `a`: a new file, or a directory if the name ends in `/`.
+  Create,
+  /// `r`: a new name for the selected node.
+  Rename,
+}
+
+#[derive(Debug)]
+pub struct FileTree {
+  pub root: PathBuf,
+  /// Visible nodes in display order: directories first, then files, each sorted by name.
+  pub entries: Vec<Entry>,
+  expanded: BTreeSet<PathBuf>,
+  pub selected: usize,
+  pub scroll_offset: usize,
+  /// Node picked with `m`, waiting for the target directory to be confirmed.
+  pub moving: Option<PathBuf>,
+  pub input: Option<(TreeInput, String)>,
+}
+
+impl FileTree {
+  pub fn new(root: PathBuf) -> Self {
+    let mut tree = FileTree {
+      root,
+      entries: Vec::new(),
+      expanded: BTreeSet::new(),
+      selected: 0,
+      scroll_offset: 0,
+      moving: None,
+      input: None,
+    };
+    tree.refresh(None);
+    tree
+  }
+
+  /// Rereads the expanded directories and selects `keep` if it is still there. Otherwise the
+  /// selection stays at the same row, so it lands next to a node that went away.
+  pub fn refresh(&mut self, keep: Option<&Path>) {
+    self.entries.clear();
+    let root = self.root.clone();
+    self.read_dir(&root, 0);
+    if let Some(index) = keep.and_then(|path| self.entries.iter().position(|e| e.path == path)) {
+      self.selected = index;
+    }
+    self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+  }
+
+  fn read_dir(&mut self, dir: &Path, depth: usize) {
+    let Ok(read) = fs::read_dir(dir) else {
+      return;
+    };
+    let mut children: Vec<Entry> = read
+      .flatten()
+      .map(|e| Entry {
+        path: e.path(),
+        depth,
+        is_dir: e.file_type().is_ok_and(|t| t.is_dir()),
+      })
+      .collect();
+    children.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.path.cmp(&b.path)));
+    for child in children {
+      let expand = child.is_dir && self.expanded.contains(&child.path);
+      let path = child.path.clone();
+      self.entries.push(child);
+      if expand {
+        self.read_dir(&path, depth + 1);
+      }
+    }
+  }
+
+  pub fn selected(&self) -> Option<&Entry> {
+    self.entries.get(self.selected)
+  }
+
+  pub fn is_expanded(&self, path: &Path) -> bool {
+    self.expanded.contains(path)
+  }
+
+  pub fn move_selection(&mut self, delta: isize) {
+    let last = self.entries.len().saturating_sub(1);
+    self.selected = self.selected.saturating_add_signed(delta).min(last);
+  }
+
+  /// Expands or collapses the selected directory.
+  pub fn toggle(&mut self) {
+    let Some(entry) = self.selected().filter(|e| e.is_dir) else {
+      return;
+    };
+    let path = entry.path.clone();
+    if !self.expanded.remove(&path) {
+      self.expanded.insert(path.clone());
+    }
+    self.refresh(Some(&path));
+  }
+
+  /// Collapses the selected directory, or else selects the directory holding the node.
+  pub fn collapse(&mut self) {
+    let Some(entry) = self.selected() else {
+      return;
+    };
+    let path = entry.path.clone();
+    if entry.is_dir && self.expanded.remove(&path) {
+      self.refresh(Some(&path));
+    } else if let Some(parent) = path.parent().filter(|p| *p != self.root) {
+      let parent = parent.to_path_buf();
+      self.refresh(Some(&parent));
+    }
+  }
+
+  /// The directory a new or moved node goes into: the selected directory, or the one holding the
+  /// selected file.
+  pub fn target_dir(&self) -> PathBuf {
+    match self.selected() {
+      Some(entry) if entry.is_dir => entry.path.clone(),
+      Some(entry) => entry.path.parent().unwrap_or(&self.root).to_path_buf(),
+      None => self.root.clone(),
+    }
+  }
+
+  /// `path` relative to the root when it is inside it.
+  pub fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+    path.strip_prefix(&self.root).unwrap_or(path)
+  }
+
+  pub fn create(&mut self, name: &str) -> io::Result<PathBuf> {
+    let dir = self.target_dir();
+    let path = dir.join(name);
+    vacant(&path)?;
+    match name.ends_with('/') {
+      true => fs::create_dir_all(&path)?,
+      false => {
+        if let Some(parent) = path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::File::create_new(&path)?;
+      }
+    }
+    let path = PathBuf::from(path.to_string_lossy().trim_end_matches('/'));
+    self.expanded.insert(dir);
+    self.refresh(Some(&path));
+    Ok(path)
+  }
+
+  /// Renames the selected node within its directory. Returns the old and new paths.
+  pub fn rename(&mut self, name: &str) -> io::Result<(PathBuf, PathBuf)> {
+    let from = self.selected_path()?;
+    let to = from.parent().unwrap_or(&self.root).join(name);
+    self.relocate(from, to)
+  }
+
+  /// Moves the node picked with `m` into the target directory. Returns the old and new paths.
+  pub fn move_to_target(&mut self) -> io::Result<(PathBuf, PathBuf)> {
+    let from = self
+      .moving
+      .take()
+      .ok_or_else(|| io::Error::other("nothing to move"))?;
+    let dir = self.target_dir();
+    if dir.starts_with(&from) {
+      return Err(io::Error::other("can't move a directory into itself"));
+    }
+    let name = from
+      .file_name()
+      .ok_or_else(|| io::Error::other("no file name"))?;
+    let to = dir.join(name);
+    self.expanded.insert(dir);
+    self.relocate(from, to)
+  }
+
+  fn relocate(&mut self, from: PathBuf, to: PathBuf) -> io::Result<(PathBuf, PathBuf)> {
+    if from != to {
+      vacant(&to)?;
+      fs::rename(&from, &to)?;
+    }
+    if self.expanded.remove(&from) {
+      self.expanded.insert(to.clone());
+    }
+    self.refresh(Some(&to));
+    Ok((from, to))
+  }
+
+  /// Deletes the selected file, or the selected directory if it is empty.
+  pub fn delete(&mut self) -> io::Result<PathBuf> {
+    let path = self.selected_path()?;
+    match fs::symlink_metadata(&path)?.is_dir() {
+      true => fs::remove_dir(&path)?,
+      false => fs::remove_file(&path)?,
+    }
+    self.expanded.remove(&path);
+    self.refresh(None);
+    Ok(path)
+  }
+
+  fn selected_path(&self) -> io::Result<PathBuf> {
+    self
+      .selected()
+      .map(|e| e.path.clone())
+      .ok_or_else(|| io::Error::other("nothing selected"))
+  }
+}
+
+/// Refuses to overwrite an existing file.
+fn vacant(path: &Path) -> io::Result<()> {
+  match fs::symlink_metadata(path) {
+    Ok(_) => Err(io::Error::new(
+      io::ErrorKind::AlreadyExists,
+      format!("{} already exists", path.display()),
+    )),
+    Err(_) => Ok(()),
+  }
+}
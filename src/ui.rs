@@ -0,0 +1,1213 @@
+use crate::buffer::{Buffer, Position};
+use crate::complete::Completion;
+use crate::editor::{Editor, Mode};
+use crate::fold::{self, FoldKind};
+use crate::job::Output;
+use crate::lsp::{Hint, Signature};
+use crate::options::SignColumn;
+use crate::search;
+use crate::sign::Sign;
+use crate::statusline;
+use crate::substitute::Substitute;
+use crate::tree::TreeInput;
+use crate::visual::VisualKind;
+use crate::vt::Screen;
+use crate::window::Layout as WindowLayout;
+use crate::word::Words;
+use std::collections::BTreeMap;
+use std::env;
+use tui::backend::Backend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Clear, Paragraph, Tabs};
+use tui::Frame;
+
+pub fn ui<B: Backend>(f: &mut Frame<B>, editor: &mut Editor) {
+  let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .constraints([
+      Constraint::Length(1), // tabs
+      Constraint::Min(1),    // content
+      Constraint::Length(1), // status
+      Constraint::Length(1), // footer
+    ])
+    .split(f.size());
+
+  render_tabs(f, editor, chunks[0]);
+  let mut content = chunks[1];
+  // The terminal pane goes at the very bottom, below the output panel.
+  if let Some(shell) = &mut editor.shell {
+    let height = (content.height / 3)
+      .max(3)
+      .min(content.height.saturating_sub(1));
+    content.height -= height;
+    let panel = Rect::new(content.x, content.bottom(), content.width, height);
+    shell.resize(height.saturating_sub(1) as usize, panel.width as usize);
+    render_shell(f, &shell.screen, editor.shell_focused, panel);
+  }
+  if let Some(output) = editor.output.as_mut().filter(|_| editor.output_open) {
+    let height = (content.height / 3)
+      .max(3)
+      .min(content.height.saturating_sub(1));
+    content.height -= height;
+    let panel = Rect::new(content.x, content.bottom(), content.width, height);
+    output.height = height.saturating_sub(1) as usize;
+    render_output(f, output, editor.output_focused, panel);
+  }
+  render_windows(f, editor, content);
+  render_status(f, editor, chunks[2]);
+  render_footer(f, editor, chunks[3]);
+  if let Some(selected) = editor.buffer_list {
+    render_buffer_list(f, editor, selected, chunks[1]);
+  }
+  if let Some((backups, selected)) = &editor.backup_list {
+    let rows = backups.iter().map(|b| {
+      b.file_name().map_or(String::new(), |name| {
+        format!(" {} ", name.to_string_lossy())
+      })
+    });
+    render_list_popup(f, " Backups ", rows.collect(), *selected, chunks[1]);
+  }
+  if let Some(selected) = editor.undo_list {
+    render_undo_list(f, editor, selected, chunks[1]);
+  }
+  if let Some((paths, selected)) = &editor.alternate_list {
+    let rows = paths.iter().map(|path| format!(" {} ", path.display()));
+    render_list_popup(f, " Alternate files ", rows.collect(), *selected, chunks[1]);
+  }
+  if let Some((names, selected)) = &editor.register_list {
+    let rows = names.iter().filter_map(|&name| {
+      let register = editor.registers.get(Some(name))?;
+      let kind = match register.kind {
+        VisualKind::Char => 'c',
+        VisualKind::Line => 'l',
+        VisualKind::Block => 'b',
+      };
+      let text: String = register.lines.join("^J").chars().take(60).collect();
+      Some(format!(" \"{}  {}  {} ", name, kind, text))
+    });
+    render_list_popup(f, " Registers ", rows.collect(), *selected, chunks[1]);
+  }
+  if let Some((symbols, selected)) = &editor.symbol_list {
+    let rows = symbols.iter().map(|symbol| {
+      let indent = "  ".repeat(symbol.depth);
+      match symbol.kind {
+        "" => format!(" {}{} ", indent, symbol.name),
+        kind => format!(" {}{:<7} {} ", indent, kind, symbol.name),
+      }
+    });
+    render_list_popup(f, " Symbols ", rows.collect(), *selected, chunks[1]);
+  }
+  if let Some((actions, selected)) = &editor.code_actions {
+    let rows = actions
+      .iter()
+      .map(|action| format!(" {} ", action["title"].as_str().unwrap_or_default()));
+    render_list_popup(f, " Code actions ", rows.collect(), *selected, chunks[1]);
+  }
+}
+
+/// The `:undotree` popup: each change with the time it was made, branches indented under the
+/// change they split off from, and the current state marked with `>`.
+fn render_undo_list<B: Backend>(f: &mut Frame<B>, editor: &Editor, selected: usize, area: Rect) {
+  let undo = &editor.buffer().undo;
+  let rows = undo.entries().into_iter().map(|entry| {
+    let mark = if entry.id == undo.current() { '>' } else { ' ' };
+    let name = match entry.id {
+      0 => "original".to_owned(),
+      id => format!("change {}", id),
+    };
+    format!(
+      " {} {}{}  {} ",
+      mark,
+      "  ".repeat(entry.depth),
+      name,
+      entry.time.format("%H:%M:%S")
+    )
+  });
+  render_list_popup(f, " Undo tree ", rows.collect(), selected, area);
+}
+
+/// The `:ls` popup, marking the current buffer with `%` and the alternate one with `#`.
+fn render_buffer_list<B: Backend>(f: &mut Frame<B>, editor: &Editor, selected: usize, area: Rect) {
+  let current = editor.window().buffer;
+  let rows = editor.buffers.iter().enumerate().map(|(i, buffer)| {
+    let mark = match i {
+      _ if i == current => '%',
+      _ if Some(i) == editor.alternate => '#',
+      _ => ' ',
+    };
+    let modified = if buffer.modified { " [+]" } else { "" };
+    format!("{:>3} {} \"{}\"{}", i + 1, mark, buffer.name(), modified)
+  });
+  render_list_popup(f, " Buffers ", rows.collect(), selected, area);
+}
+
+/// A bordered list centred in `area`, with the `selected` row highlighted and kept in view.
+fn render_list_popup<B: Backend>(
+  f: &mut Frame<B>,
+  title: &str,
+  rows: Vec<String>,
+  selected: usize,
+  area: Rect,
+) {
+  let lines: Vec<Spans> = rows
+    .into_iter()
+    .enumerate()
+    .map(|(i, row)| {
+      let style = match i == selected {
+        true => Style::default().add_modifier(Modifier::REVERSED),
+        false => Style::default(),
+      };
+      Spans::from(Span::styled(row, style))
+    })
+    .collect();
+  let width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16 + 2;
+  let width = width.max(20).min(area.width);
+  let height = (lines.len() as u16 + 2).min(area.height);
+  let popup = Rect::new(
+    area.x + (area.width - width) / 2,
+    area.y + (area.height - height) / 2,
+    width,
+    height,
+  );
+  let scroll = (selected as u16 + 3).saturating_sub(height);
+  let block = Block::default().borders(Borders::ALL).title(title);
+  f.render_widget(Clear, popup);
+  f.render_widget(
+    Paragraph::new(lines).block(block).scroll((scroll, 0)),
+    popup,
+  );
+}
+
+fn render_tabs<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+  let labels: Vec<String> = editor
+    .buffers
+    .iter()
+    .enumerate()
+    .map(|(i, buffer)| match &editor.config.tabline {
+      Some(format) => {
+        let cursor = match i == editor.window().buffer {
+          true => editor.window().cursor,
+          false => buffer.last_cursor,
+        };
+        let fields = status_fields(buffer, i, cursor);
+        let (left, right) = statusline::expand(format, &fields);
+        left + &right
+      }
+      None => {
+        let mut title = buffer.name();
+        if buffer.modified {
+          title.push_str(" [+]");
+        }
+        title
+      }
+    })
+    .collect();
+  let titles = fit_labels(labels, area.width as usize)
+    .into_iter()
+    .map(Spans::from)
+    .collect();
+  let tabs = Tabs::new(titles)
+    .select(editor.window().buffer)
+    .style(Style::default().fg(Color::White).bg(Color::DarkGray))
+    .highlight_style(Style::default().fg(Color::Yellow));
+  f.render_widget(tabs, area);
+}
+
+/// Shortens the longest of `labels` from the front until all of them fit in `width` columns
+/// as tabs, keeping at least a few characters of each.
+fn fit_labels(labels: Vec<String>, width: usize) -> Vec<String> {
+  // Each tab is padded by a space on both sides, with a divider between tabs.
+  let needed = |limit: usize| {
+    labels
+      .iter()
+      .map(|l| l.chars().count().min(limit) + 3)
+      .sum::<usize>()
+      .saturating_sub(1)
+  };
+  let longest = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+  let mut limit = longest;
+  while limit > 4 && needed(limit) > width {
+    limit -= 1;
+  }
+  if limit == longest {
+    return labels;
+  }
+  labels
+    .into_iter()
+    .map(|label| {
+      let len = label.chars().count();
+      match len > limit {
+        true => "…"
+          .chars()
+          .chain(label.chars().skip(len - limit + 1))
+          .collect(),
+        false => label,
+      }
+    })
+    .collect()
+}
+
+/// What the `statusline` and `tabline` placeholders stand for in buffer `index`.
+fn status_fields(buffer: &Buffer, index: usize, cursor: Position) -> statusline::Fields<'_> {
+  let name = match &buffer.path {
+    Some(path) => {
+      let cwd = env::current_dir().unwrap_or_default();
+      path
+        .strip_prefix(&cwd)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+    }
+    None => buffer.name(),
+  };
+  statusline::Fields {
+    name,
+    number: index + 1,
+    modified: buffer.modified,
+    filetype: buffer.filetype,
+    line: cursor.line + 1,
+    col: cursor.col + 1,
+    lines: buffer.lines.len(),
+    dir: {
+      let dir = buffer.local_dir.clone();
+      let dir = dir.unwrap_or_else(|| env::current_dir().unwrap_or_default());
+      let home = env::var_os("HOME").map(std::path::PathBuf::from);
+      match home.as_deref().and_then(|home| dir.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_owned(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => dir.display().to_string(),
+      }
+    },
+    search: String::new(),
+    view: None,
+  }
+}
+
+/// The first and last line in view in the focused window.
+fn view_lines(editor: &Editor) -> (usize, usize) {
+  let (buffer, window) = (editor.buffer(), editor.window());
+  let top = window.scroll_offset;
+  let last = buffer.lines.len() - 1;
+  let bottom = fold::rows_down(&buffer.folds, top, window.height.max(1) - 1, last);
+  (top, bottom)
+}
+
+/// `[3/17]` for the third of 17 search matches, `[?/>999]` past what is counted.
+fn search_count(editor: &Editor) -> String {
+  let Some((index, total)) = editor.search_count else {
+    return String::new();
+  };
+  let index = match index {
+    0 => "?".to_owned(),
+    index => index.to_string(),
+  };
+  match total >= search::MAX_COUNT {
+    true => format!("[{}/>{}]", index, search::MAX_COUNT),
+    false => format!("[{}/{}]", index, total),
+  }
+}
+
+fn render_windows<B: Backend>(f: &mut Frame<B>, editor: &mut Editor, mut area: Rect) {
+  if editor.tree.is_some() {
+    let width = 30.min(area.width / 2);
+    render_tree(f, editor, Rect::new(area.x, area.y, width, area.height));
+    render_separator(f, Rect::new(area.x + width, area.y, 1, area.height));
+    area.x += width + 1;
+    area.width -= width + 1;
+  }
+  let mut areas = vec![Rect::default(); editor.windows.len()];
+  let layout = editor.layout.clone();
+  layout_windows(f, editor, &layout, area, &mut areas);
+
+  // The focused window scrolls first so bound windows can follow it in the same frame.
+  editor.scroll(editor.focus, areas[editor.focus].height as usize);
+  let focus = editor.focus;
+  for i in (0..editor.windows.len()).filter(|&i| i != focus) {
+    editor.scroll(i, areas[i].height as usize);
+  }
+  // Only for this frame: the cursor stays put until the command runs.
+  let preview = editor.line_preview();
+  let scroll = preview.map(|top| std::mem::replace(&mut editor.windows[focus].scroll_offset, top));
+  for (i, window_area) in areas.iter().enumerate() {
+    let mut text_area = *window_area;
+    let options = editor.windows[i].options.clone();
+    if options.scrollbar && text_area.width > 1 {
+      text_area.width -= 1;
+      let bar_area = Rect::new(text_area.right(), text_area.y, 1, text_area.height);
+      render_scrollbar(f, editor, i, bar_area);
+    }
+    let fold_width = (options.foldcolumn as u16).min(text_area.width.saturating_sub(1));
+    if fold_width > 0 {
+      let column_area = Rect::new(text_area.x, text_area.y, fold_width, text_area.height);
+      render_fold_column(f, editor, i, column_area);
+      text_area.x += fold_width;
+      text_area.width -= fold_width;
+    }
+    let signs = editor.signs(i);
+    let sign_column = match options.signcolumn {
+      SignColumn::Auto => !signs.is_empty(),
+      SignColumn::Yes => true,
+      SignColumn::No => false,
+    };
+    if sign_column && text_area.width > 1 {
+      let column_area = Rect::new(text_area.x, text_area.y, 1, text_area.height);
+      render_sign_column(f, editor, i, &signs, column_area);
+      text_area.x += 1;
+      text_area.width -= 1;
+    }
+    editor.windows[i].area = *window_area;
+    render_window(f, editor, i, text_area);
+    if options.stickyheader {
+      render_sticky_header(f, editor, i, *window_area, text_area);
+    }
+  }
+  if let Some(scroll) = scroll {
+    editor.windows[focus].scroll_offset = scroll;
+  }
+}
+
+/// Works out where each window of `layout` goes within `area`, drawing the separators between
+/// side-by-side windows and a status row under every stacked window but the last.
+fn layout_windows<B: Backend>(
+  f: &mut Frame<B>,
+  editor: &Editor,
+  layout: &WindowLayout,
+  area: Rect,
+  areas: &mut [Rect],
+) {
+  let (vertical, children) = match layout {
+    WindowLayout::Window(i) => {
+      areas[*i] = area;
+      return;
+    }
+    WindowLayout::Split { vertical, children } => (*vertical, children),
+  };
+  let parts = split_area(area, vertical, children.len());
+  for (i, (child, mut part)) in children.iter().zip(parts).enumerate() {
+    if vertical && i > 0 {
+      render_separator(f, Rect::new(part.x, part.y, 1, part.height));
+      part.x += 1;
+      part.width = part.width.saturating_sub(1);
+    }
+    if !vertical && i + 1 < children.len() && part.height > 1 {
+      part.height -= 1;
+      render_pane_status(
+        f,
+        editor,
+        child,
+        Rect::new(part.x, part.bottom(), part.width, 1),
+      );
+    }
+    layout_windows(f, editor, child, part, areas);
+  }
+}
+
+/// The row under a stacked window naming its buffer, highlighted when the window has focus.
+/// Under side-by-side windows each gets its own part of the row.
+fn render_pane_status<B: Backend>(
+  f: &mut Frame<B>,
+  editor: &Editor,
+  layout: &WindowLayout,
+  area: Rect,
+) {
+  let index = match layout {
+    WindowLayout::Window(index) => *index,
+    WindowLayout::Split {
+      vertical: false,
+      children,
+    } => {
+      let last = children.last().expect("splits have children");
+      return render_pane_status(f, editor, last, area);
+    }
+    WindowLayout::Split {
+      vertical: true,
+      children,
+    } => {
+      for (i, (child, part)) in children
+        .iter()
+        .zip(split_area(area, true, children.len()))
+        .enumerate()
+      {
+        let part = match i {
+          0 => part,
+          _ => Rect::new(part.x + 1, part.y, part.width.saturating_sub(1), 1),
+        };
+        render_pane_status(f, editor, child, part);
+      }
+      return;
+    }
+  };
+  let buffer = &editor.buffers[editor.windows[index].buffer];
+  let modified = if buffer.modified { " [+]" } else { "" };
+  let style = match index == editor.focus {
+    true => Style::default().fg(Color::Black).bg(Color::Yellow),
+    false => Style::default().fg(Color::White).bg(Color::DarkGray),
+  };
+  let text = format!(" {}{}", buffer.name(), modified);
+  f.render_widget(Paragraph::new(text).style(style), area);
+}
+
+/// Divides `area` evenly between `count` children of a split.
+fn split_area(area: Rect, vertical: bool, count: usize) -> Vec<Rect> {
+  let direction = match vertical {
+    true => Direction::Horizontal,
+    false => Direction::Vertical,
+  };
+  Layout::default()
+    .direction(direction)
+    .constraints(vec![Constraint::Ratio(1, count as u32); count])
+    .split(area)
+}
+
+/// The output panel: a title row naming the command, then its output.
+fn render_output<B: Backend>(f: &mut Frame<B>, output: &Output, focused: bool, area: Rect) {
+  let style = match focused {
+    true => Style::default().fg(Color::Black).bg(Color::Yellow),
+    false => Style::default().fg(Color::White).bg(Color::DarkGray),
+  };
+  let status = match output.status {
+    Some(code) => format!("exit {}", code),
+    None => "killed".to_owned(),
+  };
+  let title = format!(" :!{}  [{}]", output.command, status);
+  f.render_widget(
+    Paragraph::new(title).style(style),
+    Rect::new(area.x, area.y, area.width, 1),
+  );
+  let lines: Vec<Spans> = output
+    .lines
+    .iter()
+    .skip(output.scroll)
+    .take(area.height.saturating_sub(1) as usize)
+    .map(|line| Spans::from(line.as_str()))
+    .collect();
+  let body = Rect::new(
+    area.x,
+    area.y + 1,
+    area.width,
+    area.height.saturating_sub(1),
+  );
+  f.render_widget(Paragraph::new(lines), body);
+}
+
+/// Draws the terminal pane: a title row and the shell's screen, with its cursor when focused.
+fn render_shell<B: Backend>(f: &mut Frame<B>, screen: &Screen, focused: bool, area: Rect) {
+  let style = match focused {
+    true => Style::default().fg(Color::Black).bg(Color::Yellow),
+    false => Style::default().fg(Color::White).bg(Color::DarkGray),
+  };
+  f.render_widget(
+    Paragraph::new(" :terminal").style(style),
+    Rect::new(area.x, area.y, area.width, 1),
+  );
+  // Runs of cells with the same style are one span.
+  let lines: Vec<Spans> = screen
+    .cells
+    .iter()
+    .map(|row| {
+      let mut spans: Vec<(String, Style)> = Vec::new();
+      for cell in row {
+        match spans.last_mut() {
+          Some((text, style)) if *style == cell.style => text.push(cell.c),
+          _ => spans.push((cell.c.to_string(), cell.style)),
+        }
+      }
+      let spans = spans
+        .into_iter()
+        .map(|(text, style)| Span::styled(text, style));
+      Spans::from(spans.collect::<Vec<_>>())
+    })
+    .collect();
+  let body = Rect::new(
+    area.x,
+    area.y + 1,
+    area.width,
+    area.height.saturating_sub(1),
+  );
+  f.render_widget(Paragraph::new(lines), body);
+  if focused {
+    let (row, col) = screen.cursor;
+    f.set_cursor(body.x + col as u16, body.y + row as u16);
+  }
+}
+
+fn render_separator<B: Backend>(f: &mut Frame<B>, area: Rect) {
+  let separator = vec![Spans::from("│"); area.height as usize];
+  f.render_widget(
+    Paragraph::new(separator).style(Style::default().fg(Color::DarkGray)),
+    area,
+  );
+}
+
+/// Draws the file tree sidebar. The node picked for a move is shown in yellow.
+fn render_tree<B: Backend>(f: &mut Frame<B>, editor: &mut Editor, area: Rect) {
+  let focused = editor.tree_focused;
+  let Some(tree) = &mut editor.tree else {
+    return;
+  };
+  let height = area.height as usize;
+  if tree.selected < tree.scroll_offset {
+    tree.scroll_offset = tree.selected;
+  } else if height > 0 && tree.selected >= tree.scroll_offset + height {
+    tree.scroll_offset = tree.selected + 1 - height;
+  }
+  let rows: Vec<Spans> = tree
+    .entries
+    .iter()
+    .enumerate()
+    .skip(tree.scroll_offset)
+    .take(height)
+    .map(|(i, entry)| {
+      let name = entry.path.file_name().unwrap_or_default().to_string_lossy();
+      let text = match entry.is_dir {
+        true if tree.is_expanded(&entry.path) => format!("▾ {}/", name),
+        true => format!("▸ {}/", name),
+        false => format!("  {}", name),
+      };
+      let mut style = Style::default();
+      if entry.is_dir {
+        style = style.fg(Color::LightBlue);
+      }
+      if tree.moving.as_ref() == Some(&entry.path) {
+        style = style.fg(Color::Yellow);
+      }
+      if i == tree.selected {
+        style = match focused {
+          true => style.add_modifier(Modifier::REVERSED),
+          false => style.bg(Color::DarkGray),
+        };
+      }
+      Spans::from(Span::styled(
+        format!("{}{}", "  ".repeat(entry.depth), text),
+        style,
+      ))
+    })
+    .collect();
+  f.render_widget(Paragraph::new(rows), area);
+}
+
+/// Draws the scrollbar of window `index`. With `minimap` set, the track shades each row by how
+/// long the lines it stands for are, giving a rough outline of the document.
+fn render_scrollbar<B: Backend>(f: &mut Frame<B>, editor: &Editor, index: usize, area: Rect) {
+  // Many stacked splits can leave a window no rows at all, and then there is no track.
+  if area.height == 0 {
+    return;
+  }
+  let window = &editor.windows[index];
+  let lines = &editor.buffers[window.buffer].lines;
+  let height = area.height as usize;
+  let total = lines.len().max(1);
+  let tabstop = editor.buffers[window.buffer].options.tabstop.max(1);
+  let thumb_len = (height * height / total).clamp(1, height);
+  let thumb_start = (window.scroll_offset * height / total).min(height - thumb_len);
+
+  let rows: Vec<Spans> = (0..height)
+    .map(|row| {
+      if (thumb_start..thumb_start + thumb_len).contains(&row) {
+        return Spans::from(Span::styled(" ", Style::default().bg(Color::Gray)));
+      }
+      let track = if window.options.minimap {
+        let first = row * total / height;
+        let last = ((row + 1) * total / height).max(first + 1).min(total);
+        let longest = lines[first..last]
+          .iter()
+          .map(|l| render_col(l, usize::MAX, tabstop))
+          .max()
+          .unwrap_or(0);
+        match longest {
+          0 => " ",
+          1..=20 => "░",
+          21..=60 => "▒",
+          _ => "▓",
+        }
+      } else {
+        "│"
+      };
+      Spans::from(Span::styled(track, Style::default().fg(Color::DarkGray)))
+    })
+    .collect();
+  f.render_widget(Paragraph::new(rows), area);
+}
+
+/// Draws the lines `stickyheader` pins over the top rows of window `index`, shaded and with
+/// the columns left of the text blank.
+fn render_sticky_header<B: Backend>(
+  f: &mut Frame<B>,
+  editor: &Editor,
+  index: usize,
+  area: Rect,
+  text_area: Rect,
+) {
+  let window = &editor.windows[index];
+  let buffer = &editor.buffers[window.buffer];
+  let tabstop = buffer.options.tabstop.max(1);
+  let shade = Style::default().bg(Color::Indexed(235));
+  let width = text_area.right().saturating_sub(area.x);
+  for (row, line) in editor.sticky_lines(index).into_iter().enumerate() {
+    let row_area = Rect::new(area.x, area.y + row as u16, width, 1);
+    let mut spans = render_line(
+      &buffer.lines[line],
+      |_| Style::default(),
+      &[],
+      window.col_offset,
+      tabstop,
+      window.options.showcontrol,
+    );
+    let gutter = " ".repeat((text_area.x - area.x) as usize);
+    spans.0.insert(0, Span::raw(gutter));
+    f.render_widget(Clear, row_area);
+    f.render_widget(Paragraph::new(spans).style(shade), row_area);
+  }
+}
+
+/// Draws the `foldcolumn` of window `index`: `-` where an open fold starts, `+` for a closed one
+/// and `│` alongside the rest of an open fold.
+fn render_fold_column<B: Backend>(f: &mut Frame<B>, editor: &Editor, index: usize, area: Rect) {
+  let window = &editor.windows[index];
+  let buffer = &editor.buffers[window.buffer];
+  let folds = &buffer.folds;
+  let rows: Vec<Spans> = fold::visible_lines(folds, window.scroll_offset, buffer.lines.len())
+    .take(area.height as usize)
+    .map(|line| {
+      let starts = folds.iter().filter(|f| f.start == line);
+      let marker = match starts.map(|f| f.closed).reduce(|a, b| a || b) {
+        Some(true) => "+",
+        Some(false) => "-",
+        None if folds.iter().any(|f| f.contains(line)) => "│",
+        None => " ",
+      };
+      Spans::from(Span::styled(
+        format!("{:<1$}", marker, area.width as usize),
+        Style::default().fg(Color::DarkGray),
+      ))
+    })
+    .collect();
+  f.render_widget(Paragraph::new(rows), area);
+}
+
+/// Draws the sign column of window `index`, with the `signs` of its lines.
+fn render_sign_column<B: Backend>(
+  f: &mut Frame<B>,
+  editor: &Editor,
+  index: usize,
+  signs: &BTreeMap<usize, Sign>,
+  area: Rect,
+) {
+  let window = &editor.windows[index];
+  let buffer = &editor.buffers[window.buffer];
+  let rows: Vec<Spans> =
+    fold::visible_lines(&buffer.folds, window.scroll_offset, buffer.lines.len())
+      .take(area.height as usize)
+      .map(|line| {
+        Spans::from(match signs.get(&line) {
+          Some(sign) => Span::styled(sign.text.to_string(), Style::default().fg(sign.color)),
+          None => Span::raw(" "),
+        })
+      })
+      .collect();
+  f.render_widget(Paragraph::new(rows), area);
+}
+
+fn render_window<B: Backend>(f: &mut Frame<B>, editor: &mut Editor, index: usize, area: Rect) {
+  let focused = index == editor.focus;
+  let selection = editor.selection().filter(|_| focused);
+  let flash = editor
+    .flash
+    .filter(|flash| flash.buffer == editor.windows[index].buffer)
+    .map(|flash| flash.selection);
+  // While a `:s` command is being typed, show its effect on the lines it works on.
+  let preview = match editor.mode {
+    Mode::Command if focused => Substitute::parse(&editor.command_line)
+      .filter(|sub| !sub.pattern.is_empty())
+      .and_then(|sub| {
+        let (first, last) = editor.range_lines(&sub.range).ok()?;
+        let columns = editor.range_columns(&sub.range);
+        Some((sub, first..=last, columns))
+      }),
+    _ => None,
+  };
+  let window = &mut editor.windows[index];
+  let buffer = &editor.buffers[window.buffer];
+  let height = area.height as usize;
+  let width = area.width as usize;
+  let tabstop = buffer.options.tabstop.max(1);
+  // Inlay hints are only good for the text they were made for.
+  let hints: &[Hint] = match editor.options.inlayhints && buffer.hints.version == buffer.version() {
+    true => &buffer.hints.hints,
+    false => &[],
+  };
+  let hints_on =
+    |line: usize| -> Vec<&Hint> { hints.iter().filter(|h| h.position.line == line).collect() };
+  let control = window.options.showcontrol;
+  let cursor_x = screen_col(
+    &buffer.lines[window.cursor.line],
+    window.cursor.col,
+    tabstop,
+    control,
+  ) + hints_on(window.cursor.line)
+    .iter()
+    .filter(|h| h.position.col <= window.cursor.col)
+    .map(|h| h.label.chars().count())
+    .sum::<usize>();
+  // Scroll horizontally in rendered columns so tabs count with their full width.
+  if cursor_x < window.col_offset {
+    window.col_offset = cursor_x;
+  } else if width > 0 && cursor_x >= window.col_offset + width {
+    window.col_offset = cursor_x + 1 - width;
+  }
+
+  let highlight = Style::default().fg(Color::Black).bg(Color::Yellow);
+  let selected = Style::default().add_modifier(Modifier::REVERSED);
+  let flashed = Style::default()
+    .fg(Color::Black)
+    .bg(editor.options.yankcolor);
+  let block = match editor.options.blockhighlight && focused {
+    true => indent_block(&buffer.lines, window.cursor.line, tabstop),
+    false => None,
+  };
+  let words = Words {
+    lines: &buffer.lines,
+    is_keyword: |c| buffer.options.is_keyword(c),
+  };
+  let cursor = window.cursor;
+  let cursor_word = editor.cursor_word.as_deref().filter(|_| focused);
+  let cursors: &[Position] = match focused {
+    true => &editor.cursors,
+    false => &[],
+  };
+  let word_style = Style::default().bg(Color::Indexed(239));
+  let folds = &buffer.folds;
+  let lines: Vec<Spans> = fold::visible_lines(folds, window.scroll_offset, buffer.lines.len())
+    .take(height)
+    .map(|i| {
+      let line = &buffer.lines[i];
+      let hidden = fold::visible_end(folds, i);
+      // The lines hidden by `:fold /pattern/` are summed up rather than shown by their first.
+      if hidden > i
+        && folds
+          .iter()
+          .any(|f| f.closed && f.start == i && f.kind == FoldKind::Search)
+      {
+        let summary = format!("┄┄ {} lines ┄┄", hidden - i + 1);
+        return Spans::from(Span::styled(summary, Style::default().fg(Color::DarkGray)));
+      }
+      let mut spans = match &preview {
+        Some((sub, lines, columns)) if lines.contains(&i) => {
+          let (text, ranges) = match columns {
+            Some(columns) => sub.apply_within(line, columns),
+            None => sub.apply(line),
+          };
+          let style_at = |col| match ranges.iter().any(|r| r.contains(&col)) {
+            true => highlight,
+            false => Style::default(),
+          };
+          render_line(&text, style_at, &[], window.col_offset, tabstop, control)
+        }
+        _ => {
+          // The word under the cursor itself is left as it is.
+          let others: Vec<(usize, usize)> = cursor_word
+            .map(|word| words.occurrences(i, word))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&(start, end)| !(i == cursor.line && (start..end).contains(&cursor.col)))
+            .collect();
+          let style_at = |col| {
+            if selection.is_some_and(|s| s.contains(i, col))
+              || cursors.contains(&Position::new(i, col))
+            {
+              selected
+            } else if flash.is_some_and(|s| s.contains(i, col)) {
+              flashed
+            } else if others
+              .iter()
+              .any(|&(start, end)| (start..end).contains(&col))
+            {
+              word_style
+            } else {
+              Style::default()
+            }
+          };
+          render_line(
+            line,
+            style_at,
+            &hints_on(i),
+            window.col_offset,
+            tabstop,
+            control,
+          )
+        }
+      };
+      if let Some((start, end, indent)) = block {
+        if (start..=end).contains(&i) && indent_width(line, tabstop) == Some(indent) {
+          let shade = Style::default().bg(Color::Indexed(236));
+          for span in &mut spans.0 {
+            span.style = shade.patch(span.style);
+          }
+        }
+      }
+      // A closed fold shows as its first line and its last, such as the `}` that closes it,
+      // with how many lines it has.
+      if hidden > i {
+        let dim = Style::default().fg(Color::DarkGray);
+        spans.0.push(Span::styled(" ⋯ ", dim));
+        let last = buffer.lines[hidden].trim().to_owned();
+        spans.0.push(Span::raw(last));
+        let count = format!(" ({} lines)", hidden - i + 1);
+        spans.0.push(Span::styled(count, dim));
+      }
+      // Virtual text goes after everything else on the row.
+      let notes: Vec<&str> = buffer.annotations.on(i).map(|n| n.text.as_str()).collect();
+      if !notes.is_empty() {
+        let style = Style::default()
+          .fg(Color::DarkGray)
+          .add_modifier(Modifier::DIM);
+        spans
+          .0
+          .push(Span::styled(format!("  {}", notes.join("  ")), style));
+      }
+      spans
+    })
+    .collect();
+  f.render_widget(Paragraph::new(lines), area);
+
+  if focused
+    && !editor.mode.on_command_line()
+    && editor.prompt.is_none()
+    && !editor.tree_focused
+    && !editor.output_focused
+    && !editor.shell_focused
+  {
+    let x = area.x + (cursor_x - window.col_offset) as u16;
+    let y = area.y + fold::rows_between(folds, window.scroll_offset, window.cursor.line) as u16;
+    f.set_cursor(x, y);
+    if let (Mode::Insert, Some(completion)) = (editor.mode, &editor.insert_completion) {
+      // The popup stays lined up with the start of the word while candidates change its end.
+      let line = &buffer.lines[window.cursor.line];
+      let start_x =
+        screen_col(line, completion.start, tabstop, control).saturating_sub(window.col_offset);
+      render_completion_popup(f, completion, area.x + start_x as u16, y);
+    }
+    if let (Mode::Insert, Some(signature)) = (editor.mode, &editor.signature) {
+      render_signature(f, signature, x, y);
+    }
+  }
+}
+
+/// The signature of the call being typed, above `x`, `y` if it fits there and below it if
+/// not, with the parameter being typed highlighted.
+fn render_signature<B: Backend>(f: &mut Frame<B>, signature: &Signature, x: u16, y: u16) {
+  let screen = f.size();
+  let chars: Vec<char> = signature.label.chars().collect();
+  let width = (chars.len() as u16 + 2).min(screen.width);
+  let top = match y >= 3 {
+    true => y - 3,
+    false => y + 1,
+  };
+  let popup = Rect::new(
+    x.min(screen.width - width),
+    top,
+    width,
+    3.min(screen.height - top),
+  );
+  let (start, end) = signature.active.unwrap_or((0, 0));
+  let part = |from: usize, to: usize| {
+    chars[from.min(chars.len())..to.min(chars.len())]
+      .iter()
+      .collect::<String>()
+  };
+  let text = Spans::from(vec![
+    Span::raw(part(0, start)),
+    Span::styled(
+      part(start, end),
+      Style::default()
+        .fg(Color::Yellow)
+        .add_modifier(Modifier::BOLD),
+    ),
+    Span::raw(part(end, chars.len())),
+  ]);
+  f.render_widget(Clear, popup);
+  f.render_widget(
+    Paragraph::new(text).block(Block::default().borders(Borders::ALL)),
+    popup,
+  );
+}
+
+/// The candidates of insert mode completion, below `x`, `y` if they fit there and above it if
+/// not. Only the last part of each path is shown.
+fn render_completion_popup<B: Backend>(f: &mut Frame<B>, completion: &Completion, x: u16, y: u16) {
+  let screen = f.size();
+  let names: Vec<&str> = completion
+    .candidates
+    .iter()
+    .map(|c| {
+      let end = c.len() - c.ends_with('/') as usize;
+      c[..end].rfind('/').map_or(c.as_str(), |i| &c[i + 1..])
+    })
+    .collect();
+  let width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0) as u16 + 2;
+  let width = width.min(screen.width);
+  let height = (names.len() as u16).min(8);
+  let below = y + 1 + height <= screen.height;
+  let top = match below {
+    true => y + 1,
+    false => y.saturating_sub(height),
+  };
+  let left = x.min(screen.width - width);
+  let popup = Rect::new(left, top, width, height.min(screen.height));
+  let first = (completion.index + 1).saturating_sub(height as usize);
+  let rows: Vec<Spans> = names
+    .iter()
+    .enumerate()
+    .skip(first)
+    .take(height as usize)
+    .map(|(i, name)| {
+      let style = match i == completion.index {
+        true => Style::default().fg(Color::Black).bg(Color::Yellow),
+        false => Style::default().fg(Color::White).bg(Color::DarkGray),
+      };
+      Spans::from(Span::styled(
+        format!(" {:<1$}", name, width as usize - 1),
+        style,
+      ))
+    })
+    .collect();
+  f.render_widget(Clear, popup);
+  f.render_widget(Paragraph::new(rows), popup);
+}
+
+fn render_status<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+  if let Some(completion) = &editor.completion {
+    render_completion(f, completion, area);
+    return;
+  }
+  let mode = match editor.mode {
+    _ if editor.tree_focused => "TREE",
+    _ if editor.output_focused => "OUTPUT",
+    _ if editor.shell_focused => "TERMINAL",
+    Mode::Normal => "NORMAL",
+    Mode::Insert => "INSERT",
+    Mode::Command => "COMMAND",
+    Mode::Search => "SEARCH",
+    Mode::Visual(VisualKind::Char) => "VISUAL",
+    Mode::Visual(VisualKind::Line) => "V-LINE",
+    Mode::Visual(VisualKind::Block) => "V-BLOCK",
+  };
+  let recording = match &editor.recording {
+    Some((name, _)) => format!("recording @{} ", name),
+    None => String::new(),
+  };
+  let badge = Span::styled(
+    format!(" {} {}", mode, recording),
+    Style::default()
+      .fg(Color::Black)
+      .bg(Color::Yellow)
+      .add_modifier(Modifier::BOLD),
+  );
+  let (buffer, cursor) = (editor.buffer(), editor.window().cursor);
+  let status = match &editor.config.statusline {
+    Some(format) => {
+      let mut fields = status_fields(buffer, editor.window().buffer, cursor);
+      fields.search = search_count(editor);
+      fields.view = Some(view_lines(editor));
+      let (left, right) = statusline::expand(format, &fields);
+      let left = format!(" {}", left);
+      let used = badge.content.chars().count() + left.chars().count() + right.chars().count();
+      let gap = (area.width as usize).saturating_sub(used + 1);
+      Spans::from(vec![
+        badge,
+        Span::raw(left),
+        Span::raw(" ".repeat(gap)),
+        Span::raw(right),
+      ])
+    }
+    None => Spans::from(vec![
+      badge,
+      Span::raw(format!(
+        " {}{}  {}:{}  {}  {}",
+        buffer.name(),
+        if buffer.modified { " [+]" } else { "" },
+        cursor.line + 1,
+        cursor.col + 1,
+        statusline::ruler(view_lines(editor), buffer.lines.len()),
+        search_count(editor)
+      )),
+    ]),
+  };
+  let status = Paragraph::new(status).style(Style::default().fg(Color::White).bg(Color::DarkGray));
+  f.render_widget(status, area);
+}
+
+/// Lists completion candidates in place of the status line, keeping the current one in view.
+fn render_completion<B: Backend>(f: &mut Frame<B>, completion: &Completion, area: Rect) {
+  let candidates = &completion.candidates;
+  let width_of = |range: &[String]| range.iter().map(|c| c.chars().count() + 2).sum::<usize>();
+  let mut first = 0;
+  while first < completion.index
+    && width_of(&candidates[first..=completion.index]) > area.width as usize
+  {
+    first += 1;
+  }
+  let spans: Vec<Span> = candidates
+    .iter()
+    .enumerate()
+    .skip(first)
+    .map(|(i, candidate)| {
+      let style = match i == completion.index {
+        true => Style::default().fg(Color::Black).bg(Color::Yellow),
+        false => Style::default(),
+      };
+      Span::styled(format!(" {} ", candidate), style)
+    })
+    .collect();
+  let list =
+    Paragraph::new(Spans::from(spans)).style(Style::default().fg(Color::White).bg(Color::DarkGray));
+  f.render_widget(list, area);
+}
+
+fn render_footer<B: Backend>(f: &mut Frame<B>, editor: &Editor, area: Rect) {
+  let text = if let Some(prompt) = editor.prompt {
+    match &editor.message {
+      Some(message) => format!("{} {}", message, prompt.text()),
+      None => prompt.text().to_owned(),
+    }
+  } else if let Some((input, name)) = editor.tree.as_ref().and_then(|t| t.input.as_ref()) {
+    match input {
+      TreeInput::Create => format!("New file (end with / for a directory): {}", name),
+      TreeInput::Rename => format!("Rename to: {}", name),
+    }
+  } else if editor.mode == Mode::Command {
+    format!(":{}", editor.command_line)
+  } else if editor.mode == Mode::Search {
+    format!("/{}", editor.command_line)
+  } else {
+    editor.message.clone().unwrap_or_default()
+  };
+  let style = match editor.prompt {
+    Some(_) => Style::default().fg(Color::LightRed),
+    None => Style::default(),
+  };
+  f.render_widget(Paragraph::new(text.as_str()).style(style), area);
+  let typing = editor.tree.as_ref().is_some_and(|t| t.input.is_some());
+  if editor.prompt.is_none() && typing {
+    f.set_cursor(area.x + text.chars().count() as u16, area.y);
+  } else if editor.mode.on_command_line() && editor.prompt.is_none() {
+    f.set_cursor(
+      area.x + 1 + editor.command_line.chars().count() as u16,
+      area.y,
+    );
+  }
+}
+
+/// Renders `line` with per-char styles and the inlay `hints` on it, expanding tabs and
+/// dropping the first `skip` columns. With `control`, control chars are drawn as
+/// `control_glyphs` has them.
+fn render_line(
+  line: &str,
+  style_at: impl Fn(usize) -> Style,
+  hints: &[&Hint],
+  skip: usize,
+  tabstop: usize,
+  control: bool,
+) -> Spans<'static> {
+  let mut spans: Vec<Span> = Vec::new();
+  let mut x = 0;
+  let mut put = |c: char, style: Style, x: &mut usize| {
+    if *x >= skip {
+      match spans.last_mut() {
+        Some(span) if span.style == style => span.content.to_mut().push(c),
+        _ => spans.push(Span::styled(c.to_string(), style)),
+      }
+    }
+    *x += 1;
+  };
+  let hint_style = Style::default()
+    .fg(Color::DarkGray)
+    .add_modifier(Modifier::DIM);
+  let mut hints = hints.iter().peekable();
+  // Tabs line up by the text alone, as `render_col` has them.
+  let mut text_x = 0;
+  for (col, c) in line.chars().enumerate() {
+    while let Some(hint) = hints.next_if(|h| h.position.col <= col) {
+      hint.label.chars().for_each(|h| put(h, hint_style, &mut x));
+    }
+    let style = style_at(col);
+    if let Some(glyphs) = control_glyphs(c).filter(|_| control) {
+      let style = style.fg(Color::LightBlue);
+      glyphs.chars().for_each(|g| put(g, style, &mut x));
+      text_x += glyphs.len();
+      continue;
+    }
+    let width = match c {
+      '\t' => tabstop - text_x % tabstop,
+      _ => 1,
+    };
+    for _ in 0..width {
+      put(if c == '\t' { ' ' } else { c }, style, &mut x);
+    }
+    text_x += width;
+  }
+  // Those at the end of the line.
+  for hint in hints {
+    hint.label.chars().for_each(|h| put(h, hint_style, &mut x));
+  }
+  Spans::from(spans)
+}
+
+/// The rendered width of the indent of `line`, or `None` for a blank line.
+fn indent_width(line: &str, tabstop: usize) -> Option<usize> {
+  let text = line.trim_start();
+  match text.is_empty() {
+    true => None,
+    false => {
+      let indent = line.len() - text.len();
+      Some(render_col(line, line[..indent].chars().count(), tabstop))
+    }
+  }
+}
+
+/// For `blockhighlight`: the first and last line of the block around `line`, which ends where
+/// the indentation drops below that of `line`, and that indent. Blank lines don't end a block
+/// and are skipped when `line` itself is one. Only the block's own lines are looked at.
+fn indent_block(lines: &[String], line: usize, tabstop: usize) -> Option<(usize, usize, usize)> {
+  let (line, indent) =
+    (line..lines.len()).find_map(|l| indent_width(&lines[l], tabstop).map(|indent| (l, indent)))?;
+  let inside = |l: &usize| indent_width(&lines[*l], tabstop).is_none_or(|i| i >= indent);
+  let start = (0..line).rev().take_while(inside).last().unwrap_or(line);
+  let end = (line + 1..lines.len())
+    .take_while(inside)
+    .last()
+    .unwrap_or(line);
+  Some((start, end, indent))
+}
+
+/// The screen column of char `col` in `line` once tabs are expanded.
+pub fn render_col(line: &str, col: usize, tabstop: usize) -> usize {
+  screen_col(line, col, tabstop, false)
+}
+
+/// Like `render_col`, but with control chars as wide as their `control_glyphs` if `control`.
+fn screen_col(line: &str, col: usize, tabstop: usize, control: bool) -> usize {
+  line.chars().take(col).fold(0, |x, c| match c {
+    '\t' => x + tabstop - x % tabstop,
+    _ => match control_glyphs(c).filter(|_| control) {
+      Some(glyphs) => x + glyphs.len(),
+      None => x + 1,
+    },
+  })
+}
+
+/// How `showcontrol` draws `c`, if not as itself: ASCII control chars in caret notation like
+/// `^M` or `^?`, the other control chars by code like `<85>`, and the char that stands in for
+/// bytes that weren't UTF-8 as `<?>`. Tabs are left as they are.
+pub fn control_glyphs(c: char) -> Option<String> {
+  match c {
+    '\t' => None,
+    '\0'..='\x1f' => Some(format!("^{}", (c as u8 + b'@') as char)),
+    '\x7f' => Some("^?".to_owned()),
+    '\u{80}'..='\u{9f}' => Some(format!("<{:02x}>", c as u32)),
+    char::REPLACEMENT_CHARACTER => Some("<?>".to_owned()),
+    _ => None,
+  }
+}
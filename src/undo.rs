@@ -0,0 +1,389 @@
+use crate::buffer::Position;
+use chrono::{DateTime, Duration, Local};
+use std::collections::BTreeMap;
+
+/// A single change to a buffer, with enough information to reverse it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+  Insert { at: Position, text: String },
+  Delete { at: Position, text: String },
+}
+
+impl Edit {
+  /// The change that undoes this one.
+  pub fn inverse(&self) -> Edit {
+    match self {
+      Edit::Insert { at, text } => Edit::Delete {
+        at: *at,
+        text: text.clone(),
+      },
+      Edit::Delete { at, text } => Edit::Insert {
+        at: *at,
+        text: text.clone(),
+      },
+    }
+  }
+
+  pub fn at(&self) -> Position {
+    match self {
+      Edit::Insert { at, .. } | Edit::Delete { at, .. } => *at,
+    }
+  }
+
+  fn text(&self) -> &str {
+    match self {
+      Edit::Insert { text, .. } | Edit::Delete { text, .. } => text,
+    }
+  }
+}
+
+/// Edits that are undone and redone together, e.g. everything typed in one insert session.
+pub type UndoGroup = Vec<Edit>;
+
+/// A state of the buffer, reached from its parent by redoing `group`.
+#[derive(Debug)]
+struct Node {
+  parent: Option<usize>,
+  /// Oldest first.
+  children: Vec<usize>,
+  group: UndoGroup,
+  time: DateTime<Local>,
+  /// The child redo goes to: the last one made or undone from.
+  redo: Option<usize>,
+}
+
+/// A row of `:undotree`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+  /// The change's number, counting from 1 in the order they were made; 0 is the oldest state
+  /// kept.
+  pub id: usize,
+  /// How many branches off the oldest changes' line this one is.
+  pub depth: usize,
+  pub time: DateTime<Local>,
+}
+
+/// How far `:earlier` and `:later` go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Distance {
+  /// A number of states, in the order they were made, whichever branch they are on.
+  Changes(usize),
+  Time(Duration),
+}
+
+impl Distance {
+  /// Reads `10` as ten changes and `30s`, `5m`, `2h` or `1d` as a time. Nothing means one
+  /// change.
+  pub fn parse(arg: &str) -> Option<Distance> {
+    if arg.is_empty() {
+      return Some(Distance::Changes(1));
+    }
+    let digits = arg.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    // Unsigned, so `:later -5` is refused instead of going the other way.
+    let n: u64 = digits.parse().ok()?;
+    let unit = match &arg[digits.len()..] {
+      "" => return Some(Distance::Changes(usize::try_from(n).ok()?)),
+      "s" => 1,
+      "m" => 60,
+      "h" => 60 * 60,
+      "d" => 24 * 60 * 60,
+      _ => return None,
+    };
+    // A `Duration` counts milliseconds in an `i64` and panics past that.
+    let seconds = n.checked_mul(unit)?;
+    (seconds <= i64::MAX as u64 / 1000).then(|| Distance::Time(Duration::seconds(seconds as i64)))
+  }
+}
+
+/// Every state the buffer has been in, as a tree: undoing and then making a new change starts
+/// a branch instead of throwing away what was undone.
+#[derive(Debug)]
+pub struct UndoTree {
+  /// By number, which is also the order they were made in.
+  nodes: BTreeMap<usize, Node>,
+  /// The oldest state kept, which can't be undone.
+  root: usize,
+  /// The state the buffer is in.
+  current: usize,
+  next: usize,
+  /// The group still being recorded.
+  recording: UndoGroup,
+  /// Text held by all the nodes together.
+  bytes: usize,
+}
+
+impl Default for UndoTree {
+  fn default() -> Self {
+    let root = Node {
+      parent: None,
+      children: Vec::new(),
+      group: UndoGroup::new(),
+      time: Local::now(),
+      redo: None,
+    };
+    UndoTree {
+      nodes: BTreeMap::from([(0, root)]),
+      root: 0,
+      current: 0,
+      next: 1,
+      recording: UndoGroup::new(),
+      bytes: 0,
+    }
+  }
+}
+
+impl UndoTree {
+  pub fn record(&mut self, edit: Edit) {
+    self.recording.push(edit);
+  }
+
+  /// Closes the group being recorded as part of the current state's own group instead, as if
+  /// made with it, unless something branches off that state or it is the oldest one. Returns
+  /// whether there was anything to close.
+  pub fn join(&mut self) -> bool {
+    if self.recording.is_empty() {
+      return false;
+    }
+    let node = &self.nodes[&self.current];
+    if self.current == self.root || !node.children.is_empty() {
+      self.commit();
+      return true;
+    }
+    let group = std::mem::take(&mut self.recording);
+    self.bytes += size(&group);
+    self.node_mut(self.current).group.extend(group);
+    true
+  }
+
+  /// Closes the group being recorded as a new state after the current one, so the next edit
+  /// starts a new group.
+  pub fn commit(&mut self) {
+    if self.recording.is_empty() {
+      return;
+    }
+    let group = std::mem::take(&mut self.recording);
+    let id = self.next;
+    self.next += 1;
+    self.bytes += size(&group);
+    self.nodes.insert(
+      id,
+      Node {
+        parent: Some(self.current),
+        children: Vec::new(),
+        group,
+        time: Local::now(),
+        redo: None,
+      },
+    );
+    let parent = self.node_mut(self.current);
+    parent.children.push(id);
+    parent.redo = Some(id);
+    self.current = id;
+  }
+
+  /// Forgets the oldest states until at most `levels` changes and `max_bytes` of text remain,
+  /// along with the branches that split off before them. Undo then simply stops at the oldest
+  /// state left. The current state and what can be redone from it are always kept.
+  pub fn trim(&mut self, levels: usize, max_bytes: usize) {
+    while self.root != self.current && (self.nodes.len() - 1 > levels || self.bytes > max_bytes) {
+      let next_root = self
+        .ancestors(self.current)
+        .take_while(|&id| id != self.root)
+        .last();
+      let Some(next_root) = next_root else {
+        break;
+      };
+      let old = self.nodes.remove(&self.root).expect("the root is a node");
+      for child in old.children.into_iter().filter(|&c| c != next_root) {
+        self.remove_subtree(child);
+      }
+      let root = self.node_mut(next_root);
+      root.parent = None;
+      let group = std::mem::take(&mut root.group);
+      self.bytes -= size(&group);
+      self.root = next_root;
+    }
+  }
+
+  /// Moves back to the state before the current one, returning the group to undo. The caller
+  /// applies its inverse edits in reverse order.
+  pub fn undo(&mut self) -> Option<UndoGroup> {
+    self.commit();
+    let node = &self.nodes[&self.current];
+    let parent = node.parent?;
+    let (group, from) = (node.group.clone(), self.current);
+    self.node_mut(parent).redo = Some(from);
+    self.current = parent;
+    Some(group)
+  }
+
+  /// Moves forward along the branch last made or undone from, returning the group to redo.
+  pub fn redo(&mut self) -> Option<UndoGroup> {
+    let child = self.nodes[&self.current].redo?;
+    self.current = child;
+    Some(self.nodes[&child].group.clone())
+  }
+
+  /// The number of undos and then the states to redo, in order, to get from the current state
+  /// to `target`. Redoing one of them needs `choose` first, since it may not be the branch
+  /// redo would take.
+  pub fn route(&self, target: usize) -> Option<(usize, Vec<usize>)> {
+    self.nodes.get(&target)?;
+    let ours: Vec<usize> = self.ancestors(self.current).collect();
+    let mut down = Vec::new();
+    for id in self.ancestors(target) {
+      if let Some(up) = ours.iter().position(|&a| a == id) {
+        down.reverse();
+        return Some((up, down));
+      }
+      down.push(id);
+    }
+    None
+  }
+
+  /// Makes redo from `id`'s parent go to `id`.
+  pub fn choose(&mut self, id: usize) {
+    if let Some(parent) = self.nodes.get(&id).and_then(|node| node.parent) {
+      self.node_mut(parent).redo = Some(id);
+    }
+  }
+
+  pub fn current(&self) -> usize {
+    self.current
+  }
+
+  pub fn time(&self, id: usize) -> Option<DateTime<Local>> {
+    self.nodes.get(&id).map(|node| node.time)
+  }
+
+  /// The state `distance` before the current one, or after it if `forward`, stopping at the
+  /// oldest and newest. By time, that is the last state made by then going back, and the first
+  /// made since then going forward.
+  pub fn travel(&self, distance: Distance, forward: bool) -> usize {
+    let ids: Vec<usize> = self.nodes.keys().copied().collect();
+    let here = ids.iter().position(|&id| id == self.current).unwrap_or(0);
+    match (distance, forward) {
+      (Distance::Changes(n), false) => ids[here.saturating_sub(n)],
+      (Distance::Changes(n), true) => ids[here.saturating_add(n).min(ids.len() - 1)],
+      (Distance::Time(span), false) => {
+        // Before the earliest time there is, which only the oldest state can be from.
+        let Some(then) = self.nodes[&self.current].time.checked_sub_signed(span) else {
+          return self.root;
+        };
+        let made = ids[..here]
+          .iter()
+          .rev()
+          .find(|id| self.nodes[*id].time <= then);
+        made.copied().unwrap_or(self.root)
+      }
+      (Distance::Time(span), true) => {
+        let Some(then) = self.nodes[&self.current].time.checked_add_signed(span) else {
+          return ids.last().copied().unwrap_or(self.current);
+        };
+        let made = ids[here + 1..]
+          .iter()
+          .find(|id| self.nodes[*id].time >= then);
+        made.or(ids.last()).copied().unwrap_or(self.current)
+      }
+    }
+  }
+
+  /// The states oldest first, each branch right after the change it split off from.
+  pub fn entries(&self) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut stack = vec![(self.root, 0)];
+    while let Some((id, depth)) = stack.pop() {
+      let node = &self.nodes[&id];
+      entries.push(Entry {
+        id,
+        depth,
+        time: node.time,
+      });
+      // The oldest child continues the line its parent is on; later ones are branches.
+      for (i, &child) in node.children.iter().enumerate().rev() {
+        stack.push((child, depth + usize::from(i > 0)));
+      }
+    }
+    entries
+  }
+
+  pub fn clear(&mut self) {
+    *self = UndoTree::default();
+  }
+
+  /// `id` and the states before it, back to the root.
+  fn ancestors(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+    std::iter::successors(Some(id), |id| self.nodes[id].parent)
+  }
+
+  fn remove_subtree(&mut self, id: usize) {
+    if let Some(node) = self.nodes.remove(&id) {
+      self.bytes -= size(&node.group);
+      for child in node.children {
+        self.remove_subtree(child);
+      }
+    }
+  }
+
+  fn node_mut(&mut self, id: usize) -> &mut Node {
+    self
+      .nodes
+      .get_mut(&id)
+      .expect("undo states refer to existing ones")
+  }
+}
+
+fn size(group: &UndoGroup) -> usize {
+  group.iter().map(|edit| edit.text().len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A tree with `count` states after the oldest, each from typing `text` on its own line.
+  fn tree(count: usize, text: &str) -> UndoTree {
+    let mut tree = UndoTree::default();
+    for line in 0..count {
+      tree.record(Edit::Insert {
+        at: Position::new(line, 0),
+        text: text.to_owned(),
+      });
+      tree.commit();
+    }
+    tree
+  }
+
+  #[test]
+  fn trim_keeps_at_most_levels_changes() {
+    let mut tree = tree(10, "x");
+    tree.trim(3, usize::MAX);
+    assert_eq!(tree.entries().len(), 4);
+    for _ in 0..3 {
+      assert!(tree.undo().is_some());
+    }
+    assert!(tree.undo().is_none());
+  }
+
+  #[test]
+  fn trim_keeps_at_most_max_bytes() {
+    let mut tree = tree(10, "0123456789");
+    tree.trim(usize::MAX, 35);
+    assert!(tree.bytes <= 35);
+    assert_eq!(tree.entries().len(), 4);
+  }
+
+  #[test]
+  fn trim_keeps_the_current_state_and_its_redos() {
+    let mut tree = tree(5, "x");
+    tree.undo();
+    tree.undo();
+    let current = tree.current();
+    tree.trim(0, 0);
+    assert_eq!(tree.current(), current);
+    assert!(tree.undo().is_none());
+    assert!(tree.redo().is_some());
+    assert!(tree.redo().is_some());
+    assert!(tree.redo().is_none());
+  }
+}
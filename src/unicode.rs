@@ -0,0 +1,207 @@
+//! Character names for `ga`.
+//!
+//! ASCII, Latin-1 and the ranges whose names follow a pattern are known here. Anything else is
+//! looked up in the Unicode Character Database if the system has a copy of `UnicodeData.txt`.
+
+use std::fs;
+use std::sync::OnceLock;
+
+/// Where distributions put the Unicode Character Database.
+const DATABASES: &[&str] = &[
+  "/usr/share/unicode/UnicodeData.txt",
+  "/usr/share/unicode/ucd/UnicodeData.txt",
+  "/usr/share/unicode-data/UnicodeData.txt",
+];
+
+const ASCII_PUNCTUATION: [&str; 33] = [
+  "SPACE",
+  "EXCLAMATION MARK",
+  "QUOTATION MARK",
+  "NUMBER SIGN",
+  "DOLLAR SIGN",
+  "PERCENT SIGN",
+  "AMPERSAND",
+  "APOSTROPHE",
+  "LEFT PARENTHESIS",
+  "RIGHT PARENTHESIS",
+  "ASTERISK",
+  "PLUS SIGN",
+  "COMMA",
+  "HYPHEN-MINUS",
+  "FULL STOP",
+  "SOLIDUS",
+  "COLON",
+  "SEMICOLON",
+  "LESS-THAN SIGN",
+  "EQUALS SIGN",
+  "GREATER-THAN SIGN",
+  "QUESTION MARK",
+  "COMMERCIAL AT",
+  "LEFT SQUARE BRACKET",
+  "REVERSE SOLIDUS",
+  "RIGHT SQUARE BRACKET",
+  "CIRCUMFLEX ACCENT",
+  "LOW LINE",
+  "GRAVE ACCENT",
+  "LEFT CURLY BRACKET",
+  "VERTICAL LINE",
+  "RIGHT CURLY BRACKET",
+  "TILDE",
+];
+
+/// The names of U+00A0 to U+00BF.
+const LATIN1_SYMBOLS: [&str; 32] = [
+  "NO-BREAK SPACE",
+  "INVERTED EXCLAMATION MARK",
+  "CENT SIGN",
+  "POUND SIGN",
+  "CURRENCY SIGN",
+  "YEN SIGN",
+  "BROKEN BAR",
+  "SECTION SIGN",
+  "DIAERESIS",
+  "COPYRIGHT SIGN",
+  "FEMININE ORDINAL INDICATOR",
+  "LEFT-POINTING DOUBLE ANGLE QUOTATION MARK",
+  "NOT SIGN",
+  "SOFT HYPHEN",
+  "REGISTERED SIGN",
+  "MACRON",
+  "DEGREE SIGN",
+  "PLUS-MINUS SIGN",
+  "SUPERSCRIPT TWO",
+  "SUPERSCRIPT THREE",
+  "ACUTE ACCENT",
+  "MICRO SIGN",
+  "PILCROW SIGN",
+  "MIDDLE DOT",
+  "CEDILLA",
+  "SUPERSCRIPT ONE",
+  "MASCULINE ORDINAL INDICATOR",
+  "RIGHT-POINTING DOUBLE ANGLE QUOTATION MARK",
+  "VULGAR FRACTION ONE QUARTER",
+  "VULGAR FRACTION ONE HALF",
+  "VULGAR FRACTION THREE QUARTERS",
+  "INVERTED QUESTION MARK",
+];
+
+/// U+00C0 to U+00DF after "LATIN CAPITAL LETTER ", and U+00E0 to U+00FF after "LATIN SMALL
+/// LETTER ". The gaps are the multiplication and division signs and the unpaired letters.
+const LATIN1_LETTERS: [&str; 32] = [
+  "A WITH GRAVE",
+  "A WITH ACUTE",
+  "A WITH CIRCUMFLEX",
+  "A WITH TILDE",
+  "A WITH DIAERESIS",
+  "A WITH RING ABOVE",
+  "AE",
+  "C WITH CEDILLA",
+  "E WITH GRAVE",
+  "E WITH ACUTE",
+  "E WITH CIRCUMFLEX",
+  "E WITH DIAERESIS",
+  "I WITH GRAVE",
+  "I WITH ACUTE",
+  "I WITH CIRCUMFLEX",
+  "I WITH DIAERESIS",
+  "ETH",
+  "N WITH TILDE",
+  "O WITH GRAVE",
+  "O WITH ACUTE",
+  "O WITH CIRCUMFLEX",
+  "O WITH TILDE",
+  "O WITH DIAERESIS",
+  "",
+  "O WITH STROKE",
+  "U WITH GRAVE",
+  "U WITH ACUTE",
+  "U WITH CIRCUMFLEX",
+  "U WITH DIAERESIS",
+  "Y WITH ACUTE",
+  "THORN",
+  "",
+];
+
+const HANGUL_LEADS: [&str; 19] = [
+  "G", "GG", "N", "D", "DD", "R", "M", "B", "BB", "S", "SS", "", "J", "JJ", "C", "K", "T", "P", "H",
+];
+const HANGUL_VOWELS: [&str; 21] = [
+  "A", "AE", "YA", "YAE", "EO", "E", "YEO", "YE", "O", "WA", "WAE", "OE", "YO", "U", "WEO", "WE",
+  "WI", "YU", "EU", "YI", "I",
+];
+const HANGUL_TAILS: [&str; 28] = [
+  "", "G", "GG", "GS", "N", "NJ", "NH", "D", "L", "LG", "LM", "LB", "LS", "LT", "LP", "LH", "M",
+  "B", "BS", "S", "SS", "NG", "J", "C", "K", "T", "P", "H",
+];
+
+/// The Unicode name of `c`, if it has one we know. Control characters have none.
+pub fn name(c: char) -> Option<String> {
+  let code = c as u32;
+  let name = match c {
+    ' '..='/' | ':'..='@' | '['..='`' | '{'..='~' => {
+      let index = match code {
+        0x20..=0x2f => code - 0x20,
+        0x3a..=0x40 => code - 0x3a + 16,
+        0x5b..=0x60 => code - 0x5b + 23,
+        _ => code - 0x7b + 29,
+      };
+      ASCII_PUNCTUATION[index as usize].to_owned()
+    }
+    '0'..='9' => {
+      let digits = [
+        "ZERO", "ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX", "SEVEN", "EIGHT", "NINE",
+      ];
+      format!("DIGIT {}", digits[(code - 0x30) as usize])
+    }
+    'A'..='Z' => format!("LATIN CAPITAL LETTER {}", c),
+    'a'..='z' => format!("LATIN SMALL LETTER {}", c.to_ascii_uppercase()),
+    '\u{a0}'..='\u{bf}' => LATIN1_SYMBOLS[(code - 0xa0) as usize].to_owned(),
+    '\u{d7}' => "MULTIPLICATION SIGN".to_owned(),
+    '\u{f7}' => "DIVISION SIGN".to_owned(),
+    '\u{df}' => "LATIN SMALL LETTER SHARP S".to_owned(),
+    '\u{ff}' => "LATIN SMALL LETTER Y WITH DIAERESIS".to_owned(),
+    '\u{c0}'..='\u{de}' => format!(
+      "LATIN CAPITAL LETTER {}",
+      LATIN1_LETTERS[(code - 0xc0) as usize]
+    ),
+    '\u{e0}'..='\u{fe}' => format!(
+      "LATIN SMALL LETTER {}",
+      LATIN1_LETTERS[(code - 0xe0) as usize]
+    ),
+    '\u{ac00}'..='\u{d7a3}' => {
+      let index = (code - 0xac00) as usize;
+      format!(
+        "HANGUL SYLLABLE {}{}{}",
+        HANGUL_LEADS[index / (21 * 28)],
+        HANGUL_VOWELS[index % (21 * 28) / 28],
+        HANGUL_TAILS[index % 28]
+      )
+    }
+    '\u{3400}'..='\u{4dbf}'
+    | '\u{4e00}'..='\u{9fff}'
+    | '\u{20000}'..='\u{2a6df}'
+    | '\u{2a700}'..='\u{2ebef}'
+    | '\u{30000}'..='\u{3134f}' => format!("CJK UNIFIED IDEOGRAPH-{:04X}", code),
+    _ if c.is_control() => return None,
+    _ => return lookup(code),
+  };
+  Some(name)
+}
+
+/// Looks `code` up in the system's Unicode Character Database, read on first use.
+fn lookup(code: u32) -> Option<String> {
+  static DATABASE: OnceLock<Option<String>> = OnceLock::new();
+  let database = DATABASE.get_or_init(|| {
+    DATABASES
+      .iter()
+      .find_map(|path| fs::read_to_string(path).ok())
+  });
+  let code = format!("{:04X};", code);
+  database
+    .as_deref()?
+    .lines()
+    .find(|line| line.starts_with(&code))
+    .and_then(|line| line.split(';').nth(1))
+    .filter(|name| !name.starts_with('<'))
+    .map(str::to_owned)
+}
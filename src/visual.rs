@@ -0,0 +1,106 @@
+//! The text selected in visual mode.
+
+use crate::buffer::Position;
+use std::time::Instant;
+
+/// How a selection extends between its two ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisualKind {
+  /// `v`: every char from one end to the other.
+  Char,
+  /// `V`: whole lines.
+  Line,
+  /// `Ctrl+V`: a rectangle of columns.
+  Block,
+}
+
+/// A selection with its ends in order. Both ends are included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+  pub kind: VisualKind,
+  pub start: Position,
+  pub end: Position,
+}
+
+impl Selection {
+  pub fn new(kind: VisualKind, anchor: Position, cursor: Position) -> Self {
+    let (start, end) = match kind {
+      VisualKind::Block => (
+        Position::new(anchor.line.min(cursor.line), anchor.col.min(cursor.col)),
+        Position::new(anchor.line.max(cursor.line), anchor.col.max(cursor.col)),
+      ),
+      _ => (anchor.min(cursor), anchor.max(cursor)),
+    };
+    Selection { kind, start, end }
+  }
+
+  pub fn contains(&self, line: usize, col: usize) -> bool {
+    if line < self.start.line || line > self.end.line {
+      return false;
+    }
+    match self.kind {
+      VisualKind::Char => {
+        (line > self.start.line || col >= self.start.col)
+          && (line < self.end.line || col <= self.end.col)
+      }
+      VisualKind::Line => true,
+      VisualKind::Block => col >= self.start.col && col <= self.end.col,
+    }
+  }
+
+  /// The selected text of `lines`, a line at a time. A character selection ending past the
+  /// end of a line takes the line break too, as an empty last entry.
+  pub fn text(&self, lines: &[String]) -> Vec<String> {
+    let (start, end) = (self.start, self.end);
+    let slice = |line: usize, from: usize, to: usize| -> String {
+      lines[line]
+        .chars()
+        .skip(from)
+        .take(to.saturating_sub(from))
+        .collect()
+    };
+    match self.kind {
+      VisualKind::Line => lines[start.line..=end.line].to_vec(),
+      VisualKind::Block => (start.line..=end.line)
+        .map(|line| slice(line, start.col, end.col + 1))
+        .collect(),
+      VisualKind::Char => {
+        let mut text: Vec<String> = (start.line..=end.line)
+          .map(|line| {
+            let from = if line == start.line { start.col } else { 0 };
+            let to = if line == end.line {
+              end.col + 1
+            } else {
+              usize::MAX
+            };
+            slice(line, from, to)
+          })
+          .collect();
+        if end.col >= lines[end.line].chars().count() && end.line + 1 < lines.len() {
+          text.push(String::new());
+        }
+        text
+      }
+    }
+  }
+}
+
+/// Text just yanked, highlighted until `until`.
+#[derive(Clone, Copy, Debug)]
+pub struct Flash {
+  pub buffer: usize,
+  pub selection: Selection,
+  pub until: Instant,
+}
+
+/// Text typed after `I` or `A` on a block, copied to the block's other lines when insert mode
+/// ends.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockInsert {
+  /// Where typing started on the first line of the block.
+  pub at: Position,
+  /// Last line of the block.
+  pub last: usize,
+  /// Whether the text goes after the block, padding shorter lines to reach it.
+  pub append: bool,
+}
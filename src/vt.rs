@@ -0,0 +1,360 @@
+//! The screen of the shell `:terminal` runs: a grid of cells kept up to date from what the
+//! shell prints, understanding the common xterm control sequences for moving the cursor,
+//! erasing, scrolling and colors. Anything else it is sent is skipped.
+
+use tui::style::{Color, Modifier, Style};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+  pub c: char,
+  pub style: Style,
+}
+
+const BLANK: Cell = Cell {
+  c: ' ',
+  style: Style {
+    fg: None,
+    bg: None,
+    add_modifier: Modifier::empty(),
+    sub_modifier: Modifier::empty(),
+  },
+};
+
+/// Where in a control sequence the last byte left off.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+  Ground,
+  /// After `ESC`.
+  Escape,
+  /// After `ESC [`, with the parameters so far.
+  Csi(String),
+  /// In an operating system command such as a window title, which ends with `BEL` or `ESC \`.
+  Osc,
+  /// After `ESC (` and the like, which pick a character set from the next byte.
+  Charset,
+}
+
+#[derive(Clone, Debug)]
+pub struct Screen {
+  pub rows: usize,
+  pub cols: usize,
+  pub cells: Vec<Vec<Cell>>,
+  /// Row and column.
+  pub cursor: (usize, usize),
+  /// Whether the arrow keys are to be sent as `ESC O A` rather than `ESC [ A`.
+  pub app_cursor: bool,
+  style: Style,
+  saved: (usize, usize),
+  /// The first and last row that scroll.
+  region: (usize, usize),
+  /// Set after writing the last column, so the next char goes on a new line.
+  wrap_pending: bool,
+  state: State,
+  /// The bytes so far of a char that isn't complete yet.
+  utf8: Vec<u8>,
+}
+
+impl Screen {
+  pub fn new(rows: usize, cols: usize) -> Self {
+    let (rows, cols) = (rows.max(1), cols.max(1));
+    Screen {
+      rows,
+      cols,
+      cells: vec![vec![BLANK; cols]; rows],
+      cursor: (0, 0),
+      app_cursor: false,
+      style: Style::default(),
+      saved: (0, 0),
+      region: (0, rows - 1),
+      wrap_pending: false,
+      state: State::Ground,
+      utf8: Vec::new(),
+    }
+  }
+
+  /// Changes the size, keeping the top left of what is there.
+  pub fn resize(&mut self, rows: usize, cols: usize) {
+    let (rows, cols) = (rows.max(1), cols.max(1));
+    self.cells.resize(rows, vec![BLANK; cols]);
+    for row in &mut self.cells {
+      row.resize(cols, BLANK);
+    }
+    self.rows = rows;
+    self.cols = cols;
+    self.region = (0, rows - 1);
+    self.cursor = (self.cursor.0.min(rows - 1), self.cursor.1.min(cols - 1));
+    self.wrap_pending = false;
+  }
+
+  /// Takes in what the shell printed. A char split between two calls is put together.
+  pub fn feed(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      if byte < 0x80 && self.utf8.is_empty() {
+        self.process(byte as char);
+        continue;
+      }
+      self.utf8.push(byte);
+      match std::str::from_utf8(&self.utf8) {
+        Ok(s) => {
+          let c = s.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER);
+          self.utf8.clear();
+          self.process(c);
+        }
+        Err(e) if e.error_len().is_some() || self.utf8.len() >= 4 => {
+          self.utf8.clear();
+          self.process(char::REPLACEMENT_CHARACTER);
+        }
+        Err(_) => (),
+      }
+    }
+  }
+
+  fn process(&mut self, c: char) {
+    match std::mem::replace(&mut self.state, State::Ground) {
+      State::Ground => self.control(c),
+      State::Escape => self.escape(c),
+      State::Csi(mut params) => match c {
+        '\x40'..='\x7e' => self.csi(&params, c),
+        _ => {
+          params.push(c);
+          self.state = State::Csi(params);
+        }
+      },
+      State::Osc => match c {
+        '\x07' => (),
+        '\x1b' => self.state = State::Escape,
+        _ => self.state = State::Osc,
+      },
+      State::Charset => (),
+    }
+  }
+
+  fn control(&mut self, c: char) {
+    let (row, col) = self.cursor;
+    match c {
+      '\x1b' => self.state = State::Escape,
+      '\r' => self.move_to(row, 0),
+      '\n' | '\x0b' | '\x0c' => self.line_feed(),
+      '\x08' => self.move_to(row, col.saturating_sub(1)),
+      '\t' => self.move_to(row, (col / 8 + 1) * 8),
+      _ if c < ' ' || c == '\x7f' => (),
+      _ => self.put(c),
+    }
+  }
+
+  fn escape(&mut self, c: char) {
+    match c {
+      '[' => self.state = State::Csi(String::new()),
+      ']' => self.state = State::Osc,
+      '(' | ')' | '*' | '+' => self.state = State::Charset,
+      '7' => self.saved = self.cursor,
+      '8' => self.move_to(self.saved.0, self.saved.1),
+      'D' => self.line_feed(),
+      'E' => {
+        self.move_to(self.cursor.0, 0);
+        self.line_feed();
+      }
+      'M' => self.reverse_line_feed(),
+      'c' => *self = Screen::new(self.rows, self.cols),
+      _ => (),
+    }
+  }
+
+  fn csi(&mut self, params: &str, c: char) {
+    let private = params.starts_with('?');
+    let numbers: Vec<usize> = params
+      .trim_start_matches('?')
+      .split(';')
+      .map(|n| n.parse().unwrap_or(0))
+      .collect();
+    // Most parameters count something, where 0 or none means 1.
+    let n = numbers[0].max(1);
+    let nth = |i: usize| numbers.get(i).copied().unwrap_or(0).max(1) - 1;
+    let (row, col) = self.cursor;
+    match c {
+      'A' => self.move_to(row.saturating_sub(n), col),
+      'B' | 'e' => self.move_to(row.saturating_add(n), col),
+      'C' | 'a' => self.move_to(row, col.saturating_add(n)),
+      'D' => self.move_to(row, col.saturating_sub(n)),
+      'E' => self.move_to(row.saturating_add(n), 0),
+      'F' => self.move_to(row.saturating_sub(n), 0),
+      'G' | '`' => self.move_to(row, n - 1),
+      'd' => self.move_to(n - 1, col),
+      'H' | 'f' => self.move_to(nth(0), nth(1)),
+      'J' => {
+        let (before, after) = (row * self.cols + col, self.rows * self.cols);
+        match numbers[0] {
+          0 => self.erase(before, after),
+          1 => self.erase(0, before + 1),
+          _ => self.erase(0, after),
+        }
+      }
+      'K' => {
+        let start = row * self.cols;
+        match numbers[0] {
+          0 => self.erase(start + col, start + self.cols),
+          1 => self.erase(start, start + col + 1),
+          _ => self.erase(start, start + self.cols),
+        }
+      }
+      'X' => {
+        let start = row * self.cols + col;
+        self.erase(start, start + n.min(self.cols - col));
+      }
+      'P' => {
+        let line = &mut self.cells[row];
+        line.drain(col..col.saturating_add(n).min(line.len()));
+        line.resize(self.cols, BLANK);
+      }
+      '@' => {
+        let line = &mut self.cells[row];
+        for _ in 0..n.min(self.cols - col) {
+          line.insert(col, BLANK);
+        }
+        line.truncate(self.cols);
+      }
+      'L' if (self.region.0..=self.region.1).contains(&row) => self.scroll_down(row, n),
+      'M' if (self.region.0..=self.region.1).contains(&row) => self.scroll_up(row, n),
+      'S' => self.scroll_up(self.region.0, n),
+      'T' => self.scroll_down(self.region.0, n),
+      'r' => {
+        let bottom = match numbers.get(1) {
+          Some(&bottom) if bottom > 0 => bottom.min(self.rows) - 1,
+          _ => self.rows - 1,
+        };
+        self.region = (nth(0).min(bottom), bottom);
+        self.move_to(0, 0);
+      }
+      's' => self.saved = self.cursor,
+      'u' => self.move_to(self.saved.0, self.saved.1),
+      'm' if !private => self.select_style(&numbers),
+      'h' | 'l' if private => {
+        let on = c == 'h';
+        for mode in numbers {
+          match mode {
+            1 => self.app_cursor = on,
+            // The alternate screen of full-screen programs is the one screen, cleared.
+            1049 => {
+              if on {
+                self.saved = self.cursor;
+              }
+              self.erase(0, self.rows * self.cols);
+              if !on {
+                self.move_to(self.saved.0, self.saved.1);
+              }
+            }
+            _ => (),
+          }
+        }
+      }
+      _ => (),
+    }
+  }
+
+  /// `CSI ... m`: the colors and attributes of the text that follows.
+  fn select_style(&mut self, numbers: &[usize]) {
+    let mut numbers = numbers.iter().copied();
+    while let Some(n) = numbers.next() {
+      let mut color = || match numbers.next() {
+        Some(5) => numbers.next().map(|i| Color::Indexed(i as u8)),
+        Some(2) => {
+          let mut component = || numbers.next().unwrap_or(0) as u8;
+          Some(Color::Rgb(component(), component(), component()))
+        }
+        _ => None,
+      };
+      let style = self.style;
+      self.style = match n {
+        0 => Style::default(),
+        1 => style.add_modifier(Modifier::BOLD),
+        2 => style.add_modifier(Modifier::DIM),
+        3 => style.add_modifier(Modifier::ITALIC),
+        4 => style.add_modifier(Modifier::UNDERLINED),
+        7 => style.add_modifier(Modifier::REVERSED),
+        22 => style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+        23 => style.remove_modifier(Modifier::ITALIC),
+        24 => style.remove_modifier(Modifier::UNDERLINED),
+        27 => style.remove_modifier(Modifier::REVERSED),
+        30..=37 => style.fg(Color::Indexed((n - 30) as u8)),
+        90..=97 => style.fg(Color::Indexed((n - 90 + 8) as u8)),
+        40..=47 => style.bg(Color::Indexed((n - 40) as u8)),
+        100..=107 => style.bg(Color::Indexed((n - 100 + 8) as u8)),
+        38 => match color() {
+          Some(color) => style.fg(color),
+          None => style,
+        },
+        48 => match color() {
+          Some(color) => style.bg(color),
+          None => style,
+        },
+        39 => style.fg(Color::Reset),
+        49 => style.bg(Color::Reset),
+        _ => style,
+      };
+    }
+  }
+
+  fn put(&mut self, c: char) {
+    if self.wrap_pending {
+      self.wrap_pending = false;
+      self.cursor.1 = 0;
+      self.line_feed();
+    }
+    let (row, col) = self.cursor;
+    self.cells[row][col] = Cell {
+      c,
+      style: self.style,
+    };
+    match col + 1 < self.cols {
+      true => self.cursor.1 += 1,
+      false => self.wrap_pending = true,
+    }
+  }
+
+  fn move_to(&mut self, row: usize, col: usize) {
+    self.cursor = (row.min(self.rows - 1), col.min(self.cols - 1));
+    self.wrap_pending = false;
+  }
+
+  fn line_feed(&mut self) {
+    let row = self.cursor.0;
+    if row == self.region.1 {
+      self.scroll_up(self.region.0, 1);
+    } else if row + 1 < self.rows {
+      self.cursor.0 += 1;
+    }
+    self.wrap_pending = false;
+  }
+
+  fn reverse_line_feed(&mut self) {
+    match self.cursor.0 == self.region.0 {
+      true => self.scroll_down(self.region.0, 1),
+      false => self.cursor.0 = self.cursor.0.saturating_sub(1),
+    }
+  }
+
+  /// Moves the rows from `top` to the bottom of the scrolling region up by `n`.
+  fn scroll_up(&mut self, top: usize, n: usize) {
+    let bottom = self.region.1;
+    for _ in 0..n.min(bottom + 1 - top) {
+      self.cells.remove(top);
+      self.cells.insert(bottom, vec![BLANK; self.cols]);
+    }
+  }
+
+  /// Moves the rows from `top` to the bottom of the scrolling region down by `n`.
+  fn scroll_down(&mut self, top: usize, n: usize) {
+    let bottom = self.region.1;
+    for _ in 0..n.min(bottom + 1 - top) {
+      self.cells.remove(bottom);
+      self.cells.insert(top, vec![BLANK; self.cols]);
+    }
+  }
+
+  /// Blanks the cells from `start` up to but not including `end`, counted row by row.
+  fn erase(&mut self, start: usize, end: usize) {
+    for i in start..end.min(self.rows * self.cols) {
+      self.cells[i / self.cols][i % self.cols] = BLANK;
+    }
+  }
+}
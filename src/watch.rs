@@ -0,0 +1,53 @@
+//! Watches open files for changes made by other programs.
+//!
+//! There is no native watcher here, so a thread polls modification times. A change is only
+//! reported once the time has stayed put for `SETTLE`, so a program that writes a file in several
+//! steps causes a single notification.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL: Duration = Duration::from_millis(250);
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// The files to watch. The editor replaces the list as buffers come and go.
+pub type Watched = Arc<Mutex<Vec<PathBuf>>>;
+
+struct State {
+  mtime: Option<SystemTime>,
+  /// When the last unreported change was seen.
+  changed: Option<Instant>,
+}
+
+/// Polls `watched` on a thread of its own, calling `notify` with each file that changed. The
+/// thread ends when `notify` returns false.
+pub fn spawn(watched: Watched, notify: impl Fn(PathBuf) -> bool + Send + 'static) {
+  thread::spawn(move || {
+    let mut states: HashMap<PathBuf, State> = HashMap::new();
+    loop {
+      thread::sleep(POLL);
+      let paths = watched.lock().map(|w| w.clone()).unwrap_or_default();
+      states.retain(|path, _| paths.contains(path));
+      for path in paths {
+        let mtime = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let state = states.entry(path.clone()).or_insert(State {
+          mtime,
+          changed: None,
+        });
+        if state.mtime != mtime {
+          state.mtime = mtime;
+          state.changed = Some(Instant::now());
+        } else if state.changed.is_some_and(|at| at.elapsed() >= SETTLE) {
+          state.changed = None;
+          if !notify(path) {
+            return;
+          }
+        }
+      }
+    }
+  });
+}
@@ -0,0 +1,110 @@
+use crate::buffer::Position;
+use crate::options::WindowOptions;
+use tui::layout::Rect;
+
+/// A view onto a buffer. Several windows may show the same buffer.
+#[derive(Clone, Debug, Default)]
+pub struct Window {
+  /// Index into `Editor::buffers`.
+  pub buffer: usize,
+  pub cursor: Position,
+  /// The column `j` and `k` aim for, and where the last of them left the cursor. While the
+  /// cursor is still there, the next one aims for that column again, even if a shorter line
+  /// pulled the cursor left of it. After `$` it is the end of every line.
+  pub goal_column: Option<(Position, usize)>,
+  pub scroll_offset: usize,
+  pub col_offset: usize,
+  /// Content height at the last render, used by commands that scroll by screen lines.
+  pub height: usize,
+  /// Where the window was last drawn, including its scrollbar, for mouse hit-testing.
+  pub area: Rect,
+  pub options: WindowOptions,
+}
+
+impl Window {
+  pub fn new(buffer: usize, options: WindowOptions) -> Self {
+    Window {
+      buffer,
+      options,
+      ..Window::default()
+    }
+  }
+}
+
+/// How the windows share the screen: a tree of splits with windows, by index into
+/// `Editor::windows`, at the leaves.
+#[derive(Clone, Debug)]
+pub enum Layout {
+  Window(usize),
+  /// Children side by side when `vertical` (`:vsplit`), else stacked (`:split`).
+  Split {
+    vertical: bool,
+    children: Vec<Layout>,
+  },
+}
+
+impl Layout {
+  /// Makes room for a new window inserted into `Editor::windows` at `index`, placed left of or
+  /// above the window that was there.
+  pub fn split(&mut self, index: usize, vertical: bool) {
+    self.renumber(|i| if i >= index { i + 1 } else { i });
+    self.insert_before(index + 1, index, vertical);
+  }
+
+  fn insert_before(&mut self, existing: usize, new: usize, vertical: bool) -> bool {
+    match self {
+      Layout::Window(i) if *i == existing => {
+        *self = Layout::Split {
+          vertical,
+          children: vec![Layout::Window(new), Layout::Window(existing)],
+        };
+        true
+      }
+      Layout::Window(_) => false,
+      Layout::Split {
+        vertical: direction,
+        children,
+      } => {
+        // Splitting along the parent's direction just adds a sibling.
+        let sibling = children
+          .iter()
+          .position(|c| matches!(c, Layout::Window(i) if *i == existing));
+        match sibling {
+          Some(at) if *direction == vertical => {
+            children.insert(at, Layout::Window(new));
+            true
+          }
+          _ => children
+            .iter_mut()
+            .any(|c| c.insert_before(existing, new, vertical)),
+        }
+      }
+    }
+  }
+
+  /// Forgets the window at `index`, which was removed from `Editor::windows`. A split left with
+  /// one child is replaced by that child.
+  pub fn remove(&mut self, index: usize) {
+    self.prune(index);
+    self.renumber(|i| if i > index { i - 1 } else { i });
+  }
+
+  fn prune(&mut self, index: usize) {
+    if let Layout::Split { children, .. } = self {
+      children.retain(|c| !matches!(c, Layout::Window(i) if *i == index));
+      for child in children.iter_mut() {
+        child.prune(index);
+      }
+      if children.len() == 1 {
+        *self = children.remove(0);
+      }
+    }
+  }
+
+  fn renumber(&mut self, f: impl Fn(usize) -> usize + Copy) {
+    match self {
+      Layout::Window(i) => *i = f(*i),
+      Layout::Split { children, .. } => children.iter_mut().for_each(|c| c.renumber(f)),
+    }
+  }
+}
@@ -0,0 +1,209 @@
+//! Word motions and the word text objects. A word is a run of keyword chars, which `iskeyword`
+//! decides, or a run of other non-blank chars; an empty line counts as a word too.
+//!
+//! Positions here may be one past the last char of a line, standing for its line break.
+
+use crate::buffer::Position;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Class {
+  Blank,
+  Keyword,
+  Other,
+}
+
+/// The text words are looked for in, and which chars make them up.
+pub struct Words<'a, F> {
+  pub lines: &'a [String],
+  pub is_keyword: F,
+}
+
+impl<F: Fn(char) -> bool> Words<'_, F> {
+  /// `w`: the start of the next word, or just past the end of the text if there is none.
+  pub fn next_start(&self, pos: Position) -> Position {
+    let last = self.lines.len() - 1;
+    let end = Position::new(last, self.len(last));
+    let mut p = pos;
+    let class = self.class(p);
+    if class != Class::Blank {
+      while self.class(p) == class {
+        match self.next(p) {
+          Some(n) => p = n,
+          None => return end,
+        }
+      }
+    }
+    while self.class(p) == Class::Blank {
+      if p != pos && self.is_empty_line(p) {
+        return p;
+      }
+      match self.next(p) {
+        Some(n) => p = n,
+        None => return end,
+      }
+    }
+    p
+  }
+
+  /// The last char of the word under `pos`, if it isn't on a blank.
+  pub fn end_of_word(&self, pos: Position) -> Option<Position> {
+    let class = self.class(pos);
+    if class == Class::Blank {
+      return None;
+    }
+    let mut p = pos;
+    while let Some(n) = self.next(p).filter(|&n| self.class(n) == class) {
+      p = n;
+    }
+    Some(p)
+  }
+
+  /// `e`: the end of the word, or of the next one if already at its end.
+  pub fn next_end(&self, pos: Position) -> Position {
+    let Some(mut p) = self.next(pos) else {
+      return pos;
+    };
+    while self.class(p) == Class::Blank {
+      match self.next(p) {
+        Some(n) => p = n,
+        None => return p,
+      }
+    }
+    let class = self.class(p);
+    while let Some(n) = self.next(p).filter(|&n| self.class(n) == class) {
+      p = n;
+    }
+    p
+  }
+
+  /// `b`: the start of the word, or of the one before if already at its start.
+  pub fn prev_start(&self, pos: Position) -> Position {
+    let Some(mut p) = self.prev(pos) else {
+      return pos;
+    };
+    while self.class(p) == Class::Blank {
+      if self.is_empty_line(p) {
+        return p;
+      }
+      match self.prev(p) {
+        Some(n) => p = n,
+        None => return p,
+      }
+    }
+    let class = self.class(p);
+    while let Some(n) = self.prev(p).filter(|&n| self.class(n) == class) {
+      p = n;
+    }
+    p
+  }
+
+  /// `iw` and `aw` on the cursor line: the word or run of blanks under `pos`, and with `aw` the
+  /// blanks after it, or before it if there are none after. From its start up to but not
+  /// including its end.
+  pub fn object(&self, pos: Position, inner: bool) -> Option<(usize, usize)> {
+    let chars: Vec<char> = self.lines[pos.line].chars().collect();
+    let class = |i: usize| self.class_of(chars[i]);
+    if pos.col >= chars.len() {
+      return None;
+    }
+    let run = |col: usize| {
+      let mut start = col;
+      while start > 0 && class(start - 1) == class(col) {
+        start -= 1;
+      }
+      let mut end = col + 1;
+      while end < chars.len() && class(end) == class(col) {
+        end += 1;
+      }
+      (start, end)
+    };
+    let (start, end) = run(pos.col);
+    if inner {
+      return Some((start, end));
+    }
+    // On blanks, `aw` is them and the word after.
+    if class(pos.col) == Class::Blank {
+      return Some(match end < chars.len() {
+        true => (start, run(end).1),
+        false => (start, end),
+      });
+    }
+    Some(match end < chars.len() && class(end) == Class::Blank {
+      true => (start, run(end).1),
+      false if start > 0 && class(start - 1) == Class::Blank => (run(start - 1).0, end),
+      false => (start, end),
+    })
+  }
+
+  /// The run of keyword chars under `pos`, if it is on one.
+  pub fn keyword_at(&self, pos: Position) -> Option<String> {
+    if self.class(pos) != Class::Keyword {
+      return None;
+    }
+    let (start, end) = self.object(pos, true)?;
+    Some(
+      self.lines[pos.line]
+        .chars()
+        .skip(start)
+        .take(end - start)
+        .collect(),
+    )
+  }
+
+  /// Where `word` is on line `line` as a whole word, each from its start up to but not
+  /// including its end.
+  pub fn occurrences(&self, line: usize, word: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = self.lines[line].chars().collect();
+    let word: Vec<char> = word.chars().collect();
+    let keyword = |i: usize| chars.get(i).is_some_and(|&c| (self.is_keyword)(c));
+    (0..(chars.len() + 1).saturating_sub(word.len()))
+      .filter(|&i| chars[i..i + word.len()] == word[..])
+      .filter(|&i| (i == 0 || !keyword(i - 1)) && !keyword(i + word.len()))
+      .map(|i| (i, i + word.len()))
+      .collect()
+  }
+
+  fn class(&self, pos: Position) -> Class {
+    match self.lines[pos.line].chars().nth(pos.col) {
+      Some(c) => self.class_of(c),
+      None => Class::Blank,
+    }
+  }
+
+  fn class_of(&self, c: char) -> Class {
+    match c {
+      _ if c.is_whitespace() => Class::Blank,
+      _ if (self.is_keyword)(c) => Class::Keyword,
+      _ => Class::Other,
+    }
+  }
+
+  fn is_empty_line(&self, pos: Position) -> bool {
+    pos.col == 0 && self.lines[pos.line].is_empty()
+  }
+
+  fn len(&self, line: usize) -> usize {
+    self.lines[line].chars().count()
+  }
+
+  /// The position after `pos`, going through line breaks but not past the last char.
+  fn next(&self, pos: Position) -> Option<Position> {
+    let last_line = pos.line + 1 == self.lines.len();
+    let len = self.len(pos.line);
+    if pos.col < len && !(last_line && pos.col + 1 == len) {
+      Some(Position::new(pos.line, pos.col + 1))
+    } else if !last_line {
+      Some(Position::new(pos.line + 1, 0))
+    } else {
+      None
+    }
+  }
+
+  fn prev(&self, pos: Position) -> Option<Position> {
+    match (pos.col, pos.line) {
+      (0, 0) => None,
+      (0, line) => Some(Position::new(line - 1, self.len(line - 1))),
+      (col, line) => Some(Position::new(line, col - 1)),
+    }
+  }
+}